@@ -214,9 +214,11 @@ fn bench_string_function_comparison(c: &mut Criterion) {
                     params: vec![FunctionParam {
                         arg_kind: FunctionArgKind::Field,
                         val_type: Type::Bytes,
+                        generic: false,
                     }],
                     opt_params: vec![],
                     return_type: Type::Bytes,
+                    pure: true,
                     implementation: FunctionImpl::new(lowercase),
                 },
             ),
@@ -226,9 +228,11 @@ fn bench_string_function_comparison(c: &mut Criterion) {
                     params: vec![FunctionParam {
                         arg_kind: FunctionArgKind::Field,
                         val_type: Type::Bytes,
+                        generic: false,
                     }],
                     opt_params: vec![],
                     return_type: Type::Bytes,
+                    pure: true,
                     implementation: FunctionImpl::new(uppercase),
                 },
             ),
@@ -236,12 +240,65 @@ fn bench_string_function_comparison(c: &mut Criterion) {
         filters: &[
             r#"lowercase(http.host) == "example.org""#,
             r#"uppercase(lowercase(http.host)) == "EXAMPLE.ORG""#,
+            r#"lowercase(http.host) == "example.com" || lowercase(http.host) == "example.net" || lowercase(http.host) contains ".org" || lowercase(http.host) contains ".io""#,
         ],
         values: &["example.org", "EXAMPLE.ORG"],
     }
     .run(c)
 }
 
+fn bench_shared_calls(c: &mut Criterion) {
+    // The same call in every clause, as in rules checking many conditions on
+    // a normalized field. Calls to pure functions are made once per execution,
+    // while impure ones are repeated.
+    let filter = r#"lowercase(http.host) == "example.com"
+        || lowercase(http.host) == "example.net"
+        || lowercase(http.host) == "example.org"
+        || lowercase(http.host) contains ".example.com"
+        || lowercase(http.host) contains ".example.net"
+        || lowercase(http.host) contains ".example.org"
+        || lowercase(http.host) contains "static."
+        || lowercase(http.host) contains "cdn."
+        || lowercase(http.host) contains "api."
+        || lowercase(http.host) contains "www.""#;
+
+    let host = "Some.Long.Subdomain.Of.Another.Long.Subdomain.Of.Example.ORG";
+
+    let run = |pure: bool| {
+        let mut scheme = Scheme::default();
+        scheme.add_field("http.host".into(), Type::Bytes).unwrap();
+        scheme
+            .add_function(
+                "lowercase".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Field,
+                        val_type: Type::Bytes,
+                        generic: false,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    pure,
+                    implementation: FunctionImpl::new(lowercase),
+                },
+            )
+            .unwrap();
+        move |b: &mut Bencher| {
+            let filter = scheme.parse(filter).unwrap().compile();
+
+            let mut exec_ctx = ExecutionContext::new(&scheme);
+            exec_ctx.set_field_value("http.host", host).unwrap();
+
+            b.iter(|| filter.execute(&exec_ctx));
+        }
+    };
+
+    c.bench(
+        "shared calls",
+        Benchmark::new("shared", run(true)).with_function("repeated", run(false)),
+    );
+}
+
 criterion_group! {
     name = field_benchmarks;
     config = Criterion::default();
@@ -251,6 +308,7 @@ criterion_group! {
         bench_string_comparisons,
         bench_string_matches,
         bench_string_function_comparison,
+        bench_shared_calls,
 }
 
 criterion_main!(field_benchmarks);
@@ -27,12 +27,14 @@ fn main() {
                 params: vec![FunctionParam {
                     arg_kind: FunctionArgKind::Field,
                     val_type: Type::Bytes,
+                    generic: false,
                 }],
                 opt_params: vec![FunctionOptParam {
                     arg_kind: FunctionArgKind::Literal,
                     default_value: "".into(),
                 }],
                 return_type: Type::Bytes,
+                pure: false,
                 implementation: FunctionImpl::new(panic_function),
             },
         )
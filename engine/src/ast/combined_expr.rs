@@ -1,10 +1,23 @@
-use super::{simple_expr::SimpleExpr, Expr};
+use super::{
+    function_expr::SharedCalls,
+    simple_expr::{SimpleExpr, UnaryOp, UnboundSimpleExpr},
+    span::{NodeSpan, SerializeSpans, Span, WithSpans},
+    Expr, NodeKind,
+};
 use crate::{
     filter::CompiledExpr,
-    lex::{skip_space, Lex, LexResult, LexWith},
-    scheme::{Field, Scheme},
+    lex::{
+        expect, lex_comment, lex_raw_str, skip_space, span, Lex, LexError, LexErrorKind, LexResult,
+        LexWith,
+    },
+    limits::{Nesting, Parser},
+    scheme::{Comment, Field, Rebinder, Scheme},
+};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::{
+    fmt::{self, Display, Formatter},
+    mem,
 };
-use serde::Serialize;
 
 lex_enum!(#[derive(PartialOrd, Ord)] CombiningOp {
     "or" | "||" => Or,
@@ -12,17 +25,57 @@ lex_enum!(#[derive(PartialOrd, Ord)] CombiningOp {
     "and" | "&&" => And,
 });
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
-#[serde(untagged)]
+impl Display for CombiningOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CombiningOp::Or => "or",
+            CombiningOp::Xor => "xor",
+            CombiningOp::And => "and",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CombinedExpr<'s> {
     Simple(SimpleExpr<'s>),
     Combining {
         op: CombiningOp,
         items: Vec<CombinedExpr<'s>>,
+        span: NodeSpan,
     },
 }
 
+impl<'s> Serialize for CombinedExpr<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.serialize_spans(false, ser)
+    }
+}
+
+impl<'s> SerializeSpans for CombinedExpr<'s> {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        match self {
+            CombinedExpr::Simple(op) => op.serialize_spans(spans, ser),
+            CombinedExpr::Combining { op, items, span } => {
+                let mut out = ser.serialize_struct("CombinedExpr", 3)?;
+                out.serialize_field("op", op)?;
+                out.serialize_field("items", &WithSpans(&items[..], spans))?;
+                if let Some(span) = span.shown(spans) {
+                    out.serialize_field("span", &span)?;
+                }
+                out.end()
+            }
+        }
+    }
+}
+
 impl<'s> CombinedExpr<'s> {
+    pub(crate) fn span(&self) -> NodeSpan {
+        match self {
+            CombinedExpr::Simple(op) => op.span(),
+            CombinedExpr::Combining { span, .. } => *span,
+        }
+    }
+
     fn lex_combining_op(input: &str) -> (Option<CombiningOp>, &str) {
         match CombiningOp::lex(skip_space(input)) {
             Ok((op, input)) => (Some(op), skip_space(input)),
@@ -32,14 +85,14 @@ impl<'s> CombinedExpr<'s> {
 
     fn lex_more_with_precedence<'i>(
         self,
-        scheme: &'s Scheme,
+        parser: &Parser<'s>,
         min_prec: Option<CombiningOp>,
         mut lookahead: (Option<CombiningOp>, &'i str),
     ) -> LexResult<'i, Self> {
         let mut lhs = self;
 
         while let Some(op) = lookahead.0 {
-            let mut rhs = SimpleExpr::lex_with(lookahead.1, scheme)
+            let mut rhs = SimpleExpr::lex_with(lookahead.1, parser)
                 .map(|(op, input)| (CombinedExpr::Simple(op), input))?;
 
             loop {
@@ -49,19 +102,22 @@ impl<'s> CombinedExpr<'s> {
                 }
                 rhs = rhs
                     .0
-                    .lex_more_with_precedence(scheme, lookahead.0, lookahead)?;
+                    .lex_more_with_precedence(parser, lookahead.0, lookahead)?;
             }
 
             match lhs {
                 CombinedExpr::Combining {
                     op: lhs_op,
                     ref mut items,
+                    ref mut span,
                 } if lhs_op == op => {
+                    *span = span.to(rhs.0.span());
                     items.push(rhs.0);
                 }
                 _ => {
                     lhs = CombinedExpr::Combining {
                         op,
+                        span: lhs.span().to(rhs.0.span()),
                         items: vec![lhs, rhs.0],
                     };
                 }
@@ -76,13 +132,355 @@ impl<'s> CombinedExpr<'s> {
 
         Ok((lhs, lookahead.1))
     }
+
+    /// Lexes a list of operands separated by combining ops without building
+    /// an expression, collecting every error instead of stopping at the first.
+    ///
+    /// After an error, lexing resumes from the next combining op or closing
+    /// parenthesis on the same nesting level. Returns the unconsumed input,
+    /// which is either empty or starts with `)` when `nested` is set.
+    pub(crate) fn recover<'i>(
+        mut input: &'i str,
+        parser: &Parser<'s>,
+        nested: bool,
+        errors: &mut Vec<LexError<'i>>,
+    ) -> &'i str {
+        loop {
+            input = skip_space(Self::recover_operand(
+                skip_space(input),
+                parser,
+                nested,
+                errors,
+            ));
+
+            if let Ok((_, rest)) = CombiningOp::lex(input) {
+                input = rest;
+            } else if input.is_empty() || (nested && input.starts_with(')')) {
+                return input;
+            } else {
+                // The same errors as the ones reported by a regular parse.
+                errors.push(if nested {
+                    (LexErrorKind::ExpectedLiteral(")"), input)
+                } else {
+                    (LexErrorKind::EOF, input)
+                });
+                input = skip_to_boundary(input, nested);
+
+                match CombiningOp::lex(input) {
+                    Ok((_, rest)) => input = rest,
+                    Err(_) => return input,
+                }
+            }
+        }
+    }
+
+    fn recover_operand<'i>(
+        input: &'i str,
+        parser: &Parser<'s>,
+        nested: bool,
+        errors: &mut Vec<LexError<'i>>,
+    ) -> &'i str {
+        // Both parentheses and unary ops nest the operand.
+        let opening = expect(input, "(").or_else(|_| UnaryOp::lex(input).map(|(_, rest)| rest));
+        let _nesting = match opening {
+            Ok(rest) => match Nesting::Expr.enter(parser, span(input, rest)) {
+                Ok(guard) => Some(guard),
+                Err(err) => {
+                    errors.push(err);
+                    return skip_to_boundary(input, nested);
+                }
+            },
+            Err(_) => None,
+        };
+
+        if let Ok(rest) = expect(input, "(") {
+            let rest = Self::recover(rest, parser, true, errors);
+            expect(rest, ")").unwrap_or_else(|err| {
+                errors.push(err);
+                rest
+            })
+        } else if let Ok((_, rest)) = UnaryOp::lex(input) {
+            Self::recover_operand(skip_space(rest), parser, nested, errors)
+        } else {
+            match SimpleExpr::lex_with(input, parser) {
+                Ok((_, rest)) => rest,
+                Err(err) => {
+                    errors.push(err);
+                    // Skip from the start of the operand rather than from
+                    // the error so that we don't end up inside of a string.
+                    skip_to_boundary(input, nested)
+                }
+            }
+        }
+    }
+
+    /// Strips any parentheses around the expression.
+    fn unparenthesized(&self) -> &Self {
+        match self {
+            CombinedExpr::Simple(SimpleExpr::Parenthesized(op, _)) => op.unparenthesized(),
+            _ => self,
+        }
+    }
+
+    /// Prints the expression as filter source, without parentheses around
+    /// it.
+    ///
+    /// In the alternate mode, operands of combining ops are put on separate
+    /// lines, indented by `indent` levels.
+    pub(crate) fn print(
+        &self,
+        f: &mut Formatter<'_>,
+        indent: usize,
+        comments: &mut Comments<'_>,
+    ) -> fmt::Result {
+        match self.unparenthesized() {
+            CombinedExpr::Simple(op) => op.print(f, indent, comments),
+            CombinedExpr::Combining { op, items, .. } => {
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        // Comments before an operand go before its op, so
+                        // that they stay next to it.
+                        let start = item.span().get().start;
+                        if f.alternate() {
+                            new_line(f, indent)?;
+                        } else {
+                            f.write_str(" ")?;
+                        }
+                        comments.print_before(f, start, indent)?;
+                        write!(f, "{} ", op)?;
+                    }
+                    // Operands with a higher precedence don't need
+                    // parentheses, but ones with the same op still do to
+                    // keep the structure of the AST.
+                    item.print_nested(f, indent, comments, |item_op| item_op <= *op)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Prints the expression as an operand, in parentheses if it's a
+    /// combining expression with an op for which `needs_parens` is true.
+    pub(crate) fn print_nested(
+        &self,
+        f: &mut Formatter<'_>,
+        indent: usize,
+        comments: &mut Comments<'_>,
+        needs_parens: impl FnOnce(CombiningOp) -> bool,
+    ) -> fmt::Result {
+        match self.unparenthesized() {
+            CombinedExpr::Combining { op, .. } if needs_parens(*op) => {
+                f.write_str("(")?;
+                if f.alternate() {
+                    new_line(f, indent + 1)?;
+                    self.print(f, indent + 1, comments)?;
+                    new_line(f, indent)?;
+                } else {
+                    self.print(f, indent, comments)?;
+                }
+                f.write_str(")")
+            }
+            op => op.print(f, indent, comments),
+        }
+    }
+
+    pub(crate) fn unbind(&self) -> UnboundCombinedExpr {
+        match self {
+            CombinedExpr::Simple(op) => UnboundCombinedExpr::Simple(op.unbind()),
+            CombinedExpr::Combining { op, items, span } => UnboundCombinedExpr::Combining {
+                op: *op,
+                items: items.iter().map(CombinedExpr::unbind).collect(),
+                span: *span,
+            },
+        }
+    }
+
+    pub(crate) fn rebind<'t>(&self, rebinder: &mut Rebinder<'t>) -> Option<CombinedExpr<'t>> {
+        match self {
+            CombinedExpr::Simple(op) => op.rebind(rebinder).map(CombinedExpr::Simple),
+            CombinedExpr::Combining { op, items, span } => {
+                // Rebind every item first to report all incompatibilities.
+                let items = items
+                    .iter()
+                    .map(|item| item.rebind(rebinder))
+                    .collect::<Vec<_>>();
+                Some(CombinedExpr::Combining {
+                    op: *op,
+                    items: items.into_iter().collect::<Option<_>>()?,
+                    span: *span,
+                })
+            }
+        }
+    }
+}
+
+/// A [`CombinedExpr`] detached from its scheme, so that it can be stored in
+/// the scheme as the expansion of a macro.
+///
+/// Fields are kept by their indices and functions by their names, which stay
+/// valid because neither can be removed from a scheme.
+#[derive(Debug, Clone)]
+pub(crate) enum UnboundCombinedExpr {
+    Simple(UnboundSimpleExpr),
+    Combining {
+        op: CombiningOp,
+        items: Vec<UnboundCombinedExpr>,
+        span: NodeSpan,
+    },
+}
+
+impl UnboundCombinedExpr {
+    /// Binds the expression to the scheme it was taken from.
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> CombinedExpr<'s> {
+        match self {
+            UnboundCombinedExpr::Simple(op) => CombinedExpr::Simple(op.bind(scheme)),
+            UnboundCombinedExpr::Combining { op, items, span } => CombinedExpr::Combining {
+                op: *op,
+                items: items.iter().map(|item| item.bind(scheme)).collect(),
+                span: *span,
+            },
+        }
+    }
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for CombinedExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
-        let (lhs, input) = SimpleExpr::lex_with(input, scheme)?;
+/// Comments that are yet to be printed along with an expression, in the
+/// order they were written.
+#[derive(Default)]
+pub(crate) struct Comments<'a>(pub &'a [Comment]);
+
+impl<'a> Comments<'a> {
+    /// Prints the comments that were written before `start` in the parsed
+    /// filter.
+    ///
+    /// Each one is followed by a line break in the alternate mode, and
+    /// otherwise only line comments are.
+    pub fn print_before(
+        &mut self,
+        f: &mut Formatter<'_>,
+        start: usize,
+        indent: usize,
+    ) -> fmt::Result {
+        while let Some((comment, rest)) = self.0.split_first() {
+            if comment.span.start >= start {
+                break;
+            }
+            f.write_str(&comment.text)?;
+            if f.alternate() {
+                new_line(f, indent)?;
+            } else if comment.text.starts_with('#') {
+                f.write_str("\n")?;
+            } else {
+                f.write_str(" ")?;
+            }
+            self.0 = rest;
+        }
+        Ok(())
+    }
+
+    /// Prints all remaining comments after the end of the expression.
+    pub fn print_rest(&mut self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut after_line_comment = false;
+        for comment in mem::take(&mut self.0) {
+            if f.alternate() || after_line_comment {
+                new_line(f, 0)?;
+            } else {
+                f.write_str(" ")?;
+            }
+            f.write_str(&comment.text)?;
+            after_line_comment = comment.text.starts_with('#');
+        }
+        Ok(())
+    }
+}
+
+fn new_line(f: &mut Formatter<'_>, indent: usize) -> fmt::Result {
+    f.write_str("\n")?;
+    for _ in 0..indent {
+        f.write_str("    ")?;
+    }
+    Ok(())
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// Whether a slash after `before` starts a regex literal, rather than e.g. a
+// prefix length of a CIDR.
+fn starts_regex(before: &str) -> bool {
+    let before = before.trim_end();
+    before.ends_with('~') || before.ends_with("matches")
+}
+
+/// Skips input until a combining op or a closing parenthesis that is not
+/// part of a string, of a regex or of a nested parenthesized expression.
+///
+/// Unbalanced closing parentheses are only treated as boundaries when
+/// `nested` is set, otherwise they are skipped too.
+fn skip_to_boundary(input: &str, nested: bool) -> &str {
+    let mut depth = 0usize;
+    // Ending quote or slash of the string or regex being skipped, if any.
+    let mut delimiter = None;
+    let mut in_char_class = false;
+    let mut after_word = false;
+    let mut iter = input.char_indices();
+
+    while let Some((pos, c)) = iter.next() {
+        let rest = &input[pos..];
+
+        if let Some(end) = delimiter {
+            match c {
+                '\\' => {
+                    iter.next();
+                }
+                '[' if end == '/' => in_char_class = true,
+                ']' => in_char_class = false,
+                c if c == end && !in_char_class => delimiter = None,
+                _ => {}
+            }
+        } else if let Some((comment, _)) = lex_comment(rest) {
+            // The first character of the comment is already consumed.
+            for _ in comment.chars().skip(1) {
+                iter.next();
+            }
+        } else if let Some(raw_str) = lex_raw_str(rest).filter(|_| !after_word) {
+            // Unterminated raw strings run until the end of input.
+            let len = raw_str.map_or(rest.len(), |(_, after)| rest.len() - after.len());
+            for _ in rest[..len].chars().skip(1) {
+                iter.next();
+            }
+        } else {
+            match c {
+                '"' => delimiter = Some('"'),
+                '/' if starts_regex(&input[..pos]) => delimiter = Some('/'),
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                ')' if nested => return rest,
+                _ if depth == 0 => {
+                    if let Ok((_, after)) = CombiningOp::lex(rest) {
+                        // Word ops like `and` must not be a part of a name.
+                        let is_word = c.is_ascii_alphabetic();
+
+                        if !is_word || !(after_word || after.starts_with(is_word_char)) {
+                            return rest;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        after_word = is_word_char(c);
+    }
+
+    &input[input.len()..]
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for CombinedExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let (lhs, input) = SimpleExpr::lex_with(input, parser)?;
         let lookahead = Self::lex_combining_op(input);
-        CombinedExpr::Simple(lhs).lex_more_with_precedence(scheme, None, lookahead)
+        CombinedExpr::Simple(lhs).lex_more_with_precedence(parser, None, lookahead)
     }
 }
 
@@ -94,41 +492,106 @@ impl<'s> Expr<'s> for CombinedExpr<'s> {
         }
     }
 
-    fn compile(self) -> CompiledExpr<'s> {
+    fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
         match self {
-            CombinedExpr::Simple(op) => op.compile(),
-            CombinedExpr::Combining { op, items } => {
-                let items = items
-                    .into_iter()
-                    .map(Expr::compile)
-                    .collect::<Vec<_>>()
-                    .into_boxed_slice();
+            CombinedExpr::Simple(op) => op.walk_fields(visitor),
+            CombinedExpr::Combining { items, .. } => {
+                for op in items {
+                    op.walk_fields(visitor);
+                }
+            }
+        }
+    }
+
+    fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
+        match self {
+            CombinedExpr::Simple(op) => op.walk_nodes(visitor),
+            CombinedExpr::Combining { items, span, .. } => {
+                visitor(NodeKind::Combining, span.get());
+                for op in items {
+                    op.walk_nodes(visitor);
+                }
+            }
+        }
+    }
+
+    fn resolve_spans(&mut self, end: usize) {
+        match self {
+            CombinedExpr::Simple(op) => op.resolve_spans(end),
+            CombinedExpr::Combining { items, span, .. } => {
+                span.resolve(end);
+                for op in items {
+                    op.resolve_spans(end);
+                }
+            }
+        }
+    }
+
+    fn fold(self, calls: &mut SharedCalls<'s>) -> Self {
+        match self {
+            CombinedExpr::Simple(op) => CombinedExpr::Simple(op.fold(calls)),
+            CombinedExpr::Combining { op, items, span } => CombinedExpr::Combining {
+                op,
+                items: items.into_iter().map(|item| item.fold(calls)).collect(),
+                span,
+            },
+        }
+    }
+
+    fn compile_with(self, calls: &mut SharedCalls<'s>) -> CompiledExpr<'s> {
+        match self {
+            CombinedExpr::Simple(op) => op.compile_with(calls),
+            CombinedExpr::Combining { op, items, .. } => {
+                // Missing values count as `false`, so the result of constant
+                // operands can be accumulated upfront and, for `and` and
+                // `or`, might even decide the result of the whole expression.
+                let mut acc = op == CombiningOp::And;
+                let mut dynamic_items = Vec::with_capacity(items.len());
+
+                for item in items {
+                    match item.compile_with(calls) {
+                        CompiledExpr::Constant(value) => {
+                            let value = value.unwrap_or(false);
+                            match op {
+                                CombiningOp::And if !value => {
+                                    return CompiledExpr::Constant(Some(false));
+                                }
+                                CombiningOp::Or if value => {
+                                    return CompiledExpr::Constant(Some(true));
+                                }
+                                CombiningOp::Xor => acc ^= value,
+                                _ => {}
+                            }
+                        }
+                        item => dynamic_items.push(item),
+                    }
+                }
+
+                if dynamic_items.is_empty() {
+                    return CompiledExpr::Constant(Some(acc));
+                }
+
+                let items = dynamic_items.into_boxed_slice();
 
                 match op {
-                    CombiningOp::And => {
-                        CompiledExpr::new(move |ctx| {
-                            let r = items
+                    CombiningOp::And => CompiledExpr::new(move |ctx, cache| {
+                        Some(
+                            items
                                 .iter()
-                                .map(|item| item.execute(ctx))
-                                .all(|r| r.unwrap_or_else(|| false));
-                            Some(r)
-                        })
-                    }
-                    CombiningOp::Or => {
-                        CompiledExpr::new(move |ctx| {
-                            let r = items
+                                .all(|item| item.execute_with_cache(ctx, cache).unwrap_or(false)),
+                        )
+                    }),
+                    CombiningOp::Or => CompiledExpr::new(move |ctx, cache| {
+                        Some(
+                            items
                                 .iter()
-                                .map(|item| item.execute(ctx))
-                                .any(|r| r.unwrap_or_else(|| false));
-                            Some(r)
-                        })
-                    }
-                    CombiningOp::Xor => CompiledExpr::new(move |ctx| {
-                        items
-                            .iter()
-                            .fold(Some(false), |acc, item| {
-                                acc.xor( item.execute(ctx))
-                            })
+                                .any(|item| item.execute_with_cache(ctx, cache).unwrap_or(false)),
+                        )
+                    }),
+                    CombiningOp::Xor => CompiledExpr::new(move |ctx, cache| {
+                        Some(items.iter().fold(acc, |acc, item| {
+                            acc ^ item.execute_with_cache(ctx, cache).unwrap_or(false)
+                        }))
                     }),
                 }
             }
@@ -149,27 +612,31 @@ fn test() {
     let ctx = &mut ExecutionContext::new(scheme);
 
     let t_expr = CombinedExpr::Simple(SimpleExpr::Field(
-        complete(FieldExpr::lex_with("t", scheme)).unwrap(),
+        complete(FieldExpr::lex_with("t", &Parser::trusted(scheme))).unwrap(),
     ));
 
     let t_expr = || t_expr.clone();
 
     let f_expr = CombinedExpr::Simple(SimpleExpr::Field(
-        complete(FieldExpr::lex_with("f", scheme)).unwrap(),
+        complete(FieldExpr::lex_with("f", &Parser::trusted(scheme))).unwrap(),
     ));
 
     let f_expr = || f_expr.clone();
 
-    assert_ok!(CombinedExpr::lex_with("t", scheme), t_expr());
+    assert_ok!(
+        CombinedExpr::lex_with("t", &Parser::trusted(scheme)),
+        t_expr()
+    );
 
     ctx.set_field_value("t", true).unwrap();
     ctx.set_field_value("f", false).unwrap();
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t and t", scheme),
+            CombinedExpr::lex_with("t and t", &Parser::trusted(scheme)),
             CombinedExpr::Combining {
                 op: CombiningOp::And,
+                span: NodeSpan::default(),
                 items: vec![t_expr(), t_expr()],
             }
         );
@@ -181,9 +648,10 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t and f", scheme),
+            CombinedExpr::lex_with("t and f", &Parser::trusted(scheme)),
             CombinedExpr::Combining {
                 op: CombiningOp::And,
+                span: NodeSpan::default(),
                 items: vec![t_expr(), f_expr()],
             }
         );
@@ -212,9 +680,10 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t or f", scheme),
+            CombinedExpr::lex_with("t or f", &Parser::trusted(scheme)),
             CombinedExpr::Combining {
                 op: CombiningOp::Or,
+                span: NodeSpan::default(),
                 items: vec![t_expr(), f_expr()],
             }
         );
@@ -243,9 +712,10 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("f or f", scheme),
+            CombinedExpr::lex_with("f or f", &Parser::trusted(scheme)),
             CombinedExpr::Combining {
                 op: CombiningOp::Or,
+                span: NodeSpan::default(),
                 items: vec![f_expr(), f_expr()],
             }
         );
@@ -257,9 +727,10 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t xor f", scheme),
+            CombinedExpr::lex_with("t xor f", &Parser::trusted(scheme)),
             CombinedExpr::Combining {
                 op: CombiningOp::Xor,
+                span: NodeSpan::default(),
                 items: vec![t_expr(), f_expr()],
             }
         );
@@ -288,9 +759,10 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("f xor f", scheme),
+            CombinedExpr::lex_with("f xor f", &Parser::trusted(scheme)),
             CombinedExpr::Combining {
                 op: CombiningOp::Xor,
+                span: NodeSpan::default(),
                 items: vec![f_expr(), f_expr()],
             }
         );
@@ -302,9 +774,10 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("f xor t", scheme),
+            CombinedExpr::lex_with("f xor t", &Parser::trusted(scheme)),
             CombinedExpr::Combining {
                 op: CombiningOp::Xor,
+                span: NodeSpan::default(),
                 items: vec![f_expr(), t_expr()],
             }
         );
@@ -315,21 +788,28 @@ fn test() {
     }
 
     assert_ok!(
-        CombinedExpr::lex_with("t or t && t and t or t ^^ t and t || t", scheme),
+        CombinedExpr::lex_with(
+            "t or t && t and t or t ^^ t and t || t",
+            &Parser::trusted(scheme)
+        ),
         CombinedExpr::Combining {
             op: CombiningOp::Or,
+            span: NodeSpan::default(),
             items: vec![
                 t_expr(),
                 CombinedExpr::Combining {
                     op: CombiningOp::And,
+                    span: NodeSpan::default(),
                     items: vec![t_expr(), t_expr(), t_expr()],
                 },
                 CombinedExpr::Combining {
                     op: CombiningOp::Xor,
+                    span: NodeSpan::default(),
                     items: vec![
                         t_expr(),
                         CombinedExpr::Combining {
                             op: CombiningOp::And,
+                            span: NodeSpan::default(),
                             items: vec![t_expr(), t_expr()],
                         },
                     ],
@@ -339,3 +819,31 @@ fn test() {
         }
     );
 }
+
+#[test]
+fn test_xor_of_many_items() {
+    use crate::execution_context::ExecutionContext;
+
+    let scheme = &Scheme! {
+        t: Bool,
+        f: Bool,
+    };
+
+    let ctx = &mut ExecutionContext::new(scheme);
+
+    ctx.set_field_value("t", true).unwrap();
+    ctx.set_field_value("f", false).unwrap();
+
+    // `Option::xor` of two present values is `None`, so it can't be used to
+    // accumulate the result of an `xor` chain.
+    for &(input, expected) in &[
+        ("t xor f", true),
+        ("t xor t", false),
+        ("f xor f xor t", true),
+        ("t xor t xor t", true),
+        ("t ^^ f ^^ t ^^ f", false),
+    ] {
+        let filter = scheme.parse(input).unwrap().compile();
+        assert_eq!(filter.execute(ctx), Ok(Some(expected)), "{}", input);
+    }
+}
@@ -1,20 +1,33 @@
 // use crate::filter::CompiledExpr;
-use super::{function_expr::FunctionCallExpr, Expr};
+use super::{
+    function_expr::{CallCache, FunctionCallExpr, SharedCalls, UnboundFunctionCallExpr},
+    span::{NodeSpan, SerializeSpans, Span, WithSpans},
+    Expr, NodeKind,
+};
 use crate::{
+    execution_context::ExecutionContext,
     filter::CompiledExpr,
     heap_searcher::HeapSearcher,
-    lex::{skip_space, span, Lex, LexErrorKind, LexResult, LexWith},
+    lex::{expect, skip_space, span, Lex, LexErrorKind, LexResult, LexWith},
+    limits::Parser,
     range_set::RangeSet,
-    rhs_types::{Bytes, ExplicitIpRange, Regex},
-    scheme::{Field, Scheme},
+    rhs_types::{lex_prefix_len, mask_addr, Bytes, ExplicitIpRange, Regex},
+    scheme::{Field, Rebinder, Scheme, UnboundField},
     strict_partial_ord::StrictPartialOrd,
     types::{GetType, LhsValue, RhsValue, RhsValues, Type},
 };
 use fnv::FnvBuildHasher;
 use indexmap::IndexSet;
 use memmem::Searcher;
-use serde::{Serialize, Serializer};
-use std::{cmp::Ordering, net::IpAddr};
+use serde::{
+    ser::{self, SerializeStruct},
+    Serialize, Serializer,
+};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+};
 
 const LESS: u8 = 0b001;
 const GREATER: u8 = 0b010;
@@ -49,10 +62,31 @@ impl OrderingOp {
     }
 }
 
+impl Display for OrderingOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OrderingOp::Equal => "==",
+            OrderingOp::NotEqual => "!=",
+            OrderingOp::GreaterThanEqual => ">=",
+            OrderingOp::LessThanEqual => "<=",
+            OrderingOp::GreaterThan => ">",
+            OrderingOp::LessThan => "<",
+        })
+    }
+}
+
 lex_enum!(IntOp {
     "&" | "bitwise_and" => BitwiseAnd,
 });
 
+impl Display for IntOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IntOp::BitwiseAnd => "&",
+        })
+    }
+}
+
 lex_enum!(BytesOp {
     "contains" => Contains,
     "~" | "matches" => Matches,
@@ -96,8 +130,6 @@ fn serialize_op_rhs<T: Serialize, S: Serializer>(
     rhs: &T,
     ser: S,
 ) -> Result<S::Ok, S::Error> {
-    use serde::ser::SerializeStruct;
-
     let mut out = ser.serialize_struct("FieldOp", 2)?;
     out.serialize_field("op", op)?;
     out.serialize_field("rhs", rhs)?;
@@ -105,8 +137,6 @@ fn serialize_op_rhs<T: Serialize, S: Serializer>(
 }
 
 fn serialize_is_true<S: Serializer>(ser: S) -> Result<S::Ok, S::Error> {
-    use serde::ser::SerializeStruct;
-
     let mut out = ser.serialize_struct("FieldOp", 1)?;
     out.serialize_field("op", "IsTrue")?;
     out.end()
@@ -124,51 +154,267 @@ fn serialize_one_of<S: Serializer>(rhs: &RhsValues, ser: S) -> Result<S::Ok, S::
     serialize_op_rhs("OneOf", rhs, ser)
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
-#[serde(untagged)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) enum LhsFieldExpr<'s> {
     Field(Field<'s>),
     FunctionCallExpr(FunctionCallExpr<'s>),
+    IpMask(IpMaskExpr<'s>),
+    // Produced only when compiling, never by the parser.
+    Constant(LhsValue<'static>),
+    SharedCall(usize),
+}
+
+impl<'s> Serialize for LhsFieldExpr<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.serialize_spans(false, ser)
+    }
+}
+
+impl<'s> SerializeSpans for LhsFieldExpr<'s> {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        match self {
+            LhsFieldExpr::Field(field) => field.serialize(ser),
+            LhsFieldExpr::FunctionCallExpr(call) => call.serialize_spans(spans, ser),
+            LhsFieldExpr::IpMask(mask) => mask.serialize_spans(spans, ser),
+            LhsFieldExpr::Constant(_) | LhsFieldExpr::SharedCall(_) => Err(ser::Error::custom(
+                "compiled expressions cannot be serialized",
+            )),
+        }
+    }
+}
+
+/// An IP address with all but the first `prefix_len` bits cleared, written
+/// as `ip.src/24`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct IpMaskExpr<'s> {
+    pub lhs: Box<LhsFieldExpr<'s>>,
+    pub prefix_len: u8,
+}
+
+impl<'s> SerializeSpans for IpMaskExpr<'s> {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        let mut out = ser.serialize_struct("IpMaskExpr", 2)?;
+        out.serialize_field("lhs", &WithSpans(&*self.lhs, spans))?;
+        out.serialize_field("prefix_len", &self.prefix_len)?;
+        out.end()
+    }
 }
 
 impl<'s> LhsFieldExpr<'s> {
     pub fn uses(&self, field: Field<'s>) -> bool {
         match self {
-            LhsFieldExpr::Field(f) => *f == field,
+            LhsFieldExpr::Field(f) => *f == field || f.depends_on(field),
             LhsFieldExpr::FunctionCallExpr(call) => call.uses(field),
+            LhsFieldExpr::IpMask(mask) => mask.lhs.uses(field),
+            LhsFieldExpr::Constant(_) => false,
+            LhsFieldExpr::SharedCall(_) => unreachable!(),
         }
     }
 
-    fn compile_with<F: 's>(self, func: F) -> CompiledExpr<'s>
-    where
-        F: Fn(LhsValue<'_>) -> bool + Send + Sync,
-    {
+    pub fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
         match self {
-            LhsFieldExpr::FunctionCallExpr(call) => {
-                CompiledExpr::new(move |ctx| {
-                    Some(func(call.execute(ctx)))
+            LhsFieldExpr::Field(f) => visitor(*f),
+            LhsFieldExpr::FunctionCallExpr(call) => call.walk_fields(visitor),
+            LhsFieldExpr::IpMask(mask) => mask.lhs.walk_fields(visitor),
+            LhsFieldExpr::Constant(_) => {}
+            LhsFieldExpr::SharedCall(_) => unreachable!(),
+        }
+    }
+
+    pub fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
+        match self {
+            LhsFieldExpr::FunctionCallExpr(call) => call.walk_nodes(visitor),
+            LhsFieldExpr::IpMask(mask) => mask.lhs.walk_nodes(visitor),
+            _ => {}
+        }
+    }
+
+    pub fn resolve_spans(&mut self, end: usize) {
+        match self {
+            LhsFieldExpr::FunctionCallExpr(call) => call.resolve_spans(end),
+            LhsFieldExpr::IpMask(mask) => mask.lhs.resolve_spans(end),
+            _ => {}
+        }
+    }
+
+    pub fn execute<'a, 'e: 'a>(
+        &'a self,
+        ctx: &'e ExecutionContext<'e>,
+        cache: &'e CallCache<'e>,
+    ) -> Option<LhsValue<'a>> {
+        match self {
+            LhsFieldExpr::Field(f) => ctx.get_field_value(*f),
+            LhsFieldExpr::FunctionCallExpr(call) => call.execute(ctx, cache),
+            LhsFieldExpr::IpMask(mask) => match mask.lhs.execute(ctx, cache)? {
+                LhsValue::Ip(addr) => Some(LhsValue::Ip(mask_addr(addr, mask.prefix_len))),
+                _ => unreachable!(),
+            },
+            LhsFieldExpr::Constant(value) => Some(value.as_ref()),
+            LhsFieldExpr::SharedCall(slot) => cache.get(*slot, ctx),
+        }
+    }
+
+    /// Replaces calls to pure functions that don't depend on any fields with
+    /// their results, and records the remaining ones in `calls` along with
+    /// virtual fields.
+    pub fn fold(self, calls: &mut SharedCalls<'s>) -> Self {
+        match self {
+            LhsFieldExpr::Field(f) if f.is_virtual() => match calls.fold_virtual(f) {
+                Some(value) => LhsFieldExpr::Constant(value),
+                None => LhsFieldExpr::Field(f),
+            },
+            LhsFieldExpr::FunctionCallExpr(call) => match call.fold(calls) {
+                Ok(value) => LhsFieldExpr::Constant(value),
+                Err(call) => LhsFieldExpr::FunctionCallExpr(call),
+            },
+            LhsFieldExpr::IpMask(IpMaskExpr { lhs, prefix_len }) => match lhs.fold(calls) {
+                LhsFieldExpr::Constant(LhsValue::Ip(addr)) => {
+                    LhsFieldExpr::Constant(LhsValue::Ip(mask_addr(addr, prefix_len)))
+                }
+                lhs => LhsFieldExpr::IpMask(IpMaskExpr {
+                    lhs: Box::new(lhs),
+                    prefix_len,
+                }),
+            },
+            lhs => lhs,
+        }
+    }
+
+    /// Replaces calls that occur more than once in the filter, as well as
+    /// virtual fields, with references to their shared results.
+    pub fn share_calls(self, calls: &mut SharedCalls<'s>) -> Self {
+        match self {
+            LhsFieldExpr::Field(f) if f.is_virtual() => {
+                LhsFieldExpr::SharedCall(calls.share_virtual(f))
+            }
+            LhsFieldExpr::FunctionCallExpr(call) => match calls.share(call) {
+                Ok(slot) => LhsFieldExpr::SharedCall(slot),
+                Err(call) => LhsFieldExpr::FunctionCallExpr(call.share_args(calls)),
+            },
+            LhsFieldExpr::IpMask(IpMaskExpr { lhs, prefix_len }) => {
+                LhsFieldExpr::IpMask(IpMaskExpr {
+                    lhs: Box::new(lhs.share_calls(calls)),
+                    prefix_len,
                 })
             }
-            LhsFieldExpr::Field(f) => {
-                CompiledExpr::new(move |ctx| {
-                    let field_opt = ctx.get_field_value(f);
-                    field_opt.map(|f| func(f))
+            lhs => lhs,
+        }
+    }
+
+    pub fn rebind<'t>(&self, rebinder: &mut Rebinder<'t>) -> Option<LhsFieldExpr<'t>> {
+        Some(match self {
+            LhsFieldExpr::Field(f) => LhsFieldExpr::Field(rebinder.field(f.name(), f.get_type())?),
+            LhsFieldExpr::FunctionCallExpr(call) => {
+                LhsFieldExpr::FunctionCallExpr(call.rebind(rebinder)?)
+            }
+            LhsFieldExpr::IpMask(IpMaskExpr { lhs, prefix_len }) => {
+                LhsFieldExpr::IpMask(IpMaskExpr {
+                    lhs: Box::new(lhs.rebind(rebinder)?),
+                    prefix_len: *prefix_len,
                 })
             }
+            LhsFieldExpr::Constant(value) => LhsFieldExpr::Constant(value.clone()),
+            LhsFieldExpr::SharedCall(slot) => LhsFieldExpr::SharedCall(*slot),
+        })
+    }
+
+    pub fn unbind(&self) -> UnboundLhsFieldExpr {
+        match self {
+            LhsFieldExpr::Field(f) => UnboundLhsFieldExpr::Field(f.unbind()),
+            LhsFieldExpr::FunctionCallExpr(call) => {
+                UnboundLhsFieldExpr::FunctionCallExpr(call.unbind())
+            }
+            LhsFieldExpr::IpMask(IpMaskExpr { lhs, prefix_len }) => UnboundLhsFieldExpr::IpMask {
+                lhs: Box::new(lhs.unbind()),
+                prefix_len: *prefix_len,
+            },
+            LhsFieldExpr::Constant(_) | LhsFieldExpr::SharedCall(_) => unreachable!(),
+        }
+    }
+
+    fn compile_with<F>(self, calls: &mut SharedCalls<'s>, func: F) -> CompiledExpr<'s>
+    where
+        F: Fn(LhsValue<'_>) -> bool + Send + Sync + 's,
+    {
+        match self {
+            LhsFieldExpr::Constant(value) => CompiledExpr::Constant(Some(func(value))),
+            lhs => {
+                let lhs = lhs.share_calls(calls);
+                CompiledExpr::new(move |ctx, cache| lhs.execute(ctx, cache).map(&func))
+            }
         }
     }
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for LhsFieldExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
-        Ok(match FunctionCallExpr::lex_with(input, scheme) {
+impl<'s> Display for LhsFieldExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LhsFieldExpr::Field(field) => f.write_str(field.spelling()),
+            LhsFieldExpr::FunctionCallExpr(call) => call.fmt(f),
+            LhsFieldExpr::IpMask(mask) => write!(f, "{}/{}", mask.lhs, mask.prefix_len),
+            LhsFieldExpr::Constant(_) | LhsFieldExpr::SharedCall(_) => unreachable!(),
+        }
+    }
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for LhsFieldExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let (lhs, input) = match FunctionCallExpr::lex_with(input, parser) {
             Ok((call, input)) => (LhsFieldExpr::FunctionCallExpr(call), input),
-            // Fallback to field
-            Err(_) => {
-                let (field, input) = Field::lex_with(input, scheme)?;
+            // Fallback to field unless the input is a call to a known
+            // function, in which case the error is in its arguments
+            Err((LexErrorKind::ExpectedName("function character"), _))
+            | Err((LexErrorKind::ExpectedLiteral("("), _)) => {
+                let (field, input) = Field::lex_with(input, parser)?;
                 (LhsFieldExpr::Field(field), input)
             }
-        })
+            // A field can still be followed by a parenthesis, but otherwise
+            // the input was meant as a call
+            Err(err @ (LexErrorKind::UnknownFunction(..), _)) => {
+                let (field, input) = Field::lex_with(input, parser).map_err(|_| err)?;
+                (LhsFieldExpr::Field(field), input)
+            }
+            Err(err) => return Err(err),
+        };
+
+        if lhs.get_type() == Type::Ip {
+            if let Ok(rest) = expect(skip_space(input), "/") {
+                let (prefix_len, rest) = lex_prefix_len(skip_space(rest))?;
+                let mask = IpMaskExpr {
+                    lhs: Box::new(lhs),
+                    prefix_len,
+                };
+                return Ok((LhsFieldExpr::IpMask(mask), rest));
+            }
+        }
+
+        Ok((lhs, input))
+    }
+}
+
+/// An [`LhsFieldExpr`] detached from its scheme.
+#[derive(Debug, Clone)]
+pub(crate) enum UnboundLhsFieldExpr {
+    Field(UnboundField),
+    FunctionCallExpr(UnboundFunctionCallExpr),
+    IpMask {
+        lhs: Box<UnboundLhsFieldExpr>,
+        prefix_len: u8,
+    },
+}
+
+impl UnboundLhsFieldExpr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> LhsFieldExpr<'s> {
+        match self {
+            UnboundLhsFieldExpr::Field(f) => LhsFieldExpr::Field(f.bind(scheme)),
+            UnboundLhsFieldExpr::FunctionCallExpr(call) => {
+                LhsFieldExpr::FunctionCallExpr(call.bind(scheme))
+            }
+            UnboundLhsFieldExpr::IpMask { lhs, prefix_len } => LhsFieldExpr::IpMask(IpMaskExpr {
+                lhs: Box::new(lhs.bind(scheme)),
+                prefix_len: *prefix_len,
+            }),
+        }
     }
 }
 
@@ -177,28 +423,61 @@ impl<'s> GetType for LhsFieldExpr<'s> {
         match self {
             LhsFieldExpr::Field(field) => field.get_type(),
             LhsFieldExpr::FunctionCallExpr(call) => call.function.return_type,
+            LhsFieldExpr::IpMask(_) => Type::Ip,
+            LhsFieldExpr::Constant(value) => value.get_type(),
+            LhsFieldExpr::SharedCall(_) => unreachable!(),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct FieldExpr<'s> {
     lhs: LhsFieldExpr<'s>,
-
-    #[serde(flatten)]
     op: FieldOp,
+    span: NodeSpan,
+    // Span of the literal, unless the field is a boolean one.
+    rhs_span: Option<NodeSpan>,
+}
+
+impl<'s> Serialize for FieldExpr<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.serialize_spans(false, ser)
+    }
+}
+
+impl<'s> SerializeSpans for FieldExpr<'s> {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Repr<'a, 's> {
+            lhs: WithSpans<'a, LhsFieldExpr<'s>>,
+            #[serde(flatten)]
+            op: &'a FieldOp,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            span: Option<Span>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            rhs_span: Option<Span>,
+        }
+
+        Repr {
+            lhs: WithSpans(&self.lhs, spans),
+            op: &self.op,
+            span: self.span.shown(spans),
+            rhs_span: self.rhs_span.and_then(|span| span.shown(spans)),
+        }
+        .serialize(ser)
+    }
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for FieldExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for FieldExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
         let initial_input = input;
 
-        let (lhs, input) = LhsFieldExpr::lex_with(input, scheme)?;
+        let (lhs, input) = LhsFieldExpr::lex_with(input, parser)?;
 
         let lhs_type = lhs.get_type();
 
-        let (op, input) = if lhs_type == Type::Bool {
-            (FieldOp::IsTrue, input)
+        let (op, rhs_span, input) = if lhs_type == Type::Bool {
+            (FieldOp::IsTrue, None, input)
         } else {
             let (op, input) = ComparisonOp::lex(skip_space(input))?;
 
@@ -206,9 +485,11 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FieldExpr<'s> {
 
             let input = skip_space(input);
 
-            match (lhs_type, op) {
+            let rhs_input = input;
+
+            let (op, input) = match (lhs_type, op) {
                 (_, ComparisonOp::In) => {
-                    let (rhs, input) = RhsValues::lex_with(input, lhs_type)?;
+                    let (rhs, input) = RhsValues::lex_with(input, (lhs_type, &parser.limits))?;
                     (FieldOp::OneOf(rhs), input)
                 }
                 (_, ComparisonOp::Ordering(op)) => {
@@ -225,7 +506,7 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FieldExpr<'s> {
                         (FieldOp::Contains(bytes), input)
                     }
                     BytesOp::Matches => {
-                        let (regex, input) = Regex::lex(input)?;
+                        let (regex, input) = Regex::lex_with(input, &parser.limits)?;
                         (FieldOp::Matches(regex), input)
                     }
                 },
@@ -235,10 +516,78 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FieldExpr<'s> {
                         span(initial_input, input_after_op),
                     ));
                 }
-            }
+            };
+
+            (op, Some(NodeSpan::new(rhs_input, input)), input)
         };
 
-        Ok((FieldExpr { lhs, op }, input))
+        Ok((
+            FieldExpr {
+                lhs,
+                op,
+                span: NodeSpan::new(initial_input, input),
+                rhs_span,
+            },
+            input,
+        ))
+    }
+}
+
+impl<'s> Display for FieldExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.lhs)?;
+        match &self.op {
+            FieldOp::IsTrue => Ok(()),
+            FieldOp::Ordering { op, rhs } => write!(f, " {} {}", op, rhs),
+            FieldOp::Int { op, rhs } => write!(f, " {} {}", op, rhs),
+            FieldOp::Contains(bytes) => write!(f, " contains {}", bytes),
+            FieldOp::Matches(regex) => write!(f, " matches {}", regex),
+            FieldOp::OneOf(values) => write!(f, " in {}", values),
+        }
+    }
+}
+
+/// A [`FieldExpr`] detached from its scheme.
+#[derive(Debug, Clone)]
+pub(crate) struct UnboundFieldExpr {
+    lhs: UnboundLhsFieldExpr,
+    op: FieldOp,
+    span: NodeSpan,
+    rhs_span: Option<NodeSpan>,
+}
+
+impl UnboundFieldExpr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> FieldExpr<'s> {
+        FieldExpr {
+            lhs: self.lhs.bind(scheme),
+            op: self.op.clone(),
+            span: self.span,
+            rhs_span: self.rhs_span,
+        }
+    }
+}
+
+impl<'s> FieldExpr<'s> {
+    pub(crate) fn span(&self) -> NodeSpan {
+        self.span
+    }
+
+    pub(crate) fn unbind(&self) -> UnboundFieldExpr {
+        UnboundFieldExpr {
+            lhs: self.lhs.unbind(),
+            op: self.op.clone(),
+            span: self.span,
+            rhs_span: self.rhs_span,
+        }
+    }
+
+    pub(crate) fn rebind<'t>(&self, rebinder: &mut Rebinder<'t>) -> Option<FieldExpr<'t>> {
+        Some(FieldExpr {
+            lhs: self.lhs.rebind(rebinder)?,
+            op: self.op.clone(),
+            span: self.span,
+            rhs_span: self.rhs_span,
+        })
     }
 }
 
@@ -247,7 +596,36 @@ impl<'s> Expr<'s> for FieldExpr<'s> {
         self.lhs.uses(field)
     }
 
-    fn compile(self) -> CompiledExpr<'s> {
+    fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
+        self.lhs.walk_fields(visitor)
+    }
+
+    fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
+        visitor(NodeKind::Comparison, self.span.get());
+        self.lhs.walk_nodes(visitor);
+        if let Some(span) = self.rhs_span {
+            visitor(NodeKind::Literal, span.get());
+        }
+    }
+
+    fn resolve_spans(&mut self, end: usize) {
+        self.span.resolve(end);
+        self.lhs.resolve_spans(end);
+        if let Some(span) = &mut self.rhs_span {
+            span.resolve(end);
+        }
+    }
+
+    fn fold(self, calls: &mut SharedCalls<'s>) -> Self {
+        FieldExpr {
+            lhs: self.lhs.fold(calls),
+            op: self.op,
+            span: self.span,
+            rhs_span: self.rhs_span,
+        }
+    }
+
+    fn compile_with(self, calls: &mut SharedCalls<'s>) -> CompiledExpr<'s> {
         let lhs = self.lhs;
 
         macro_rules! cast_value {
@@ -260,21 +638,23 @@ impl<'s> Expr<'s> for FieldExpr<'s> {
         }
 
         match self.op {
-            FieldOp::IsTrue => lhs.compile_with(move |x| cast_value!(x, Bool)),
+            FieldOp::IsTrue => lhs.compile_with(calls, move |x| cast_value!(x, Bool)),
             FieldOp::Ordering { op, rhs } => {
-                lhs.compile_with(move |x| op.matches_opt(x.strict_partial_cmp(&rhs)))
+                lhs.compile_with(calls, move |x| op.matches_opt(x.strict_partial_cmp(&rhs)))
             }
             FieldOp::Int {
                 op: IntOp::BitwiseAnd,
                 rhs,
-            } => lhs.compile_with(move |x| cast_value!(x, Int) & rhs != 0),
+            } => lhs.compile_with(calls, move |x| cast_value!(x, Int) & rhs != 0),
             FieldOp::Contains(bytes) => {
                 let searcher = HeapSearcher::new(bytes);
 
-                lhs.compile_with(move |x| searcher.search_in(&cast_value!(x, Bytes)).is_some())
+                lhs.compile_with(calls, move |x| {
+                    searcher.search_in(&cast_value!(x, Bytes)).is_some()
+                })
             }
             FieldOp::Matches(regex) => {
-                lhs.compile_with(move |x| regex.is_match(&cast_value!(x, Bytes)))
+                lhs.compile_with(calls, move |x| regex.is_match(&cast_value!(x, Bytes)))
             }
             FieldOp::OneOf(values) => match values {
                 RhsValues::Ip(ranges) => {
@@ -289,7 +669,7 @@ impl<'s> Expr<'s> for FieldExpr<'s> {
                     let v4 = RangeSet::from(v4);
                     let v6 = RangeSet::from(v6);
 
-                    lhs.compile_with(move |x| match cast_value!(x, Ip) {
+                    lhs.compile_with(calls, move |x| match cast_value!(x, Ip) {
                         IpAddr::V4(addr) => v4.contains(&addr),
                         IpAddr::V6(addr) => v6.contains(&addr),
                     })
@@ -297,13 +677,15 @@ impl<'s> Expr<'s> for FieldExpr<'s> {
                 RhsValues::Int(values) => {
                     let values: RangeSet<_> = values.iter().cloned().collect();
 
-                    lhs.compile_with(move |x| values.contains(&cast_value!(x, Int)))
+                    lhs.compile_with(calls, move |x| values.contains(&cast_value!(x, Int)))
                 }
                 RhsValues::Bytes(values) => {
                     let values: IndexSet<Box<[u8]>, FnvBuildHasher> =
                         values.into_iter().map(Into::into).collect();
 
-                    lhs.compile_with(move |x| values.contains(&cast_value!(x, Bytes) as &[u8]))
+                    lhs.compile_with(calls, move |x| {
+                        values.contains(&cast_value!(x, Bytes) as &[u8])
+                    })
                 }
                 RhsValues::Bool(_) => unreachable!(),
             },
@@ -321,6 +703,7 @@ mod tests {
             Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionOptParam, FunctionParam,
         },
         rhs_types::IpRange,
+        scheme::Scheme,
     };
     use cidr::{Cidr, IpCidr};
     use lazy_static::lazy_static;
@@ -369,9 +752,11 @@ mod tests {
                         params: vec![FunctionParam {
                             arg_kind: FunctionArgKind::Field,
                             val_type: Type::Bytes,
+                            generic: false,
                         }],
                         opt_params: vec![],
                         return_type: Type::Bytes,
+                        pure: true,
                         implementation: FunctionImpl::new(echo_function),
                     },
                 )
@@ -383,9 +768,11 @@ mod tests {
                         params: vec![FunctionParam {
                             arg_kind: FunctionArgKind::Field,
                             val_type: Type::Bytes,
+                            generic: false,
                         }],
                         opt_params: vec![],
                         return_type: Type::Bytes,
+                        pure: true,
                         implementation: FunctionImpl::new(lowercase_function),
                     },
                 )
@@ -406,6 +793,7 @@ mod tests {
                             },
                         ],
                         return_type: Type::Bytes,
+                        pure: true,
                         implementation: FunctionImpl::new(concat_function),
                     },
                 )
@@ -421,8 +809,10 @@ mod tests {
     #[test]
     fn test_is_true() {
         let expr = assert_ok!(
-            FieldExpr::lex_with("ssl", &SCHEME),
+            FieldExpr::lex_with("ssl", &Parser::trusted(&SCHEME)),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: None,
                 lhs: LhsFieldExpr::Field(field("ssl")),
                 op: FieldOp::IsTrue
             }
@@ -449,8 +839,13 @@ mod tests {
     #[test]
     fn test_ip_compare() {
         let expr = assert_ok!(
-            FieldExpr::lex_with("ip.addr <= 10:20:30:40:50:60:70:80", &SCHEME),
+            FieldExpr::lex_with(
+                "ip.addr <= 10:20:30:40:50:60:70:80",
+                &Parser::trusted(&SCHEME)
+            ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("ip.addr")),
                 op: FieldOp::Ordering {
                     op: OrderingOp::LessThanEqual,
@@ -496,13 +891,120 @@ mod tests {
         assert_eq!(expr.execute(ctx), Some(false));
     }
 
+    #[test]
+    fn test_ip_mask() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("ip.addr/24 == 192.0.2.0", &Parser::trusted(&SCHEME)),
+            FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
+                lhs: LhsFieldExpr::IpMask(IpMaskExpr {
+                    lhs: Box::new(LhsFieldExpr::Field(field("ip.addr"))),
+                    prefix_len: 24,
+                }),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Ip(IpAddr::from([192, 0, 2, 0])),
+                },
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "lhs": {
+                    "lhs": "ip.addr",
+                    "prefix_len": 24
+                },
+                "op": "Equal",
+                "rhs": "192.0.2.0"
+            }
+        );
+
+        assert!(expr.uses(field("ip.addr")));
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        assert_eq!(expr.execute(ctx), None);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([192, 0, 2, 123]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), Some(true));
+
+        ctx.set_field_value("ip.addr", IpAddr::from([192, 0, 3, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), Some(false));
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(
+                "ip.addr / 16 in { 10.1.0.0 2001:: }",
+                &Parser::trusted(&SCHEME)
+            ),
+            FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
+                lhs: LhsFieldExpr::IpMask(IpMaskExpr {
+                    lhs: Box::new(LhsFieldExpr::Field(field("ip.addr"))),
+                    prefix_len: 16,
+                }),
+                op: FieldOp::OneOf(RhsValues::Ip(vec![
+                    IpRange::Cidr(IpCidr::new_host([10, 1, 0, 0].into())),
+                    IpRange::Cidr(IpCidr::new_host(
+                        [0x2001, 0, 0, 0, 0, 0, 0, 0].into()
+                    )),
+                ])),
+            }
+        );
+
+        let expr = expr.compile();
+
+        ctx.set_field_value("ip.addr", IpAddr::from([10, 1, 2, 3]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), Some(true));
+
+        ctx.set_field_value("ip.addr", IpAddr::from([0x2001, 0xdb9, 0, 0, 0, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), Some(true));
+
+        ctx.set_field_value("ip.addr", IpAddr::from([10, 2, 2, 3]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), Some(false));
+
+        assert_err!(
+            FieldExpr::lex_with("ip.addr/129 == ::", &Parser::trusted(&SCHEME)),
+            LexErrorKind::ParseNetwork(cidr::NetworkParseError::NetworkLengthTooLongError(
+                cidr::NetworkLengthTooLongError::new(129, cidr::Family::Ipv6)
+            )),
+            "129"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("ip.addr/ == ::", &Parser::trusted(&SCHEME)),
+            LexErrorKind::ExpectedName("digit"),
+            "== ::"
+        );
+
+        // the mask operator only applies to IPs
+        assert_err!(
+            FieldExpr::lex_with("tcp.port/2 == 1", &Parser::trusted(&SCHEME)),
+            LexErrorKind::ExpectedName("ComparisonOp"),
+            "/2 == 1"
+        );
+    }
+
     #[test]
     fn test_bytes_compare() {
         // just check that parsing doesn't conflict with IPv6
         {
             let expr = assert_ok!(
-                FieldExpr::lex_with("http.host >= 10:20:30:40:50:60:70:80", &SCHEME),
+                FieldExpr::lex_with(
+                    "http.host >= 10:20:30:40:50:60:70:80",
+                    &Parser::trusted(&SCHEME)
+                ),
                 FieldExpr {
+                    span: NodeSpan::default(),
+                    rhs_span: Some(NodeSpan::default()),
                     lhs: LhsFieldExpr::Field(field("http.host")),
                     op: FieldOp::Ordering {
                         op: OrderingOp::GreaterThanEqual,
@@ -526,8 +1028,10 @@ mod tests {
         // just check that parsing doesn't conflict with regular numbers
         {
             let expr = assert_ok!(
-                FieldExpr::lex_with(r#"http.host < 12"#, &SCHEME),
+                FieldExpr::lex_with(r#"http.host < 12"#, &Parser::trusted(&SCHEME)),
                 FieldExpr {
+                    span: NodeSpan::default(),
+                    rhs_span: Some(NodeSpan::default()),
                     lhs: LhsFieldExpr::Field(field("http.host")),
                     op: FieldOp::Ordering {
                         op: OrderingOp::LessThan,
@@ -547,8 +1051,10 @@ mod tests {
         }
 
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host == "example.org""#, &SCHEME),
+            FieldExpr::lex_with(r#"http.host == "example.org""#, &Parser::trusted(&SCHEME)),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::Ordering {
                     op: OrderingOp::Equal,
@@ -579,8 +1085,10 @@ mod tests {
     #[test]
     fn test_bitwise_and() {
         let expr = assert_ok!(
-            FieldExpr::lex_with("tcp.port & 1", &SCHEME),
+            FieldExpr::lex_with("tcp.port & 1", &Parser::trusted(&SCHEME)),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("tcp.port")),
                 op: FieldOp::Int {
                     op: IntOp::BitwiseAnd,
@@ -611,8 +1119,13 @@ mod tests {
     #[test]
     fn test_int_in() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"tcp.port in { 80 443 2082..2083 }"#, &SCHEME),
+            FieldExpr::lex_with(
+                r#"tcp.port in { 80 443 2082..2083 }"#,
+                &Parser::trusted(&SCHEME)
+            ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("tcp.port")),
                 op: FieldOp::OneOf(RhsValues::Int(vec![80..=80, 443..=443, 2082..=2083])),
             }
@@ -659,8 +1172,13 @@ mod tests {
     #[test]
     fn test_bytes_in() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host in { "example.org" "example.com" }"#, &SCHEME),
+            FieldExpr::lex_with(
+                r#"http.host in { "example.org" "example.com" }"#,
+                &Parser::trusted(&SCHEME)
+            ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::OneOf(RhsValues::Bytes(
                     ["example.org", "example.com",]
@@ -701,9 +1219,11 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(
                 r#"ip.addr in { 127.0.0.0/8 ::1 10.0.0.0..10.0.255.255 }"#,
-                &SCHEME
+                &Parser::trusted(&SCHEME)
             ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("ip.addr")),
                 op: FieldOp::OneOf(RhsValues::Ip(vec![
                     IpRange::Cidr(IpCidr::new([127, 0, 0, 0].into(), 8).unwrap()),
@@ -755,8 +1275,10 @@ mod tests {
     #[test]
     fn test_contains_bytes() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host contains "abc""#, &SCHEME),
+            FieldExpr::lex_with(r#"http.host contains "abc""#, &Parser::trusted(&SCHEME)),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::Contains("abc".to_owned().into())
             }
@@ -784,8 +1306,10 @@ mod tests {
     #[test]
     fn test_contains_str() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host contains 6F:72:67"#, &SCHEME),
+            FieldExpr::lex_with(r#"http.host contains 6F:72:67"#, &Parser::trusted(&SCHEME)),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::Contains(vec![0x6F, 0x72, 0x67].into()),
             }
@@ -813,8 +1337,10 @@ mod tests {
     #[test]
     fn test_int_compare() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"tcp.port < 8000"#, &SCHEME),
+            FieldExpr::lex_with(r#"tcp.port < 8000"#, &Parser::trusted(&SCHEME)),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::Field(field("tcp.port")),
                 op: FieldOp::Ordering {
                     op: OrderingOp::LessThan,
@@ -845,11 +1371,17 @@ mod tests {
     #[test]
     fn test_bytes_compare_with_echo_function() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"echo(http.host) == "example.org""#, &SCHEME),
+            FieldExpr::lex_with(
+                r#"echo(http.host) == "example.org""#,
+                &Parser::trusted(&SCHEME)
+            ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("echo"),
                     function: SCHEME.get_function("echo").unwrap(),
+                    span: NodeSpan::default(),
                     args: vec![FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(
                         field("http.host")
                     ))],
@@ -891,11 +1423,17 @@ mod tests {
     #[test]
     fn test_bytes_compare_with_lowercase_function() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"lowercase(http.host) == "example.org""#, &SCHEME),
+            FieldExpr::lex_with(
+                r#"lowercase(http.host) == "example.org""#,
+                &Parser::trusted(&SCHEME)
+            ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("lowercase"),
                     function: SCHEME.get_function("lowercase").unwrap(),
+                    span: NodeSpan::default(),
                     args: vec![FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(
                         field("http.host")
                     ))],
@@ -937,11 +1475,17 @@ mod tests {
     #[test]
     fn test_bytes_compare_with_concat_function() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"concat(http.host) == "example.org""#, &SCHEME),
+            FieldExpr::lex_with(
+                r#"concat(http.host) == "example.org""#,
+                &Parser::trusted(&SCHEME)
+            ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("concat"),
                     function: SCHEME.get_function("concat").unwrap(),
+                    span: NodeSpan::default(),
                     args: vec![FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(
                         field("http.host")
                     ))],
@@ -980,16 +1524,23 @@ mod tests {
         assert_eq!(expr.execute(ctx), Some(false));
 
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"concat(http.host, ".org") == "example.org""#, &SCHEME),
+            FieldExpr::lex_with(
+                r#"concat(http.host, ".org") == "example.org""#,
+                &Parser::trusted(&SCHEME)
+            ),
             FieldExpr {
+                span: NodeSpan::default(),
+                rhs_span: Some(NodeSpan::default()),
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("concat"),
                     function: SCHEME.get_function("concat").unwrap(),
+                    span: NodeSpan::default(),
                     args: vec![
                         FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(field("http.host"))),
-                        FunctionCallArgExpr::Literal(RhsValue::Bytes(Bytes::from(
-                            ".org".to_owned()
-                        ))),
+                        FunctionCallArgExpr::Literal(
+                            RhsValue::Bytes(Bytes::from(".org".to_owned())),
+                            NodeSpan::default(),
+                        ),
                     ],
                 }),
                 op: FieldOp::Ordering {
@@ -1,55 +1,167 @@
-use super::field_expr::LhsFieldExpr;
+use super::{
+    combined_expr::CombinedExpr,
+    field_expr::{LhsFieldExpr, UnboundLhsFieldExpr},
+    span::{NodeSpan, SerializeSpans, Span, WithSpans},
+    virtual_expr::VirtualFieldExpr,
+    Expr, NodeKind,
+};
 use crate::{
     execution_context::ExecutionContext,
+    filter::CompiledExpr,
     functions::{Function, FunctionArgKind, FunctionParam},
     lex::{expect, skip_space, span, take, take_while, LexError, LexErrorKind, LexResult, LexWith},
-    scheme::{Field, Scheme},
+    limits::{Nesting, Parser},
+    scheme::{Field, Rebinder, Scheme},
     types::{GetType, LhsValue, RhsValue, TypeMismatchError},
 };
-use serde::Serialize;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::{
+    cell::OnceCell,
+    fmt::{self, Display, Formatter},
+};
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
-#[serde(tag = "kind", content = "value")]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) enum FunctionCallArgExpr<'s> {
     LhsFieldExpr(LhsFieldExpr<'s>),
-    Literal(RhsValue),
+    Literal(RhsValue, NodeSpan),
+}
+
+// Serialized as `{ "kind": ..., "value": ... }`, along with the span of
+// literals if requested.
+impl<'s> SerializeSpans for FunctionCallArgExpr<'s> {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        let mut out = ser.serialize_struct("FunctionCallArgExpr", 3)?;
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => {
+                out.serialize_field("kind", "LhsFieldExpr")?;
+                out.serialize_field("value", &WithSpans(lhs, spans))?;
+            }
+            FunctionCallArgExpr::Literal(literal, span) => {
+                out.serialize_field("kind", "Literal")?;
+                out.serialize_field("value", literal)?;
+                if let Some(span) = span.shown(spans) {
+                    out.serialize_field("span", &span)?;
+                }
+            }
+        }
+        out.end()
+    }
+}
+
+impl<'s> Display for FunctionCallArgExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.fmt(f),
+            FunctionCallArgExpr::Literal(literal, _) => literal.fmt(f),
+        }
+    }
 }
 
 impl<'s> FunctionCallArgExpr<'s> {
     pub fn uses(&self, field: Field<'s>) -> bool {
         match self {
             FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.uses(field),
-            FunctionCallArgExpr::Literal(_) => false,
+            FunctionCallArgExpr::Literal(..) => false,
         }
     }
 
-    pub fn execute(&'s self, ctx: &'s ExecutionContext<'s>) -> Option<LhsValue<'s>> {
+    pub fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
+        if let FunctionCallArgExpr::LhsFieldExpr(lhs) = self {
+            lhs.walk_fields(visitor);
+        }
+    }
+
+    pub fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
         match self {
-            FunctionCallArgExpr::LhsFieldExpr(lhs) => match lhs {
-                LhsFieldExpr::Field(field) => {
-                    ctx.get_field_value(*field)
-                },
-                LhsFieldExpr::FunctionCallExpr(call) => Some(call.execute(ctx)),
-            },
-            FunctionCallArgExpr::Literal(literal) => Some(literal.into()),
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.walk_nodes(visitor),
+            FunctionCallArgExpr::Literal(_, span) => visitor(NodeKind::Literal, span.get()),
+        }
+    }
+
+    pub fn resolve_spans(&mut self, end: usize) {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.resolve_spans(end),
+            FunctionCallArgExpr::Literal(_, span) => span.resolve(end),
+        }
+    }
+
+    pub fn execute<'a, 'e: 'a>(
+        &'a self,
+        ctx: &'e ExecutionContext<'e>,
+        cache: &'e CallCache<'e>,
+    ) -> Option<LhsValue<'a>> {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.execute(ctx, cache),
+            FunctionCallArgExpr::Literal(literal, _) => Some(literal.into()),
+        }
+    }
+
+    pub fn fold(self, calls: &mut SharedCalls<'s>) -> Self {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => {
+                FunctionCallArgExpr::LhsFieldExpr(lhs.fold(calls))
+            }
+            literal => literal,
+        }
+    }
+
+    pub fn share_calls(self, calls: &mut SharedCalls<'s>) -> Self {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => {
+                FunctionCallArgExpr::LhsFieldExpr(lhs.share_calls(calls))
+            }
+            literal => literal,
+        }
+    }
+
+    pub fn rebind<'t>(&self, rebinder: &mut Rebinder<'t>) -> Option<FunctionCallArgExpr<'t>> {
+        Some(match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => {
+                FunctionCallArgExpr::LhsFieldExpr(lhs.rebind(rebinder)?)
+            }
+            FunctionCallArgExpr::Literal(literal, span) => {
+                FunctionCallArgExpr::Literal(literal.clone(), *span)
+            }
+        })
+    }
+
+    pub fn unbind(&self) -> UnboundFunctionCallArgExpr {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => {
+                UnboundFunctionCallArgExpr::LhsFieldExpr(lhs.unbind())
+            }
+            FunctionCallArgExpr::Literal(literal, span) => {
+                UnboundFunctionCallArgExpr::Literal(literal.clone(), *span)
+            }
+        }
+    }
+
+    fn as_constant(&self) -> Option<LhsValue<'_>> {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Constant(value)) => {
+                Some(value.as_ref())
+            }
+            FunctionCallArgExpr::LhsFieldExpr(_) => None,
+            FunctionCallArgExpr::Literal(literal, _) => Some(literal.into()),
         }
     }
 }
 
-struct SchemeFunctionParam<'s, 'a> {
-    scheme: &'s Scheme,
+struct SchemeFunctionParam<'p, 's, 'a> {
+    parser: &'p Parser<'s>,
+    function: &'a Function,
     param: &'a FunctionParam,
     index: usize,
 }
 
-impl<'i, 's, 'a> LexWith<'i, SchemeFunctionParam<'s, 'a>> for FunctionCallArgExpr<'s> {
-    fn lex_with(input: &'i str, ctx: SchemeFunctionParam<'s, 'a>) -> LexResult<'i, Self> {
+impl<'i, 'p, 's, 'a> LexWith<'i, SchemeFunctionParam<'p, 's, 'a>> for FunctionCallArgExpr<'s> {
+    fn lex_with(input: &'i str, ctx: SchemeFunctionParam<'p, 's, 'a>) -> LexResult<'i, Self> {
         let initial_input = input;
 
         match ctx.param.arg_kind {
             FunctionArgKind::Field => {
-                let (lhs, input) = LhsFieldExpr::lex_with(input, ctx.scheme)?;
-                if lhs.get_type() != ctx.param.val_type {
+                let (lhs, input) = LhsFieldExpr::lex_with(input, ctx.parser)?;
+                if !ctx.param.generic && lhs.get_type() != ctx.param.val_type {
                     Err((
                         LexErrorKind::InvalidArgumentType {
                             index: ctx.index,
@@ -65,19 +177,63 @@ impl<'i, 's, 'a> LexWith<'i, SchemeFunctionParam<'s, 'a>> for FunctionCallArgExp
                 }
             }
             FunctionArgKind::Literal => {
-                let (rhs_value, input) = RhsValue::lex_with(input, ctx.param.val_type)?;
-                Ok((FunctionCallArgExpr::Literal(rhs_value), input))
+                let (rhs_value, rest) = RhsValue::lex_with(input, ctx.param.val_type)?;
+                ctx.function
+                    .implementation
+                    .check_literal(ctx.index, &(&rhs_value).into())
+                    .map_err(|message| {
+                        (
+                            LexErrorKind::InvalidArgumentValue {
+                                index: ctx.index,
+                                message,
+                            },
+                            span(input, rest),
+                        )
+                    })?;
+                let span = NodeSpan::new(input, rest);
+                Ok((FunctionCallArgExpr::Literal(rhs_value, span), rest))
             }
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) struct FunctionCallExpr<'s> {
     pub name: String,
-    #[serde(skip)]
     pub function: &'s Function,
     pub args: Vec<FunctionCallArgExpr<'s>>,
+    pub span: NodeSpan,
+}
+
+impl<'s> Serialize for FunctionCallExpr<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.serialize_spans(false, ser)
+    }
+}
+
+impl<'s> SerializeSpans for FunctionCallExpr<'s> {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        let mut out = ser.serialize_struct("FunctionCallExpr", 3)?;
+        out.serialize_field("name", &self.name)?;
+        out.serialize_field("args", &WithSpans(&self.args[..], spans))?;
+        if let Some(span) = self.span.shown(spans) {
+            out.serialize_field("span", &span)?;
+        }
+        out.end()
+    }
+}
+
+impl<'s> Display for FunctionCallExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        f.write_str(")")
+    }
 }
 
 impl<'s> FunctionCallExpr<'s> {
@@ -86,6 +242,7 @@ impl<'s> FunctionCallExpr<'s> {
             name: name.into(),
             function,
             args: Vec::default(),
+            span: NodeSpan::default(),
         }
     }
 
@@ -93,9 +250,112 @@ impl<'s> FunctionCallExpr<'s> {
         self.args.iter().any(|arg| arg.uses(field))
     }
 
-    pub fn execute(&self, ctx: &'s ExecutionContext<'s>) -> LhsValue<'_> {
-        self.function.implementation.execute(
-            self.args.iter().flat_map(|arg| arg.execute(ctx)).chain(
+    pub fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
+        for arg in &self.args {
+            arg.walk_fields(visitor);
+        }
+    }
+
+    pub fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
+        visitor(NodeKind::FunctionCall(&self.name), self.span.get());
+        for arg in &self.args {
+            arg.walk_nodes(visitor);
+        }
+    }
+
+    pub fn resolve_spans(&mut self, end: usize) {
+        self.span.resolve(end);
+        for arg in &mut self.args {
+            arg.resolve_spans(end);
+        }
+    }
+
+    /// Calls the function, or returns `None` if any of the fields it's given
+    /// is missing from the context.
+    pub fn execute<'a, 'e: 'a>(
+        &'a self,
+        ctx: &'e ExecutionContext<'e>,
+        cache: &'e CallCache<'e>,
+    ) -> Option<LhsValue<'a>> {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.execute(ctx, cache))
+            .collect::<Option<Vec<_>>>()?;
+
+        self.call(args)
+    }
+
+    /// Folds constant arguments and, if the function is pure and all of its
+    /// arguments are known at compile time, returns the result of the call.
+    ///
+    /// Calls to pure functions that can't be folded are recorded in `calls`.
+    pub fn fold(mut self, calls: &mut SharedCalls<'s>) -> Result<LhsValue<'static>, Self> {
+        self.args = self.args.into_iter().map(|arg| arg.fold(calls)).collect();
+
+        if !self.function.pure {
+            return Err(self);
+        }
+
+        let args = self
+            .args
+            .iter()
+            .map(FunctionCallArgExpr::as_constant)
+            .collect::<Option<Vec<_>>>();
+
+        match args {
+            Some(args) => {
+                // Calls without a result are kept, so that they still
+                // evaluate to a missing value.
+                if let Some(value) = self.call(args) {
+                    return Ok(value.into_owned());
+                }
+                Err(self)
+            }
+            None => {
+                calls.record(&self);
+                Err(self)
+            }
+        }
+    }
+
+    pub fn share_args(mut self, calls: &mut SharedCalls<'s>) -> Self {
+        self.args = self
+            .args
+            .into_iter()
+            .map(|arg| arg.share_calls(calls))
+            .collect();
+        self
+    }
+
+    pub fn rebind<'t>(&self, rebinder: &mut Rebinder<'t>) -> Option<FunctionCallExpr<'t>> {
+        let function = rebinder.function(&self.name, self.function);
+        // Rebind all arguments even if the function is gone to report all
+        // incompatibilities.
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.rebind(rebinder))
+            .collect::<Vec<_>>();
+        Some(FunctionCallExpr {
+            name: self.name.clone(),
+            function: function?,
+            args: args.into_iter().collect::<Option<_>>()?,
+            span: self.span,
+        })
+    }
+
+    pub fn unbind(&self) -> UnboundFunctionCallExpr {
+        UnboundFunctionCallExpr {
+            name: self.name.clone(),
+            args: self.args.iter().map(FunctionCallArgExpr::unbind).collect(),
+            span: self.span,
+        }
+    }
+
+    fn call<'e>(&'e self, args: Vec<LhsValue<'e>>) -> Option<LhsValue<'e>> {
+        self.function.implementation.try_execute(
+            args.into_iter().chain(
                 self.function.opt_params[self.args.len() - self.function.params.len()..]
                     .iter()
                     .map(|opt_arg| opt_arg.default_value.as_ref()),
@@ -104,6 +364,216 @@ impl<'s> FunctionCallExpr<'s> {
     }
 }
 
+/// A [`FunctionCallArgExpr`] detached from its scheme.
+#[derive(Debug, Clone)]
+pub(crate) enum UnboundFunctionCallArgExpr {
+    LhsFieldExpr(UnboundLhsFieldExpr),
+    Literal(RhsValue, NodeSpan),
+}
+
+impl UnboundFunctionCallArgExpr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> FunctionCallArgExpr<'s> {
+        match self {
+            UnboundFunctionCallArgExpr::LhsFieldExpr(lhs) => {
+                FunctionCallArgExpr::LhsFieldExpr(lhs.bind(scheme))
+            }
+            UnboundFunctionCallArgExpr::Literal(literal, span) => {
+                FunctionCallArgExpr::Literal(literal.clone(), *span)
+            }
+        }
+    }
+}
+
+/// A [`FunctionCallExpr`] detached from its scheme, which refers to the
+/// function by its name.
+#[derive(Debug, Clone)]
+pub(crate) struct UnboundFunctionCallExpr {
+    name: String,
+    args: Vec<UnboundFunctionCallArgExpr>,
+    span: NodeSpan,
+}
+
+impl UnboundFunctionCallExpr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> FunctionCallExpr<'s> {
+        FunctionCallExpr {
+            name: self.name.clone(),
+            function: scheme.get_parsed_function(&self.name),
+            args: self.args.iter().map(|arg| arg.bind(scheme)).collect(),
+            span: self.span,
+        }
+    }
+}
+
+/// An expression whose result is stored in a [`CallCache`] slot.
+pub(crate) enum SharedExpr<'s> {
+    /// A call to a pure function that occurs more than once.
+    Call(FunctionCallExpr<'s>),
+    /// A virtual field defined by a value expression.
+    Value(LhsFieldExpr<'s>),
+    /// A virtual field defined by a filter expression, or a macro.
+    Filter(CompiledExpr<'s>),
+}
+
+/// Calls to pure functions that occur more than once in a filter, as well as
+/// virtual fields and macros it uses.
+///
+/// Each of them is assigned a slot in the [`CallCache`], so that it's executed
+/// at most once per filter execution no matter how many times it occurs.
+#[derive(Default)]
+pub(crate) struct SharedCalls<'s> {
+    // calls seen while folding, with the number of their occurrences and
+    // their slots, if assigned
+    seen: Vec<(FunctionCallExpr<'s>, usize, Option<usize>)>,
+    // virtual fields seen while folding, with their folded definitions until
+    // they are assigned a slot
+    virtual_fields: Vec<(Field<'s>, Option<VirtualFieldExpr<'s>>, Option<usize>)>,
+    // macros compiled so far, with their slots, or their results if they
+    // are constant
+    macros: Vec<(String, Result<usize, Option<bool>>)>,
+    // expressions that have been assigned a slot, indexed by it
+    shared: Vec<SharedExpr<'s>>,
+}
+
+impl<'s> SharedCalls<'s> {
+    fn record(&mut self, call: &FunctionCallExpr<'s>) {
+        match self.seen.iter_mut().find(|(seen, ..)| seen == call) {
+            Some((_, count, _)) => *count += 1,
+            None => self.seen.push((call.clone(), 1, None)),
+        }
+    }
+
+    /// Returns the slot of a call that occurs more than once, or gives the
+    /// call back otherwise.
+    pub fn share(&mut self, call: FunctionCallExpr<'s>) -> Result<usize, FunctionCallExpr<'s>> {
+        let index = match self
+            .seen
+            .iter()
+            .position(|(seen, count, _)| *count > 1 && *seen == call)
+        {
+            Some(index) => index,
+            None => return Err(call),
+        };
+
+        if let Some(slot) = self.seen[index].2 {
+            return Ok(slot);
+        }
+
+        // nested calls get their own slots first
+        let call = call.share_args(self);
+        let slot = self.shared.len();
+        self.shared.push(SharedExpr::Call(call));
+        self.seen[index].2 = Some(slot);
+        Ok(slot)
+    }
+
+    fn virtual_field_index(&mut self, field: Field<'s>) -> usize {
+        if let Some(index) = self.virtual_fields.iter().position(|(f, ..)| *f == field) {
+            return index;
+        }
+
+        let definition = field
+            .scheme()
+            .parse_virtual_field(field.virtual_definition().unwrap())
+            // definitions are validated when they are added to the scheme
+            .unwrap()
+            .fold(self);
+        self.virtual_fields.push((field, Some(definition), None));
+        self.virtual_fields.len() - 1
+    }
+
+    /// Folds the definition of a virtual field, returning its value if it
+    /// doesn't depend on any other fields.
+    pub fn fold_virtual(&mut self, field: Field<'s>) -> Option<LhsValue<'static>> {
+        let index = self.virtual_field_index(field);
+        match &self.virtual_fields[index].1 {
+            Some(VirtualFieldExpr::Value(LhsFieldExpr::Constant(value))) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the slot holding the value of a virtual field, compiling its
+    /// definition on first use.
+    pub fn share_virtual(&mut self, field: Field<'s>) -> usize {
+        let index = self.virtual_field_index(field);
+
+        if let Some(slot) = self.virtual_fields[index].2 {
+            return slot;
+        }
+
+        // virtual fields and calls used by the definition get their own
+        // slots first; there are no cycles, so this can't come back here
+        let shared = match self.virtual_fields[index].1.take().unwrap() {
+            VirtualFieldExpr::Value(lhs) => SharedExpr::Value(lhs.share_calls(self)),
+            VirtualFieldExpr::Filter(op) => SharedExpr::Filter(op.compile_with(self)),
+        };
+        let slot = self.shared.len();
+        self.shared.push(shared);
+        self.virtual_fields[index].2 = Some(slot);
+        slot
+    }
+
+    /// Compiles a reference to a macro, so that all references to it share
+    /// a single result.
+    pub fn share_macro(&mut self, name: String, expr: CombinedExpr<'s>) -> CompiledExpr<'s> {
+        let shared = match self.macros.iter().find(|(seen, _)| *seen == name) {
+            Some((_, shared)) => *shared,
+            None => {
+                let shared = match expr.compile_with(self) {
+                    CompiledExpr::Constant(value) => Err(value),
+                    expr => {
+                        let slot = self.shared.len();
+                        self.shared.push(SharedExpr::Filter(expr));
+                        Ok(slot)
+                    }
+                };
+                self.macros.push((name, shared));
+                shared
+            }
+        };
+
+        match shared {
+            Ok(slot) => CompiledExpr::new(move |ctx, cache| match cache.get(slot, ctx)? {
+                LhsValue::Bool(value) => Some(value),
+                _ => unreachable!(),
+            }),
+            Err(value) => CompiledExpr::Constant(value),
+        }
+    }
+
+    pub fn into_shared(self) -> Box<[SharedExpr<'s>]> {
+        self.shared.into_boxed_slice()
+    }
+}
+
+/// Results of [shared expressions](SharedCalls) computed during a single
+/// filter execution.
+pub(crate) struct CallCache<'e> {
+    exprs: &'e [SharedExpr<'e>],
+    results: Box<[OnceCell<Option<LhsValue<'e>>>]>,
+}
+
+impl<'e> CallCache<'e> {
+    pub fn new(exprs: &'e [SharedExpr<'e>]) -> Self {
+        CallCache {
+            exprs,
+            results: exprs.iter().map(|_| OnceCell::new()).collect(),
+        }
+    }
+
+    /// Returns the result of a shared expression, executing it on first
+    /// access.
+    pub fn get(&'e self, slot: usize, ctx: &'e ExecutionContext<'e>) -> Option<LhsValue<'e>> {
+        self.results[slot]
+            .get_or_init(|| match &self.exprs[slot] {
+                SharedExpr::Call(call) => call.execute(ctx, self),
+                SharedExpr::Value(lhs) => lhs.execute(ctx, self),
+                SharedExpr::Filter(op) => op.execute_with_cache(ctx, self).map(LhsValue::Bool),
+            })
+            .as_ref()
+            .map(LhsValue::as_ref)
+    }
+}
+
 fn invalid_args_count<'i>(function: &Function, input: &'i str) -> LexError<'i> {
     (
         LexErrorKind::InvalidArgumentsCount {
@@ -114,8 +584,8 @@ fn invalid_args_count<'i>(function: &Function, input: &'i str) -> LexError<'i> {
     )
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for FunctionCallExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for FunctionCallExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
         let initial_input = input;
 
         let (name, mut input) = take_while(input, "function character", |c| {
@@ -128,9 +598,13 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FunctionCallExpr<'s> {
 
         input = skip_space(input);
 
-        let function = scheme
-            .get_function(name)
-            .map_err(|err| (LexErrorKind::UnknownFunction(err), initial_input))?;
+        let scheme = parser.scheme;
+        let function = scheme.get_function(name).map_err(|err| {
+            let suggestions = scheme.suggest_functions(name);
+            (LexErrorKind::UnknownFunction(err, suggestions), name)
+        })?;
+
+        let _nesting = Nesting::Call.enter(parser, name)?;
 
         let mut function_call = FunctionCallExpr::new(name, function);
 
@@ -149,7 +623,8 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FunctionCallExpr<'s> {
             let arg = FunctionCallArgExpr::lex_with(
                 input,
                 SchemeFunctionParam {
-                    scheme,
+                    parser,
+                    function,
                     param: &function.params[i],
                     index: i,
                 },
@@ -186,12 +661,14 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FunctionCallExpr<'s> {
             let param = FunctionParam {
                 arg_kind: opt_param.arg_kind.clone(),
                 val_type: opt_param.default_value.get_type(),
+                generic: false,
             };
 
             let (arg, rest) = FunctionCallArgExpr::lex_with(
                 input,
                 SchemeFunctionParam {
-                    scheme,
+                    parser,
+                    function,
                     param: &param,
                     index: function.params.len() + index,
                 },
@@ -206,6 +683,8 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FunctionCallExpr<'s> {
 
         input = expect(input, ")")?;
 
+        function_call.span = NodeSpan::new(initial_input, input);
+
         Ok((function_call, input))
     }
 }
@@ -214,7 +693,8 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FunctionCallExpr<'s> {
 fn test_function() {
     use crate::{
         functions::{FunctionArgs, FunctionImpl, FunctionOptParam},
-        scheme::UnknownFieldError,
+        scheme::{Scheme, UnknownFieldError},
+        suggest::Suggestions,
         types::Type,
     };
     use lazy_static::lazy_static;
@@ -238,12 +718,14 @@ fn test_function() {
                         params: vec![FunctionParam {
                             arg_kind: FunctionArgKind::Field,
                             val_type: Type::Bytes,
+                            generic: false,
                         }],
                         opt_params: vec![FunctionOptParam {
                             arg_kind: FunctionArgKind::Literal,
                             default_value: LhsValue::Int(10),
                         }],
                         return_type: Type::Bytes,
+                        pure: true,
                         implementation: FunctionImpl::new(echo_function),
                     },
                 )
@@ -253,10 +735,11 @@ fn test_function() {
     }
 
     let expr = assert_ok!(
-        FunctionCallExpr::lex_with("echo ( http.host );", &SCHEME),
+        FunctionCallExpr::lex_with("echo ( http.host );", &Parser::trusted(&SCHEME)),
         FunctionCallExpr {
             name: String::from("echo"),
             function: SCHEME.get_function("echo").unwrap(),
+            span: NodeSpan::default(),
             args: vec![FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(
                 SCHEME.get_field_index("http.host").unwrap()
             ))],
@@ -278,7 +761,7 @@ fn test_function() {
     );
 
     assert_err!(
-        FunctionCallExpr::lex_with("echo ( );", &SCHEME),
+        FunctionCallExpr::lex_with("echo ( );", &Parser::trusted(&SCHEME)),
         LexErrorKind::InvalidArgumentsCount {
             expected_min: 1,
             expected_max: 2
@@ -287,20 +770,22 @@ fn test_function() {
     );
 
     assert_err!(
-        FunctionCallExpr::lex_with("echo ( http.host , http.host );", &SCHEME),
+        FunctionCallExpr::lex_with("echo ( http.host , http.host );", &Parser::trusted(&SCHEME)),
         LexErrorKind::ExpectedName("digit"),
         "http.host );"
     );
 
     let expr = assert_ok!(
-        FunctionCallExpr::lex_with("echo ( echo ( http.host ) );", &SCHEME),
+        FunctionCallExpr::lex_with("echo ( echo ( http.host ) );", &Parser::trusted(&SCHEME)),
         FunctionCallExpr {
             name: String::from("echo"),
             function: SCHEME.get_function("echo").unwrap(),
+            span: NodeSpan::default(),
             args: [FunctionCallArgExpr::LhsFieldExpr(
                 LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("echo"),
                     function: SCHEME.get_function("echo").unwrap(),
+                    span: NodeSpan::default(),
                     args: vec![FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(
                         SCHEME.get_field_index("http.host").unwrap()
                     ))],
@@ -333,19 +818,19 @@ fn test_function() {
     );
 
     assert_err!(
-        FunctionCallExpr::lex_with("echo ( \"test\" );", &SCHEME),
+        FunctionCallExpr::lex_with("echo ( \"test\" );", &Parser::trusted(&SCHEME)),
         LexErrorKind::ExpectedName("identifier character"),
         "\"test\" );"
     );
 
     assert_err!(
-        FunctionCallExpr::lex_with("echo ( 10 );", &SCHEME),
-        LexErrorKind::UnknownField(UnknownFieldError),
+        FunctionCallExpr::lex_with("echo ( 10 );", &Parser::trusted(&SCHEME)),
+        LexErrorKind::UnknownField(UnknownFieldError, Suggestions::default()),
         "10"
     );
 
     assert_err!(
-        FunctionCallExpr::lex_with("echo ( ip.addr );", &SCHEME),
+        FunctionCallExpr::lex_with("echo ( ip.addr );", &Parser::trusted(&SCHEME)),
         LexErrorKind::InvalidArgumentType {
             index: 0,
             mismatch: TypeMismatchError {
@@ -357,7 +842,10 @@ fn test_function() {
     );
 
     assert_err!(
-        FunctionCallExpr::lex_with("echo ( http.host, 10, \"test\" );", &SCHEME),
+        FunctionCallExpr::lex_with(
+            "echo ( http.host, 10, \"test\" );",
+            &Parser::trusted(&SCHEME)
+        ),
         LexErrorKind::InvalidArgumentsCount {
             expected_min: 1,
             expected_max: 2,
@@ -0,0 +1,223 @@
+use super::{
+    combined_expr::{CombinedExpr, UnboundCombinedExpr},
+    function_expr::SharedCalls,
+    span::{NodeSpan, Span},
+    CompiledExpr, Expr, NodeKind,
+};
+use crate::{
+    lex::{expect, span, take_while, LexErrorKind, LexResult, LexWith},
+    limits::Parser,
+    scheme::{Field, Rebinder, Scheme},
+};
+use serde::{Serialize, Serializer};
+use std::fmt::{self, Display, Formatter};
+
+/// A reference to a [macro](::Scheme::add_macro), written as `$name`, along
+/// with its expansion.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MacroExpr<'s> {
+    name: String,
+    expr: Box<CombinedExpr<'s>>,
+    span: NodeSpan,
+}
+
+// References are transparent to the consumers of the AST. Spans of the
+// expansion point into the definition rather than the filter, so they are
+// never serialized.
+impl<'s> Serialize for MacroExpr<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.expr.serialize(ser)
+    }
+}
+
+// Printed as the reference rather than its expansion.
+impl<'s> Display for MacroExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "${}", self.name)
+    }
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for MacroExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let span_start = input;
+        let input = expect(input, "$")?;
+        let initial_input = input;
+
+        let (_, input) = take_while(input, "macro character", |c| {
+            c.is_ascii_alphanumeric() || c == '_' || c == '.'
+        })?;
+
+        let name = span(initial_input, input);
+
+        let scheme = parser.scheme;
+        let definition = scheme
+            .get_macro(name)
+            .map_err(|err| (LexErrorKind::UnknownMacro(err), name))?;
+        // the size of the whole expansion is accounted for by the reference
+        parser.expand_macro(definition.nodes, name)?;
+
+        Ok((
+            MacroExpr {
+                name: name.to_owned(),
+                expr: Box::new(definition.expansion.bind(scheme)),
+                span: NodeSpan::new(span_start, input),
+            },
+            input,
+        ))
+    }
+}
+
+impl<'s> MacroExpr<'s> {
+    pub(crate) fn span(&self) -> NodeSpan {
+        self.span
+    }
+
+    pub(crate) fn unbind(&self) -> UnboundMacroExpr {
+        UnboundMacroExpr {
+            name: self.name.clone(),
+            expr: Box::new(self.expr.unbind()),
+            span: self.span,
+        }
+    }
+
+    /// Expands the macro of the same name in another scheme.
+    pub(crate) fn rebind<'t>(&self, rebinder: &mut Rebinder<'t>) -> Option<MacroExpr<'t>> {
+        let expansion = rebinder.macro_expansion(&self.name)?;
+        Some(MacroExpr {
+            name: self.name.clone(),
+            expr: Box::new(expansion.bind(rebinder.scheme())),
+            span: self.span,
+        })
+    }
+}
+
+/// A [`MacroExpr`] detached from its scheme, along with its expansion.
+#[derive(Debug, Clone)]
+pub(crate) struct UnboundMacroExpr {
+    name: String,
+    expr: Box<UnboundCombinedExpr>,
+    span: NodeSpan,
+}
+
+impl UnboundMacroExpr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> MacroExpr<'s> {
+        MacroExpr {
+            name: self.name.clone(),
+            expr: Box::new(self.expr.bind(scheme)),
+            span: self.span,
+        }
+    }
+}
+
+impl<'s> Expr<'s> for MacroExpr<'s> {
+    fn uses(&self, field: Field<'s>) -> bool {
+        self.expr.uses(field)
+    }
+
+    fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
+        self.expr.walk_fields(visitor)
+    }
+
+    // NOTE: the expansion is parsed from the definition, so it isn't a part
+    // of the filter as far as spans are concerned.
+    fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
+        visitor(NodeKind::Macro(&self.name), self.span.get())
+    }
+
+    fn resolve_spans(&mut self, end: usize) {
+        self.span.resolve(end)
+    }
+
+    fn fold(self, calls: &mut SharedCalls<'s>) -> Self {
+        MacroExpr {
+            name: self.name,
+            expr: Box::new(self.expr.fold(calls)),
+            span: self.span,
+        }
+    }
+
+    fn compile_with(self, calls: &mut SharedCalls<'s>) -> CompiledExpr<'s> {
+        calls.share_macro(self.name, *self.expr)
+    }
+}
+
+#[test]
+fn test() {
+    use crate::execution_context::ExecutionContext;
+
+    let mut scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+    scheme
+        .add_macro("web".into(), "tcp.port in {80 443}".into())
+        .unwrap();
+    scheme
+        .add_macro(
+            "example.web".into(),
+            r#"$web && http.host == "example.org""#.into(),
+        )
+        .unwrap();
+    let scheme = &scheme;
+
+    // Expansions are stored when macros are added, and must be the same as
+    // if definitions were parsed on their own.
+    let expand = |definition| Box::new(scheme.parse(definition).unwrap().op);
+
+    let expr = assert_ok!(
+        MacroExpr::lex_with("$web and", &Parser::trusted(scheme)),
+        MacroExpr {
+            name: "web".into(),
+            expr: expand("tcp.port in {80 443}"),
+            span: NodeSpan::default(),
+        },
+        " and"
+    );
+
+    assert_json!(
+        expr,
+        {
+            "lhs": "tcp.port",
+            "op": "OneOf",
+            "rhs": [
+                { "start": 80, "end": 80 },
+                { "start": 443, "end": 443 }
+            ]
+        }
+    );
+
+    let expr = assert_ok!(
+        MacroExpr::lex_with("$example.web", &Parser::trusted(scheme)),
+        MacroExpr {
+            name: "example.web".into(),
+            expr: expand(r#"$web && http.host == "example.org""#),
+            span: NodeSpan::default(),
+        }
+    );
+
+    assert!(expr.uses(scheme.get_field_index("tcp.port").unwrap()));
+    assert!(expr.uses(scheme.get_field_index("http.host").unwrap()));
+
+    // Macros are compiled into shared slots, so they need a whole filter.
+    let filter = scheme.parse("$example.web || not $web").unwrap().compile();
+
+    let ctx = &mut ExecutionContext::new(scheme);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    assert_eq!(filter.execute(ctx), Ok(Some(true)));
+
+    ctx.set_field_value("http.host", "example.com").unwrap();
+    assert_eq!(filter.execute(ctx), Ok(Some(false)));
+
+    ctx.set_field_value("tcp.port", 22).unwrap();
+    assert_eq!(filter.execute(ctx), Ok(Some(true)));
+
+    assert_err!(
+        MacroExpr::lex_with("$bots", &Parser::trusted(scheme)),
+        LexErrorKind::UnknownMacro(crate::scheme::UnknownMacroError),
+        "bots"
+    );
+
+    assert_err!(
+        MacroExpr::lex_with("$ web", &Parser::trusted(scheme)),
+        LexErrorKind::ExpectedName("macro character"),
+        " web"
+    );
+}
@@ -1,20 +1,83 @@
 mod combined_expr;
 mod field_expr;
 mod function_expr;
+mod macro_expr;
 mod simple_expr;
+mod span;
+mod virtual_expr;
 
-use self::combined_expr::CombinedExpr;
+use self::{
+    combined_expr::{CombinedExpr, Comments},
+    function_expr::SharedCalls,
+    span::SerializeSpans,
+};
 use crate::{
-    filter::{CompiledExpr, Filter},
-    lex::{LexResult, LexWith},
-    scheme::{Field, Scheme, UnknownFieldError},
+    filter::{CompiledExpr, Filter, OwnedFilter},
+    lex::{LexError, LexResult, LexWith},
+    limits::Parser,
+    scheme::{
+        Comment, Field, IncompatibleSchemeError, ParseError, ParseWarning, Rebinder, Scheme,
+        UnknownFieldError,
+    },
+};
+use serde::{Serialize, Serializer};
+use std::{
+    fmt::{self, Debug, Display},
+    sync::Arc,
+};
+
+pub use self::span::Span;
+
+pub(crate) use self::{
+    combined_expr::UnboundCombinedExpr,
+    function_expr::{CallCache, SharedExpr},
+    virtual_expr::VirtualFieldExpr,
 };
-use serde::Serialize;
-use std::fmt::{self, Debug};
 
-trait Expr<'s>: Sized + Eq + Debug + for<'i> LexWith<'i, &'s Scheme> + Serialize {
+trait Expr<'s>: Sized + Eq + Debug + for<'i, 'p> LexWith<'i, &'p Parser<'s>> + Serialize {
     fn uses(&self, field: Field<'s>) -> bool;
-    fn compile(self) -> CompiledExpr<'s>;
+
+    /// Calls `visitor` for every field referred to by the expression.
+    fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>));
+
+    /// Calls `visitor` for the expression and every node within it, parents
+    /// first.
+    fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span));
+
+    /// Resolves spans recorded while lexing, given the offset of the end of
+    /// the input.
+    fn resolve_spans(&mut self, end: usize);
+
+    /// Folds constant subexpressions and records calls to pure functions, so
+    /// that the ones occurring more than once can be shared.
+    fn fold(self, calls: &mut SharedCalls<'s>) -> Self;
+
+    fn compile_with(self, calls: &mut SharedCalls<'s>) -> CompiledExpr<'s>;
+
+    #[cfg(test)]
+    fn compile(self) -> CompiledExpr<'s> {
+        self.compile_with(&mut SharedCalls::default())
+    }
+}
+
+/// Kind of a node of a [`FilterAst`], as reported by
+/// [`FilterAst::walk_nodes`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NodeKind<'a> {
+    /// Expressions combined with `and`, `or` or `xor`.
+    Combining,
+    /// An expression in parentheses.
+    Parenthesized,
+    /// An expression negated with `not`.
+    Not,
+    /// A reference to a macro with the given name.
+    Macro(&'a str),
+    /// A comparison, or a boolean field on its own.
+    Comparison,
+    /// A call to the function with the given name.
+    FunctionCall(&'a str),
+    /// A literal on the right side of a comparison, or passed to a function.
+    Literal,
 }
 
 /// A parsed filter AST.
@@ -37,25 +100,256 @@ impl<'s> Debug for FilterAst<'s> {
     }
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for FilterAst<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
-        let (op, input) = CombinedExpr::lex_with(input, scheme)?;
-        Ok((FilterAst { scheme, op }, input))
+/// Prints the AST back as a filter in canonical form.
+///
+/// Operators are spelled out the same way regardless of how they were
+/// written, and parentheses are kept only where they affect the structure of
+/// the AST. Macros are printed as references, and fields as they were
+/// referred to, see [`FilterAst::canonicalize`] to replace aliases.
+///
+/// The alternate form (`{:#}`) puts every operand of `and`, `or` and `xor` on
+/// its own line, indenting nested groups.
+///
+/// Comments aren't a part of the AST, see [`FilterAst::with_comments`] to
+/// print them as well.
+impl<'s> Display for FilterAst<'s> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.op.print(f, 0, &mut Comments::default())
+    }
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for FilterAst<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let (op, input) = CombinedExpr::lex_with(input, parser)?;
+        Ok((
+            FilterAst {
+                scheme: parser.scheme,
+                op,
+            },
+            input,
+        ))
     }
 }
 
 impl<'s> FilterAst<'s> {
+    /// Collects all errors in the input, recovering from each of them at
+    /// the next combining op or parenthesis.
+    pub(crate) fn recover<'i>(input: &'i str, parser: &Parser<'s>) -> Vec<LexError<'i>> {
+        let mut errors = Vec::new();
+        CombinedExpr::recover(input, parser, false, &mut errors);
+        errors
+    }
+
+    pub(crate) fn resolve_spans(&mut self, end: usize) {
+        self.op.resolve_spans(end)
+    }
+
+    pub(crate) fn unbind(&self) -> UnboundCombinedExpr {
+        self.op.unbind()
+    }
+
+    /// Returns the span of the whole filter.
+    pub fn span(&self) -> Span {
+        self.op.span().get()
+    }
+
+    /// Calls `visitor` for every node of the AST along with its span in the
+    /// parsed filter, parents first.
+    ///
+    /// References to macros are reported as single nodes, because their
+    /// expansions aren't a part of the filter.
+    pub fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
+        self.op.walk_nodes(visitor)
+    }
+
+    /// Returns a view of the AST that is serialized along with the spans of
+    /// its nodes.
+    ///
+    /// Spans are added to the JSON objects of nodes as `span`, as well as
+    /// `rhs_span` for the literal of a comparison.
+    pub fn with_spans(&self) -> FilterAstWithSpans<'_, 's> {
+        FilterAstWithSpans(self)
+    }
+
+    /// Returns a view of the AST that is printed along with the comments
+    /// returned by [`Scheme::parse_with_comments`](struct@Scheme) for it.
+    ///
+    /// Each comment is printed before the operand that follows it in the
+    /// parsed filter, or at the end if there is none. In the compact form,
+    /// line comments are followed by a line break.
+    pub fn with_comments<'a>(&'a self, comments: &'a [Comment]) -> FilterAstWithComments<'a, 's> {
+        FilterAstWithComments(self, comments)
+    }
+
     /// Recursively checks whether a [`FilterAst`] uses a given field name.
     ///
+    /// If the name refers to a [nested object](::Scheme::add_nested_field),
+    /// checks whether any of its fields is used instead.
+    ///
     /// This is useful to lazily initialise expensive fields only if necessary.
     pub fn uses(&self, field_name: &str) -> Result<bool, UnknownFieldError> {
+        if let Some(fields) = self.scheme.get_object_fields(field_name) {
+            return Ok(fields.into_iter().any(|field| self.op.uses(field)));
+        }
         self.scheme
             .get_field_index(field_name)
             .map(|field| self.op.uses(field))
     }
 
+    /// Returns warnings about usages of deprecated fields or aliases.
+    pub(crate) fn warnings(&self) -> Vec<ParseWarning> {
+        let mut warnings = Vec::new();
+        self.op.walk_fields(&mut |field| {
+            let name = field.spelling();
+            if let Some(note) = self.scheme.get_deprecation(name) {
+                let warning = ParseWarning::DeprecatedField {
+                    name: name.to_owned(),
+                    note: note.to_owned(),
+                };
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+        });
+        warnings
+    }
+
+    /// Rewrites all field aliases used in the AST to canonical field names,
+    /// returning whether anything has changed.
+    ///
+    /// This allows to migrate filters after fields are renamed.
+    pub fn canonicalize(&mut self) -> bool {
+        let mut changed = false;
+        self.op
+            .walk_fields(&mut |field| changed |= field.alias().is_some());
+        if changed {
+            // Rebinding looks up fields by their canonical names.
+            *self = self.rebind(self.scheme).unwrap();
+        }
+        changed
+    }
+
+    /// Rebinds the AST to another scheme, such as a newer version of the
+    /// one it was parsed with.
+    ///
+    /// Fails with a list of all fields and functions used by the filter
+    /// that are missing or have a different type in `scheme`. Field aliases
+    /// are replaced with canonical names in the process.
+    pub fn rebind<'t>(&self, scheme: &'t Scheme) -> Result<FilterAst<'t>, IncompatibleSchemeError> {
+        let mut rebinder = Rebinder::new(scheme);
+        let op = self.op.rebind(&mut rebinder);
+        rebinder.finish(op.map(|op| FilterAst { scheme, op }))
+    }
+
     /// Compiles a [`FilterAst`] into a [`Filter`].
     pub fn compile(self) -> Filter<'s> {
-        Filter::new(self.op.compile(), self.scheme)
+        let mut calls = SharedCalls::default();
+        let op = self.op.fold(&mut calls);
+        let root_expr = op.compile_with(&mut calls);
+        Filter::new(root_expr, calls.into_shared(), self.scheme)
+    }
+}
+
+/// A [`FilterAst`] that is serialized with spans, as returned by
+/// [`FilterAst::with_spans`].
+#[derive(Debug, Clone, Copy)]
+pub struct FilterAstWithSpans<'a, 's>(&'a FilterAst<'s>);
+
+impl<'a, 's> Serialize for FilterAstWithSpans<'a, 's> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.0.op.serialize_spans(true, ser)
+    }
+}
+
+/// A view of a [`FilterAst`] that is printed along with comments, see
+/// [`FilterAst::with_comments`].
+pub struct FilterAstWithComments<'a, 's>(&'a FilterAst<'s>, &'a [Comment]);
+
+impl<'a, 's> Display for FilterAstWithComments<'a, 's> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut comments = Comments(self.1);
+        self.0.op.print(f, 0, &mut comments)?;
+        comments.print_rest(f)
+    }
+}
+
+/// A parsed filter AST that owns a shared reference to its
+/// [`Scheme`](struct@Scheme).
+///
+/// Unlike [`FilterAst`], it isn't bound to the lifetime of the scheme, so it
+/// can be stored in long-lived structures and sent between threads. The
+/// borrowed AST is still available via [`OwnedFilterAst::as_ast`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct OwnedFilterAst {
+    // Borrows from the scheme below, so must be declared (and dropped) first.
+    ast: FilterAst<'static>,
+    scheme: Arc<Scheme>,
+}
+
+impl Debug for OwnedFilterAst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.ast, f)
+    }
+}
+
+impl Display for OwnedFilterAst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.ast, f)
+    }
+}
+
+impl Serialize for OwnedFilterAst {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.ast.serialize(ser)
+    }
+}
+
+impl OwnedFilterAst {
+    /// Parses a filter into an AST form associated with a shared scheme.
+    pub fn parse(scheme: Arc<Scheme>, input: &str) -> Result<Self, ParseError<'_>> {
+        // This is safe because the scheme lives on the heap for as long as we
+        // hold an `Arc` to it, and the AST never escapes with a lifetime
+        // longer than a borrow of `self`.
+        let scheme_ref: &'static Scheme = unsafe { &*Arc::as_ptr(&scheme) };
+        let ast = scheme_ref.parse(input)?;
+        Ok(OwnedFilterAst { ast, scheme })
+    }
+
+    /// Returns the borrowed form of this AST.
+    pub fn as_ast(&self) -> &FilterAst<'_> {
+        &self.ast
+    }
+
+    /// Returns the associated scheme.
+    pub fn scheme(&self) -> &Arc<Scheme> {
+        &self.scheme
+    }
+
+    /// Recursively checks whether the AST uses a given field name.
+    pub fn uses(&self, field_name: &str) -> Result<bool, UnknownFieldError> {
+        self.ast.uses(field_name)
+    }
+
+    /// Rebinds the AST to another shared scheme.
+    ///
+    /// See [`FilterAst::rebind`] for details.
+    pub fn rebind(&self, scheme: Arc<Scheme>) -> Result<Self, IncompatibleSchemeError> {
+        // This is safe for the same reasons as in `OwnedFilterAst::parse`.
+        let scheme_ref: &'static Scheme = unsafe { &*Arc::as_ptr(&scheme) };
+        let ast = self.ast.rebind(scheme_ref)?;
+        Ok(OwnedFilterAst { ast, scheme })
+    }
+
+    /// Rewrites all field aliases used in the AST to canonical field names.
+    ///
+    /// See [`FilterAst::canonicalize`] for details.
+    pub fn canonicalize(&mut self) -> bool {
+        self.ast.canonicalize()
+    }
+
+    /// Compiles an [`OwnedFilterAst`] into an [`OwnedFilter`].
+    pub fn compile(self) -> OwnedFilter {
+        // The compiled filter keeps borrowing from the same scheme.
+        unsafe { OwnedFilter::new(self.ast.compile(), self.scheme) }
     }
 }
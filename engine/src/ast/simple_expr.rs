@@ -1,70 +1,280 @@
-use super::{combined_expr::CombinedExpr, field_expr::FieldExpr, CompiledExpr, Expr};
+use super::{
+    combined_expr::{CombinedExpr, Comments, UnboundCombinedExpr},
+    field_expr::{FieldExpr, UnboundFieldExpr},
+    function_expr::SharedCalls,
+    macro_expr::{MacroExpr, UnboundMacroExpr},
+    span::{NodeSpan, SerializeSpans, Span, WithSpans},
+    CompiledExpr, Expr, NodeKind,
+};
 use crate::{
-    lex::{expect, skip_space, Lex, LexResult, LexWith},
-    scheme::{Field, Scheme},
+    lex::{expect, skip_space, span, Lex, LexResult, LexWith},
+    limits::{Nesting, Parser},
+    scheme::{Field, Rebinder, Scheme},
 };
-use serde::Serialize;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::fmt::{self, Display, Formatter};
 
 lex_enum!(UnaryOp {
     "not" | "!" => Not,
 });
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
-#[serde(untagged)]
+impl Display for UnaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnaryOp::Not => "not",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum SimpleExpr<'s> {
     Field(FieldExpr<'s>),
-    Parenthesized(Box<CombinedExpr<'s>>),
+    Macro(MacroExpr<'s>),
+    Parenthesized(Box<CombinedExpr<'s>>, NodeSpan),
     Unary {
         op: UnaryOp,
         arg: Box<SimpleExpr<'s>>,
+        span: NodeSpan,
     },
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for SimpleExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+impl<'s> Serialize for SimpleExpr<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.serialize_spans(false, ser)
+    }
+}
+
+impl<'s> SerializeSpans for SimpleExpr<'s> {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SimpleExpr::Field(op) => op.serialize_spans(spans, ser),
+            // Spans of the expansion are never serialized, see `MacroExpr`.
+            SimpleExpr::Macro(op) => op.serialize(ser),
+            // Parentheses are transparent to the consumers of the JSON.
+            SimpleExpr::Parenthesized(op, _) => op.serialize_spans(spans, ser),
+            SimpleExpr::Unary { op, arg, span } => {
+                let mut out = ser.serialize_struct("SimpleExpr", 3)?;
+                out.serialize_field("op", op)?;
+                out.serialize_field("arg", &WithSpans(&**arg, spans))?;
+                if let Some(span) = span.shown(spans) {
+                    out.serialize_field("span", &span)?;
+                }
+                out.end()
+            }
+        }
+    }
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for SimpleExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let initial_input = input;
+
         Ok(if let Ok(input) = expect(input, "(") {
+            let _nesting = Nesting::Expr.enter(parser, span(initial_input, input))?;
             let input = skip_space(input);
-            let (op, input) = CombinedExpr::lex_with(input, scheme)?;
+            let (op, input) = CombinedExpr::lex_with(input, parser)?;
             let input = skip_space(input);
             let input = expect(input, ")")?;
-            (SimpleExpr::Parenthesized(Box::new(op)), input)
+            (
+                SimpleExpr::Parenthesized(Box::new(op), NodeSpan::new(initial_input, input)),
+                input,
+            )
         } else if let Ok((op, input)) = UnaryOp::lex(input) {
+            let _nesting = Nesting::Expr.enter(parser, span(initial_input, input))?;
             let input = skip_space(input);
-            let (arg, input) = SimpleExpr::lex_with(input, scheme)?;
+            let (arg, input) = SimpleExpr::lex_with(input, parser)?;
             (
                 SimpleExpr::Unary {
                     op,
                     arg: Box::new(arg),
+                    span: NodeSpan::new(initial_input, input),
                 },
                 input,
             )
+        } else if input.starts_with('$') {
+            let (op, input) = MacroExpr::lex_with(input, parser)?;
+            (SimpleExpr::Macro(op), input)
         } else {
-            let (op, input) = FieldExpr::lex_with(input, scheme)?;
+            let (op, input) = FieldExpr::lex_with(input, parser)?;
             (SimpleExpr::Field(op), input)
         })
     }
 }
 
+impl<'s> SimpleExpr<'s> {
+    pub(crate) fn span(&self) -> NodeSpan {
+        match self {
+            SimpleExpr::Field(op) => op.span(),
+            SimpleExpr::Macro(op) => op.span(),
+            SimpleExpr::Parenthesized(_, span) | SimpleExpr::Unary { span, .. } => *span,
+        }
+    }
+
+    /// Prints the expression as filter source, see [`CombinedExpr::print`].
+    pub(crate) fn print(
+        &self,
+        f: &mut Formatter<'_>,
+        indent: usize,
+        comments: &mut Comments<'_>,
+    ) -> fmt::Result {
+        comments.print_before(f, self.span().get().start, indent)?;
+        match self {
+            SimpleExpr::Field(op) => op.fmt(f),
+            SimpleExpr::Macro(op) => op.fmt(f),
+            SimpleExpr::Parenthesized(op, _) => op.print(f, indent, comments),
+            SimpleExpr::Unary { op, arg, .. } => {
+                write!(f, "{} ", op)?;
+                match &**arg {
+                    // Unary ops bind tighter than any combining op.
+                    SimpleExpr::Parenthesized(arg, _) => {
+                        arg.print_nested(f, indent, comments, |_| true)
+                    }
+                    arg => arg.print(f, indent, comments),
+                }
+            }
+        }
+    }
+
+    pub(crate) fn unbind(&self) -> UnboundSimpleExpr {
+        match self {
+            SimpleExpr::Field(op) => UnboundSimpleExpr::Field(op.unbind()),
+            SimpleExpr::Macro(op) => UnboundSimpleExpr::Macro(op.unbind()),
+            SimpleExpr::Parenthesized(op, span) => {
+                UnboundSimpleExpr::Parenthesized(Box::new(op.unbind()), *span)
+            }
+            SimpleExpr::Unary { op, arg, span } => UnboundSimpleExpr::Unary {
+                op: *op,
+                arg: Box::new(arg.unbind()),
+                span: *span,
+            },
+        }
+    }
+
+    pub(crate) fn rebind<'t>(&self, rebinder: &mut Rebinder<'t>) -> Option<SimpleExpr<'t>> {
+        Some(match self {
+            SimpleExpr::Field(op) => SimpleExpr::Field(op.rebind(rebinder)?),
+            SimpleExpr::Macro(op) => SimpleExpr::Macro(op.rebind(rebinder)?),
+            SimpleExpr::Parenthesized(op, span) => {
+                SimpleExpr::Parenthesized(Box::new(op.rebind(rebinder)?), *span)
+            }
+            SimpleExpr::Unary { op, arg, span } => SimpleExpr::Unary {
+                op: *op,
+                arg: Box::new(arg.rebind(rebinder)?),
+                span: *span,
+            },
+        })
+    }
+}
+
+/// A [`SimpleExpr`] detached from its scheme.
+#[derive(Debug, Clone)]
+pub(crate) enum UnboundSimpleExpr {
+    Field(UnboundFieldExpr),
+    Macro(UnboundMacroExpr),
+    Parenthesized(Box<UnboundCombinedExpr>, NodeSpan),
+    Unary {
+        op: UnaryOp,
+        arg: Box<UnboundSimpleExpr>,
+        span: NodeSpan,
+    },
+}
+
+impl UnboundSimpleExpr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> SimpleExpr<'s> {
+        match self {
+            UnboundSimpleExpr::Field(op) => SimpleExpr::Field(op.bind(scheme)),
+            UnboundSimpleExpr::Macro(op) => SimpleExpr::Macro(op.bind(scheme)),
+            UnboundSimpleExpr::Parenthesized(op, span) => {
+                SimpleExpr::Parenthesized(Box::new(op.bind(scheme)), *span)
+            }
+            UnboundSimpleExpr::Unary { op, arg, span } => SimpleExpr::Unary {
+                op: *op,
+                arg: Box::new(arg.bind(scheme)),
+                span: *span,
+            },
+        }
+    }
+}
+
 impl<'s> Expr<'s> for SimpleExpr<'s> {
     fn uses(&self, field: Field<'s>) -> bool {
         match self {
             SimpleExpr::Field(op) => op.uses(field),
-            SimpleExpr::Parenthesized(op) => op.uses(field),
+            SimpleExpr::Macro(op) => op.uses(field),
+            SimpleExpr::Parenthesized(op, _) => op.uses(field),
             SimpleExpr::Unary { arg, .. } => arg.uses(field),
         }
     }
 
-    fn compile(self) -> CompiledExpr<'s> {
+    fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
+        match self {
+            SimpleExpr::Field(op) => op.walk_fields(visitor),
+            SimpleExpr::Macro(op) => op.walk_fields(visitor),
+            SimpleExpr::Parenthesized(op, _) => op.walk_fields(visitor),
+            SimpleExpr::Unary { arg, .. } => arg.walk_fields(visitor),
+        }
+    }
+
+    fn walk_nodes<'a>(&'a self, visitor: &mut dyn FnMut(NodeKind<'a>, Span)) {
         match self {
-            SimpleExpr::Field(op) => op.compile(),
-            SimpleExpr::Parenthesized(op) => op.compile(),
+            SimpleExpr::Field(op) => op.walk_nodes(visitor),
+            SimpleExpr::Macro(op) => op.walk_nodes(visitor),
+            SimpleExpr::Parenthesized(op, span) => {
+                visitor(NodeKind::Parenthesized, span.get());
+                op.walk_nodes(visitor);
+            }
+            SimpleExpr::Unary { arg, span, .. } => {
+                visitor(NodeKind::Not, span.get());
+                arg.walk_nodes(visitor);
+            }
+        }
+    }
+
+    fn resolve_spans(&mut self, end: usize) {
+        match self {
+            SimpleExpr::Field(op) => op.resolve_spans(end),
+            SimpleExpr::Macro(op) => op.resolve_spans(end),
+            SimpleExpr::Parenthesized(op, span) => {
+                span.resolve(end);
+                op.resolve_spans(end);
+            }
+            SimpleExpr::Unary { arg, span, .. } => {
+                span.resolve(end);
+                arg.resolve_spans(end);
+            }
+        }
+    }
+
+    fn fold(self, calls: &mut SharedCalls<'s>) -> Self {
+        match self {
+            SimpleExpr::Field(op) => SimpleExpr::Field(op.fold(calls)),
+            SimpleExpr::Macro(op) => SimpleExpr::Macro(op.fold(calls)),
+            SimpleExpr::Parenthesized(op, span) => {
+                SimpleExpr::Parenthesized(Box::new(op.fold(calls)), span)
+            }
+            SimpleExpr::Unary { op, arg, span } => SimpleExpr::Unary {
+                op,
+                arg: Box::new(arg.fold(calls)),
+                span,
+            },
+        }
+    }
+
+    fn compile_with(self, calls: &mut SharedCalls<'s>) -> CompiledExpr<'s> {
+        match self {
+            SimpleExpr::Field(op) => op.compile_with(calls),
+            SimpleExpr::Macro(op) => op.compile_with(calls),
+            SimpleExpr::Parenthesized(op, _) => op.compile_with(calls),
             SimpleExpr::Unary {
                 op: UnaryOp::Not,
                 arg,
-            } => {
-                let arg = arg.compile();
-                CompiledExpr::new(move |ctx| arg.execute(ctx).map(|x| !x))
-            }
+                ..
+            } => match arg.compile_with(calls) {
+                CompiledExpr::Constant(value) => CompiledExpr::Constant(value.map(|x| !x)),
+                arg => CompiledExpr::new(move |ctx, cache| {
+                    arg.execute_with_cache(ctx, cache).map(|x| !x)
+                }),
+            },
         }
     }
 }
@@ -78,11 +288,15 @@ fn test() {
     let ctx = &mut ExecutionContext::new(scheme);
     ctx.set_field_value("t", true).unwrap();
 
-    let t_expr = SimpleExpr::Field(complete(FieldExpr::lex_with("t", scheme)).unwrap());
+    let t_expr =
+        SimpleExpr::Field(complete(FieldExpr::lex_with("t", &Parser::trusted(scheme))).unwrap());
     let t_expr = || t_expr.clone();
 
     {
-        let expr = assert_ok!(SimpleExpr::lex_with("t", scheme), t_expr());
+        let expr = assert_ok!(
+            SimpleExpr::lex_with("t", &Parser::trusted(scheme)),
+            t_expr()
+        );
 
         assert_json!(
             expr,
@@ -97,11 +311,12 @@ fn test() {
         assert_eq!(expr.execute(ctx), Some(true));
     }
 
-    let parenthesized_expr = |expr| SimpleExpr::Parenthesized(Box::new(CombinedExpr::Simple(expr)));
+    let parenthesized_expr =
+        |expr| SimpleExpr::Parenthesized(Box::new(CombinedExpr::Simple(expr)), NodeSpan::default());
 
     {
         let expr = assert_ok!(
-            SimpleExpr::lex_with("((t))", scheme),
+            SimpleExpr::lex_with("((t))", &Parser::trusted(scheme)),
             parenthesized_expr(parenthesized_expr(t_expr()))
         );
 
@@ -121,10 +336,14 @@ fn test() {
     let not_expr = |expr| SimpleExpr::Unary {
         op: UnaryOp::Not,
         arg: Box::new(expr),
+        span: NodeSpan::default(),
     };
 
     {
-        let expr = assert_ok!(SimpleExpr::lex_with("not t", scheme), not_expr(t_expr()));
+        let expr = assert_ok!(
+            SimpleExpr::lex_with("not t", &Parser::trusted(scheme)),
+            not_expr(t_expr())
+        );
 
         assert_json!(
             expr,
@@ -142,11 +361,14 @@ fn test() {
         assert_eq!(expr.execute(ctx), Some(false));
     }
 
-    assert_ok!(SimpleExpr::lex_with("!t", scheme), not_expr(t_expr()));
+    assert_ok!(
+        SimpleExpr::lex_with("!t", &Parser::trusted(scheme)),
+        not_expr(t_expr())
+    );
 
     {
         let expr = assert_ok!(
-            SimpleExpr::lex_with("!!t", scheme),
+            SimpleExpr::lex_with("!!t", &Parser::trusted(scheme)),
             not_expr(not_expr(t_expr()))
         );
 
@@ -170,7 +392,7 @@ fn test() {
     }
 
     assert_ok!(
-        SimpleExpr::lex_with("! (not !t)", scheme),
+        SimpleExpr::lex_with("! (not !t)", &Parser::trusted(scheme)),
         not_expr(parenthesized_expr(not_expr(not_expr(t_expr()))))
     );
 }
@@ -0,0 +1,102 @@
+use serde::{Serialize, Serializer};
+
+/// A range of byte offsets of a node of a [`FilterAst`](::FilterAst) in the
+/// filter it was parsed from.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
+pub struct Span {
+    /// Offset of the first byte of the node.
+    pub start: usize,
+    /// Offset right after the last byte of the node.
+    pub end: usize,
+}
+
+impl Span {
+    /// Returns the source of the node, given the original filter.
+    pub fn source<'i>(&self, input: &'i str) -> Option<&'i str> {
+        input.get(self.start..self.end)
+    }
+}
+
+/// A node of the AST, which can be serialized with or without the spans of
+/// the nodes it contains.
+///
+/// Its plain `Serialize` implementation omits spans, see
+/// [`FilterAst::with_spans`](::FilterAst::with_spans).
+pub(crate) trait SerializeSpans {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error>;
+}
+
+/// Serializes a node along with its spans if the flag is set.
+pub(crate) struct WithSpans<'a, T: ?Sized>(pub &'a T, pub bool);
+
+impl<'a, T: SerializeSpans + ?Sized> Serialize for WithSpans<'a, T> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_spans(self.1, ser)
+    }
+}
+
+impl<T: SerializeSpans> SerializeSpans for [T] {
+    fn serialize_spans<S: Serializer>(&self, spans: bool, ser: S) -> Result<S::Ok, S::Error> {
+        ser.collect_seq(self.iter().map(|item| WithSpans(item, spans)))
+    }
+}
+
+/// The [`Span`] of a node, as stored in the AST.
+///
+/// The lexer only sees the rest of the input, so while parsing the span holds
+/// distances from the end of the input instead, until it's resolved once the
+/// whole input is parsed.
+///
+/// Spans don't take part in comparisons, so that the same expression is equal
+/// to itself wherever it's written, e.g. when sharing function calls.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct NodeSpan(Span);
+
+impl NodeSpan {
+    /// Creates an unresolved span of `input` up to `rest`.
+    pub fn new(input: &str, rest: &str) -> Self {
+        NodeSpan(Span {
+            start: input.len(),
+            end: rest.len(),
+        })
+    }
+
+    /// Creates an unresolved span from the start of this one to the end of
+    /// `other`.
+    pub fn to(self, other: NodeSpan) -> Self {
+        NodeSpan(Span {
+            start: self.0.start,
+            end: other.0.end,
+        })
+    }
+
+    /// Turns distances from the end of the input into offsets, given the
+    /// offset of the end of the input.
+    pub fn resolve(&mut self, end: usize) {
+        self.0 = Span {
+            start: end - self.0.start,
+            end: end - self.0.end,
+        };
+    }
+
+    pub fn get(self) -> Span {
+        self.0
+    }
+
+    /// Returns the span if spans are serialized, see [`SerializeSpans`].
+    pub fn shown(self, spans: bool) -> Option<Span> {
+        if spans {
+            Some(self.0)
+        } else {
+            None
+        }
+    }
+}
+
+impl PartialEq for NodeSpan {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for NodeSpan {}
@@ -0,0 +1,57 @@
+use super::{
+    combined_expr::CombinedExpr, field_expr::LhsFieldExpr, function_expr::SharedCalls, Expr,
+};
+use crate::{
+    lex::{skip_space, LexResult, LexWith},
+    limits::Parser,
+    scheme::Field,
+    types::{GetType, Type},
+};
+
+/// A parsed definition of a virtual field.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum VirtualFieldExpr<'s> {
+    /// A field, a function call or a masked IP whose value is used as is.
+    Value(LhsFieldExpr<'s>),
+    /// Any other filter expression, evaluating to a boolean.
+    Filter(CombinedExpr<'s>),
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for VirtualFieldExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        // A definition is a value only if nothing follows it, otherwise a
+        // boolean field or call can still start a larger filter.
+        if let Ok((lhs, rest)) = LhsFieldExpr::lex_with(input, parser) {
+            if skip_space(rest).is_empty() {
+                return Ok((VirtualFieldExpr::Value(lhs), rest));
+            }
+        }
+        let (op, input) = CombinedExpr::lex_with(input, parser)?;
+        Ok((VirtualFieldExpr::Filter(op), input))
+    }
+}
+
+impl<'s> GetType for VirtualFieldExpr<'s> {
+    fn get_type(&self) -> Type {
+        match self {
+            VirtualFieldExpr::Value(lhs) => lhs.get_type(),
+            VirtualFieldExpr::Filter(_) => Type::Bool,
+        }
+    }
+}
+
+impl<'s> VirtualFieldExpr<'s> {
+    pub fn walk_fields(&self, visitor: &mut dyn FnMut(Field<'s>)) {
+        match self {
+            VirtualFieldExpr::Value(lhs) => lhs.walk_fields(visitor),
+            VirtualFieldExpr::Filter(op) => op.walk_fields(visitor),
+        }
+    }
+
+    pub fn fold(self, calls: &mut SharedCalls<'s>) -> Self {
+        match self {
+            VirtualFieldExpr::Value(lhs) => VirtualFieldExpr::Value(lhs.fold(calls)),
+            VirtualFieldExpr::Filter(op) => VirtualFieldExpr::Filter(op.fold(calls)),
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{
+    functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+    types::{LhsValue, Type},
+};
+use failure::Fail;
+use idna::AsciiDenyList;
+use publicsuffix::{List, Psl};
+use std::{borrow::Cow, fs, io, path::Path, sync::Arc};
+
+/// An error that occurs when a Public Suffix List can't be loaded.
+#[derive(Debug, Fail)]
+pub enum PublicSuffixListError {
+    /// The list file couldn't be read.
+    #[fail(display = "could not read the public suffix list: {}", _0)]
+    Io(#[cause] io::Error),
+
+    /// The list is malformed.
+    #[fail(display = "invalid public suffix list: {}", _0)]
+    Parse(#[cause] publicsuffix::Error),
+}
+
+/// A parsed [Public Suffix List](https://publicsuffix.org/) used by the
+/// domain functions to find registrable domains.
+///
+/// Cloning is cheap, as the parsed list is shared.
+#[derive(Debug, Clone)]
+pub struct PublicSuffixList(Arc<List>);
+
+impl PublicSuffixList {
+    /// Parses a list in the `public_suffix_list.dat` format.
+    pub fn parse(list: &str) -> Result<Self, PublicSuffixListError> {
+        list.parse()
+            .map(|list| PublicSuffixList(Arc::new(list)))
+            .map_err(PublicSuffixListError::Parse)
+    }
+
+    /// Reads and parses a list from a local file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PublicSuffixListError> {
+        let list = fs::read_to_string(path).map_err(PublicSuffixListError::Io)?;
+        Self::parse(&list)
+    }
+
+    /// Returns the snapshot of the list compiled into the crate.
+    #[cfg(feature = "bundled-psl")]
+    pub fn bundled() -> Self {
+        Self::parse(include_str!("../../data/public_suffix_list.dat"))
+            .expect("bundled public suffix list is invalid")
+    }
+}
+
+// Converts a host name to its canonical ASCII form as per IDNA (lowercase,
+// with internationalized labels in Punycode and without the trailing dot of
+// a fully qualified name).
+fn to_ascii(host: &[u8]) -> Option<Cow<'_, str>> {
+    let ascii = idna::domain_to_ascii_cow(host, AsciiDenyList::URL).ok()?;
+    if ascii.is_empty() {
+        return None;
+    }
+    Some(match ascii {
+        Cow::Borrowed(ascii) => Cow::Borrowed(ascii.strip_suffix('.').unwrap_or(ascii)),
+        Cow::Owned(mut ascii) => {
+            if ascii.ends_with('.') {
+                ascii.pop();
+            }
+            Cow::Owned(ascii)
+        }
+    })
+}
+
+fn bytes_result<'a>(value: Option<&str>) -> LhsValue<'a> {
+    value.unwrap_or_default().as_bytes().to_vec().into()
+}
+
+fn next_bytes<'a>(args: FunctionArgs<'_, 'a>) -> Cow<'a, [u8]> {
+    match args.next() {
+        Some(LhsValue::Bytes(bytes)) => bytes,
+        arg => panic!("Invalid type: expected Bytes, got {:?}", arg),
+    }
+}
+
+fn domain_to_ascii_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let host = next_bytes(args);
+    bytes_result(to_ascii(&host).as_deref())
+}
+
+fn domain_to_unicode_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let host = next_bytes(args);
+    let unicode = to_ascii(&host).and_then(|ascii| {
+        let (unicode, res) = idna::domain_to_unicode(&ascii);
+        res.ok().map(|()| unicode)
+    });
+    bytes_result(unicode.as_deref())
+}
+
+fn subdomain_of_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let host = next_bytes(args);
+    let domain = next_bytes(args);
+    LhsValue::Bool(match (to_ascii(&host), to_ascii(&domain)) {
+        (Some(host), Some(domain)) => {
+            host.len() > domain.len()
+                && host.ends_with(&*domain)
+                && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+        }
+        _ => false,
+    })
+}
+
+fn label_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let host = next_bytes(args);
+    let index = match args.next() {
+        Some(LhsValue::Int(integer)) => integer,
+        arg => panic!("Invalid type: expected Int, got {:?}", arg),
+    };
+    let label = to_ascii(&host).and_then(|host| {
+        let label = if index >= 0 {
+            host.split('.').nth(index as usize)
+        } else {
+            host.rsplit('.').nth((-(index + 1)) as usize)
+        };
+        label.map(str::to_owned)
+    });
+    bytes_result(label.as_deref())
+}
+
+fn host_param() -> FunctionParam {
+    FunctionParam {
+        arg_kind: FunctionArgKind::Field,
+        val_type: Type::Bytes,
+        generic: false,
+    }
+}
+
+/// Returns functions for working with domain names, using the given Public
+/// Suffix List to find registrable domains:
+///
+/// * `registrable_domain(host)` returns the domain under which names can be
+///   registered, i.e. the public suffix plus one more label, e.g.
+///   `example.co.uk` for `www.example.co.uk`, or an empty string if the host
+///   is a public suffix itself.
+/// * `subdomain_of(host, "example.co.uk")` checks whether the host is a proper
+///   subdomain of the given domain.
+/// * `label(host, index)` returns the label at the given zero-based index,
+///   counting from the end if the index is negative (`label(host, -1)` is the
+///   top-level domain), or an empty string if there's no such label.
+/// * `domain_to_ascii(host)` normalizes the host name as per IDNA, e.g.
+///   `xn--bcher-kva.example` for `Bücher.example`.
+/// * `domain_to_unicode(host)` normalizes the host name and decodes Punycode
+///   labels, e.g. `bücher.example` for `XN--BCHER-KVA.example`.
+///
+/// All functions apply the IDNA normalization to their arguments first and
+/// ignore the trailing dot of fully qualified names. Hosts that aren't valid
+/// domain names produce an empty string (or `false`).
+pub fn domain_functions(list: PublicSuffixList) -> Vec<(String, Function)> {
+    vec![
+        (
+            "registrable_domain".into(),
+            Function {
+                params: vec![host_param()],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                pure: true,
+                implementation: FunctionImpl::new(move |args| {
+                    let host = next_bytes(args);
+                    let ascii = to_ascii(&host);
+                    let domain = ascii
+                        .as_ref()
+                        .and_then(|ascii| list.0.domain(ascii.as_bytes()));
+                    domain
+                        .map_or_else(Vec::new, |domain| domain.as_bytes().to_vec())
+                        .into()
+                }),
+            },
+        ),
+        (
+            "subdomain_of".into(),
+            Function {
+                params: vec![
+                    host_param(),
+                    FunctionParam {
+                        arg_kind: FunctionArgKind::Literal,
+                        val_type: Type::Bytes,
+                        generic: false,
+                    },
+                ],
+                opt_params: vec![],
+                return_type: Type::Bool,
+                pure: true,
+                implementation: FunctionImpl::new(subdomain_of_function),
+            },
+        ),
+        (
+            "label".into(),
+            Function {
+                params: vec![
+                    host_param(),
+                    FunctionParam {
+                        arg_kind: FunctionArgKind::Literal,
+                        val_type: Type::Int,
+                        generic: false,
+                    },
+                ],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                pure: true,
+                implementation: FunctionImpl::new(label_function),
+            },
+        ),
+        (
+            "domain_to_ascii".into(),
+            Function {
+                params: vec![host_param()],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                pure: true,
+                implementation: FunctionImpl::new(domain_to_ascii_function),
+            },
+        ),
+        (
+            "domain_to_unicode".into(),
+            Function {
+                params: vec![host_param()],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                pure: true,
+                implementation: FunctionImpl::new(domain_to_unicode_function),
+            },
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution_context::ExecutionContext;
+
+    const LIST: &str = "
+        // ===BEGIN ICANN DOMAINS===
+        com
+        uk
+        co.uk
+        *.ck
+        !www.ck
+        公司.cn
+        // ===END ICANN DOMAINS===
+        // ===BEGIN PRIVATE DOMAINS===
+        github.io
+        // ===END PRIVATE DOMAINS===
+    ";
+
+    fn call<'a>(name: &str, args: Vec<LhsValue<'a>>) -> LhsValue<'a> {
+        let list = PublicSuffixList::parse(&LIST.replace("        ", "")).unwrap();
+        let (_, function) = domain_functions(list)
+            .into_iter()
+            .find(|(function_name, _)| function_name == name)
+            .unwrap();
+        function.implementation.execute(args)
+    }
+
+    #[test]
+    fn test_registrable_domain() {
+        for &(host, expected) in &[
+            ("www.example.co.uk", "example.co.uk"),
+            ("example.co.uk", "example.co.uk"),
+            ("co.uk", ""),
+            ("WWW.Example.COM.", "example.com"),
+            ("foo.bar.github.io", "bar.github.io"),
+            ("a.b.example.ck", "b.example.ck"),
+            ("www.ck", "www.ck"),
+            ("shop.例子.公司.cn", "xn--fsqu00a.xn--55qx5d.cn"),
+            ("localhost", ""),
+            ("", ""),
+            ("exa mple.com", ""),
+        ] {
+            assert_eq!(
+                call("registrable_domain", vec![host.into()]),
+                LhsValue::from(expected),
+                "registrable_domain({:?})",
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn test_subdomain_of() {
+        for &(host, domain, expected) in &[
+            ("www.example.co.uk", "example.co.uk", true),
+            ("a.b.example.co.uk", "Example.CO.UK.", true),
+            ("example.co.uk", "example.co.uk", false),
+            ("badexample.co.uk", "example.co.uk", false),
+            ("bücher.example", "example", true),
+            ("xn--bcher-kva.example", "example", true),
+            ("", "example", false),
+        ] {
+            assert_eq!(
+                call("subdomain_of", vec![host.into(), domain.into()]),
+                LhsValue::Bool(expected),
+                "subdomain_of({:?}, {:?})",
+                host,
+                domain
+            );
+        }
+    }
+
+    #[test]
+    fn test_label() {
+        for &(index, expected) in &[
+            (0, "www"),
+            (1, "example"),
+            (3, "uk"),
+            (4, ""),
+            (-1, "uk"),
+            (-2, "co"),
+            (-4, "www"),
+            (-5, ""),
+        ] {
+            assert_eq!(
+                call(
+                    "label",
+                    vec!["WWW.example.co.uk.".into(), LhsValue::Int(index)]
+                ),
+                LhsValue::from(expected),
+                "label({})",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn test_idna() {
+        assert_eq!(
+            call("domain_to_ascii", vec!["Bücher.Example.".into()]),
+            LhsValue::from("xn--bcher-kva.example")
+        );
+        assert_eq!(
+            call("domain_to_unicode", vec!["XN--BCHER-KVA.example".into()]),
+            LhsValue::from("bücher.example")
+        );
+        assert_eq!(
+            call("domain_to_ascii", vec!["exa mple.com".into()]),
+            LhsValue::from("")
+        );
+        assert_eq!(
+            call("domain_to_ascii", vec![LhsValue::from(&b"\xFF.com"[..])]),
+            LhsValue::from("")
+        );
+    }
+
+    #[test]
+    fn test_from_file() {
+        match PublicSuffixList::from_file("does/not/exist.dat") {
+            Err(PublicSuffixListError::Io(_)) => {}
+            res => panic!("Expected an I/O error, got {:?}", res),
+        }
+
+        match PublicSuffixList::parse("// nothing here") {
+            Err(PublicSuffixListError::Parse(_)) => {}
+            res => panic!("Expected a parse error, got {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "bundled-psl")]
+    #[test]
+    fn test_filter() {
+        let mut scheme = Scheme! { http.host: Bytes };
+        scheme
+            .add_functions(domain_functions(PublicSuffixList::bundled()))
+            .unwrap();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("http.host", "static.Example.co.uk")
+            .unwrap();
+
+        for &(filter, expected) in &[
+            (r#"registrable_domain(http.host) == "example.co.uk""#, true),
+            (r#"subdomain_of(http.host, "example.co.uk")"#, true),
+            (r#"subdomain_of(http.host, "other.co.uk")"#, false),
+            (r#"label(http.host, -3) == "example""#, true),
+            (
+                r#"domain_to_ascii(http.host) == "static.example.co.uk""#,
+                true,
+            ),
+        ] {
+            let compiled = scheme.parse(filter).unwrap().compile();
+            assert_eq!(compiled.execute(&ctx), Ok(Some(expected)), "{}", filter);
+        }
+    }
+}
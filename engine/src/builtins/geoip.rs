@@ -0,0 +1,263 @@
+use crate::{
+    functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+    types::{LhsValue, Type},
+};
+use arc_swap::ArcSwap;
+use failure::Fail;
+use maxminddb::{MaxMindDBError, Reader};
+use serde::Deserialize;
+use std::{net::IpAddr, path::Path, sync::Arc};
+
+/// An error that occurs when a MaxMind DB file can't be loaded.
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "could not load the GeoIP database: {}", _0)]
+pub struct GeoIpError(#[cause] MaxMindDBError);
+
+/// A shared handle to a database in the [MaxMind DB
+/// format](https://maxmind.github.io/MaxMind-DB/), such as GeoLite2 Country
+/// or ASN.
+///
+/// The handle can be cloned and kept around after registering the functions
+/// on a [`Scheme`](struct@crate::Scheme) in order to replace the database
+/// later with [`reload`](GeoIpDatabase::reload) or
+/// [`replace`](GeoIpDatabase::replace). Filters pick up the new database on
+/// their next execution without having to be recompiled, while executions
+/// that are already running keep using the old one.
+#[derive(Clone)]
+pub struct GeoIpDatabase(Arc<ArcSwap<Reader<Vec<u8>>>>);
+
+impl GeoIpDatabase {
+    /// Reads a database from a local file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, GeoIpError> {
+        Self::load(path).map(Self::new)
+    }
+
+    /// Parses a database from its contents.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, GeoIpError> {
+        Self::parse(bytes).map(Self::new)
+    }
+
+    /// Replaces the database with the one from a local file.
+    ///
+    /// The current database stays in use if the new one fails to load.
+    pub fn reload(&self, path: impl AsRef<Path>) -> Result<(), GeoIpError> {
+        Self::load(path).map(|reader| self.0.store(Arc::new(reader)))
+    }
+
+    /// Replaces the database with the one parsed from the given contents.
+    ///
+    /// The current database stays in use if the new one fails to parse.
+    pub fn replace(&self, bytes: Vec<u8>) -> Result<(), GeoIpError> {
+        Self::parse(bytes).map(|reader| self.0.store(Arc::new(reader)))
+    }
+
+    fn new(reader: Reader<Vec<u8>>) -> Self {
+        GeoIpDatabase(Arc::new(ArcSwap::from_pointee(reader)))
+    }
+
+    fn load(path: impl AsRef<Path>) -> Result<Reader<Vec<u8>>, GeoIpError> {
+        Reader::open_readfile(path).map_err(GeoIpError)
+    }
+
+    fn parse(bytes: Vec<u8>) -> Result<Reader<Vec<u8>>, GeoIpError> {
+        Reader::from_source(bytes).map_err(GeoIpError)
+    }
+
+    // Looks up the record for an address, treating addresses that aren't in
+    // the database and records of unexpected shape alike.
+    fn lookup<T, R>(&self, addr: IpAddr, map: impl FnOnce(T) -> Option<R>) -> Option<R>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        self.0.load().lookup(addr).ok().and_then(map)
+    }
+}
+
+#[derive(Deserialize)]
+struct CountryRecord {
+    country: Option<Country>,
+    registered_country: Option<Country>,
+}
+
+#[derive(Deserialize)]
+struct Country {
+    iso_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AsnRecord {
+    autonomous_system_number: Option<u32>,
+}
+
+fn next_ip(args: FunctionArgs<'_, '_>) -> IpAddr {
+    match args.next() {
+        Some(LhsValue::Ip(addr)) => addr,
+        arg => panic!("Invalid type: expected Ip, got {:?}", arg),
+    }
+}
+
+fn ip_function(
+    return_type: Type,
+    implementation: impl 'static + for<'a> Fn(FunctionArgs<'_, 'a>) -> LhsValue<'a> + Send + Sync,
+) -> Function {
+    Function {
+        params: vec![FunctionParam {
+            arg_kind: FunctionArgKind::Field,
+            val_type: Type::Ip,
+            generic: false,
+        }],
+        opt_params: vec![],
+        return_type,
+        // not pure, since results change when the database is replaced
+        pure: false,
+        implementation: FunctionImpl::new(implementation),
+    }
+}
+
+/// Returns the `ip_country(ip)` function, which looks up the address in a
+/// country database (such as GeoLite2 Country or City) and returns its ISO
+/// 3166-1 country code, e.g. `"US"`.
+///
+/// The country where the network is registered is used if the database
+/// doesn't know where the address is located. An empty string is returned if
+/// the address isn't in the database at all.
+pub fn geoip_country_functions(db: GeoIpDatabase) -> Vec<(String, Function)> {
+    vec![(
+        "ip_country".into(),
+        ip_function(Type::Bytes, move |args| {
+            let addr = next_ip(args);
+            db.lookup(addr, |record: CountryRecord| {
+                let registered_country = record.registered_country;
+                record
+                    .country
+                    .and_then(|country| country.iso_code)
+                    .or_else(|| registered_country?.iso_code)
+            })
+            .unwrap_or_default()
+            .into()
+        }),
+    )]
+}
+
+/// Returns the `ip_asn(ip)` function, which looks up the address in an ASN
+/// database (such as GeoLite2 ASN) and returns the number of the autonomous
+/// system that announces it, or `0` if the address isn't in the database.
+///
+/// Since filters operate on signed 32-bit integers, 4-byte AS numbers above
+/// `2147483647` are reinterpreted as negative ones.
+pub fn geoip_asn_functions(db: GeoIpDatabase) -> Vec<(String, Function)> {
+    vec![(
+        "ip_asn".into(),
+        ip_function(Type::Int, move |args| {
+            let addr = next_ip(args);
+            let asn = db.lookup(addr, |record: AsnRecord| record.autonomous_system_number);
+            LhsValue::Int(asn.unwrap_or_default() as i32)
+        }),
+    )]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution_context::ExecutionContext;
+    use std::str::FromStr;
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/geoip-test.mmdb"
+    );
+
+    const FIXTURE_V2: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/geoip-test-v2.mmdb"
+    );
+
+    fn call<'a>(db: &GeoIpDatabase, name: &str, addr: &str) -> LhsValue<'a> {
+        let (_, function) = geoip_country_functions(db.clone())
+            .into_iter()
+            .chain(geoip_asn_functions(db.clone()))
+            .find(|(function_name, _)| function_name == name)
+            .unwrap();
+        function
+            .implementation
+            .execute(vec![LhsValue::Ip(IpAddr::from_str(addr).unwrap())])
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = GeoIpDatabase::open(FIXTURE).unwrap();
+
+        for &(addr, country, asn) in &[
+            ("192.0.2.1", "US", 64496),
+            ("198.51.100.1", "FR", 64497),
+            ("198.51.100.200", "", 0),
+            ("2001:db8::1", "DE", 4_200_000_000_u32 as i32),
+            ("2001:db9::1", "", 0),
+            ("10.0.0.1", "", 0),
+        ] {
+            assert_eq!(
+                call(&db, "ip_country", addr),
+                LhsValue::from(country),
+                "ip_country({})",
+                addr
+            );
+            assert_eq!(
+                call(&db, "ip_asn", addr),
+                LhsValue::Int(asn),
+                "ip_asn({})",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_errors() {
+        match GeoIpDatabase::open("does/not/exist.mmdb") {
+            Err(GeoIpError(MaxMindDBError::IoError(_))) => {}
+            Err(err) => panic!("Expected an I/O error, got {:?}", err),
+            Ok(_) => panic!("Expected an I/O error"),
+        }
+
+        match GeoIpDatabase::from_bytes(b"not a database".to_vec()) {
+            Err(GeoIpError(MaxMindDBError::InvalidDatabaseError(_))) => {}
+            Err(err) => panic!("Expected an invalid database error, got {:?}", err),
+            Ok(_) => panic!("Expected an invalid database error"),
+        }
+    }
+
+    #[test]
+    fn test_hot_swap() {
+        let db = GeoIpDatabase::open(FIXTURE).unwrap();
+
+        let mut scheme = Scheme! { ip.src: Ip };
+        scheme
+            .add_functions(geoip_country_functions(db.clone()))
+            .unwrap();
+        scheme
+            .add_functions(geoip_asn_functions(db.clone()))
+            .unwrap();
+
+        let filter = scheme
+            .parse(r#"ip_country(ip.src) == "US" && ip_asn(ip.src) == 64496"#)
+            .unwrap()
+            .compile();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("ip.src", IpAddr::from([192, 0, 2, 1]))
+            .unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+
+        // failed reloads keep the previous database
+        assert!(db.reload("does/not/exist.mmdb").is_err());
+        assert!(db.replace(b"not a database".to_vec()).is_err());
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+
+        db.reload(FIXTURE_V2).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(Some(false)));
+        assert_eq!(call(&db, "ip_country", "192.0.2.1"), LhsValue::from("CA"));
+        assert_eq!(call(&db, "ip_asn", "192.0.2.1"), LhsValue::Int(64511));
+
+        db.replace(std::fs::read(FIXTURE).unwrap()).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+    }
+}
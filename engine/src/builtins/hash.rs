@@ -0,0 +1,324 @@
+use crate::{
+    functions::{
+        Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionOptParam, FunctionParam,
+    },
+    types::{LhsValue, Type},
+};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+const FNV1A_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV1A_PRIME: u32 = 0x0100_0193;
+
+// Feeds a value into a hasher using a canonical byte representation that
+// doesn't depend on the platform: IPs are hashed as their octets, integers as
+// 4 big-endian bytes and booleans as a single byte.
+//
+// A non-zero seed is prepended as 4 big-endian bytes, so that a zero seed
+// yields the standard hash of the value itself.
+fn feed(value: &LhsValue<'_>, seed: i32, write: &mut dyn FnMut(&[u8])) {
+    if seed != 0 {
+        write(&seed.to_be_bytes());
+    }
+
+    match value {
+        LhsValue::Ip(IpAddr::V4(addr)) => write(&addr.octets()),
+        LhsValue::Ip(IpAddr::V6(addr)) => write(&addr.octets()),
+        LhsValue::Bytes(bytes) => write(bytes),
+        LhsValue::Int(integer) => write(&integer.to_be_bytes()),
+        LhsValue::Bool(b) => write(&[*b as u8]),
+    }
+}
+
+fn next_int(args: FunctionArgs<'_, '_>) -> i32 {
+    match args.next() {
+        Some(LhsValue::Int(integer)) => integer,
+        arg => panic!("Invalid type: expected Int, got {:?}", arg),
+    }
+}
+
+fn sha256_digest(value: &LhsValue<'_>, seed: i32) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    feed(value, seed, &mut |bytes| hasher.update(bytes));
+    hasher.finalize().into()
+}
+
+fn sha256_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let value = args.next().unwrap();
+    let seed = next_int(args);
+    sha256_digest(&value, seed).to_vec().into()
+}
+
+fn crc32_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let value = args.next().unwrap();
+    let seed = next_int(args);
+    let mut hasher = crc32fast::Hasher::new();
+    feed(&value, seed, &mut |bytes| hasher.update(bytes));
+    LhsValue::Int(hasher.finalize() as i32)
+}
+
+fn fnv1a_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let value = args.next().unwrap();
+    let seed = next_int(args);
+    let mut hash = FNV1A_OFFSET_BASIS;
+    feed(&value, seed, &mut |bytes| {
+        for &b in bytes {
+            hash ^= u32::from(b);
+            hash = hash.wrapping_mul(FNV1A_PRIME);
+        }
+    });
+    LhsValue::Int(hash as i32)
+}
+
+fn hash_bucket_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    let value = args.next().unwrap();
+    let buckets = next_int(args);
+    let seed = next_int(args);
+    let digest = sha256_digest(&value, seed);
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    LhsValue::Int((u64::from_be_bytes(prefix) % buckets as u64) as i32)
+}
+
+fn check_buckets(index: usize, value: &LhsValue<'_>) -> Result<(), String> {
+    match (index, value) {
+        (1, LhsValue::Int(buckets)) if *buckets <= 0 => Err(format!(
+            "expected a positive number of buckets, got {}",
+            buckets
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn value_param() -> FunctionParam {
+    FunctionParam {
+        arg_kind: FunctionArgKind::Field,
+        val_type: Type::Bytes,
+        generic: true,
+    }
+}
+
+fn seed_param() -> FunctionOptParam {
+    FunctionOptParam {
+        arg_kind: FunctionArgKind::Literal,
+        default_value: LhsValue::Int(0),
+    }
+}
+
+/// Returns hashing functions suitable for deterministic sampling, such as
+/// gradual rollouts of rules to a percentage of traffic.
+///
+/// Each function accepts a field (or a nested function call) of type `Ip`,
+/// `Int`, `Bytes` or `Bool` and an optional integer seed literal:
+///
+/// * `sha256(value[, seed])` returns the 32-byte SHA-256 digest as `Bytes`.
+/// * `crc32(value[, seed])` returns the CRC-32 (IEEE) checksum as `Int`.
+/// * `fnv1a(value[, seed])` returns the 32-bit FNV-1a hash as `Int`.
+/// * `hash_bucket(value, buckets[, seed])` deterministically assigns the value
+///   to one of `buckets` buckets and returns its number in `0..buckets`, e.g.
+///   `hash_bucket(ip.src, 100) < 5` selects 5% of client IPs. `buckets` must
+///   be positive.
+///
+/// 32-bit results are reinterpreted as signed integers. The results are
+/// stable across platforms and releases.
+pub fn hash_functions() -> Vec<(String, Function)> {
+    vec![
+        (
+            "sha256".into(),
+            Function {
+                params: vec![value_param()],
+                opt_params: vec![seed_param()],
+                return_type: Type::Bytes,
+                pure: true,
+                implementation: FunctionImpl::new(sha256_function),
+            },
+        ),
+        (
+            "crc32".into(),
+            Function {
+                params: vec![value_param()],
+                opt_params: vec![seed_param()],
+                return_type: Type::Int,
+                pure: true,
+                implementation: FunctionImpl::new(crc32_function),
+            },
+        ),
+        (
+            "fnv1a".into(),
+            Function {
+                params: vec![value_param()],
+                opt_params: vec![seed_param()],
+                return_type: Type::Int,
+                pure: true,
+                implementation: FunctionImpl::new(fnv1a_function),
+            },
+        ),
+        (
+            "hash_bucket".into(),
+            Function {
+                params: vec![
+                    value_param(),
+                    FunctionParam {
+                        arg_kind: FunctionArgKind::Literal,
+                        val_type: Type::Int,
+                        generic: false,
+                    },
+                ],
+                opt_params: vec![seed_param()],
+                return_type: Type::Int,
+                pure: true,
+                implementation: FunctionImpl::new(hash_bucket_function)
+                    .check_literals(check_buckets),
+            },
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution_context::ExecutionContext;
+
+    fn call<'a>(name: &str, args: Vec<LhsValue<'a>>) -> LhsValue<'a> {
+        let (_, function) = hash_functions()
+            .into_iter()
+            .find(|(function_name, _)| function_name == name)
+            .unwrap();
+        function.implementation.execute(args)
+    }
+
+    #[test]
+    fn test_known_vectors() {
+        assert_eq!(
+            call("crc32", vec!["123456789".into(), LhsValue::Int(0)]),
+            LhsValue::Int(0xCBF4_3926_u32 as i32)
+        );
+
+        assert_eq!(
+            call("fnv1a", vec!["".into(), LhsValue::Int(0)]),
+            LhsValue::Int(FNV1A_OFFSET_BASIS as i32)
+        );
+
+        assert_eq!(
+            call("fnv1a", vec!["a".into(), LhsValue::Int(0)]),
+            LhsValue::Int(0xE40C_292C_u32 as i32)
+        );
+
+        assert_eq!(
+            call("sha256", vec!["abc".into(), LhsValue::Int(0)]),
+            LhsValue::from(vec![
+                0xBA, 0x78, 0x16, 0xBF, 0x8F, 0x01, 0xCF, 0xEA, 0x41, 0x41, 0x40, 0xDE, 0x5D, 0xAE,
+                0x22, 0x23, 0xB0, 0x03, 0x61, 0xA3, 0x96, 0x17, 0x7A, 0x9C, 0xB4, 0x10, 0xFF, 0x61,
+                0xF2, 0x00, 0x15, 0xAD,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_canonical_encoding() {
+        assert_eq!(
+            call(
+                "crc32",
+                vec![LhsValue::Ip(IpAddr::from([192, 0, 2, 1])), LhsValue::Int(0)]
+            ),
+            LhsValue::Int(305_685_262)
+        );
+
+        assert_eq!(
+            call("crc32", vec![LhsValue::Int(443), LhsValue::Int(0)]),
+            LhsValue::Int(1_693_221_977)
+        );
+
+        assert_eq!(
+            call("crc32", vec!["abc".into(), LhsValue::Int(7)]),
+            LhsValue::Int(-891_388_137)
+        );
+
+        assert_eq!(
+            call("fnv1a", vec!["a".into(), LhsValue::Int(1)]),
+            LhsValue::Int(1_292_824_153)
+        );
+    }
+
+    #[test]
+    fn test_hash_bucket() {
+        let ip = || LhsValue::Ip(IpAddr::from([192, 0, 2, 1]));
+
+        assert_eq!(
+            call("hash_bucket", vec![ip(), LhsValue::Int(100), LhsValue::Int(0)]),
+            LhsValue::Int(32)
+        );
+
+        assert_eq!(
+            call("hash_bucket", vec![ip(), LhsValue::Int(100), LhsValue::Int(42)]),
+            LhsValue::Int(50)
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        let mut scheme = Scheme! { ip.src: Ip };
+        scheme.add_functions(hash_functions()).unwrap();
+
+        assert_json!(
+            scheme.get_function("hash_bucket").unwrap().params,
+            [
+                { "arg_kind": "Field", "val_type": "Bytes", "generic": true },
+                { "arg_kind": "Literal", "val_type": "Int", "generic": false }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut scheme = Scheme! {
+            ip.src: Ip,
+            http.host: Bytes,
+            tcp.port: Int,
+        };
+        scheme.add_functions(hash_functions()).unwrap();
+
+        let filter = scheme
+            .parse("hash_bucket(ip.src, 100) < 50")
+            .unwrap()
+            .compile();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+
+        ctx.set_field_value("ip.src", IpAddr::from([192, 0, 2, 1]))
+            .unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+
+        ctx.set_field_value("ip.src", IpAddr::from([192, 0, 2, 2]))
+            .unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(Some(false)));
+
+        let filter = scheme
+            .parse("hash_bucket(ip.src, 100, 42) == 58")
+            .unwrap()
+            .compile();
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+
+        let err = scheme.parse("hash_bucket(ip.src, 0) == 0").unwrap_err();
+        assert_eq!(
+            err.message(),
+            "invalid value of argument #1: expected a positive number of buckets, got 0"
+        );
+
+        let filter = scheme
+            .parse(r#"crc32(http.host) == -873187034 && fnv1a(tcp.port) != 0"#)
+            .unwrap()
+            .compile();
+        ctx.set_field_value("http.host", "123456789").unwrap();
+        ctx.set_field_value("tcp.port", 443).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+
+        let filter = scheme
+            .parse(
+                "sha256(http.host) == 15:e2:b0:d3:c3:38:91:eb:b0:f1:ef:60:9e:c4:19:42:0c:20:e3:20:ce:94:c6:5f:bc:8c:33:12:44:8e:b2:25",
+            )
+            .unwrap()
+            .compile();
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+    }
+}
@@ -0,0 +1,388 @@
+use crate::{
+    functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+    rhs_types::mask_addr,
+    types::{LhsValue, Type},
+};
+use cidr::{Cidr, Family, Ipv4Cidr, Ipv6Cidr, NetworkLengthTooLongError};
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+// Private-use networks (RFC 1918 and unique local IPv6 addresses, RFC 4193).
+const PRIVATE_V4: &[([u8; 4], u8)] = &[
+    ([10, 0, 0, 0], 8),
+    ([172, 16, 0, 0], 12),
+    ([192, 168, 0, 0], 16),
+];
+const PRIVATE_V6: &[([u16; 8], u8)] = &[([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7)];
+
+// Networks that the IANA special-purpose address registries mark as not
+// globally reachable.
+const NON_GLOBAL_V4: &[([u8; 4], u8)] = &[
+    ([0, 0, 0, 0], 8),
+    ([10, 0, 0, 0], 8),
+    ([100, 64, 0, 0], 10),
+    ([127, 0, 0, 0], 8),
+    ([169, 254, 0, 0], 16),
+    ([172, 16, 0, 0], 12),
+    ([192, 0, 0, 0], 24),
+    ([192, 0, 2, 0], 24),
+    ([192, 88, 99, 0], 24),
+    ([192, 168, 0, 0], 16),
+    ([198, 18, 0, 0], 15),
+    ([198, 51, 100, 0], 24),
+    ([203, 0, 113, 0], 24),
+    ([240, 0, 0, 0], 4),
+];
+const NON_GLOBAL_V6: &[([u16; 8], u8)] = &[
+    ([0, 0, 0, 0, 0, 0, 0, 0], 128),
+    ([0, 0, 0, 0, 0, 0, 0, 1], 128),
+    ([0, 0, 0, 0, 0, 0xffff, 0, 0], 96),
+    ([0x64, 0xff9b, 1, 0, 0, 0, 0, 0], 48),
+    ([0x100, 0, 0, 0, 0, 0, 0, 0], 64),
+    ([0x2001, 0, 0, 0, 0, 0, 0, 0], 23),
+    ([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0], 32),
+    ([0x3fff, 0, 0, 0, 0, 0, 0, 0], 20),
+    ([0x5f00, 0, 0, 0, 0, 0, 0, 0], 16),
+    ([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7),
+    ([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10),
+];
+
+// Exceptions from the non-global networks above that are globally reachable
+// nonetheless, e.g. anycast services allocated from `192.0.0.0/24`.
+const GLOBAL_V4: &[([u8; 4], u8)] = &[([192, 0, 0, 9], 32), ([192, 0, 0, 10], 32)];
+const GLOBAL_V6: &[([u16; 8], u8)] = &[
+    ([0x2001, 1, 0, 0, 0, 0, 0, 1], 128),
+    ([0x2001, 1, 0, 0, 0, 0, 0, 2], 128),
+    ([0x2001, 1, 0, 0, 0, 0, 0, 3], 128),
+    ([0x2001, 3, 0, 0, 0, 0, 0, 0], 32),
+    ([0x2001, 4, 0x112, 0, 0, 0, 0, 0], 48),
+    ([0x2001, 0x20, 0, 0, 0, 0, 0, 0], 28),
+    ([0x2001, 0x30, 0, 0, 0, 0, 0, 0], 28),
+];
+
+fn in_v4_networks(addr: Ipv4Addr, networks: &[([u8; 4], u8)]) -> bool {
+    networks
+        .iter()
+        .any(|&(network, len)| Ipv4Cidr::new(network.into(), len).unwrap().contains(&addr))
+}
+
+fn in_v6_networks(addr: Ipv6Addr, networks: &[([u16; 8], u8)]) -> bool {
+    networks
+        .iter()
+        .any(|&(network, len)| Ipv6Cidr::new(network.into(), len).unwrap().contains(&addr))
+}
+
+fn to_ipv4_mapped(addr: Ipv6Addr) -> Option<Ipv4Addr> {
+    match addr.octets() {
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => Some(Ipv4Addr::new(a, b, c, d)),
+        _ => None,
+    }
+}
+
+fn next_ip(args: FunctionArgs<'_, '_>) -> IpAddr {
+    match args.next() {
+        Some(LhsValue::Ip(addr)) => addr,
+        arg => panic!("Invalid type: expected Ip, got {:?}", arg),
+    }
+}
+
+fn is_private_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    LhsValue::Bool(match next_ip(args) {
+        IpAddr::V4(addr) => in_v4_networks(addr, PRIVATE_V4),
+        IpAddr::V6(addr) => in_v6_networks(addr, PRIVATE_V6),
+    })
+}
+
+fn is_loopback_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    LhsValue::Bool(next_ip(args).is_loopback())
+}
+
+fn is_global_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    LhsValue::Bool(match next_ip(args) {
+        IpAddr::V4(addr) => in_v4_networks(addr, GLOBAL_V4) || !in_v4_networks(addr, NON_GLOBAL_V4),
+        IpAddr::V6(addr) => in_v6_networks(addr, GLOBAL_V6) || !in_v6_networks(addr, NON_GLOBAL_V6),
+    })
+}
+
+fn ip_version_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    LhsValue::Int(match next_ip(args) {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 6,
+    })
+}
+
+// Prefix lengths are checked the same way as by the `ip/prefix_len` operator
+// when a filter is parsed, so invalid ones only have no result when the
+// function is called directly.
+fn mask_function<'a>(args: FunctionArgs<'_, 'a>) -> Option<LhsValue<'a>> {
+    let addr = next_ip(args);
+    let prefix_len = match args.next() {
+        Some(LhsValue::Int(integer)) => integer,
+        arg => panic!("Invalid type: expected Int, got {:?}", arg),
+    };
+    let prefix_len = u8::try_from(prefix_len)
+        .ok()
+        .filter(|&len| len <= Family::Ipv6.len())?;
+    Some(LhsValue::Ip(mask_addr(addr, prefix_len)))
+}
+
+fn check_prefix_len(index: usize, value: &LhsValue<'_>) -> Result<(), String> {
+    match (index, value) {
+        (1, &LhsValue::Int(prefix_len)) if prefix_len < 0 => {
+            Err(format!("network length {} is negative", prefix_len))
+        }
+        (1, &LhsValue::Int(prefix_len)) if prefix_len > Family::Ipv6.len().into() => {
+            Err(NetworkLengthTooLongError::new(prefix_len as usize, Family::Ipv6).to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn ipv4_mapped_to_v4_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    LhsValue::Ip(match next_ip(args) {
+        IpAddr::V6(addr) => to_ipv4_mapped(addr).map_or(IpAddr::V6(addr), IpAddr::V4),
+        addr => addr,
+    })
+}
+
+fn ip_param() -> FunctionParam {
+    FunctionParam {
+        arg_kind: FunctionArgKind::Field,
+        val_type: Type::Ip,
+        generic: false,
+    }
+}
+
+fn ip_function(
+    implementation: for<'a> fn(FunctionArgs<'_, 'a>) -> LhsValue<'a>,
+    return_type: Type,
+) -> Function {
+    Function {
+        params: vec![ip_param()],
+        opt_params: vec![],
+        return_type,
+        pure: true,
+        implementation: FunctionImpl::new(implementation),
+    }
+}
+
+/// Returns functions for classifying and transforming IP addresses:
+///
+/// * `is_private(ip)` checks whether the address belongs to a private-use
+///   network (`10.0.0.0/8`, `172.16.0.0/12`, `192.168.0.0/16` or `fc00::/7`).
+/// * `is_loopback(ip)` checks whether the address is a loopback address
+///   (`127.0.0.0/8` or `::1`).
+/// * `is_global(ip)` checks whether the address is globally reachable
+///   according to the IANA special-purpose address registries.
+/// * `ip_version(ip)` returns `4` or `6`.
+/// * `mask(ip, prefix_len)` clears all but the first `prefix_len` bits of the
+///   address, same as the `ip/prefix_len` operator. `prefix_len` can't be
+///   negative or longer than an IPv6 address.
+/// * `ipv4_mapped_to_v4(ip)` converts an IPv4-mapped IPv6 address such as
+///   `::ffff:192.0.2.1` to the IPv4 address it embeds and returns any other
+///   address unchanged.
+///
+/// Note that IPv4-mapped addresses are classified as IPv6 ones, so
+/// `ipv4_mapped_to_v4` should be applied first if such addresses need to be
+/// treated as IPv4.
+pub fn ip_functions() -> Vec<(String, Function)> {
+    vec![
+        (
+            "is_private".into(),
+            ip_function(is_private_function, Type::Bool),
+        ),
+        (
+            "is_loopback".into(),
+            ip_function(is_loopback_function, Type::Bool),
+        ),
+        (
+            "is_global".into(),
+            ip_function(is_global_function, Type::Bool),
+        ),
+        (
+            "ip_version".into(),
+            ip_function(ip_version_function, Type::Int),
+        ),
+        (
+            "mask".into(),
+            Function {
+                params: vec![
+                    ip_param(),
+                    FunctionParam {
+                        arg_kind: FunctionArgKind::Literal,
+                        val_type: Type::Int,
+                        generic: false,
+                    },
+                ],
+                opt_params: vec![],
+                return_type: Type::Ip,
+                pure: true,
+                implementation: FunctionImpl::new_optional(mask_function)
+                    .check_literals(check_prefix_len),
+            },
+        ),
+        (
+            "ipv4_mapped_to_v4".into(),
+            ip_function(ipv4_mapped_to_v4_function, Type::Ip),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution_context::ExecutionContext;
+    use std::str::FromStr;
+
+    fn call<'a>(name: &str, args: Vec<LhsValue<'a>>) -> LhsValue<'a> {
+        let (_, function) = ip_functions()
+            .into_iter()
+            .find(|(function_name, _)| function_name == name)
+            .unwrap();
+        function.implementation.execute(args)
+    }
+
+    fn ip(s: &str) -> LhsValue<'static> {
+        LhsValue::Ip(IpAddr::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn test_classification() {
+        let cases = &[
+            // address, private, loopback, global
+            ("10.1.2.3", true, false, false),
+            ("172.31.255.255", true, false, false),
+            ("172.32.0.0", false, false, true),
+            ("192.168.0.1", true, false, false),
+            ("127.0.0.1", false, true, false),
+            ("100.64.0.1", false, false, false),
+            ("169.254.1.1", false, false, false),
+            ("192.0.0.9", false, false, true),
+            ("192.0.2.1", false, false, false),
+            ("255.255.255.255", false, false, false),
+            ("1.1.1.1", false, false, true),
+            ("::1", false, true, false),
+            ("::", false, false, false),
+            ("fd00::1", true, false, false),
+            ("fe80::1", false, false, false),
+            ("2001:db8::1", false, false, false),
+            ("2001:4:112::1", false, false, true),
+            ("::ffff:1.1.1.1", false, false, false),
+            ("2606:4700:4700::1111", false, false, true),
+        ];
+
+        for &(addr, private, loopback, global) in cases {
+            assert_eq!(
+                call("is_private", vec![ip(addr)]),
+                LhsValue::Bool(private),
+                "is_private({})",
+                addr
+            );
+            assert_eq!(
+                call("is_loopback", vec![ip(addr)]),
+                LhsValue::Bool(loopback),
+                "is_loopback({})",
+                addr
+            );
+            assert_eq!(
+                call("is_global", vec![ip(addr)]),
+                LhsValue::Bool(global),
+                "is_global({})",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn test_ip_version() {
+        assert_eq!(call("ip_version", vec![ip("192.0.2.1")]), LhsValue::Int(4));
+        assert_eq!(
+            call("ip_version", vec![ip("2001:db8::1")]),
+            LhsValue::Int(6)
+        );
+        assert_eq!(
+            call("ip_version", vec![ip("::ffff:192.0.2.1")]),
+            LhsValue::Int(6)
+        );
+    }
+
+    #[test]
+    fn test_mask() {
+        let mask = |addr, prefix_len| {
+            let (_, function) = ip_functions()
+                .into_iter()
+                .find(|(function_name, _)| function_name == "mask")
+                .unwrap();
+            function
+                .implementation
+                .try_execute(vec![ip(addr), LhsValue::Int(prefix_len)])
+        };
+
+        assert_eq!(mask("192.0.2.123", 24), Some(ip("192.0.2.0")));
+        assert_eq!(mask("2001:db8:1:2::1", 32), Some(ip("2001:db8::")));
+        assert_eq!(mask("192.0.2.123", 64), Some(ip("192.0.2.123")));
+        assert_eq!(mask("2001:db8:1:2::1", 128), Some(ip("2001:db8:1:2::1")));
+        assert_eq!(mask("192.0.2.123", -1), None);
+        assert_eq!(mask("192.0.2.123", 129), None);
+        assert_eq!(mask("192.0.2.123", 1000), None);
+    }
+
+    #[test]
+    fn test_ipv4_mapped_to_v4() {
+        assert_eq!(
+            call("ipv4_mapped_to_v4", vec![ip("::ffff:192.0.2.1")]),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            call("ipv4_mapped_to_v4", vec![ip("::192.0.2.1")]),
+            ip("::192.0.2.1")
+        );
+        assert_eq!(
+            call("ipv4_mapped_to_v4", vec![ip("192.0.2.1")]),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut scheme = Scheme! {
+            ip.src: Ip,
+            ip.dst: Ip,
+        };
+        scheme.add_functions(ip_functions()).unwrap();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("ip.src", IpAddr::from_str("::ffff:10.0.0.1").unwrap())
+            .unwrap();
+
+        for &(filter, expected) in &[
+            ("is_private(ip.src)", Some(false)),
+            ("is_private(ipv4_mapped_to_v4(ip.src))", Some(true)),
+            ("not is_global(ip.src)", Some(true)),
+            ("ip_version(ipv4_mapped_to_v4(ip.src)) == 4", Some(true)),
+            ("mask(ipv4_mapped_to_v4(ip.src), 8) == 10.0.0.0", Some(true)),
+            ("ipv4_mapped_to_v4(ip.src)/8 == 10.0.0.0", Some(true)),
+            ("is_loopback(ip.dst)", None),
+            ("is_private(ip.src) or is_private(ip.dst)", Some(false)),
+        ] {
+            let compiled = scheme.parse(filter).unwrap().compile();
+            assert_eq!(compiled.execute(&ctx), Ok(expected), "{}", filter);
+        }
+
+        for &(filter, message) in &[
+            (
+                "mask(ip.src, 1000) == ::ffff:10.0.0.1",
+                "invalid value of argument #1: Network length 1000 is too long for Ipv6 (maximum: 128)",
+            ),
+            (
+                "mask(ip.src, -1) == ::ffff:10.0.0.1",
+                "invalid value of argument #1: network length -1 is negative",
+            ),
+        ] {
+            let err = scheme.parse(filter).unwrap_err();
+            assert_eq!(err.message(), message, "{}", filter);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Ready-made functions that can be registered on a
+//! [`Scheme`](struct@crate::Scheme) via
+//! [`Scheme::add_functions`](crate::Scheme::add_functions).
+//!
+//! All of them are implemented in pure Rust without any platform-specific
+//! behaviour, so they produce identical results whether called from Rust,
+//! through the FFI or in WebAssembly.
+//!
+//! The GeoIP functions are only available with the `geoip` feature, which
+//! pulls in a MaxMind DB reader and isn't enabled by default.
+
+mod domain;
+#[cfg(feature = "geoip")]
+mod geoip;
+mod hash;
+mod ip;
+
+#[cfg(feature = "geoip")]
+pub use self::geoip::{
+    geoip_asn_functions, geoip_country_functions, GeoIpDatabase, GeoIpError,
+};
+
+pub use self::{
+    domain::{domain_functions, PublicSuffixList, PublicSuffixListError},
+    hash::hash_functions,
+    ip::ip_functions,
+};
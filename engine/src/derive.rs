@@ -1,5 +1,5 @@
 use crate::scheme::Scheme;
-use crate::{ExecutionContext, LhsValue, Type};
+use crate::{ExecutionContext, FieldType, LhsValue, Type};
 use crate::errors::Error;
 use std::net::IpAddr;
 ///
@@ -20,35 +20,35 @@ pub trait GenContext {
 impl GenContext for String {
     fn generate_context<'s>(&self, ctx: &mut ExecutionContext<'s>, field_name: &str) -> Result<(), Error> {
         let value: LhsValue = LhsValue::from(self.to_owned());
-        ctx.set_field_value(field_name, value).map_err(Error::TypeMismatchError)?;
+        ctx.set_field_value(field_name, value).map_err(Error::SetFieldValueError)?;
         Ok(())
     }
 }
 
 impl GenContext for usize {
     fn generate_context<'s>(&self, ctx: &mut ExecutionContext<'s>, field_name: &str) -> Result<(), Error>{
-        ctx.set_field_value(field_name, LhsValue::Int(*self as _)).map_err(Error::TypeMismatchError)?;
+        ctx.set_field_value(field_name, LhsValue::Int(*self as _)).map_err(Error::SetFieldValueError)?;
         Ok(())
     }
 }
 
 impl GenContext for i32 {
     fn generate_context<'s>(&self, ctx: &mut ExecutionContext<'s>, field_name: &str) -> Result<(), Error>{
-        ctx.set_field_value(field_name, LhsValue::Int(*self as _)).map_err(Error::TypeMismatchError)?;
+        ctx.set_field_value(field_name, LhsValue::Int(*self as _)).map_err(Error::SetFieldValueError)?;
         Ok(())
     }
 }
 
 impl GenContext for i64 {
     fn generate_context<'s>(&self, ctx: &mut ExecutionContext<'s>, field_name: &str) -> Result<(), Error>{
-        ctx.set_field_value(field_name, LhsValue::Int(*self as _)).map_err(Error::TypeMismatchError)?;
+        ctx.set_field_value(field_name, LhsValue::Int(*self as _)).map_err(Error::SetFieldValueError)?;
         Ok(())
     }
 }
 
 impl GenContext for IpAddr {
     fn generate_context<'s>(&self, ctx: &mut ExecutionContext<'s>, field_name: &str) -> Result<(), Error> {
-        ctx.set_field_value(field_name, LhsValue::Ip(*self)).map_err(Error::TypeMismatchError)?;
+        ctx.set_field_value(field_name, LhsValue::Ip(*self)).map_err(Error::SetFieldValueError)?;
         Ok(())
     }
 }
@@ -82,6 +82,48 @@ impl GenContext for Vec<(String, String)> {
 ///
 pub trait HasFields {
     fn fields() -> Vec<(String, Type)>;
+
+    ///
+    /// Default values declared with `#[field(default = ...)]`, to be set with `Scheme::set_field_default`.
+    ///
+    fn defaults() -> Vec<(String, LhsValue<'static>)> {
+        Vec::new()
+    }
+
+    ///
+    /// The fields as an object type, to be registered with `Scheme::add_nested_field`.
+    /// Unlike `fields`, names don't include the `#[field(name = ...)]` of the struct itself.
+    ///
+    fn field_type() -> FieldType {
+        Self::fields().into_iter().collect()
+    }
+}
+
+///
+/// Flattens a field type into fields named `name.member`, the same way `Scheme::add_nested_field` registers them.
+///
+pub fn nested_fields(name: &str, ty: FieldType) -> Vec<(String, Type)> {
+    match ty {
+        FieldType::Value(ty) => vec![(name.to_owned(), ty)],
+        FieldType::Object(members) => members
+            .into_iter()
+            .flat_map(|(member, ty)| nested_fields(&format!("{}.{}", name, member), ty))
+            .collect(),
+    }
+}
+
+///
+/// Converts a literal from a `#[field(default = ...)]` attribute into a value of the given type.
+/// String literals are parsed for IP fields, and anything else is left for the scheme to typecheck.
+///
+pub fn default_value<V: Into<LhsValue<'static>>>(value: V, ty: Type) -> LhsValue<'static> {
+    let value = value.into();
+    if let (Type::Ip, LhsValue::Bytes(bytes)) = (ty, &value) {
+        if let Some(ip) = std::str::from_utf8(bytes).ok().and_then(|s| s.parse().ok()) {
+            return LhsValue::Ip(ip);
+        }
+    }
+    value
 }
 
 //TODO could be part of the `declare_types` macro?
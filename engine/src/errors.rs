@@ -1,8 +1,8 @@
 use super::filter::SchemeMismatchError;
-use crate::TypeMismatchError;
+use crate::SetFieldValueError;
 
 #[derive(Debug)]
 pub enum Error {
     SchemaMismatch(SchemeMismatchError),
-    TypeMismatchError(TypeMismatchError)
+    SetFieldValueError(SetFieldValueError)
 }
\ No newline at end of file
@@ -1,8 +1,26 @@
 use crate::{
-    scheme::{Field, Scheme},
-    types::{GetType, LhsValue, TypeMismatchError},
+    scheme::{Field, Scheme, UnknownFieldError},
+    types::{GetType, LhsValue, RawValue, TypeMismatchError},
 };
-use std::ops::Add;
+use failure::Fail;
+use serde::{
+    de::{self, DeserializeSeed, Deserializer, MapAccess, Visitor},
+    Deserialize,
+};
+use std::{fmt, ops::Add};
+
+/// An error that occurs when setting the value of a field in an
+/// [`ExecutionContext`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum SetFieldValueError {
+    /// The value isn't of the type of the field.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+
+    /// The field is virtual, so its value is computed from other fields.
+    #[fail(display = "cannot set value of virtual field {}", _0)]
+    VirtualField(String),
+}
 
 /// An execution context stores an associated [`Scheme`](struct@Scheme) and a
 /// set of runtime values to execute [`Filter`](::Filter) against.
@@ -12,6 +30,8 @@ use std::ops::Add;
 pub struct ExecutionContext<'e> {
     scheme: &'e Scheme,
     values: Box<[Option<LhsValue<'e>>]>,
+    // Whether each value was set explicitly rather than taken from defaults.
+    explicit: Box<[bool]>,
 }
 
 /// Combines two executionContexts. `rhs` overwrites `self` so it is not really associative, maybe Add is not the best to use
@@ -25,13 +45,13 @@ impl<'s> Add for ExecutionContext<'s> {
         let mut new = ExecutionContext::new(self.scheme);
 
         for i in 0..values {
-            if let Some(v) = (&mut self.values[i]).take() {
-                new.values[i] = Some(v)
-            }
-
-            if let Some(v) = (&mut rhs.values[i]).take() {
-                new.values[i] = Some(v)
+            // Defaults of `rhs` shouldn't overwrite values set in `self`.
+            if rhs.explicit[i] || !self.explicit[i] {
+                new.values[i] = rhs.values[i].take();
+            } else {
+                new.values[i] = self.values[i].take();
             }
+            new.explicit[i] = self.explicit[i] || rhs.explicit[i];
         }
         new
     }
@@ -41,10 +61,16 @@ impl<'e> ExecutionContext<'e> {
     /// Creates an execution context associated with a given scheme.
     ///
     /// This scheme will be used for resolving any field names and indices.
+    /// Fields with [default values](::Scheme::set_field_default) start with
+    /// those, and all other fields start unset.
     pub fn new<'s: 'e>(scheme: &'s Scheme) -> Self {
+        let count = scheme.get_field_count();
         ExecutionContext {
             scheme,
-            values: vec![None; scheme.get_field_count()].into(),
+            values: (0..count)
+                .map(|index| scheme.get_default(index).map(LhsValue::as_ref))
+                .collect(),
+            explicit: vec![false; count].into(),
         }
     }
 
@@ -64,12 +90,18 @@ impl<'e> ExecutionContext<'e> {
     }
 
     /// Sets a runtime value for a given field name.
+    ///
+    /// Fails for virtual fields, since they are computed from other fields
+    /// instead.
     pub fn set_field_value<'v: 'e, V: Into<LhsValue<'v>>>(
         &mut self,
         name: &str,
         value: V,
-    ) -> Result<(), TypeMismatchError> {
+    ) -> Result<(), SetFieldValueError> {
         let field = self.scheme.get_field_index(name).unwrap();
+        if field.is_virtual() {
+            return Err(SetFieldValueError::VirtualField(name.to_owned()));
+        }
         let value = value.into();
 
         let field_type = field.get_type();
@@ -77,13 +109,108 @@ impl<'e> ExecutionContext<'e> {
 
         if field_type == value_type {
             self.values[field.index()] = Some(value);
+            self.explicit[field.index()] = true;
             Ok(())
         } else {
-            Err(TypeMismatchError {
+            Err(SetFieldValueError::TypeMismatch(TypeMismatchError {
                 expected: field_type,
                 actual: value_type,
-            })
+            }))
+        }
+    }
+
+    /// Sets runtime values for all fields nested in an object at once.
+    ///
+    /// The value is deserialized as a map of member names to their values,
+    /// with nested maps for nested objects, so it can come e.g. from a JSON
+    /// document. Members set to `null` are left as they were. A plain field
+    /// name can be used as well, in which case the value is set directly.
+    pub fn set_object_value<'de, D: Deserializer<'de>>(
+        &mut self,
+        name: &str,
+        value: D,
+    ) -> Result<(), D::Error> {
+        ObjectValueSeed { ctx: self, name }.deserialize(value)
+    }
+
+    /// Returns whether a value was set for a given field name with
+    /// [`ExecutionContext::set_field_value`], as opposed to being left unset
+    /// or taken from the field's default value.
+    pub fn is_field_set(&self, name: &str) -> Result<bool, UnknownFieldError> {
+        let field = self.scheme.get_field_index(name)?;
+        Ok(self.explicit[field.index()])
+    }
+}
+
+struct ObjectValueSeed<'c, 'e, 'n> {
+    ctx: &'c mut ExecutionContext<'e>,
+    name: &'n str,
+}
+
+impl<'c, 'e, 'n, 'de> DeserializeSeed<'de> for ObjectValueSeed<'c, 'e, 'n> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, de: D) -> Result<(), D::Error> {
+        if self.ctx.scheme.get_object_members(self.name).is_some() {
+            return de.deserialize_option(self);
+        }
+
+        let field = self
+            .ctx
+            .scheme
+            .get_field_index(self.name)
+            .map_err(|err| de::Error::custom(format!("{} {}", err, self.name)))?;
+        if field.is_virtual() {
+            return Err(de::Error::custom(format!(
+                "cannot set value of virtual field {}",
+                self.name
+            )));
+        }
+        if let Some(value) = Option::<RawValue>::deserialize(de)? {
+            let value = value
+                .into_typed(field.get_type())
+                .map_err(|err| de::Error::custom(format!("{} for field {}", err, self.name)))?;
+            self.ctx.values[field.index()] = Some(value);
+            self.ctx.explicit[field.index()] = true;
+        }
+        Ok(())
+    }
+}
+
+impl<'c, 'e, 'n, 'de> Visitor<'de> for ObjectValueSeed<'c, 'e, 'n> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a map of members of {}", self.name)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, de: D) -> Result<(), D::Error> {
+        de.deserialize_map(self)
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<(), M::Error> {
+        let members = self.ctx.scheme.get_object_members(self.name).unwrap();
+        while let Some(member) = map.next_key::<String>()? {
+            if !members.contains(&member) {
+                return Err(de::Error::custom(format!(
+                    "unknown field {}.{}",
+                    self.name, member
+                )));
+            }
+            map.next_value_seed(ObjectValueSeed {
+                ctx: self.ctx,
+                name: &format!("{}.{}", self.name, member),
+            })?;
         }
+        Ok(())
     }
 }
 
@@ -97,10 +224,122 @@ fn test_field_value_type_mismatch() {
 
     assert_eq!(
         ctx.set_field_value("foo", LhsValue::Bool(false)),
-        Err(TypeMismatchError {
+        Err(SetFieldValueError::TypeMismatch(TypeMismatchError {
             expected: Type::Int,
             actual: Type::Bool
-        })
+        }))
     );
 }
 
+#[test]
+fn test_default_values() {
+    let mut scheme = Scheme! { foo: Int, bar: Bytes, baz: Bool };
+    scheme.set_field_default("foo", LhsValue::Int(42)).unwrap();
+    scheme.set_field_default("bar", "default".into()).unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    let foo = scheme.get_field_index("foo").unwrap();
+    let bar = scheme.get_field_index("bar").unwrap();
+    let baz = scheme.get_field_index("baz").unwrap();
+
+    assert_eq!(ctx.get_field_value(foo), Some(LhsValue::Int(42)));
+    assert_eq!(ctx.get_field_value(bar), Some(LhsValue::from("default")));
+    assert_eq!(ctx.get_field_value(baz), None);
+    assert_eq!(ctx.is_field_set("foo"), Ok(false));
+    assert_eq!(ctx.is_field_set("baz"), Ok(false));
+    assert_eq!(ctx.is_field_set("qux"), Err(UnknownFieldError));
+
+    ctx.set_field_value("foo", 7).unwrap();
+    assert_eq!(ctx.is_field_set("foo"), Ok(true));
+    assert_eq!(ctx.is_field_set("bar"), Ok(false));
+
+    let mut other = ExecutionContext::new(&scheme);
+    other.set_field_value("bar", "explicit").unwrap();
+    other.set_field_value("baz", true).unwrap();
+
+    let ctx = ctx + other;
+    assert_eq!(ctx.get_field_value(foo), Some(LhsValue::Int(7)));
+    assert_eq!(ctx.get_field_value(bar), Some(LhsValue::from("explicit")));
+    assert_eq!(ctx.get_field_value(baz), Some(LhsValue::Bool(true)));
+    assert_eq!(ctx.is_field_set("foo"), Ok(true));
+    assert_eq!(ctx.is_field_set("bar"), Ok(true));
+}
+
+#[test]
+fn test_object_value() {
+    use crate::types::{FieldType, Type};
+
+    let mut scheme = Scheme! { tcp.port: Int };
+    let uri: FieldType = vec![("path", Type::Bytes), ("query", Type::Bytes)]
+        .into_iter()
+        .collect();
+    scheme
+        .add_nested_field(
+            "http".into(),
+            vec![
+                ("host", Type::Bytes.into()),
+                ("uri", uri),
+                ("src", Type::Ip.into()),
+            ]
+            .into_iter()
+            .collect(),
+        )
+        .unwrap();
+    scheme
+        .add_virtual_field("http.is_root".into(), r#"http.uri.path == "/""#.into())
+        .unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_object_value(
+        "http",
+        serde_json::json!({
+            "host": "example.org",
+            "uri": { "path": "/", "query": null },
+            "src": "10.0.0.1"
+        }),
+    )
+    .unwrap();
+    ctx.set_object_value("tcp.port", serde_json::json!(443))
+        .unwrap();
+
+    let value = |name| ctx.get_field_value(scheme.get_field_index(name).unwrap());
+    assert_eq!(value("http.host"), Some(LhsValue::from("example.org")));
+    assert_eq!(value("http.uri.path"), Some(LhsValue::from("/")));
+    assert_eq!(value("http.uri.query"), None);
+    assert_eq!(
+        value("http.src"),
+        Some(LhsValue::Ip("10.0.0.1".parse().unwrap()))
+    );
+    assert_eq!(value("tcp.port"), Some(LhsValue::Int(443)));
+    assert_eq!(ctx.is_field_set("http.uri.path"), Ok(true));
+    assert_eq!(ctx.is_field_set("http.uri.query"), Ok(false));
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    let err = |ctx: &mut ExecutionContext<'_>, name, value: serde_json::Value| {
+        ctx.set_object_value(name, value).unwrap_err().to_string()
+    };
+    assert_eq!(
+        err(&mut ctx, "http", serde_json::json!({ "path": "/" })),
+        "unknown field http.path"
+    );
+    assert_eq!(
+        err(
+            &mut ctx,
+            "http",
+            serde_json::json!({ "uri": { "path": 1 } })
+        ),
+        "value is not of type Bytes for field http.uri.path"
+    );
+    assert_eq!(
+        err(&mut ctx, "http", serde_json::json!({ "uri": "/" })),
+        "invalid type: string \"/\", expected a map of members of http.uri"
+    );
+    assert_eq!(
+        err(&mut ctx, "http.is_root", serde_json::json!(true)),
+        "cannot set value of virtual field http.is_root"
+    );
+    assert_eq!(
+        err(&mut ctx, "tcp", serde_json::json!({})),
+        "unknown field tcp"
+    );
+}
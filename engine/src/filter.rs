@@ -1,5 +1,10 @@
-use crate::{execution_context::ExecutionContext, scheme::Scheme};
+use crate::{
+    ast::{CallCache, SharedExpr},
+    execution_context::ExecutionContext,
+    scheme::Scheme,
+};
 use failure::Fail;
+use std::{ptr, sync::Arc};
 
 /// An error that occurs if filter and provided [`ExecutionContext`] have
 /// different [schemes](struct@Scheme).
@@ -13,17 +18,49 @@ pub struct SchemeMismatchError;
 // under the hood propagates field values to its leafs by recursively calling
 // their `execute` methods and aggregating results into a single boolean value
 // as recursion unwinds.
-pub(crate) struct CompiledExpr<'s>(Box<dyn 's + Fn(&ExecutionContext) -> Option<bool> + Sync + Send>);
+//
+// Expressions that don't depend on the context are folded into constants
+// during compilation, which allows their parents to simplify themselves too.
+//
+// Results of function calls that occur in several expressions, as well as
+// values of virtual fields, are shared between them through a `CallCache`
+// that lives for a single execution.
+pub(crate) enum CompiledExpr<'s> {
+    Constant(Option<bool>),
+    Dynamic(Box<CompiledClosure<'s>>),
+}
+
+type CompiledClosure<'s> =
+    dyn 's + for<'e> Fn(&'e ExecutionContext<'e>, &'e CallCache<'e>) -> Option<bool> + Sync + Send;
 
 impl<'s> CompiledExpr<'s> {
     /// Creates a compiled expression IR from a generic closure.
-    pub(crate) fn new(closure: impl 's + Fn(&ExecutionContext) -> Option<bool> + Sync + Send) -> Self {
-        CompiledExpr(Box::new(closure))
+    pub(crate) fn new(
+        closure: impl 's
+            + for<'e> Fn(&'e ExecutionContext<'e>, &'e CallCache<'e>) -> Option<bool>
+            + Sync
+            + Send,
+    ) -> Self {
+        CompiledExpr::Dynamic(Box::new(closure))
     }
 
-    /// Executes a filter against a provided context with values.
+    /// Executes a filter against a provided context with values, using a
+    /// cache for shared function calls.
+    pub fn execute_with_cache<'e>(
+        &self,
+        ctx: &'e ExecutionContext<'e>,
+        cache: &'e CallCache<'e>,
+    ) -> Option<bool> {
+        match self {
+            CompiledExpr::Constant(value) => *value,
+            CompiledExpr::Dynamic(closure) => closure(ctx, cache),
+        }
+    }
+
+    /// Executes an expression that doesn't share any function calls.
+    #[cfg(test)]
     pub fn execute(&self, ctx: &ExecutionContext) -> Option<bool> {
-        self.0(ctx)
+        self.execute_with_cache(ctx, &CallCache::new(&[]))
     }
 }
 
@@ -44,30 +81,110 @@ impl<'s> CompiledExpr<'s> {
 /// and execution.
 pub struct Filter<'s> {
     root_expr: CompiledExpr<'s>,
+    shared_exprs: Box<[SharedExpr<'s>]>,
     scheme: &'s Scheme,
 }
 
 impl<'s> Filter<'s> {
     /// Creates a compiled expression IR from a generic closure.
-    pub(crate) fn new(root_expr: CompiledExpr<'s>, scheme: &'s Scheme) -> Self {
-        Filter { root_expr, scheme }
+    pub(crate) fn new(
+        root_expr: CompiledExpr<'s>,
+        shared_exprs: Box<[SharedExpr<'s>]>,
+        scheme: &'s Scheme,
+    ) -> Self {
+        Filter {
+            root_expr,
+            shared_exprs,
+            scheme,
+        }
     }
 
     /// Executes a filter against a provided context with values.
     pub fn execute(&self, ctx: &ExecutionContext<'s>) -> Result<Option<bool>, SchemeMismatchError> {
         if self.scheme == ctx.scheme() {
-            Ok(self.root_expr.execute(ctx))
+            let cache = CallCache::new(&self.shared_exprs);
+            Ok(self.root_expr.execute_with_cache(ctx, &cache))
         } else {
             Err(SchemeMismatchError)
         }
     }
 }
 
+/// A compiled filter that owns a shared reference to its
+/// [`Scheme`](struct@Scheme).
+///
+/// This is an owned counterpart of [`Filter`] that can be cached or stored
+/// in long-lived structures. It is produced by
+/// [`OwnedFilterAst::compile`](::OwnedFilterAst::compile) and can be
+/// executed against any [`ExecutionContext`] created from the same scheme.
+pub struct OwnedFilter {
+    // Borrows from the scheme below, so must be declared (and dropped) first.
+    filter: Filter<'static>,
+    scheme: Arc<Scheme>,
+}
+
+impl OwnedFilter {
+    /// Creates an owned filter from one that borrows the given scheme.
+    ///
+    /// This is unsafe because `filter` must have been created from the very
+    /// same `scheme` allocation rather than from a `'static` one.
+    pub(crate) unsafe fn new(filter: Filter<'static>, scheme: Arc<Scheme>) -> Self {
+        debug_assert!(ptr::eq(filter.scheme, &*scheme));
+        OwnedFilter { filter, scheme }
+    }
+
+    /// Returns the borrowed form of this filter.
+    pub fn as_filter(&self) -> &Filter<'_> {
+        &self.filter
+    }
+
+    /// Returns the associated scheme.
+    pub fn scheme(&self) -> &Arc<Scheme> {
+        &self.scheme
+    }
+
+    /// Executes a filter against a provided context with values.
+    pub fn execute(&self, ctx: &ExecutionContext<'_>) -> Result<Option<bool>, SchemeMismatchError> {
+        self.as_filter().execute(ctx)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Filter, SchemeMismatchError};
-    use crate::execution_context::ExecutionContext;
-    use crate::LhsValue;
+    use super::{Filter, OwnedFilter, SchemeMismatchError};
+    use crate::execution_context::{ExecutionContext, SetFieldValueError};
+    use crate::{
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::Type,
+    };
+    use crate::{LhsValue, OwnedFilterAst};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Returns a `lower(bytes)` function which counts its calls in `calls`.
+    fn counted_lower(calls: &Arc<AtomicUsize>, arg_kind: FunctionArgKind, pure: bool) -> Function {
+        let calls = Arc::clone(calls);
+        let implementation = FunctionImpl::new(move |args: FunctionArgs<'_, '_>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            match args.next() {
+                Some(LhsValue::Bytes(bytes)) => bytes.to_ascii_lowercase().into(),
+                arg => panic!("Invalid type: expected Bytes, got {:?}", arg),
+            }
+        });
+        Function {
+            params: vec![FunctionParam {
+                arg_kind,
+                val_type: Type::Bytes,
+                generic: false,
+            }],
+            opt_params: vec![],
+            return_type: Type::Bytes,
+            pure,
+            implementation,
+        }
+    }
 
     #[test]
     fn test_scheme_mismatch() {
@@ -123,6 +240,251 @@ mod tests {
         assert_eq!(filter.execute(&ctx), Ok(Some(true)));
     }
 
+    #[test]
+    fn test_constant_folding() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+        scheme
+            .add_function(
+                "lower".into(),
+                counted_lower(&calls, FunctionArgKind::Literal, true),
+            )
+            .unwrap();
+        scheme
+            .add_function(
+                "lower_impure".into(),
+                counted_lower(&calls, FunctionArgKind::Literal, false),
+            )
+            .unwrap();
+
+        let filters = [
+            r#"lower("ABC") == "abc""#,
+            r#"lower("ABC") contains "d""#,
+            r#"not lower("ABC") in {"abc" "def"}"#,
+            r#"lower("ABC") == "abc" && tcp.port == 80"#,
+            r#"lower("ABC") != "abc" && tcp.port == 80"#,
+            r#"lower("ABC") == "abc" || tcp.port == 80"#,
+            r#"lower("ABC") != "abc" || tcp.port == 80"#,
+            r#"lower("ABC") == "abc" ^^ tcp.port == 80 ^^ lower("X") == "x""#,
+            r#"(lower("ABC") == "abc" or http.host == "abc") and tcp.port != 80"#,
+            r#"!(lower("ABC") == "abc" xor lower("ABC") == "abc")"#,
+        ];
+
+        let mut contexts = Vec::new();
+        for &port in &[None, Some(80), Some(443)] {
+            for &host in &[None, Some("abc"), Some("ABC")] {
+                let mut ctx = ExecutionContext::new(&scheme);
+                if let Some(port) = port {
+                    ctx.set_field_value("tcp.port", port).unwrap();
+                }
+                if let Some(host) = host {
+                    ctx.set_field_value("http.host", host).unwrap();
+                }
+                contexts.push(ctx);
+            }
+        }
+
+        for filter in filters.iter() {
+            let reference = scheme
+                .parse(&filter.replace("lower(", "lower_impure("))
+                .unwrap()
+                .compile();
+
+            let folded_calls = calls.load(Ordering::SeqCst);
+            let folded = scheme.parse(filter).unwrap().compile();
+            let folded_calls = calls.load(Ordering::SeqCst) - folded_calls;
+            assert_eq!(folded_calls, filter.matches("lower(").count(), "{}", filter);
+
+            for ctx in &contexts {
+                let expected = reference.execute(ctx);
+                let before = calls.load(Ordering::SeqCst);
+                assert_eq!(folded.execute(ctx), expected, "{}", filter);
+                assert_eq!(calls.load(Ordering::SeqCst), before, "{}", filter);
+            }
+        }
+    }
+
+    #[test]
+    fn test_shared_calls() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+        scheme
+            .add_function(
+                "lower".into(),
+                counted_lower(&calls, FunctionArgKind::Field, true),
+            )
+            .unwrap();
+        scheme
+            .add_function(
+                "lower_impure".into(),
+                counted_lower(&calls, FunctionArgKind::Field, false),
+            )
+            .unwrap();
+
+        // filters with the number of calls made by each execution
+        let filters = [
+            (r#"lower(http.host) == "abc""#, 1),
+            (
+                r#"lower(http.host) == "x" || lower(http.host) contains "b" || tcp.port == 80"#,
+                1,
+            ),
+            (
+                r#"tcp.port == 80 && (lower(http.host) in {"abc" "def"} xor not lower(http.host) == "abc")"#,
+                1,
+            ),
+            (
+                r#"lower(lower(http.host)) == "abc" && lower(http.host) != """#,
+                2,
+            ),
+            (
+                r#"lower(lower(http.host)) != "x" && lower(lower(http.host)) contains "b""#,
+                2,
+            ),
+        ];
+
+        for &(filter, expected_calls) in filters.iter() {
+            let reference = scheme
+                .parse(&filter.replace("lower(", "lower_impure("))
+                .unwrap()
+                .compile();
+
+            let shared = scheme.parse(filter).unwrap().compile();
+
+            for &host in &[None, Some("abc"), Some("ABC"), Some("xyz")] {
+                let mut ctx = ExecutionContext::new(&scheme);
+                ctx.set_field_value("tcp.port", 80).unwrap();
+                if let Some(host) = host {
+                    ctx.set_field_value("http.host", host).unwrap();
+                }
+
+                let expected = reference.execute(&ctx);
+
+                let before = calls.load(Ordering::SeqCst);
+                assert_eq!(shared.execute(&ctx), expected, "{}", filter);
+                assert_eq!(
+                    calls.load(Ordering::SeqCst) - before,
+                    if host.is_some() { expected_calls } else { 0 },
+                    "{} with {:?}",
+                    filter,
+                    host
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_virtual_fields() {
+        use std::net::IpAddr;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut scheme = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+        scheme
+            .add_function(
+                "lower".into(),
+                counted_lower(&calls, FunctionArgKind::Field, true),
+            )
+            .unwrap();
+        scheme
+            .add_virtual_fields(vec![
+                (
+                    "http.is_example".into(),
+                    r#"http.lower_host == "example.org" || http.lower_host contains ".example.org""#
+                        .into(),
+                ),
+                ("http.lower_host".into(), "lower(http.host)".into()),
+                ("ip.src_net".into(), "ip.src/8".into()),
+            ])
+            .unwrap();
+
+        // filters with the number of calls made by each execution
+        let filters = [
+            (r#"http.lower_host == "www.example.org""#, 1),
+            (r#"http.is_example && http.lower_host != "example.org""#, 1),
+            ("tcp.port == 443 && http.is_example", 0),
+            ("tcp.port == 80 && not http.is_example", 1),
+            ("ip.src_net == 10.0.0.0", 0),
+        ];
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("http.host", "WWW.Example.org").unwrap();
+        ctx.set_field_value("ip.src", IpAddr::from([10, 1, 2, 3]))
+            .unwrap();
+        ctx.set_field_value("tcp.port", 80).unwrap();
+
+        let results = [Some(true), Some(true), Some(false), Some(false), Some(true)];
+
+        for (&(filter, expected_calls), &expected) in filters.iter().zip(results.iter()) {
+            let filter_ast = scheme.parse(filter).unwrap();
+            let filter_ast_calls = calls.load(Ordering::SeqCst);
+            let compiled = filter_ast.compile();
+            assert_eq!(calls.load(Ordering::SeqCst), filter_ast_calls, "{}", filter);
+
+            let before = calls.load(Ordering::SeqCst);
+            assert_eq!(compiled.execute(&ctx), Ok(expected), "{}", filter);
+            assert_eq!(
+                calls.load(Ordering::SeqCst) - before,
+                expected_calls,
+                "{}",
+                filter
+            );
+        }
+
+        let ast = scheme.parse("http.is_example").unwrap();
+        assert_eq!(ast.uses("http.host"), Ok(true));
+        assert_eq!(ast.uses("http.lower_host"), Ok(true));
+        assert_eq!(ast.uses("tcp.port"), Ok(false));
+    }
+
+    #[test]
+    fn test_set_virtual_field_value() {
+        let mut scheme = Scheme! { http.host: Bytes };
+        scheme
+            .add_virtual_field("http.lower_host".into(), "http.host".into())
+            .unwrap();
+        let mut ctx = ExecutionContext::new(&scheme);
+        assert_eq!(
+            ctx.set_field_value("http.lower_host", "example.org"),
+            Err(SetFieldValueError::VirtualField("http.lower_host".into()))
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+        scheme
+            .add_function(
+                "lower_impure".into(),
+                counted_lower(&calls, FunctionArgKind::Field, false),
+            )
+            .unwrap();
+        scheme
+            .add_macro(
+                "example".into(),
+                r#"lower_impure(http.host) == "example.org""#.into(),
+            )
+            .unwrap();
+
+        let filter = scheme
+            .parse("$example || (tcp.port == 80 && not $example)")
+            .unwrap()
+            .compile();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("http.host", "Example.com").unwrap();
+        ctx.set_field_value("tcp.port", 80).unwrap();
+
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
     #[test]
     fn ensure_send_and_sync() {
         fn is_send<T: Send>() {}
@@ -131,4 +493,48 @@ mod tests {
         is_send::<Filter>();
         is_sync::<Filter>();
     }
+
+    #[test]
+    fn ensure_owned_static_send_and_sync() {
+        fn is_owned<T: 'static + Send + Sync>() {}
+
+        is_owned::<OwnedFilterAst>();
+        is_owned::<OwnedFilter>();
+    }
+
+    #[test]
+    fn test_owned_filter() {
+        use std::{sync::Arc, thread};
+
+        struct Cache {
+            filters: Vec<OwnedFilter>,
+        }
+
+        let scheme = Arc::new(Scheme! { foo: Int, bar: Int });
+
+        let ast = OwnedFilterAst::parse(Arc::clone(&scheme), "bar == 41").unwrap();
+        assert_eq!(ast.as_ast(), &scheme.parse("bar == 41").unwrap());
+        assert_eq!(ast.uses("bar"), Ok(true));
+        assert_json!(ast, { "lhs": "bar", "op": "Equal", "rhs": 41 });
+
+        let cache = Cache {
+            filters: vec![ast.compile()],
+        };
+        drop(scheme);
+
+        let cache = thread::spawn(move || {
+            let filter = &cache.filters[0];
+            let mut ctx = ExecutionContext::new(filter.scheme());
+            ctx.set_field_value("bar", LhsValue::Int(41)).unwrap();
+            assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+            assert_eq!(filter.as_filter().execute(&ctx), Ok(Some(true)));
+            cache
+        })
+        .join()
+        .unwrap();
+
+        let other_scheme = Scheme! { foo: Int, bar: Int };
+        let ctx = ExecutionContext::new(&other_scheme);
+        assert_eq!(cache.filters[0].execute(&ctx), Err(SchemeMismatchError));
+    }
 }
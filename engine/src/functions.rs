@@ -1,45 +1,118 @@
-use crate::types::{LhsValue, Type};
-use std::fmt;
+use crate::types::{GetType, LhsValue, RawValue, Type};
+use serde::{
+    de::{self, Deserializer},
+    ser::{SerializeStruct, Serializer},
+    Deserialize, Serialize,
+};
+use std::{fmt, sync::Arc};
 
 /// An iterator over function arguments as [`LhsValue`]s.
 pub type FunctionArgs<'i, 'a> = &'i mut dyn Iterator<Item = LhsValue<'a>>;
 
-type FunctionPtr = for<'a> fn(FunctionArgs<'_, 'a>) -> LhsValue<'a>;
+type FunctionClosure = dyn for<'a> Fn(FunctionArgs<'_, 'a>) -> Option<LhsValue<'a>> + Send + Sync;
 
-/// Wrapper around a function pointer providing the runtime implemetation.
+type LiteralCheck = dyn Fn(usize, &LhsValue<'_>) -> Result<(), String> + Send + Sync;
+
+/// Wrapper around a function pointer or a closure providing the runtime
+/// implemetation.
+///
+/// Closures allow functions to carry their own state, such as lookup tables
+/// loaded when the scheme is built.
 #[derive(Clone)]
-pub struct FunctionImpl(FunctionPtr);
+pub struct FunctionImpl {
+    func: Arc<FunctionClosure>,
+    check_literal: Option<Arc<LiteralCheck>>,
+}
 
 impl FunctionImpl {
-    /// Creates a new wrapper around a function pointer.
-    pub fn new(func: FunctionPtr) -> Self {
-        Self(func)
+    /// Creates a new wrapper around a function pointer or a closure.
+    pub fn new<F>(func: F) -> Self
+    where
+        F: 'static + for<'a> Fn(FunctionArgs<'_, 'a>) -> LhsValue<'a> + Send + Sync,
+    {
+        Self::new_optional(move |args| Some(func(args)))
+    }
+
+    /// Creates a new wrapper around a function pointer or a closure which
+    /// might have no result for some arguments, e.g. if they are out of
+    /// range.
+    ///
+    /// Such a call evaluates to a missing value, same as a field which is not
+    /// set.
+    pub fn new_optional<F>(func: F) -> Self
+    where
+        F: 'static + for<'a> Fn(FunctionArgs<'_, 'a>) -> Option<LhsValue<'a>> + Send + Sync,
+    {
+        Self {
+            func: Arc::new(func),
+            check_literal: None,
+        }
+    }
+
+    /// Adds a check of literal arguments, which runs when a filter is parsed,
+    /// so that invalid values such as a negative number of buckets are
+    /// reported as parse errors.
+    ///
+    /// The check is given the index of each literal argument along with its
+    /// value, and describes what's wrong with the value if it's invalid.
+    pub fn check_literals<F>(mut self, check: F) -> Self
+    where
+        F: 'static + Fn(usize, &LhsValue<'_>) -> Result<(), String> + Send + Sync,
+    {
+        self.check_literal = Some(Arc::new(check));
+        self
+    }
+
+    pub(crate) fn check_literal(&self, index: usize, value: &LhsValue<'_>) -> Result<(), String> {
+        match &self.check_literal {
+            Some(check) => check(index, value),
+            None => Ok(()),
+        }
     }
 
-    /// Calls the wrapped function pointer.
+    /// Calls the wrapped function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the function has no result for these arguments, see
+    /// [`FunctionImpl::try_execute`].
     pub fn execute<'a>(&self, args: impl IntoIterator<Item = LhsValue<'a>>) -> LhsValue<'a> {
-        (self.0)(&mut args.into_iter())
+        self.try_execute(args)
+            .expect("function has no result for these arguments")
+    }
+
+    /// Calls the wrapped function, returning `None` if it has no result for
+    /// these arguments.
+    pub fn try_execute<'a>(
+        &self,
+        args: impl IntoIterator<Item = LhsValue<'a>>,
+    ) -> Option<LhsValue<'a>> {
+        (self.func)(&mut args.into_iter())
+    }
+
+    fn as_ptr(&self) -> *const () {
+        &*self.func as *const FunctionClosure as *const ()
     }
 }
 
 impl fmt::Debug for FunctionImpl {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_tuple("FunctionImpl")
-            .field(&(self.0 as *const ()))
+        fmt.debug_struct("FunctionImpl")
+            .field("func", &self.as_ptr())
             .finish()
     }
 }
 
 impl PartialEq for FunctionImpl {
     fn eq(&self, other: &FunctionImpl) -> bool {
-        self.0 as *const () == other.0 as *const ()
+        self.as_ptr() == other.as_ptr()
     }
 }
 
 impl Eq for FunctionImpl {}
 
 /// Defines what kind of argument a function expects.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum FunctionArgKind {
     /// Allow only literal as argument.
     Literal,
@@ -48,12 +121,16 @@ pub enum FunctionArgKind {
 }
 
 /// Defines a mandatory function argument.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct FunctionParam {
     /// How the argument can be specified when calling a function.
     pub arg_kind: FunctionArgKind,
     /// The type of its associated value.
     pub val_type: Type,
+    /// Whether field arguments of any type are accepted, as by hashing
+    /// functions, rather than only ones of `val_type`.
+    #[serde(default)]
+    pub generic: bool,
 }
 
 /// Defines an optional function argument.
@@ -65,8 +142,51 @@ pub struct FunctionOptParam {
     pub default_value: LhsValue<'static>,
 }
 
+// The type of the default value is stored alongside it, since values of
+// different types can share the same JSON representation (e.g. IPs and
+// strings).
+impl Serialize for FunctionOptParam {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let mut state = ser.serialize_struct("FunctionOptParam", 3)?;
+        state.serialize_field("arg_kind", &self.arg_kind)?;
+        state.serialize_field("val_type", &self.default_value.get_type())?;
+        state.serialize_field("default_value", &self.default_value)?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct RawOptParam {
+    arg_kind: FunctionArgKind,
+    val_type: Type,
+    default_value: RawValue,
+}
+
+impl<'de> Deserialize<'de> for FunctionOptParam {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let RawOptParam {
+            arg_kind,
+            val_type,
+            default_value,
+        } = RawOptParam::deserialize(de)?;
+
+        let default_value = default_value
+            .into_typed(val_type)
+            .map_err(de::Error::custom)?;
+
+        Ok(FunctionOptParam {
+            arg_kind,
+            default_value,
+        })
+    }
+}
+
 /// Defines a function.
-#[derive(Debug, PartialEq, Eq, Clone)]
+///
+/// Only the signature is serialized, the implementation has to be provided
+/// by a [`FunctionRegistry`](::FunctionRegistry) when deserializing a
+/// [`Scheme`](::Scheme).
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 pub struct Function {
     /// List of mandatory arguments.
     pub params: Vec<FunctionParam>,
@@ -74,6 +194,31 @@ pub struct Function {
     pub opt_params: Vec<FunctionOptParam>,
     /// Function return type.
     pub return_type: Type,
+    /// Whether the function always returns the same result for the same
+    /// arguments and has no side effects.
+    ///
+    /// Calls to pure functions whose arguments are all known at compile time,
+    /// such as `lower("ABC")`, are evaluated once when the filter is compiled
+    /// instead of on every execution.
+    pub pure: bool,
     /// Actual implementation that will be called at runtime.
+    #[serde(skip)]
     pub implementation: FunctionImpl,
 }
+
+impl Function {
+    /// Checks whether two functions take the same arguments, return the
+    /// same type and are equally pure, regardless of their implementations.
+    pub(crate) fn has_signature(
+        &self,
+        params: &[FunctionParam],
+        opt_params: &[FunctionOptParam],
+        return_type: Type,
+        pure: bool,
+    ) -> bool {
+        self.params == params
+            && self.opt_params == opt_params
+            && self.return_type == return_type
+            && self.pure == pure
+    }
+}
@@ -1,10 +1,12 @@
 use crate::{
-    rhs_types::RegexError,
-    scheme::{UnknownFieldError, UnknownFunctionError},
+    rhs_types::{Bytes, RegexError},
+    scheme::{ObjectFieldError, UnknownFieldError, UnknownFunctionError, UnknownMacroError},
+    suggest::Suggestions,
     types::{Type, TypeMismatchError},
 };
 use cidr::NetworkParseError;
 use failure::Fail;
+use serde::Serialize;
 use std::num::ParseIntError;
 
 #[derive(Debug, PartialEq, Fail)]
@@ -28,12 +30,24 @@ pub enum LexErrorKind {
     #[fail(display = "{}", _0)]
     ParseRegex(#[cause] RegexError),
 
-    #[fail(display = "expected \", xHH or OOO after \\")]
+    #[fail(display = "expected \", \\, xHH, OOO or u{{...}} after \\")]
     InvalidCharacterEscape,
 
+    #[fail(display = "expected a Unicode scalar value of at most 6 hex digits")]
+    InvalidUnicodeEscape,
+
     #[fail(display = "could not find an ending quote")]
     MissingEndingQuote,
 
+    #[fail(display = "could not find an ending slash")]
+    MissingEndingSlash,
+
+    #[fail(
+        display = "invalid regex flag {:?}, expected one of i, m, s, U or x",
+        _0
+    )]
+    InvalidRegexFlag(char),
+
     #[fail(display = "expected {} {}s, but found {}", expected, name, actual)]
     CountMismatch {
         name: &'static str,
@@ -41,11 +55,17 @@ pub enum LexErrorKind {
         expected: usize,
     },
 
+    #[fail(display = "{}{}", _0, _1)]
+    UnknownField(#[cause] UnknownFieldError, Suggestions),
+
     #[fail(display = "{}", _0)]
-    UnknownField(#[cause] UnknownFieldError),
+    ObjectField(#[cause] ObjectFieldError),
+
+    #[fail(display = "{}{}", _0, _1)]
+    UnknownFunction(#[cause] UnknownFunctionError, Suggestions),
 
     #[fail(display = "{}", _0)]
-    UnknownFunction(#[cause] UnknownFunctionError),
+    UnknownMacro(#[cause] UnknownMacroError),
 
     #[fail(display = "cannot use this operation type {:?}", lhs_type)]
     UnsupportedOp { lhs_type: Type },
@@ -68,6 +88,141 @@ pub enum LexErrorKind {
         #[cause]
         mismatch: TypeMismatchError,
     },
+
+    #[fail(display = "invalid value of argument #{}: {}", index, message)]
+    InvalidArgumentValue { index: usize, message: String },
+
+    #[fail(display = "filter is longer than {} bytes", limit)]
+    InputTooLong { limit: usize },
+
+    #[fail(display = "expression is nested deeper than {} levels", limit)]
+    TooDeep { limit: usize },
+
+    #[fail(display = "set has more than {} items", limit)]
+    SetTooLarge { limit: usize },
+
+    #[fail(display = "compiled regex is bigger than {} bytes", limit)]
+    RegexTooBig { limit: usize },
+
+    #[fail(display = "function calls are nested deeper than {} levels", limit)]
+    CallsTooDeep { limit: usize },
+
+    #[fail(display = "macros expand to more than {} nodes", limit)]
+    MacroTooLarge { limit: usize },
+}
+
+/// A stable, machine-readable code of a [`ParseError`](::ParseError).
+///
+/// Codes describe what's wrong with a filter from the point of view of its
+/// author, so several internal errors can share a code. They are serialized
+/// as their names, which never change, so they can be matched on by tools,
+/// unlike error messages.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
+pub enum ParseErrorCode {
+    /// The filter ends where more was expected, e.g. `tcp.port ==`.
+    UnexpectedEnd,
+    /// The filter goes on after a complete expression, e.g.
+    /// `tcp.port == 80 80`.
+    UnexpectedInput,
+    /// A specific token, such as a parenthesis or an operator, was expected.
+    ExpectedToken,
+    /// A name of a field, function or macro was expected.
+    ExpectedName,
+    /// A number was expected.
+    ExpectedNumber,
+    /// An IP address was expected.
+    ExpectedAddress,
+    /// A number is out of range or has invalid digits.
+    InvalidNumber,
+    /// An IP address or network is invalid.
+    InvalidAddress,
+    /// A regex or one of its flags is invalid.
+    InvalidRegex,
+    /// An escape sequence in a string is invalid.
+    InvalidEscape,
+    /// A string or a regex has no ending quote or slash.
+    UnterminatedLiteral,
+    /// A field isn't registered in the scheme.
+    UnknownField,
+    /// An object is used where a field is expected.
+    ObjectField,
+    /// A function isn't registered in the scheme.
+    UnknownFunction,
+    /// A macro isn't registered in the scheme.
+    UnknownMacro,
+    /// An operator isn't supported by the type of a field.
+    UnsupportedOp,
+    /// Bounds of a range have different types.
+    IncompatibleRangeBounds,
+    /// A function is called with too few or too many arguments.
+    InvalidArgumentsCount,
+    /// An argument of a function has the wrong type or kind.
+    InvalidArgumentType,
+    /// A literal argument of a function has a value it doesn't accept.
+    InvalidArgumentValue,
+    /// The input exceeds [`ParseLimits::max_input_len`](::ParseLimits).
+    InputTooLong,
+    /// Expressions exceed [`ParseLimits::max_depth`](::ParseLimits).
+    TooDeep,
+    /// A set exceeds [`ParseLimits::max_set_len`](::ParseLimits).
+    SetTooLarge,
+    /// A regex exceeds [`ParseLimits::max_regex_size`](::ParseLimits).
+    RegexTooBig,
+    /// Function calls exceed [`ParseLimits::max_call_depth`](::ParseLimits).
+    CallsTooDeep,
+    /// Expansions of macros exceed
+    /// [`ParseLimits::max_macro_nodes`](::ParseLimits).
+    MacroTooLarge,
+}
+
+impl LexErrorKind {
+    /// Returns the code of an error which occurred at `span`.
+    pub fn code(&self, span: &str) -> ParseErrorCode {
+        match self {
+            // Tokens are expected at the rest of the input, so nothing is
+            // left of it if the filter ends too early.
+            LexErrorKind::ExpectedName(_) | LexErrorKind::ExpectedLiteral(_) if span.is_empty() => {
+                ParseErrorCode::UnexpectedEnd
+            }
+            LexErrorKind::ExpectedName(name) => match *name {
+                "digit" => ParseErrorCode::ExpectedNumber,
+                "hex digit" => ParseErrorCode::InvalidEscape,
+                "IP address character" => ParseErrorCode::ExpectedAddress,
+                "identifier character" | "function character" | "macro character" => {
+                    ParseErrorCode::ExpectedName
+                }
+                // Names of operators and other enums lexed by `lex_enum!`.
+                _ => ParseErrorCode::ExpectedToken,
+            },
+            LexErrorKind::ExpectedLiteral(_) => ParseErrorCode::ExpectedToken,
+            LexErrorKind::ParseInt { .. } => ParseErrorCode::InvalidNumber,
+            LexErrorKind::ParseNetwork(_) => ParseErrorCode::InvalidAddress,
+            LexErrorKind::ParseRegex(_) => ParseErrorCode::InvalidRegex,
+            LexErrorKind::InvalidCharacterEscape => ParseErrorCode::InvalidEscape,
+            LexErrorKind::InvalidUnicodeEscape => ParseErrorCode::InvalidEscape,
+            LexErrorKind::MissingEndingQuote => ParseErrorCode::UnterminatedLiteral,
+            LexErrorKind::MissingEndingSlash => ParseErrorCode::UnterminatedLiteral,
+            LexErrorKind::InvalidRegexFlag(_) => ParseErrorCode::InvalidRegex,
+            // Only reported when the input runs out of characters.
+            LexErrorKind::CountMismatch { .. } => ParseErrorCode::UnexpectedEnd,
+            LexErrorKind::UnknownField(..) => ParseErrorCode::UnknownField,
+            LexErrorKind::ObjectField(_) => ParseErrorCode::ObjectField,
+            LexErrorKind::UnknownFunction(..) => ParseErrorCode::UnknownFunction,
+            LexErrorKind::UnknownMacro(_) => ParseErrorCode::UnknownMacro,
+            LexErrorKind::UnsupportedOp { .. } => ParseErrorCode::UnsupportedOp,
+            LexErrorKind::IncompatibleRangeBounds => ParseErrorCode::IncompatibleRangeBounds,
+            LexErrorKind::EOF => ParseErrorCode::UnexpectedInput,
+            LexErrorKind::InvalidArgumentsCount { .. } => ParseErrorCode::InvalidArgumentsCount,
+            LexErrorKind::InvalidArgumentType { .. } => ParseErrorCode::InvalidArgumentType,
+            LexErrorKind::InvalidArgumentValue { .. } => ParseErrorCode::InvalidArgumentValue,
+            LexErrorKind::InputTooLong { .. } => ParseErrorCode::InputTooLong,
+            LexErrorKind::TooDeep { .. } => ParseErrorCode::TooDeep,
+            LexErrorKind::SetTooLarge { .. } => ParseErrorCode::SetTooLarge,
+            LexErrorKind::RegexTooBig { .. } => ParseErrorCode::RegexTooBig,
+            LexErrorKind::CallsTooDeep { .. } => ParseErrorCode::CallsTooDeep,
+            LexErrorKind::MacroTooLarge { .. } => ParseErrorCode::MacroTooLarge,
+        }
+    }
 }
 
 pub type LexError<'i> = (LexErrorKind, &'i str);
@@ -104,8 +259,87 @@ pub fn expect<'i>(input: &'i str, s: &'static str) -> Result<&'i str, LexError<'
 // for now until someone really needs them (tabs vs spaces all the way down...).
 const SPACE_CHARS: &[char] = &[' ', '\r', '\n'];
 
-pub fn skip_space(input: &str) -> &str {
-    input.trim_start_matches(SPACE_CHARS)
+/// Splits off a `# line comment` or a `/* block comment */` at the start of
+/// `input`.
+///
+/// Unterminated block comments are left in place so that lexing fails right
+/// where they start.
+pub fn lex_comment(input: &str) -> Option<(&str, &str)> {
+    let len = if input.starts_with('#') {
+        input.find('\n').unwrap_or(input.len())
+    } else if let Some(body) = input.strip_prefix("/*") {
+        body.find("*/")? + 4
+    } else {
+        return None;
+    };
+    Some(input.split_at(len))
+}
+
+/// Lexes a raw string such as `r#"say "hi""#`, in which quotes and
+/// backslashes need no escaping, into its contents.
+///
+/// Returns `None` if the input doesn't start with a raw string at all.
+pub fn lex_raw_str(input: &str) -> Option<LexResult<'_, &str>> {
+    let rest = input.strip_prefix('r')?;
+    let body = rest.trim_start_matches('#');
+    let hashes = &rest[..rest.len() - body.len()];
+    let body = body.strip_prefix('"')?;
+    Some(
+        body.match_indices('"')
+            .map(|(pos, _)| pos + 1)
+            .find(|&end| body[end..].starts_with(hashes))
+            .map(|end| (&body[..end - 1], &body[end + hashes.len()..]))
+            .ok_or((LexErrorKind::MissingEndingQuote, body)),
+    )
+}
+
+/// Skips whitespace and comments.
+pub fn skip_space(mut input: &str) -> &str {
+    loop {
+        input = input.trim_start_matches(SPACE_CHARS);
+        match lex_comment(input) {
+            Some((_, rest)) => input = rest,
+            None => return input,
+        }
+    }
+}
+
+/// Trims whitespace and comments around a whole filter.
+pub fn trim(input: &str) -> &str {
+    skip_space(input.trim())
+}
+
+/// Returns all comments in `input`, in order.
+///
+/// `input` must not contain literals other than strings and sets of them, as
+/// `#` and `/*` in a regex are indistinguishable from a comment here.
+pub fn comments(mut input: &str) -> Vec<&str> {
+    let mut comments = Vec::new();
+    let mut after_word = false;
+    loop {
+        if let Some((comment, rest)) = lex_comment(input) {
+            comments.push(comment);
+            input = rest;
+            after_word = false;
+            continue;
+        }
+        let mut chars = input.chars();
+        let c = match chars.next() {
+            None => return comments,
+            Some(c) => c,
+        };
+        // Raw strings start with a letter, so they can't follow a name.
+        let string = if c == '"' || (c == 'r' && !after_word) {
+            Bytes::lex(input).ok()
+        } else {
+            None
+        };
+        after_word = string.is_none() && (c.is_ascii_alphanumeric() || c == '_');
+        input = match string {
+            Some((_, rest)) => rest,
+            None => chars.as_str(),
+        };
+    }
 }
 
 /// This macro generates enum declaration + lexer implementation.
@@ -229,6 +463,7 @@ pub fn take(input: &str, expected: usize) -> LexResult<'_, &str> {
 
 pub fn complete<T>(res: LexResult<'_, T>) -> Result<T, LexError<'_>> {
     let (res, input) = res?;
+    let input = skip_space(input);
     if input.is_empty() {
         Ok(res)
     } else {
@@ -66,21 +66,35 @@ mod execution_context;
 mod filter;
 mod functions;
 mod heap_searcher;
+mod limits;
+mod metadata;
 mod range_set;
 mod rhs_types;
 mod strict_partial_ord;
+mod suggest;
 mod types;
+pub mod builtins;
 pub mod derive;
 pub mod errors;
 
 pub use self::{
     errors::Error,
-    ast::FilterAst,
-    execution_context::ExecutionContext,
-    filter::{Filter, SchemeMismatchError},
+    ast::{FilterAst, FilterAstWithComments, FilterAstWithSpans, NodeKind, OwnedFilterAst, Span},
+    execution_context::{ExecutionContext, SetFieldValueError},
+    filter::{Filter, OwnedFilter, SchemeMismatchError},
     functions::{
         Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionOptParam, FunctionParam,
     },
-    scheme::{FieldRedefinitionError, ParseError, Scheme, UnknownFieldError},
-    types::{GetType, LhsValue, Type, TypeMismatchError},
+    lex::ParseErrorCode,
+    limits::ParseLimits,
+    metadata::Metadata,
+    scheme::{
+        AliasError, Comment, DefaultValueError, Diagnostic, FieldRedefinitionError,
+        FunctionRedefinitionError, FunctionRegistry, IncompatibleSchemeError, MacroError,
+        ObjectFieldError, ParseError, ParseWarning, Scheme, SchemeIncompatibility, SchemeItem,
+        SchemeItemKind, UnknownFieldError, UnknownFunctionError, UnknownMacroError,
+        VirtualFieldError,
+    },
+    suggest::Suggestions,
+    types::{FieldType, GetType, LhsValue, Type, TypeMismatchError},
 };
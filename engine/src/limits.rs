@@ -0,0 +1,157 @@
+use crate::{
+    lex::{LexError, LexErrorKind},
+    scheme::Scheme,
+};
+use std::cell::Cell;
+
+/// Limits on filters accepted by
+/// [`Scheme::parse_with_limits`](::Scheme::parse_with_limits), which protect
+/// against untrusted input.
+///
+/// Definitions of macros and virtual fields come from the scheme rather than
+/// from the filter, so their expansions aren't subject to these limits, other
+/// than [`max_macro_nodes`](ParseLimits::max_macro_nodes).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ParseLimits {
+    /// Maximum length of a filter in bytes.
+    pub max_input_len: usize,
+    /// Maximum nesting depth of parentheses and `not` operators.
+    pub max_depth: usize,
+    /// Maximum number of items in a set such as `{80 443}`.
+    pub max_set_len: usize,
+    /// Maximum size of a compiled regex in bytes.
+    ///
+    /// The memory used by a regex when matching is capped separately by the
+    /// `regex` crate, which falls back to slower matching instead of failing,
+    /// so it isn't a parse limit.
+    pub max_regex_size: usize,
+    /// Maximum nesting depth of function calls in arguments of other calls.
+    pub max_call_depth: usize,
+    /// Maximum number of AST nodes that references to macros expand to, in
+    /// total.
+    ///
+    /// Macros can refer to each other, so a short filter could otherwise
+    /// expand to an AST exponentially bigger than the filter itself.
+    pub max_macro_nodes: usize,
+}
+
+/// By default, only nesting and expansions of macros are limited, so that
+/// parsing can't overflow the stack or run out of memory, along with the
+/// regex sizes used by the `regex` crate.
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_input_len: usize::MAX,
+            max_depth: 128,
+            max_set_len: usize::MAX,
+            max_regex_size: 10 << 20,
+            max_call_depth: 32,
+            max_macro_nodes: 1 << 16,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// Limits for definitions from the scheme, which are trusted, so only
+    /// regexes keep their default limits.
+    pub(crate) fn trusted() -> Self {
+        ParseLimits {
+            max_input_len: usize::MAX,
+            max_depth: usize::MAX,
+            max_set_len: usize::MAX,
+            max_call_depth: usize::MAX,
+            max_macro_nodes: usize::MAX,
+            ..ParseLimits::default()
+        }
+    }
+
+    /// Fails if `input` is longer than allowed.
+    pub(crate) fn check_input_len<'i>(&self, input: &'i str) -> Result<(), LexError<'i>> {
+        let limit = self.max_input_len;
+        if input.len() > limit {
+            let end = (limit..).find(|&end| input.is_char_boundary(end)).unwrap();
+            return Err((LexErrorKind::InputTooLong { limit }, &input[end..]));
+        }
+        Ok(())
+    }
+}
+
+/// The state of parsing a single filter, which is passed to lexers of all
+/// AST nodes.
+pub(crate) struct Parser<'s> {
+    pub scheme: &'s Scheme,
+    pub limits: ParseLimits,
+    depth: Cell<usize>,
+    call_depth: Cell<usize>,
+    macro_nodes: Cell<usize>,
+}
+
+impl<'s> Parser<'s> {
+    pub fn new(scheme: &'s Scheme, limits: ParseLimits) -> Self {
+        Parser {
+            scheme,
+            limits,
+            depth: Cell::new(0),
+            call_depth: Cell::new(0),
+            macro_nodes: Cell::new(0),
+        }
+    }
+
+    /// Creates a parser of a definition from the scheme.
+    pub fn trusted(scheme: &'s Scheme) -> Self {
+        Parser::new(scheme, ParseLimits::trusted())
+    }
+
+    /// Accounts for a reference to a macro expanding to `nodes` nodes,
+    /// failing at `span` if the expansions exceed the limits.
+    pub fn expand_macro<'i>(&self, nodes: usize, span: &'i str) -> Result<(), LexError<'i>> {
+        let total = self.macro_nodes.get().saturating_add(nodes);
+        let limit = self.limits.max_macro_nodes;
+        if total > limit {
+            return Err((LexErrorKind::MacroTooLarge { limit }, span));
+        }
+        self.macro_nodes.set(total);
+        Ok(())
+    }
+}
+
+/// A kind of nesting with a depth limit.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Nesting {
+    /// A parenthesized expression or an argument of `not`.
+    Expr,
+    /// A function call in an argument of another call.
+    Call,
+}
+
+impl Nesting {
+    /// Enters one level deeper until the returned guard is dropped, failing
+    /// at `span` if that exceeds the limits of `parser`.
+    pub fn enter<'p, 'i>(
+        self,
+        parser: &'p Parser<'_>,
+        span: &'i str,
+    ) -> Result<NestingGuard<'p>, LexError<'i>> {
+        let (depth, limit) = match self {
+            Nesting::Expr => (&parser.depth, parser.limits.max_depth),
+            Nesting::Call => (&parser.call_depth, parser.limits.max_call_depth),
+        };
+        if depth.get() >= limit {
+            let kind = match self {
+                Nesting::Expr => LexErrorKind::TooDeep { limit },
+                Nesting::Call => LexErrorKind::CallsTooDeep { limit },
+            };
+            return Err((kind, span));
+        }
+        depth.set(depth.get() + 1);
+        Ok(NestingGuard(depth))
+    }
+}
+
+pub(crate) struct NestingGuard<'p>(&'p Cell<usize>);
+
+impl<'p> Drop for NestingGuard<'p> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
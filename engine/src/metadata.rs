@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// Optional documentation attached to a field or a function of a
+/// [`Scheme`](::Scheme).
+///
+/// This isn't used by the engine itself, but allows rule editors to provide
+/// documentation and autocompletion based on the scheme alone.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Human-readable description.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// Example values, written the way they would appear in a filter.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<String>,
+
+    /// Unit of measurement of the values, such as `bytes` or `ms`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+
+    /// Operators that make sense for the values, such as `==` or
+    /// `contains`. An empty list means that no restrictions are suggested.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub operators: Vec<String>,
+}
@@ -1,11 +1,11 @@
 use crate::{
-    lex::{expect, take, Lex, LexErrorKind, LexResult},
+    lex::{expect, lex_raw_str, span, take, take_while, Lex, LexErrorKind, LexResult},
     strict_partial_ord::StrictPartialOrd,
 };
 use serde::Serialize;
 use std::{
     borrow::Borrow,
-    fmt::{self, Debug, Formatter},
+    fmt::{self, Debug, Display, Formatter, Write},
     hash::{Hash, Hasher},
     ops::Deref,
     str,
@@ -53,7 +53,7 @@ impl From<Bytes> for Box<[u8]> {
 impl Debug for Bytes {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Bytes::Str(s) => s.fmt(f),
+            Bytes::Str(s) => Debug::fmt(s, f),
             Bytes::Raw(b) => {
                 for (i, b) in b.iter().cloned().enumerate() {
                     if i != 0 {
@@ -67,6 +67,27 @@ impl Debug for Bytes {
     }
 }
 
+// Formatted as a literal that lexes back into the same value.
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Bytes::Str(s) => {
+                f.write_char('"')?;
+                for c in s.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        // Keep the literal on a single line.
+                        c if c.is_ascii_control() => write!(f, "\\x{:02x}", c as u8)?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                f.write_char('"')
+            }
+            Bytes::Raw(_) => Debug::fmt(self, f),
+        }
+    }
+}
+
 impl Deref for Bytes {
     type Target = [u8];
 
@@ -100,6 +121,20 @@ fn oct_byte(input: &str) -> LexResult<'_, u8> {
     fixed_byte(input, 3, 8)
 }
 
+// Lexes `{1F600}` of a `\u{1F600}` escape.
+fn unicode_char(input: &str) -> LexResult<'_, char> {
+    let rest = expect(input, "{")?;
+    let (digits, rest) = take_while(rest, "hex digit", |c| c.is_ascii_hexdigit())?;
+    let rest = expect(rest, "}")?;
+    match u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+    {
+        Some(c) if digits.len() <= 6 => Ok((c, rest)),
+        _ => Err((LexErrorKind::InvalidUnicodeEscape, span(input, rest))),
+    }
+}
+
 lex_enum!(ByteSeparator {
     ":" => Colon,
     "-" => Dash,
@@ -108,7 +143,10 @@ lex_enum!(ByteSeparator {
 
 impl<'i> Lex<'i> for Bytes {
     fn lex(mut input: &str) -> LexResult<'_, Self> {
-        if let Ok(input) = expect(input, "\"") {
+        if let Some(res) = lex_raw_str(input) {
+            let (s, rest) = res?;
+            Ok((s.to_owned().into(), rest))
+        } else if let Ok(input) = expect(input, "\"") {
             let full_input = input;
             let mut res = String::new();
             let mut iter = input.chars();
@@ -136,6 +174,11 @@ impl<'i> Lex<'i> for Bytes {
                                 iter = input.chars();
                                 b as char
                             }
+                            'u' => {
+                                let (c, input) = unicode_char(iter.as_str())?;
+                                iter = input.chars();
+                                c
+                            }
                             _ => {
                                 return Err((
                                     LexErrorKind::InvalidCharacterEscape,
@@ -232,4 +275,42 @@ fn test() {
         },
         "3😢"
     );
+
+    assert_ok!(
+        Bytes::lex(r#""\u{48}\u{1f600}";"#),
+        Bytes::from("H😀".to_owned()),
+        ";"
+    );
+
+    assert_err!(
+        Bytes::lex(r#""\u{110000}""#),
+        LexErrorKind::InvalidUnicodeEscape,
+        "{110000}"
+    );
+
+    assert_err!(
+        Bytes::lex(r#""\u{0000041}""#),
+        LexErrorKind::InvalidUnicodeEscape,
+        "{0000041}"
+    );
+
+    assert_err!(
+        Bytes::lex(r#""\u41""#),
+        LexErrorKind::ExpectedLiteral("{"),
+        "41\""
+    );
+
+    assert_ok!(
+        Bytes::lex(r###"r##"C:\dir\"quoted"#" "##;"###),
+        Bytes::from(r##"C:\dir\"quoted"#" "##.to_owned()),
+        ";"
+    );
+
+    assert_ok!(Bytes::lex(r#"r"\x""#), Bytes::from(r"\x".to_owned()));
+
+    assert_err!(
+        Bytes::lex(r##"r#"abc""##),
+        LexErrorKind::MissingEndingQuote,
+        "abc\""
+    );
 }
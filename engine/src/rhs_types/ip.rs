@@ -2,10 +2,14 @@ use crate::{
     lex::{take_while, Lex, LexError, LexErrorKind, LexResult},
     strict_partial_ord::StrictPartialOrd,
 };
-use cidr::{Cidr, IpCidr, Ipv4Cidr, Ipv6Cidr, NetworkParseError};
+use cidr::{
+    Cidr, Family, Inet, IpCidr, IpInet, Ipv4Cidr, Ipv6Cidr, NetworkLengthTooLongError,
+    NetworkParseError,
+};
 use serde::Serialize;
 use std::{
     cmp::Ordering,
+    fmt::{self, Display, Formatter},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ops::RangeInclusive,
     str::FromStr,
@@ -114,6 +118,24 @@ impl_ip_range_from!(IpAddr { Ipv4Addr, Ipv6Addr }, |addr| addr..=addr);
 impl_ip_range_from!(IpCidr { Ipv4Cidr, Ipv6Cidr }, |cidr| cidr.first_address()
     ..=cidr.last_address());
 
+impl Display for ExplicitIpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ExplicitIpRange::V4(range) => write!(f, "{}..{}", range.start(), range.end()),
+            ExplicitIpRange::V6(range) => write!(f, "{}..{}", range.start(), range.end()),
+        }
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IpRange::Explicit(range) => Display::fmt(range, f),
+            IpRange::Cidr(cidr) => Display::fmt(cidr, f),
+        }
+    }
+}
+
 impl From<IpRange> for ExplicitIpRange {
     fn from(range: IpRange) -> Self {
         match range {
@@ -123,6 +145,43 @@ impl From<IpRange> for ExplicitIpRange {
     }
 }
 
+/// Lexes the prefix length of a network mask, such as `24` in `ip.src/24`.
+///
+/// Lengths up to 128 are accepted, since the address family of the masked
+/// value is only known at execution time.
+pub(crate) fn lex_prefix_len(input: &str) -> LexResult<'_, u8> {
+    let (digits, rest) = take_while(input, "digit", |c| c.is_ascii_digit())?;
+    let len = u8::from_str(digits).map_err(|err| {
+        (
+            LexErrorKind::ParseNetwork(NetworkParseError::NetworkLengthParseError(err)),
+            digits,
+        )
+    })?;
+    if len > Family::Ipv6.len() {
+        return Err((
+            LexErrorKind::ParseNetwork(NetworkParseError::NetworkLengthTooLongError(
+                NetworkLengthTooLongError::new(len.into(), Family::Ipv6),
+            )),
+            digits,
+        ));
+    }
+    Ok((len, rest))
+}
+
+/// Clears all but the first `prefix_len` bits of an address.
+///
+/// Prefix lengths longer than the address itself (e.g. `/64` applied to an
+/// IPv4 address) leave it unchanged.
+pub(crate) fn mask_addr(addr: IpAddr, prefix_len: u8) -> IpAddr {
+    let max_len = match addr {
+        IpAddr::V4(_) => Family::Ipv4.len(),
+        IpAddr::V6(_) => Family::Ipv6.len(),
+    };
+    IpInet::new(addr, prefix_len.min(max_len))
+        .unwrap()
+        .first_address()
+}
+
 impl StrictPartialOrd for IpAddr {
     fn strict_partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
@@ -233,6 +292,54 @@ fn test_lex() {
     );
 }
 
+#[test]
+fn test_lex_prefix_len() {
+    assert_ok!(lex_prefix_len("24 == 10.0.0.0"), 24, " == 10.0.0.0");
+    assert_ok!(lex_prefix_len("128"), 128);
+    assert_err!(
+        lex_prefix_len("==10.0.0.0"),
+        LexErrorKind::ExpectedName("digit"),
+        "==10.0.0.0"
+    );
+    assert_err!(
+        lex_prefix_len("129"),
+        LexErrorKind::ParseNetwork(NetworkParseError::NetworkLengthTooLongError(
+            NetworkLengthTooLongError::new(129, Family::Ipv6)
+        )),
+        "129"
+    );
+    assert_err!(
+        lex_prefix_len("1000"),
+        LexErrorKind::ParseNetwork(NetworkParseError::NetworkLengthParseError(
+            u8::from_str("1000").unwrap_err()
+        )),
+        "1000"
+    );
+}
+
+#[test]
+fn test_mask_addr() {
+    assert_eq!(
+        mask_addr(IpAddr::from([192, 0, 2, 123]), 24),
+        IpAddr::from([192, 0, 2, 0])
+    );
+    assert_eq!(
+        mask_addr(IpAddr::from([192, 0, 2, 123]), 0),
+        IpAddr::from([0, 0, 0, 0])
+    );
+    assert_eq!(
+        mask_addr(IpAddr::from([192, 0, 2, 123]), 64),
+        IpAddr::from([192, 0, 2, 123])
+    );
+    assert_eq!(
+        mask_addr(
+            IpAddr::from([0x2001, 0xdb8, 0x1234, 0x5678, 0, 0, 0, 1]),
+            48
+        ),
+        IpAddr::from([0x2001, 0xdb8, 0x1234, 0, 0, 0, 0, 0])
+    );
+}
+
 #[test]
 fn test_strict_partial_ord() {
     let ips = &[
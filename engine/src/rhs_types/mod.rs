@@ -4,6 +4,8 @@ mod int;
 mod ip;
 mod regex;
 
+pub(crate) use self::ip::{lex_prefix_len, mask_addr};
+
 pub use self::{
     bool::UninhabitedBool,
     bytes::Bytes,
@@ -1,3 +1,4 @@
+use crate::{lex::LexErrorKind, limits::ParseLimits};
 use std::str::FromStr;
 
 pub use regex::Error;
@@ -17,6 +18,18 @@ impl FromStr for Regex {
 }
 
 impl Regex {
+    pub fn with_limits(s: &str, limits: &ParseLimits) -> Result<Self, LexErrorKind> {
+        ::regex::bytes::RegexBuilder::new(s)
+            .unicode(false)
+            .size_limit(limits.max_regex_size)
+            .build()
+            .map(Regex)
+            .map_err(|err| match err {
+                Error::CompiledTooBig(limit) => LexErrorKind::RegexTooBig { limit },
+                err => LexErrorKind::ParseRegex(err),
+            })
+    }
+
     pub fn is_match(&self, text: &[u8]) -> bool {
         self.0.is_match(text)
     }
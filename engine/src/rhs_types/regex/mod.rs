@@ -1,10 +1,10 @@
-use crate::lex::{expect, span, Lex, LexErrorKind, LexResult};
+use crate::{
+    lex::{expect, lex_raw_str, span, LexErrorKind, LexResult, LexWith},
+    limits::ParseLimits,
+};
 use cfg_if::cfg_if;
 use serde::{Serialize, Serializer};
-use std::{
-    fmt::{self, Debug, Formatter},
-    str::FromStr,
-};
+use std::fmt::{self, Debug, Display, Formatter, Write};
 
 cfg_if! {
     if #[cfg(feature = "regex")] {
@@ -30,47 +30,117 @@ impl Debug for Regex {
     }
 }
 
-impl<'i> Lex<'i> for Regex {
-    fn lex(input: &str) -> LexResult<'_, Self> {
-        let input = expect(input, "\"")?;
-        let mut regex_buf = String::new();
+// Formatted as a literal, escaping quotes the same way the lexer unescapes
+// them, i.e. only outside of character classes.
+impl Display for Regex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
         let mut in_char_class = false;
-        let (regex_str, input) = {
-            let mut iter = input.chars();
-            loop {
-                let before_char = iter.as_str();
-                match iter
-                    .next()
-                    .ok_or_else(|| (LexErrorKind::MissingEndingQuote, input))?
-                {
-                    '\\' => {
-                        if let Some(c) = iter.next() {
-                            if in_char_class || c != '"' {
-                                regex_buf.push('\\');
-                            }
-                            regex_buf.push(c);
-                        }
-                    }
-                    '"' if !in_char_class => {
-                        break (span(input, before_char), iter.as_str());
-                    }
-                    '[' if !in_char_class => {
-                        in_char_class = true;
-                        regex_buf.push('[');
+        let mut iter = self.as_str().chars();
+        while let Some(c) = iter.next() {
+            match c {
+                '\\' => {
+                    f.write_char('\\')?;
+                    if let Some(c) = iter.next() {
+                        f.write_char(c)?;
                     }
-                    ']' if in_char_class => {
-                        in_char_class = false;
-                        regex_buf.push(']');
-                    }
-                    c => {
-                        regex_buf.push(c);
+                }
+                '"' if !in_char_class => f.write_str("\\\"")?,
+                '[' if !in_char_class => {
+                    in_char_class = true;
+                    f.write_char('[')?;
+                }
+                ']' if in_char_class => {
+                    in_char_class = false;
+                    f.write_char(']')?;
+                }
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+// Lexes the rest of a regex up to `delimiter`, which needs to be escaped only
+// outside of character classes, and returns it along with its span.
+fn lex_delimited(
+    input: &str,
+    delimiter: char,
+    missing_end: LexErrorKind,
+) -> LexResult<'_, (String, &str)> {
+    let mut regex_buf = String::new();
+    let mut in_char_class = false;
+    let mut iter = input.chars();
+    loop {
+        let before_char = iter.as_str();
+        let c = match iter.next() {
+            Some(c) => c,
+            None => return Err((missing_end, input)),
+        };
+        match c {
+            '\\' => {
+                if let Some(c) = iter.next() {
+                    if in_char_class || c != delimiter {
+                        regex_buf.push('\\');
                     }
-                };
+                    regex_buf.push(c);
+                }
+            }
+            c if c == delimiter && !in_char_class => {
+                return Ok(((regex_buf, span(input, before_char)), iter.as_str()));
+            }
+            '[' if !in_char_class => {
+                in_char_class = true;
+                regex_buf.push('[');
+            }
+            ']' if in_char_class => {
+                in_char_class = false;
+                regex_buf.push(']');
+            }
+            c => {
+                regex_buf.push(c);
             }
         };
-        match Regex::from_str(&regex_buf) {
+    }
+}
+
+// Lexes flags following a regex literal, such as `i` in `/a+/i`, into a
+// group which sets them for the whole regex.
+fn lex_flags(input: &str) -> LexResult<'_, String> {
+    let mut flags = String::new();
+    let mut iter = input.chars();
+    loop {
+        let rest = iter.as_str();
+        match iter.next() {
+            Some(c) if c.is_ascii_alphanumeric() => {
+                if !"imsUx".contains(c) || flags.contains(c) {
+                    return Err((LexErrorKind::InvalidRegexFlag(c), span(rest, iter.as_str())));
+                }
+                flags.push(c);
+            }
+            _ if flags.is_empty() => return Ok((flags, rest)),
+            _ => return Ok((format!("(?{})", flags), rest)),
+        }
+    }
+}
+
+impl<'i, 'a> LexWith<'i, &'a ParseLimits> for Regex {
+    fn lex_with(input: &'i str, limits: &'a ParseLimits) -> LexResult<'i, Self> {
+        let ((regex_buf, regex_str), input) = if let Some(res) = lex_raw_str(input) {
+            let (regex_str, input) = res?;
+            ((regex_str.to_owned(), regex_str), input)
+        } else if let Ok(input) = expect(input, "/") {
+            let ((regex_buf, regex_str), input) =
+                lex_delimited(input, '/', LexErrorKind::MissingEndingSlash)?;
+            let (flags, input) = lex_flags(input)?;
+            ((flags + &regex_buf, regex_str), input)
+        } else {
+            let input = expect(input, "\"")?;
+            lex_delimited(input, '"', LexErrorKind::MissingEndingQuote)?
+        };
+        match Regex::with_limits(&regex_buf, limits) {
             Ok(regex) => Ok((regex, input)),
-            Err(err) => Err((LexErrorKind::ParseRegex(err), regex_str)),
+            Err(kind) => Err((kind, regex_str)),
         }
     }
 }
@@ -83,17 +153,36 @@ impl Serialize for Regex {
 
 #[test]
 fn test() {
+    use std::str::FromStr;
+
+    let lex = |input| Regex::lex_with(input, &ParseLimits::default());
+
     let expr = assert_ok!(
-        Regex::lex(r#""[a-z"\]]+\d{1,10}\"";"#),
+        lex(r#""[a-z"\]]+\d{1,10}\"";"#),
         Regex::from_str(r#"[a-z"\]]+\d{1,10}""#).unwrap(),
         ";"
     );
 
     assert_json!(expr, r#"[a-z"\]]+\d{1,10}""#);
 
-    assert_err!(
-        Regex::lex(r#""abcd\"#),
-        LexErrorKind::MissingEndingQuote,
-        "abcd\\"
+    assert_err!(lex(r#""abcd\"#), LexErrorKind::MissingEndingQuote, "abcd\\");
+
+    assert_ok!(
+        lex(r#"/[a-z\/]+\/\d"\w/;"#),
+        Regex::from_str(r#"[a-z\/]+/\d"\w"#).unwrap(),
+        ";"
+    );
+
+    assert_ok!(lex("/a.b/is "), Regex::from_str("(?is)a.b").unwrap(), " ");
+
+    assert_ok!(
+        lex(r##"r#"\d+"[/"]"#"##),
+        Regex::from_str(r#"\d+"[/"]"#).unwrap()
     );
+
+    assert_err!(lex("/abc"), LexErrorKind::MissingEndingSlash, "abc");
+
+    assert_err!(lex("/abc/iq"), LexErrorKind::InvalidRegexFlag('q'), "q");
+
+    assert_err!(lex("/abc/ii"), LexErrorKind::InvalidRegexFlag('i'), "i");
 }
@@ -0,0 +1,164 @@
+use super::{
+    FieldRedefinitionError, FunctionRedefinitionError, ItemRedefinitionError, Scheme,
+    UnknownFieldError,
+};
+use failure::Fail;
+
+/// An error that occurs when registering a field alias.
+#[derive(Debug, PartialEq, Fail)]
+pub enum AliasError {
+    /// The aliased field doesn't exist.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// The alias name is already taken.
+    #[fail(display = "{}", _0)]
+    Redefinition(#[cause] ItemRedefinitionError),
+}
+
+impl Scheme {
+    /// Registers an alias that resolves to the same field as `field`.
+    ///
+    /// Filters can refer to the field by any of its names, and
+    /// [`FilterAst::canonicalize`](::FilterAst::canonicalize) rewrites
+    /// aliases to canonical names.
+    pub fn add_field_alias(&mut self, alias: String, field: &str) -> Result<(), AliasError> {
+        let index = match self.fields.get_full(field) {
+            Some((index, ..)) => index,
+            None => *self
+                .aliases
+                .get(field)
+                .ok_or(AliasError::UnknownField(UnknownFieldError))?,
+        };
+        if self.functions.contains_key(&alias) {
+            return Err(AliasError::Redefinition(ItemRedefinitionError::Function(
+                FunctionRedefinitionError(alias),
+            )));
+        }
+        if self.fields.contains_key(&alias)
+            || self.aliases.contains_key(&alias)
+            || self.objects.contains_key(&alias)
+        {
+            return Err(AliasError::Redefinition(ItemRedefinitionError::Field(
+                FieldRedefinitionError(alias),
+            )));
+        }
+        self.aliases.insert(alias, index);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_field_aliases() {
+    use crate::{execution_context::ExecutionContext, scheme::ParseWarning, types::Type};
+
+    let mut scheme = Scheme! { http.user_agent: Bytes, tcp.port: Int };
+    scheme
+        .add_field_alias("http.ua".into(), "http.user_agent")
+        .unwrap();
+    scheme
+        .deprecate_field("http.ua", "use http.user_agent instead".into())
+        .unwrap();
+
+    assert_eq!(
+        scheme.add_field_alias("http.ua".into(), "tcp.port"),
+        Err(AliasError::Redefinition(ItemRedefinitionError::Field(
+            FieldRedefinitionError("http.ua".into())
+        )))
+    );
+    assert_eq!(
+        scheme.add_field_alias("port".into(), "tcp.dport"),
+        Err(AliasError::UnknownField(UnknownFieldError))
+    );
+    assert_eq!(
+        scheme.add_field("http.ua".into(), Type::Bytes),
+        Err(ItemRedefinitionError::Field(FieldRedefinitionError(
+            "http.ua".into()
+        )))
+    );
+    assert_eq!(
+        scheme.deprecate_field("http.agent", "".into()),
+        Err(UnknownFieldError)
+    );
+
+    let (mut ast, warnings) = scheme
+        .parse_with_warnings(r#"http.ua contains "curl" && http.ua != "curl/1.0""#)
+        .unwrap();
+    assert_eq!(
+        warnings,
+        vec![ParseWarning::DeprecatedField {
+            name: "http.ua".into(),
+            note: "use http.user_agent instead".into(),
+        }]
+    );
+    assert_eq!(
+        warnings[0].to_string(),
+        "field http.ua is deprecated: use http.user_agent instead"
+    );
+    assert_eq!(ast.uses("http.user_agent"), Ok(true));
+    assert_eq!(ast.uses("http.ua"), Ok(true));
+    assert_eq!(
+        ast,
+        scheme
+            .parse(r#"http.user_agent contains "curl" && http.user_agent != "curl/1.0""#)
+            .unwrap()
+    );
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.ua", "curl/7.0").unwrap();
+    assert_eq!(ast.clone().compile().execute(&ctx), Ok(Some(true)));
+
+    assert_json!(
+        ast,
+        {
+            "op": "And",
+            "items": [
+                { "lhs": "http.ua", "op": "Contains", "rhs": "curl" },
+                { "lhs": "http.ua", "op": "NotEqual", "rhs": "curl/1.0" }
+            ]
+        }
+    );
+
+    assert!(ast.canonicalize());
+    assert!(!ast.canonicalize());
+    assert_json!(
+        ast,
+        {
+            "op": "And",
+            "items": [
+                { "lhs": "http.user_agent", "op": "Contains", "rhs": "curl" },
+                { "lhs": "http.user_agent", "op": "NotEqual", "rhs": "curl/1.0" }
+            ]
+        }
+    );
+
+    let (_, warnings) = scheme
+        .parse_with_warnings(r#"http.user_agent contains "curl""#)
+        .unwrap();
+    assert_eq!(warnings, vec![]);
+
+    assert_json!(
+        scheme,
+        {
+            "fields": {
+                "http.user_agent": "Bytes",
+                "tcp.port": "Int"
+            },
+            "functions": {},
+            "aliases": {
+                "http.ua": "http.user_agent"
+            },
+            "deprecations": {
+                "http.ua": "use http.user_agent instead"
+            }
+        }
+    );
+
+    let json = serde_json::to_string(&scheme).unwrap();
+    let scheme = serde_json::from_str::<Scheme>(&json).unwrap();
+    assert_eq!(serde_json::to_string(&scheme).unwrap(), json);
+    assert_eq!(
+        scheme.get_field_index("http.ua").unwrap(),
+        scheme.get_field_index("http.user_agent").unwrap()
+    );
+}
@@ -0,0 +1,279 @@
+use super::{Field, Scheme, UnknownFieldError, UnknownFunctionError, UnknownMacroError};
+use crate::{
+    ast::UnboundCombinedExpr,
+    functions::Function,
+    types::{GetType, Type},
+};
+use failure::Fail;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// A field or a function that is missing or differs in another
+/// [`Scheme`](struct@Scheme).
+#[derive(Debug, PartialEq, Eq, Clone, Fail)]
+pub enum SchemeIncompatibility {
+    /// The field doesn't exist in the other scheme.
+    #[fail(display = "field {} is missing", _0)]
+    MissingField(String),
+
+    /// The field has a different type in the other scheme.
+    #[fail(
+        display = "field {} has type {:?} instead of {:?}",
+        name, actual, expected
+    )]
+    FieldTypeMismatch {
+        /// Field name.
+        name: String,
+        /// Type of the field in the original scheme.
+        expected: Type,
+        /// Type of the field in the other scheme.
+        actual: Type,
+    },
+
+    /// The function doesn't exist in the other scheme.
+    #[fail(display = "function {} is missing", _0)]
+    MissingFunction(String),
+
+    /// The function has different parameters, return type or purity in the
+    /// other scheme.
+    #[fail(display = "function {} has a different signature", _0)]
+    FunctionSignatureMismatch(String),
+
+    /// The macro doesn't exist in the other scheme.
+    #[fail(display = "macro {} is missing", _0)]
+    MissingMacro(String),
+}
+
+/// An error that occurs if a [`Scheme`](struct@Scheme), or a filter parsed
+/// with it, isn't compatible with another scheme.
+#[derive(Debug, PartialEq)]
+pub struct IncompatibleSchemeError(pub Vec<SchemeIncompatibility>);
+
+impl Error for IncompatibleSchemeError {}
+
+impl Display for IncompatibleSchemeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "incompatible scheme")?;
+        for (i, incompatibility) in self.0.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 { ":" } else { "," }, incompatibility)?;
+        }
+        Ok(())
+    }
+}
+
+/// Looks up fields and functions of one scheme in another one, collecting
+/// any incompatibilities along the way.
+pub(crate) struct Rebinder<'t> {
+    scheme: &'t Scheme,
+    errors: Vec<SchemeIncompatibility>,
+}
+
+impl<'t> Rebinder<'t> {
+    pub fn new(scheme: &'t Scheme) -> Self {
+        Rebinder {
+            scheme,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn scheme(&self) -> &'t Scheme {
+        self.scheme
+    }
+
+    fn report(&mut self, incompatibility: SchemeIncompatibility) {
+        if !self.errors.contains(&incompatibility) {
+            self.errors.push(incompatibility);
+        }
+    }
+
+    pub fn field(&mut self, name: &str, expected: Type) -> Option<Field<'t>> {
+        match self.scheme.get_field_index(name) {
+            Ok(field) if field.get_type() == expected => Some(field),
+            Ok(field) => {
+                self.report(SchemeIncompatibility::FieldTypeMismatch {
+                    name: name.to_owned(),
+                    expected,
+                    actual: field.get_type(),
+                });
+                None
+            }
+            Err(UnknownFieldError) => {
+                self.report(SchemeIncompatibility::MissingField(name.to_owned()));
+                None
+            }
+        }
+    }
+
+    pub fn function(&mut self, name: &str, expected: &Function) -> Option<&'t Function> {
+        match self.scheme.get_function(name) {
+            Ok(function)
+                if function.has_signature(
+                    &expected.params,
+                    &expected.opt_params,
+                    expected.return_type,
+                    expected.pure,
+                ) =>
+            {
+                Some(function)
+            }
+            Ok(_) => {
+                self.report(SchemeIncompatibility::FunctionSignatureMismatch(
+                    name.to_owned(),
+                ));
+                None
+            }
+            Err(UnknownFunctionError) => {
+                self.report(SchemeIncompatibility::MissingFunction(name.to_owned()));
+                None
+            }
+        }
+    }
+
+    pub fn macro_expansion(&mut self, name: &str) -> Option<&'t UnboundCombinedExpr> {
+        match self.scheme.get_macro(name) {
+            Ok(definition) => Some(&definition.expansion),
+            Err(UnknownMacroError) => {
+                self.report(SchemeIncompatibility::MissingMacro(name.to_owned()));
+                None
+            }
+        }
+    }
+
+    /// Returns the rebound value, or all incompatibilities found so far.
+    pub fn finish<T>(self, value: Option<T>) -> Result<T, IncompatibleSchemeError> {
+        match value {
+            Some(value) if self.errors.is_empty() => Ok(value),
+            _ => Err(IncompatibleSchemeError(self.errors)),
+        }
+    }
+}
+
+impl Scheme {
+    /// Checks that every field and function of this scheme exists in `other`
+    /// with the same type or signature, and that every macro exists there as
+    /// well.
+    ///
+    /// If so, any filter parsed with this scheme can be
+    /// [rebound](::FilterAst::rebind) to `other`.
+    pub fn check_compatibility(&self, other: &Scheme) -> Result<(), IncompatibleSchemeError> {
+        let mut rebinder = Rebinder::new(other);
+        for (name, ty) in &self.fields {
+            rebinder.field(name, *ty);
+        }
+        for (alias, &index) in &self.aliases {
+            rebinder.field(alias, *self.fields.get_index(index).unwrap().1);
+        }
+        for (name, function) in &self.functions {
+            rebinder.function(name, function);
+        }
+        for name in self.macros.keys() {
+            rebinder.macro_expansion(name);
+        }
+        rebinder.finish(Some(()))
+    }
+}
+
+#[test]
+fn test_check_compatibility() {
+    use crate::scheme::test_functions;
+
+    let mut old = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+    old.add_functions(test_functions()).unwrap();
+
+    let mut new = Scheme! { http.host: Bytes, http.path: Bytes, ip.src: Ip, tcp.port: Int };
+    new.add_functions(test_functions()).unwrap();
+
+    assert_eq!(old.check_compatibility(&new), Ok(()));
+    assert_eq!(
+        new.check_compatibility(&old),
+        Err(IncompatibleSchemeError(vec![
+            SchemeIncompatibility::MissingField("http.path".into())
+        ]))
+    );
+
+    let mut changed = Scheme! { http.host: Bytes, ip.src: Bytes };
+    let mut echo = test_functions();
+    echo[0].1.return_type = Type::Int;
+    changed.add_functions(echo).unwrap();
+
+    let err = old.check_compatibility(&changed).unwrap_err();
+    assert_eq!(
+        err,
+        IncompatibleSchemeError(vec![
+            SchemeIncompatibility::FieldTypeMismatch {
+                name: "ip.src".into(),
+                expected: Type::Ip,
+                actual: Type::Bytes,
+            },
+            SchemeIncompatibility::MissingField("tcp.port".into()),
+            SchemeIncompatibility::FunctionSignatureMismatch("echo".into()),
+        ])
+    );
+    assert_eq!(
+        err.to_string(),
+        "incompatible scheme: field ip.src has type Bytes instead of Ip, field tcp.port is \
+         missing, function echo has a different signature"
+    );
+}
+
+#[test]
+fn test_rebind() {
+    use crate::{execution_context::ExecutionContext, scheme::test_functions};
+
+    let mut old = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+    old.add_functions(test_functions()).unwrap();
+
+    let mut new = Scheme! { http.path: Bytes, tcp.port: Int, http.host: Bytes };
+    new.add_functions(test_functions()).unwrap();
+
+    let ast = old
+        .parse(r#"echo(http.host) == "example.org" && tcp.port in {80 443}"#)
+        .unwrap();
+
+    let rebound = ast.rebind(&new).unwrap();
+    assert_eq!(
+        serde_json::to_value(&rebound).unwrap(),
+        serde_json::to_value(&ast).unwrap()
+    );
+    assert_eq!(rebound.uses("http.path"), Ok(false));
+
+    let filter = rebound.compile();
+    let mut ctx = ExecutionContext::new(&new);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+
+    let removed = Scheme! { ip.src: Ip, tcp.port: Bytes };
+    assert_eq!(
+        ast.rebind(&removed).err().unwrap(),
+        IncompatibleSchemeError(vec![
+            SchemeIncompatibility::MissingFunction("echo".into()),
+            SchemeIncompatibility::MissingField("http.host".into()),
+            SchemeIncompatibility::FieldTypeMismatch {
+                name: "tcp.port".into(),
+                expected: Type::Int,
+                actual: Type::Bytes,
+            },
+        ])
+    );
+
+    // Calls to pure functions may have been folded or shared, so they can't
+    // be bound to impure ones.
+    let mut impure = Scheme! { http.host: Bytes, tcp.port: Int };
+    let mut echo = test_functions();
+    echo[0].1.pure = false;
+    impure.add_functions(echo).unwrap();
+    assert_eq!(
+        ast.rebind(&impure).err().unwrap(),
+        IncompatibleSchemeError(vec![SchemeIncompatibility::FunctionSignatureMismatch(
+            "echo".into()
+        )])
+    );
+
+    // Fields that aren't used by the filter don't matter.
+    let minimal = Scheme! { tcp.port: Int };
+    let ast = old.parse("tcp.port == 80").unwrap();
+    assert!(ast.rebind(&minimal).is_ok());
+}
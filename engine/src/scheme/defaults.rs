@@ -0,0 +1,136 @@
+use super::{Scheme, UnknownFieldError};
+use crate::types::{GetType, LhsValue, TypeMismatchError};
+use failure::Fail;
+
+/// An error that occurs when setting a default value of a field.
+#[derive(Debug, PartialEq, Fail)]
+pub enum DefaultValueError {
+    /// The field doesn't exist.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// The value doesn't match the type of the field.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+
+    /// The field is virtual, so its value is always computed.
+    #[fail(display = "cannot set default value of virtual field {}", _0)]
+    VirtualField(String),
+}
+
+impl Scheme {
+    /// Sets a value that [execution contexts](::ExecutionContext) assign to
+    /// a field until another one is set, replacing any previous default.
+    pub fn set_field_default(
+        &mut self,
+        name: &str,
+        value: LhsValue<'static>,
+    ) -> Result<(), DefaultValueError> {
+        let field = self
+            .get_field_index(name)
+            .map_err(DefaultValueError::UnknownField)?;
+        if field.is_virtual() {
+            return Err(DefaultValueError::VirtualField(field.name().to_owned()));
+        }
+        let field_type = field.get_type();
+        let value_type = value.get_type();
+        if field_type != value_type {
+            return Err(DefaultValueError::TypeMismatch(TypeMismatchError {
+                expected: field_type,
+                actual: value_type,
+            }));
+        }
+        let index = field.index();
+        self.defaults.insert(index, value);
+        Ok(())
+    }
+
+    /// Returns the default value of a field.
+    pub fn get_field_default(
+        &self,
+        name: &str,
+    ) -> Result<Option<&LhsValue<'_>>, UnknownFieldError> {
+        let field = self.get_field_index(name)?;
+        Ok(self.defaults.get(&field.index()))
+    }
+
+    pub(crate) fn get_default(&self, index: usize) -> Option<&LhsValue<'static>> {
+        self.defaults.get(&index)
+    }
+}
+
+#[test]
+fn test_defaults() {
+    use crate::types::Type;
+
+    let mut scheme = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+    scheme.add_field_alias("port".into(), "tcp.port").unwrap();
+    scheme
+        .add_virtual_field("http.is_web".into(), "tcp.port in {80 443}".into())
+        .unwrap();
+
+    scheme.set_field_default("port", LhsValue::Int(80)).unwrap();
+    scheme
+        .set_field_default("ip.src", LhsValue::Ip("10.0.0.1".parse().unwrap()))
+        .unwrap();
+    scheme
+        .set_field_default("http.host", "1.1.1.1".into())
+        .unwrap();
+
+    assert_eq!(
+        scheme.get_field_default("tcp.port"),
+        Ok(Some(&LhsValue::Int(80)))
+    );
+    assert_eq!(scheme.get_field_default("http.is_web"), Ok(None));
+    assert_eq!(
+        scheme.get_field_default("http.path"),
+        Err(UnknownFieldError)
+    );
+
+    assert_eq!(
+        scheme.set_field_default("tcp.port", LhsValue::Bool(true)),
+        Err(DefaultValueError::TypeMismatch(TypeMismatchError {
+            expected: Type::Int,
+            actual: Type::Bool,
+        }))
+    );
+    assert_eq!(
+        scheme.set_field_default("http.path", "/".into()),
+        Err(DefaultValueError::UnknownField(UnknownFieldError))
+    );
+    let err = scheme
+        .set_field_default("http.is_web", LhsValue::Bool(false))
+        .unwrap_err();
+    assert_eq!(err, DefaultValueError::VirtualField("http.is_web".into()));
+    assert_eq!(
+        err.to_string(),
+        "cannot set default value of virtual field http.is_web"
+    );
+
+    let json = serde_json::to_value(&scheme).unwrap();
+    assert_eq!(
+        json["defaults"],
+        serde_json::json!({
+            "tcp.port": 80,
+            "ip.src": "10.0.0.1",
+            "http.host": "1.1.1.1"
+        })
+    );
+
+    // Values are deserialized according to the field type, so a bytes field
+    // can default to a string that looks like an IP address.
+    let deserialized: Scheme = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&deserialized).unwrap(), json);
+    assert_eq!(
+        deserialized.get_field_default("http.host"),
+        Ok(Some(&LhsValue::from("1.1.1.1")))
+    );
+
+    match serde_json::from_value::<Scheme>(serde_json::json!({
+        "fields": { "tcp.port": "Int" },
+        "defaults": { "tcp.port": "80" }
+    })) {
+        Ok(_) => panic!("mistyped default value was accepted"),
+        Err(err) => assert_eq!(err.to_string(), "value is not of type Int"),
+    }
+}
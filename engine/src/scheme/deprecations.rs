@@ -0,0 +1,54 @@
+use super::{ParseError, Scheme, UnknownFieldError};
+use crate::ast::FilterAst;
+use std::fmt::{self, Display, Formatter};
+
+/// A non-fatal problem found while parsing a filter.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseWarning {
+    /// A deprecated field or field alias is used.
+    DeprecatedField {
+        /// The name the field was referred to by.
+        name: String,
+        /// An explanation provided when the field was deprecated.
+        note: String,
+    },
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::DeprecatedField { name, note } => {
+                write!(f, "field {} is deprecated: {}", name, note)
+            }
+        }
+    }
+}
+
+impl<'s> Scheme {
+    /// Marks a field or a field alias as deprecated.
+    ///
+    /// Using it in a filter produces a [`ParseWarning`] with the given note
+    /// when parsing with [`Scheme::parse_with_warnings`].
+    pub fn deprecate_field(&mut self, name: &str, note: String) -> Result<(), UnknownFieldError> {
+        if !self.fields.contains_key(name) && !self.aliases.contains_key(name) {
+            return Err(UnknownFieldError);
+        }
+        self.deprecations.insert(name.to_owned(), note);
+        Ok(())
+    }
+
+    pub(crate) fn get_deprecation(&self, name: &str) -> Option<&str> {
+        self.deprecations.get(name).map(String::as_str)
+    }
+
+    /// Parses a filter into an AST form, also returning warnings about
+    /// usages of deprecated fields.
+    pub fn parse_with_warnings<'i>(
+        &'s self,
+        input: &'i str,
+    ) -> Result<(FilterAst<'s>, Vec<ParseWarning>), ParseError<'i>> {
+        let ast = self.parse(input)?;
+        let warnings = ast.warnings();
+        Ok((ast, warnings))
+    }
+}
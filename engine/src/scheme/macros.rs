@@ -0,0 +1,295 @@
+use super::Scheme;
+use crate::{
+    ast::{NodeKind, UnboundCombinedExpr},
+    limits::{ParseLimits, Parser},
+};
+use failure::Fail;
+use serde::{Serialize, Serializer};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    mem,
+};
+
+/// An error that occurs if an unregistered macro name was referenced in a
+/// filter.
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "unknown macro")]
+pub struct UnknownMacroError;
+
+/// An error that occurs when registering a macro.
+#[derive(Debug, PartialEq)]
+pub enum MacroError {
+    /// The name contains characters other than alphanumerics, `_` and `.`.
+    InvalidName(String),
+
+    /// A macro with the same name is already registered.
+    Redefinition(String),
+
+    /// The definition isn't a valid filter expression.
+    InvalidDefinition {
+        /// Macro name.
+        name: String,
+        /// Description of the parse error.
+        message: String,
+    },
+}
+
+impl Error for MacroError {}
+
+impl Display for MacroError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MacroError::InvalidName(name) => write!(f, "invalid macro name {:?}", name),
+            MacroError::Redefinition(name) => write!(f, "attempt to redefine macro {}", name),
+            MacroError::InvalidDefinition { name, message } => {
+                write!(f, "invalid definition of macro {}: {}", name, message)
+            }
+        }
+    }
+}
+
+/// A registered macro.
+#[derive(Debug)]
+pub(crate) struct Macro {
+    pub(crate) definition: String,
+    // The definition as parsed when the macro was added, which references
+    // are expanded into.
+    pub(crate) expansion: UnboundCombinedExpr,
+    // Number of AST nodes the definition expands to, including expansions of
+    // the macros it refers to.
+    pub(crate) nodes: usize,
+}
+
+// Serialized as the definition only.
+impl Serialize for Macro {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.definition.serialize(ser)
+    }
+}
+
+impl Scheme {
+    /// Registers a named filter that can be referred to as `$name` in other
+    /// filters, including definitions of macros registered later.
+    ///
+    /// The definition is parsed once, here, and a reference is expanded into
+    /// it when parsing, so [`FilterAst::uses`](::FilterAst::uses) and
+    /// serialization see through it. When a filter is compiled, all
+    /// references to the same macro share a single result computed at most
+    /// once per execution.
+    ///
+    /// Fails if the definition expands to more nodes than
+    /// [`ParseLimits::max_macro_nodes`] allows by default.
+    pub fn add_macro(&mut self, name: String, definition: String) -> Result<(), MacroError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            return Err(MacroError::InvalidName(name));
+        }
+        if self.macros.contains_key(&name) {
+            return Err(MacroError::Redefinition(name));
+        }
+        // Definitions are trusted, except that references to other macros
+        // mustn't expand to more nodes than filters are allowed to.
+        let parser = Parser::new(
+            self,
+            ParseLimits {
+                max_macro_nodes: ParseLimits::default().max_macro_nodes,
+                ..ParseLimits::trusted()
+            },
+        );
+        let ast = match self.parse_with(&definition, &parser) {
+            Ok(ast) => ast,
+            Err(err) => {
+                return Err(MacroError::InvalidDefinition {
+                    message: err.to_string(),
+                    name,
+                });
+            }
+        };
+        let mut nodes = 0;
+        ast.walk_nodes(&mut |node, _| {
+            nodes += match node {
+                NodeKind::Macro(name) => self.macros[name].nodes,
+                _ => 1,
+            };
+        });
+        let expansion = ast.unbind();
+        self.macros.insert(
+            name,
+            Macro {
+                definition,
+                expansion,
+                nodes,
+            },
+        );
+        Ok(())
+    }
+
+    /// Registers a list of macros, which can refer to each other regardless
+    /// of their order.
+    pub fn add_macros<I>(&mut self, macros: I) -> Result<(), MacroError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut pending = macros.into_iter().collect::<Vec<_>>();
+        // Keep adding macros whose references are resolved until no
+        // progress is made.
+        while !pending.is_empty() {
+            let count = pending.len();
+            let mut last_err = None;
+            for (name, definition) in mem::take(&mut pending) {
+                match self.add_macro(name.clone(), definition.clone()) {
+                    Ok(()) => {}
+                    Err(err @ MacroError::InvalidDefinition { .. }) => {
+                        pending.push((name, definition));
+                        last_err = Some(err);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            if pending.len() == count {
+                return Err(last_err.unwrap());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the definition of a macro.
+    pub fn get_macro_definition(&self, name: &str) -> Result<&str, UnknownMacroError> {
+        self.macros
+            .get(name)
+            .map(|m| m.definition.as_str())
+            .ok_or(UnknownMacroError)
+    }
+
+    pub(crate) fn get_macro(&self, name: &str) -> Result<&Macro, UnknownMacroError> {
+        self.macros.get(name).ok_or(UnknownMacroError)
+    }
+}
+
+#[test]
+fn test_macros() {
+    use crate::{
+        lex::LexErrorKind,
+        scheme::{IncompatibleSchemeError, SchemeIncompatibility, UnknownFieldError},
+        suggest::Suggestions,
+    };
+
+    let mut scheme = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+
+    scheme
+        .add_macro("web".into(), "tcp.port in {80 443}".into())
+        .unwrap();
+    scheme
+        .add_macro(
+            "known_bots".into(),
+            r#"$web && (http.host contains "bot" || ip.src in {10.0.0.0/8})"#.into(),
+        )
+        .unwrap();
+
+    assert_eq!(
+        scheme.add_macro("web".into(), "tcp.port == 80".into()),
+        Err(MacroError::Redefinition("web".into()))
+    );
+    assert_eq!(
+        scheme.add_macro("bad bots".into(), "tcp.port == 80".into()),
+        Err(MacroError::InvalidName("bad bots".into()))
+    );
+    assert_eq!(
+        scheme
+            .add_macro("a".into(), "$b".into())
+            .unwrap_err()
+            .to_string(),
+        "invalid definition of macro a: Filter parsing error (1:2):\n$b\n ^ unknown macro\n"
+    );
+
+    let ast = scheme.parse("$known_bots and not ssl").err().unwrap();
+    assert_eq!(
+        ast.kind,
+        LexErrorKind::UnknownField(UnknownFieldError, Suggestions::default())
+    );
+
+    let ast = scheme.parse("not $known_bots").unwrap();
+    assert_eq!(ast.uses("ip.src"), Ok(true));
+    assert_eq!(ast.uses("tcp.port"), Ok(true));
+    let expanded = r#"not (
+        tcp.port in {80 443} && (http.host contains "bot" || ip.src in {10.0.0.0/8})
+    )"#;
+    assert_eq!(
+        serde_json::to_value(&ast).unwrap(),
+        serde_json::to_value(scheme.parse(expanded).unwrap()).unwrap()
+    );
+
+    let json = serde_json::to_value(&scheme).unwrap();
+    assert_eq!(
+        json["macros"],
+        serde_json::json!({
+            "web": "tcp.port in {80 443}",
+            "known_bots": r#"$web && (http.host contains "bot" || ip.src in {10.0.0.0/8})"#
+        })
+    );
+    // JSON objects don't preserve the order of macros.
+    let deserialized: Scheme = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&deserialized).unwrap(), json);
+
+    // Rebinding expands macros as defined in the other scheme.
+    let mut other = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+    other
+        .add_macro("known_bots".into(), "tcp.port == 22".into())
+        .unwrap();
+    assert_eq!(
+        scheme.check_compatibility(&other),
+        Err(IncompatibleSchemeError(vec![
+            SchemeIncompatibility::MissingMacro("web".into())
+        ]))
+    );
+    let rebound = ast.rebind(&other).unwrap();
+    assert_eq!(rebound, other.parse("not $known_bots").unwrap());
+    assert_json!(rebound, { "op": "Not", "arg": { "lhs": "tcp.port", "op": "Equal", "rhs": 22 } });
+}
+
+#[test]
+fn test_macro_expansion_limit() {
+    use crate::lex::LexErrorKind;
+
+    let mut scheme = Scheme! { num: Int };
+    scheme.add_macro("m0".into(), "num == 1".into()).unwrap();
+    // Each macro expands to twice as many nodes as the previous one.
+    let mut count = 1;
+    let err = loop {
+        let definition = format!("$m{} or $m{}", count - 1, count - 1);
+        match scheme.add_macro(format!("m{}", count), definition) {
+            Ok(()) => count += 1,
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(count, 15);
+    assert_eq!(
+        err.to_string(),
+        "invalid definition of macro m15: Filter parsing error (1:10):\n\
+         $m14 or $m14\n         ^^^ macros expand to more than 65536 nodes\n"
+    );
+
+    assert!(scheme.parse("$m14").is_ok());
+    let err = scheme.parse("$m14 or $m14").unwrap_err();
+    assert_eq!(
+        (err.kind, err.span_start),
+        (LexErrorKind::MacroTooLarge { limit: 1 << 16 }, 9)
+    );
+
+    let limits = ParseLimits {
+        max_macro_nodes: 10,
+        ..ParseLimits::default()
+    };
+    assert!(scheme.parse_with_limits("$m0 or $m1", &limits).is_ok());
+    let err = scheme
+        .parse_with_limits("$m0 or $m1 or $m2", &limits)
+        .unwrap_err();
+    assert_eq!(
+        (err.kind, err.span_start),
+        (LexErrorKind::MacroTooLarge { limit: 10 }, 15)
+    );
+}
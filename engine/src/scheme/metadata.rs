@@ -0,0 +1,192 @@
+use super::{Scheme, UnknownFieldError, UnknownFunctionError};
+use crate::{functions::Function, metadata::Metadata, types::Type};
+use serde::Serialize;
+
+/// A field or a function of a [`Scheme`](struct@Scheme), as returned by
+/// [`Scheme::items_with_prefix`].
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SchemeItem<'s> {
+    /// Name of the item.
+    pub name: &'s str,
+    /// What kind of item this is.
+    #[serde(flatten)]
+    pub kind: SchemeItemKind<'s>,
+    /// Metadata attached to the item, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<&'s Metadata>,
+}
+
+/// Kind of a [`SchemeItem`].
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind")]
+pub enum SchemeItemKind<'s> {
+    /// A field of the given type.
+    Field {
+        /// Field type.
+        #[serde(rename = "type")]
+        ty: Type,
+    },
+    /// A function with the given signature.
+    Function(&'s Function),
+}
+
+impl Scheme {
+    /// Attaches metadata to a field, replacing any previous one.
+    pub fn set_field_metadata(
+        &mut self,
+        name: &str,
+        metadata: Metadata,
+    ) -> Result<(), UnknownFieldError> {
+        let name = self.get_field_index(name)?.name().to_owned();
+        self.metadata.insert(name, metadata);
+        Ok(())
+    }
+
+    /// Returns metadata attached to a field.
+    pub fn get_field_metadata(&self, name: &str) -> Result<Option<&Metadata>, UnknownFieldError> {
+        let field = self.get_field_index(name)?;
+        Ok(self.metadata.get(field.name()))
+    }
+
+    /// Attaches metadata to a function, replacing any previous one.
+    pub fn set_function_metadata(
+        &mut self,
+        name: &str,
+        metadata: Metadata,
+    ) -> Result<(), UnknownFunctionError> {
+        self.get_function(name)?;
+        self.metadata.insert(name.to_owned(), metadata);
+        Ok(())
+    }
+
+    /// Returns metadata attached to a function.
+    pub fn get_function_metadata(
+        &self,
+        name: &str,
+    ) -> Result<Option<&Metadata>, UnknownFunctionError> {
+        self.get_function(name)?;
+        Ok(self.metadata.get(name))
+    }
+
+    /// Returns all fields and functions whose names start with `prefix`,
+    /// along with their metadata.
+    ///
+    /// This is meant to be used for autocompletion in rule editors.
+    pub fn items_with_prefix(&self, prefix: &str) -> Vec<SchemeItem<'_>> {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(name, &ty)| (name, SchemeItemKind::Field { ty }));
+        let functions = self
+            .functions
+            .iter()
+            .map(|(name, function)| (name, SchemeItemKind::Function(function)));
+        fields
+            .chain(functions)
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, kind)| SchemeItem {
+                name,
+                kind,
+                metadata: self.metadata.get(name),
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_metadata() {
+    use crate::scheme::{test_functions, FunctionRegistry};
+
+    let mut scheme = Scheme! { http.host: Bytes, http.request.method: Bytes, ip.src: Ip };
+    scheme.add_functions(test_functions()).unwrap();
+    scheme
+        .add_field_alias("http.method".into(), "http.request.method")
+        .unwrap();
+
+    let host = Metadata {
+        description: Some("Hostname of the request".into()),
+        examples: vec![r#""example.org""#.into()],
+        ..Default::default()
+    };
+    scheme
+        .set_field_metadata("http.host", host.clone())
+        .unwrap();
+
+    let method = Metadata {
+        operators: vec!["==".into(), "in".into()],
+        ..Default::default()
+    };
+    scheme
+        .set_field_metadata("http.method", method.clone())
+        .unwrap();
+
+    let echo = Metadata {
+        description: Some("Returns its argument".into()),
+        ..Default::default()
+    };
+    scheme.set_function_metadata("echo", echo.clone()).unwrap();
+
+    assert_eq!(
+        scheme.set_field_metadata("http.path", Metadata::default()),
+        Err(UnknownFieldError)
+    );
+    assert_eq!(
+        scheme.set_function_metadata("http.host", Metadata::default()),
+        Err(UnknownFunctionError)
+    );
+
+    assert_eq!(scheme.get_field_metadata("http.host"), Ok(Some(&host)));
+    assert_eq!(
+        scheme.get_field_metadata("http.request.method"),
+        Ok(Some(&method))
+    );
+    assert_eq!(scheme.get_field_metadata("ip.src"), Ok(None));
+    assert_eq!(scheme.get_function_metadata("echo"), Ok(Some(&echo)));
+
+    let items = scheme.items_with_prefix("http.");
+    assert_eq!(
+        items.iter().map(|item| item.name).collect::<Vec<_>>(),
+        ["http.host", "http.request.method"]
+    );
+    assert_eq!(scheme.items_with_prefix("").len(), 4);
+    assert_eq!(scheme.items_with_prefix("x").len(), 0);
+
+    assert_json!(
+        scheme.items_with_prefix("e"),
+        [
+            {
+                "name": "echo",
+                "kind": "Function",
+                "params": [{ "arg_kind": "Field", "val_type": "Bytes", "generic": false }],
+                "opt_params": [{ "arg_kind": "Literal", "val_type": "Int", "default_value": 1 }],
+                "return_type": "Bytes",
+                "pure": true,
+                "metadata": { "description": "Returns its argument" }
+            }
+        ]
+    );
+
+    assert_json!(
+        scheme.items_with_prefix("ip"),
+        [{ "name": "ip.src", "kind": "Field", "type": "Ip" }]
+    );
+
+    let json = serde_json::to_value(&scheme).unwrap();
+    assert_eq!(
+        json["metadata"],
+        serde_json::json!({
+            "http.host": {
+                "description": "Hostname of the request",
+                "examples": ["\"example.org\""]
+            },
+            "http.request.method": { "operators": ["==", "in"] },
+            "echo": { "description": "Returns its argument" }
+        })
+    );
+
+    let mut registry = FunctionRegistry::new();
+    registry.add_functions(test_functions()).unwrap();
+    let scheme = registry.deserialize_scheme(json).unwrap();
+    assert_eq!(scheme.get_field_metadata("http.host"), Ok(Some(&host)));
+    assert_eq!(scheme.get_function_metadata("echo"), Ok(Some(&echo)));
+}
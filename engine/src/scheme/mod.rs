@@ -0,0 +1,1595 @@
+/// A convenience macro for constructing a [`Scheme`](struct@Scheme) with static
+/// contents.
+#[macro_export]
+macro_rules! Scheme {
+    ($($ns:ident $(. $field:ident)*: $ty:ident),* $(,)*) => {
+        $crate::Scheme::try_from_iter(
+            [$(
+                (
+                    concat!(stringify!($ns) $(, ".", stringify!($field))*),
+                    $crate::Type::$ty
+                )
+            ),*]
+            .iter()
+            .map(|&(k, v)| (k.to_owned(), v)),
+        )
+        // Treat duplciations in static schemes as a developer's mistake.
+        .unwrap_or_else(|err| panic!("{}", err))
+    };
+}
+
+mod aliases;
+mod compatibility;
+mod defaults;
+mod deprecations;
+mod macros;
+mod metadata;
+mod objects;
+mod serialization;
+mod virtual_fields;
+
+use self::{macros::Macro, objects::Object, virtual_fields::VirtualField};
+use crate::{
+    ast::{FilterAst, NodeKind, Span},
+    functions::Function,
+    lex::{
+        self, complete, expect, span, take_while, trim, LexError, LexErrorKind, LexResult, LexWith,
+        ParseErrorCode,
+    },
+    limits::{ParseLimits, Parser},
+    metadata::Metadata,
+    suggest::Suggestions,
+    types::{GetType, LhsValue, Type},
+};
+use failure::Fail;
+use fnv::FnvBuildHasher;
+use indexmap::map::{Entry, IndexMap};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::{
+    cmp::{max, min},
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    ptr,
+};
+
+pub use self::{
+    aliases::AliasError,
+    compatibility::{IncompatibleSchemeError, SchemeIncompatibility},
+    defaults::DefaultValueError,
+    deprecations::ParseWarning,
+    macros::{MacroError, UnknownMacroError},
+    metadata::{SchemeItem, SchemeItemKind},
+    objects::ObjectFieldError,
+    serialization::FunctionRegistry,
+    virtual_fields::VirtualFieldError,
+};
+
+pub(crate) use self::compatibility::Rebinder;
+
+#[derive(Clone, Copy)]
+pub(crate) struct Field<'s> {
+    scheme: &'s Scheme,
+    index: usize,
+    // Index of the alias the field was referred to by, if any.
+    alias: Option<usize>,
+}
+
+// Aliases resolve to the same field, so they aren't taken into account.
+impl<'s> PartialEq for Field<'s> {
+    fn eq(&self, other: &Self) -> bool {
+        self.scheme == other.scheme && self.index == other.index
+    }
+}
+
+impl<'s> Eq for Field<'s> {}
+
+impl<'s> Serialize for Field<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.spelling().serialize(ser)
+    }
+}
+
+impl<'s> Debug for Field<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.spelling())
+    }
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for Field<'s> {
+    fn lex_with(mut input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let scheme = parser.scheme;
+        let initial_input = input;
+
+        loop {
+            input = take_while(input, "identifier character", |c| {
+                c.is_ascii_alphanumeric() || c == '_'
+            })?
+            .1;
+
+            match expect(input, ".") {
+                Ok(rest) => input = rest,
+                Err(_) => break,
+            };
+        }
+
+        let name = span(initial_input, input);
+
+        let field = scheme.get_field_index(name).map_err(|err| {
+            let kind = match scheme.get_object_error(name) {
+                Some(err) => LexErrorKind::ObjectField(err),
+                None => LexErrorKind::UnknownField(err, scheme.suggest_fields(name)),
+            };
+            (kind, name)
+        })?;
+
+        Ok((field, input))
+    }
+}
+
+impl<'s> Field<'s> {
+    /// Returns the canonical name of the field.
+    pub fn name(&self) -> &'s str {
+        self.scheme.fields.get_index(self.index).unwrap().0
+    }
+
+    /// Returns the alias the field was referred to by, if any.
+    pub fn alias(&self) -> Option<&'s str> {
+        self.alias
+            .map(|alias| self.scheme.aliases.get_index(alias).unwrap().0.as_str())
+    }
+
+    /// Returns the name the field was referred to by.
+    pub fn spelling(&self) -> &'s str {
+        self.alias().unwrap_or_else(|| self.name())
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn scheme(&self) -> &'s Scheme {
+        self.scheme
+    }
+
+    /// Returns whether the field is computed from other fields.
+    pub fn is_virtual(&self) -> bool {
+        self.scheme.virtual_fields.contains_key(&self.index)
+    }
+
+    /// Returns the definition of a virtual field.
+    pub fn virtual_definition(&self) -> Option<&'s str> {
+        self.scheme
+            .virtual_fields
+            .get(&self.index)
+            .map(|field| field.definition.as_str())
+    }
+
+    /// Returns whether the field is virtual and computed, directly or not,
+    /// from `other`.
+    pub fn depends_on(&self, other: Field<'s>) -> bool {
+        self.scheme
+            .virtual_fields
+            .get(&self.index)
+            .is_some_and(|field| field.dependencies.contains(&other.index))
+    }
+
+    pub fn unbind(&self) -> UnboundField {
+        UnboundField {
+            index: self.index,
+            alias: self.alias,
+        }
+    }
+}
+
+/// A [`Field`] detached from its scheme, as stored in expansions of macros.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UnboundField {
+    index: usize,
+    alias: Option<usize>,
+}
+
+impl UnboundField {
+    /// Binds the field to the scheme it was taken from.
+    pub fn bind(self, scheme: &Scheme) -> Field<'_> {
+        Field {
+            scheme,
+            index: self.index,
+            alias: self.alias,
+        }
+    }
+}
+
+impl<'s> GetType for Field<'s> {
+    fn get_type(&self) -> Type {
+        *self.scheme.fields.get_index(self.index).unwrap().1
+    }
+}
+
+/// An error that occurs if an unregistered field name was queried from a
+/// [`Scheme`](struct@Scheme).
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "unknown field")]
+pub struct UnknownFieldError;
+
+/// An error that occurs if an unregistered function name was queried from a
+/// [`Scheme`](struct@Scheme).
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "unknown function")]
+pub struct UnknownFunctionError;
+
+/// An error that occurs when previously defined field gets redefined.
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "attempt to redefine field {}", _0)]
+pub struct FieldRedefinitionError(String);
+
+/// An error that occurs when previously defined function gets redefined.
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "attempt to redefine function {}", _0)]
+pub struct FunctionRedefinitionError(String);
+
+#[derive(Debug, PartialEq, Fail)]
+pub enum ItemRedefinitionError {
+    #[fail(display = "{}", _0)]
+    Field(#[cause] FieldRedefinitionError),
+
+    #[fail(display = "{}", _0)]
+    Function(#[cause] FunctionRedefinitionError),
+}
+
+/// A comment in a filter, as returned by
+/// [`Scheme::parse_with_comments`](struct@Scheme).
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Comment {
+    /// Text of the comment, including `#` or `/*` and `*/`.
+    pub text: String,
+    /// Location of the comment in the filter.
+    pub span: Span,
+}
+
+/// A filter parsing error associated with the original input.
+///
+/// Besides printing it in a human-readable fashion, you can inspect its
+/// [code](ParseError::code) and location, or serialize all of them for tools
+/// such as editors.
+#[derive(Debug, PartialEq)]
+pub struct ParseError<'i> {
+    kind: LexErrorKind,
+    code: ParseErrorCode,
+    // Line of the error, which is all of the input that gets printed.
+    input: &'i str,
+    line_number: usize,
+    // Offset of the line in the original input.
+    line_start: usize,
+    span_start: usize,
+    span_len: usize,
+}
+
+impl<'i> Error for ParseError<'i> {}
+
+impl<'i> ParseError<'i> {
+    pub(crate) fn new(mut input: &'i str, (kind, span): (LexErrorKind, &'i str)) -> Self {
+        let code = kind.code(span);
+        let mut span_start = span.as_ptr() as usize - input.as_ptr() as usize;
+
+        let (line_number, line_start) = input[..span_start]
+            .match_indices('\n')
+            .map(|(pos, _)| pos + 1)
+            .scan(0, |line_number, line_start| {
+                *line_number += 1;
+                Some((*line_number, line_start))
+            })
+            .last()
+            .unwrap_or_default();
+
+        input = &input[line_start..];
+
+        span_start -= line_start;
+        let mut span_len = span.len();
+
+        if let Some(line_end) = input.find('\n') {
+            input = &input[..line_end];
+            span_len = min(span_len, line_end - span_start);
+        }
+
+        ParseError {
+            kind,
+            code,
+            input,
+            line_number,
+            line_start,
+            span_start,
+            span_len,
+        }
+    }
+
+    /// Returns the code of the error.
+    pub fn code(&self) -> ParseErrorCode {
+        self.code
+    }
+
+    /// Returns the message of the error, without its location.
+    pub fn message(&self) -> String {
+        self.kind.to_string()
+    }
+
+    /// Returns the line of the error, starting from 1.
+    pub fn line(&self) -> usize {
+        self.line_number + 1
+    }
+
+    /// Returns the column of the error, in bytes and starting from 1.
+    pub fn column(&self) -> usize {
+        self.span_start + 1
+    }
+
+    /// Returns the range of bytes of the original input the error points at.
+    ///
+    /// Errors never span multiple lines, so the range is cut at the end of
+    /// the line it starts on.
+    pub fn span(&self) -> Span {
+        let start = self.line_start + self.span_start;
+        Span {
+            start,
+            end: start + self.span_len,
+        }
+    }
+
+    /// Returns the tokens, or the fields in case of an object, that were
+    /// expected where the error occurred.
+    ///
+    /// Literal tokens are quoted, e.g. `"("`, unlike classes of tokens such
+    /// as `digit`.
+    pub fn expected(&self) -> Vec<String> {
+        match &self.kind {
+            LexErrorKind::ExpectedName(name) => vec![(*name).to_owned()],
+            LexErrorKind::ExpectedLiteral(literal) => vec![format!("{:?}", literal)],
+            LexErrorKind::ObjectField(err) => err.fields.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns registered names that an unknown field or function might have
+    /// been meant as, best matches first.
+    pub fn suggestions(&self) -> &[String] {
+        match &self.kind {
+            LexErrorKind::UnknownField(_, suggestions)
+            | LexErrorKind::UnknownFunction(_, suggestions) => suggestions.names(),
+            _ => &[],
+        }
+    }
+}
+
+impl<'i> Serialize for ParseError<'i> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let span = self.span();
+        let mut out = ser.serialize_struct("ParseError", 8)?;
+        out.serialize_field("code", &self.code())?;
+        out.serialize_field("message", &self.message())?;
+        out.serialize_field("span_start", &span.start)?;
+        out.serialize_field("span_len", &self.span_len)?;
+        out.serialize_field("line", &self.line())?;
+        out.serialize_field("column", &self.column())?;
+        out.serialize_field("expected", &self.expected())?;
+        out.serialize_field("suggestions", self.suggestions())?;
+        out.end()
+    }
+}
+
+impl<'i> Display for ParseError<'i> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Filter parsing error ({}:{}):",
+            self.line_number + 1,
+            self.span_start + 1
+        )?;
+
+        writeln!(f, "{}", self.input)?;
+
+        for _ in 0..self.span_start {
+            write!(f, " ")?;
+        }
+
+        for _ in 0..max(1, self.span_len) {
+            write!(f, "^")?;
+        }
+
+        writeln!(f, " {}", self.kind)?;
+
+        Ok(())
+    }
+}
+
+/// A single filter parsing error, as returned by
+/// [`Scheme::parse_with_diagnostics`].
+///
+/// Unlike [`ParseError`], it doesn't borrow the input, and can be serialized
+/// to report errors to rule authors.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Diagnostic {
+    /// Code of the error.
+    pub code: ParseErrorCode,
+    /// Human-readable description of the error.
+    pub message: String,
+    /// Byte offset of the start of the error in the input.
+    pub span_start: usize,
+    /// Length of the error span in bytes.
+    pub span_len: usize,
+    /// Line of the error, starting from 1.
+    pub line: usize,
+    /// Column of the error, in bytes and starting from 1.
+    pub column: usize,
+    /// Tokens or fields that were expected instead, see
+    /// [`ParseError::expected`].
+    pub expected: Vec<String>,
+    /// Names that an unknown field or function might have been meant as.
+    pub suggestions: Vec<String>,
+}
+
+impl Diagnostic {
+    fn new<'i>(input: &'i str, err: LexError<'i>) -> Self {
+        let err = ParseError::new(input, err);
+
+        Diagnostic {
+            code: err.code(),
+            message: err.message(),
+            span_start: err.span().start,
+            span_len: err.span_len,
+            line: err.line(),
+            column: err.column(),
+            expected: err.expected(),
+            suggestions: err.suggestions().to_vec(),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// The main registry for fields and their associated types.
+///
+/// This is necessary to provide typechecking for runtime values provided
+/// to the [execution context](::ExecutionContext) and also to aid parser
+/// in ambiguous contexts.
+///
+/// A scheme is serialized as an object with `fields` and `functions`
+/// properties, where nested objects are described as maps of their members
+/// to types and functions are described by their signatures only, as
+/// well as `virtual_fields`, `macros`, `aliases`, `deprecations`,
+/// `defaults` and `metadata` if there are any. It can be deserialized either
+/// from such an object or from a plain map of field names to types, which is
+/// recognized by the value of its first entry not being a map, unless it's a
+/// nested object whose name isn't one of the properties above. Function
+/// implementations can't be serialized, so schemes declaring functions must
+/// be deserialized with a [`FunctionRegistry`].
+#[derive(Default)]
+pub struct Scheme {
+    fields: IndexMap<String, Type, FnvBuildHasher>,
+    functions: IndexMap<String, Function, FnvBuildHasher>,
+    // Maps paths of nested objects to their members.
+    objects: IndexMap<String, Object, FnvBuildHasher>,
+    // Maps indices of virtual fields to their definitions.
+    virtual_fields: IndexMap<usize, VirtualField, FnvBuildHasher>,
+    // Maps macro names to their definitions.
+    macros: IndexMap<String, Macro, FnvBuildHasher>,
+    // Maps alias names to field indices.
+    aliases: IndexMap<String, usize, FnvBuildHasher>,
+    // Maps deprecated field and alias names to explanatory notes.
+    deprecations: IndexMap<String, String, FnvBuildHasher>,
+    // Maps field indices to values used when none is set at runtime.
+    defaults: IndexMap<usize, LhsValue<'static>, FnvBuildHasher>,
+    // Maps field and function names to their documentation.
+    metadata: IndexMap<String, Metadata, FnvBuildHasher>,
+}
+
+impl PartialEq for Scheme {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl Eq for Scheme {}
+
+impl<'s> Scheme {
+    /// Creates a new scheme.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a new scheme with capacity for `n` fields.
+    pub fn with_capacity(n: usize) -> Self {
+        Scheme {
+            fields: IndexMap::with_capacity_and_hasher(n, FnvBuildHasher::default()),
+            ..Default::default()
+        }
+    }
+
+    /// Registers a field and its corresponding type.
+    pub fn add_field(&mut self, name: String, ty: Type) -> Result<(), ItemRedefinitionError> {
+        if self.functions.contains_key(&name) {
+            return Err(ItemRedefinitionError::Function(FunctionRedefinitionError(
+                name,
+            )));
+        };
+        if self.aliases.contains_key(&name) || self.objects.contains_key(&name) {
+            return Err(ItemRedefinitionError::Field(FieldRedefinitionError(name)));
+        };
+        match self.fields.entry(name) {
+            Entry::Occupied(entry) => Err(ItemRedefinitionError::Field(FieldRedefinitionError(
+                entry.key().to_string(),
+            ))),
+            Entry::Vacant(entry) => {
+                entry.insert(ty);
+                Ok(())
+            }
+        }
+    }
+
+    /// Registers a series of fields from an iterable, reporting any conflicts.
+    pub fn try_from_iter(
+        iter: impl IntoIterator<Item = (String, Type)>,
+    ) -> Result<Self, ItemRedefinitionError> {
+        let iter = iter.into_iter();
+        let (low, _) = iter.size_hint();
+        let mut scheme = Scheme::with_capacity(low);
+        for (name, value) in iter {
+            scheme.add_field(name, value)?;
+        }
+        Ok(scheme)
+    }
+
+    /// Returns the type of a field, looked up by its name or by one of its
+    /// aliases.
+    pub fn get_field_type(&self, name: &str) -> Result<Type, UnknownFieldError> {
+        self.get_field_index(name).map(|field| field.get_type())
+    }
+
+    pub(crate) fn get_field_index(&'s self, name: &str) -> Result<Field<'s>, UnknownFieldError> {
+        if let Some((index, ..)) = self.fields.get_full(name) {
+            return Ok(Field {
+                scheme: self,
+                index,
+                alias: None,
+            });
+        }
+        match self.aliases.get_full(name) {
+            Some((alias, _, &index)) => Ok(Field {
+                scheme: self,
+                index,
+                alias: Some(alias),
+            }),
+            None => Err(UnknownFieldError),
+        }
+    }
+
+    pub(crate) fn get_field_count(&self) -> usize {
+        self.fields.len()
+    }
+
+    fn suggest_fields(&self, name: &str) -> Suggestions {
+        let fields = self.fields.keys().chain(self.aliases.keys());
+        Suggestions::new(name, fields.map(String::as_str))
+    }
+
+    pub(crate) fn suggest_functions(&self, name: &str) -> Suggestions {
+        Suggestions::new(name, self.functions.keys().map(String::as_str))
+    }
+
+    /// Registers a function
+    pub fn add_function(
+        &mut self,
+        name: String,
+        function: Function,
+    ) -> Result<(), ItemRedefinitionError> {
+        if self.fields.contains_key(&name)
+            || self.aliases.contains_key(&name)
+            || self.objects.contains_key(&name)
+        {
+            return Err(ItemRedefinitionError::Field(FieldRedefinitionError(name)));
+        };
+        match self.functions.entry(name) {
+            Entry::Occupied(entry) => Err(ItemRedefinitionError::Function(
+                FunctionRedefinitionError(entry.key().to_string()),
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(function);
+                Ok(())
+            }
+        }
+    }
+
+    /// Registers a list of functions
+    pub fn add_functions<I>(&mut self, functions: I) -> Result<(), ItemRedefinitionError>
+    where
+        I: IntoIterator<Item = (String, Function)>,
+    {
+        for (name, func) in functions {
+            self.add_function(name, func)?;
+        }
+        Ok(())
+    }
+
+    /// Looks up a function by its name.
+    pub fn get_function(&'s self, name: &str) -> Result<&'s Function, UnknownFunctionError> {
+        self.functions.get(name).ok_or(UnknownFunctionError)
+    }
+
+    // Functions can't be unregistered, so this never fails for names taken
+    // from a filter parsed with this scheme.
+    pub(crate) fn get_parsed_function(&'s self, name: &str) -> &'s Function {
+        &self.functions[name]
+    }
+
+    /// Parses a filter into an AST form.
+    ///
+    /// Fails if the filter exceeds the default [limits](ParseLimits), which
+    /// only keep deeply nested filters and expansions of macros from
+    /// exhausting the stack or memory.
+    pub fn parse<'i>(&'s self, input: &'i str) -> Result<FilterAst<'s>, ParseError<'i>> {
+        self.parse_with_limits(input, &ParseLimits::default())
+    }
+
+    /// Parses a filter into an AST form, failing if it exceeds `limits`.
+    pub fn parse_with_limits<'i>(
+        &'s self,
+        input: &'i str,
+        limits: &ParseLimits,
+    ) -> Result<FilterAst<'s>, ParseError<'i>> {
+        limits
+            .check_input_len(input)
+            .map_err(|err| ParseError::new(input, err))?;
+        self.parse_with(input, &Parser::new(self, *limits))
+    }
+
+    fn parse_with<'i>(
+        &'s self,
+        input: &'i str,
+        parser: &Parser<'s>,
+    ) -> Result<FilterAst<'s>, ParseError<'i>> {
+        let mut ast = complete(FilterAst::lex_with(trim(input), parser))
+            .map_err(|err| ParseError::new(input, err))?;
+        ast.resolve_spans(input.trim_end().len());
+        Ok(ast)
+    }
+
+    /// Parses a filter into an AST form, reporting all errors found in the
+    /// input instead of only the first one.
+    ///
+    /// After an error, parsing resumes at the next `and` / `or` / `xor`
+    /// operator or closing parenthesis, so each broken subexpression is
+    /// reported once.
+    pub fn parse_with_diagnostics(&'s self, input: &str) -> Result<FilterAst<'s>, Vec<Diagnostic>> {
+        self.parse_with_diagnostics_and_limits(input, &ParseLimits::default())
+    }
+
+    /// Same as [`Scheme::parse_with_diagnostics`], but fails if the filter
+    /// exceeds `limits` instead of the default ones.
+    pub fn parse_with_diagnostics_and_limits(
+        &'s self,
+        input: &str,
+        limits: &ParseLimits,
+    ) -> Result<FilterAst<'s>, Vec<Diagnostic>> {
+        self.parse_with_limits(input, limits).map_err(|_| {
+            if let Err(err) = limits.check_input_len(input) {
+                return vec![Diagnostic::new(input, err)];
+            }
+            let errors = FilterAst::recover(trim(input), &Parser::new(self, *limits));
+
+            // NOTE: the recovering lexer is more lenient than the regular
+            // one, so fall back to the error of the latter if needed.
+            if errors.is_empty() {
+                let parser = Parser::new(self, *limits);
+                let err = complete(FilterAst::lex_with(trim(input), &parser)).unwrap_err();
+                return vec![Diagnostic::new(input, err)];
+            }
+
+            errors
+                .into_iter()
+                .map(|err| Diagnostic::new(input, err))
+                .collect()
+        })
+    }
+
+    /// Parses a filter into an AST form, also returning all comments in it in
+    /// the order they are written.
+    ///
+    /// The AST can be printed back along with the comments using
+    /// [`FilterAst::with_comments`](::FilterAst::with_comments).
+    pub fn parse_with_comments<'i>(
+        &'s self,
+        input: &'i str,
+    ) -> Result<(FilterAst<'s>, Vec<Comment>), ParseError<'i>> {
+        let ast = self.parse(input)?;
+
+        // Literals other than sets can contain anything that looks like a
+        // comment, so only look in between them.
+        let mut literals = Vec::new();
+        ast.walk_nodes(&mut |kind, span| {
+            if kind == NodeKind::Literal && !input[span.start..].starts_with('{') {
+                literals.push(span);
+            }
+        });
+        literals.sort_by_key(|span| span.start);
+
+        let end = Span {
+            start: input.len(),
+            end: input.len(),
+        };
+        let mut start = 0;
+        let mut comments = Vec::new();
+        for literal in literals.into_iter().chain(Some(end)) {
+            for text in lex::comments(&input[start..literal.start]) {
+                let offset = text.as_ptr() as usize - input.as_ptr() as usize;
+                comments.push(Comment {
+                    text: text.to_owned(),
+                    span: Span {
+                        start: offset,
+                        end: offset + text.len(),
+                    },
+                });
+            }
+            start = literal.end;
+        }
+
+        Ok((ast, comments))
+    }
+}
+
+#[test]
+fn test_parse_error() {
+    use indoc::indoc;
+
+    let scheme = &Scheme! { num: Int };
+
+    {
+        let err = scheme.parse("xyz").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                kind: LexErrorKind::UnknownField(UnknownFieldError, Suggestions::default()),
+                code: ParseErrorCode::UnknownField,
+                input: "xyz",
+                line_number: 0,
+                line_start: 0,
+                span_start: 0,
+                span_len: 3
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (1:1):
+                xyz
+                ^^^ unknown field
+                "#
+            )
+        );
+    }
+
+    {
+        let err = scheme.parse("xyz\n").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                kind: LexErrorKind::UnknownField(UnknownFieldError, Suggestions::default()),
+                code: ParseErrorCode::UnknownField,
+                input: "xyz",
+                line_number: 0,
+                line_start: 0,
+                span_start: 0,
+                span_len: 3
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (1:1):
+                xyz
+                ^^^ unknown field
+                "#
+            )
+        );
+    }
+
+    {
+        let err = scheme.parse("\n\n    xyz").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                kind: LexErrorKind::UnknownField(UnknownFieldError, Suggestions::default()),
+                code: ParseErrorCode::UnknownField,
+                input: "    xyz",
+                line_number: 2,
+                line_start: 2,
+                span_start: 4,
+                span_len: 3
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (3:5):
+                    xyz
+                    ^^^ unknown field
+                "#
+            )
+        );
+    }
+
+    {
+        let err = scheme
+            .parse(indoc!(
+                r#"
+                num == 10 or
+                num == true or
+                num == 20
+                "#
+            ))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                kind: LexErrorKind::ExpectedName("digit"),
+                code: ParseErrorCode::ExpectedNumber,
+                input: "num == true or",
+                line_number: 1,
+                line_start: 13,
+                span_start: 7,
+                span_len: 7
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (2:8):
+                num == true or
+                       ^^^^^^^ expected digit
+                "#
+            )
+        );
+    }
+}
+
+#[test]
+fn test_parse_error_details() {
+    let mut scheme = Scheme! { num: Int };
+    scheme.add_functions(test_functions()).unwrap();
+    scheme
+        .add_nested_field(
+            "http".into(),
+            vec![("method", Type::Bytes), ("uri", Type::Bytes)]
+                .into_iter()
+                .collect(),
+        )
+        .unwrap();
+
+    let input = "num == 1 or\n  num == \"a\"";
+    let err = scheme.parse(input).unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::ExpectedNumber);
+    assert_eq!(err.message(), "expected digit");
+    assert_eq!((err.line(), err.column()), (2, 10));
+    assert_eq!(err.span().source(input), Some("\"a\""));
+    assert_eq!(err.expected(), ["digit"]);
+    assert_json!(
+        err,
+        {
+            "code": "ExpectedNumber",
+            "message": "expected digit",
+            "span_start": 21,
+            "span_len": 3,
+            "line": 2,
+            "column": 10,
+            "expected": ["digit"],
+            "suggestions": []
+        }
+    );
+
+    let err = scheme.parse("(num == 1").unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::UnexpectedEnd);
+    assert_eq!(err.expected(), ["\")\""]);
+
+    let err = scheme.parse("(num == 1 num").unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::ExpectedToken);
+    assert_eq!(err.expected(), ["\")\""]);
+
+    let err = scheme.parse("num == 1 or").unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::UnexpectedEnd);
+    assert_eq!(err.expected(), ["identifier character"]);
+
+    let err = scheme.parse("num == 1 or == 1").unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::ExpectedName);
+    assert_eq!(err.expected(), ["identifier character"]);
+
+    let err = scheme.parse("num == 1 1").unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::UnexpectedInput);
+
+    let err = scheme.parse("http == \"/\"").unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::ObjectField);
+    assert_eq!(err.expected(), ["http.method", "http.uri"]);
+
+    let err = scheme.parse("echo(num)").unwrap_err();
+    assert_eq!(err.code(), ParseErrorCode::InvalidArgumentType);
+    assert_eq!(err.expected(), [] as [&str; 0]);
+
+    let err = scheme.parse("nun == 1").unwrap_err();
+    assert_json!(
+        err,
+        {
+            "code": "UnknownField",
+            "message": "unknown field, did you mean num?",
+            "span_start": 0,
+            "span_len": 3,
+            "line": 1,
+            "column": 1,
+            "expected": [],
+            "suggestions": ["num"]
+        }
+    );
+}
+
+#[test]
+fn test_parse_suggestions() {
+    use indoc::indoc;
+
+    let mut scheme = Scheme! {
+        http.host: Bytes,
+        http.request.uri: Bytes,
+        http.request.method: Bytes,
+        ip.src: Ip,
+    };
+    scheme.add_functions(test_functions()).unwrap();
+    scheme
+        .add_field_alias("http.uri".into(), "http.request.uri")
+        .unwrap();
+
+    let err = scheme.parse("http.hots == \"a\"").unwrap_err();
+    assert_eq!(err.suggestions(), ["http.host"]);
+    assert_eq!(
+        err.to_string(),
+        indoc!(
+            r#"
+            Filter parsing error (1:1):
+            http.hots == "a"
+            ^^^^^^^^^ unknown field, did you mean http.host?
+            "#
+        )
+    );
+
+    let err = scheme.parse("http.req.uri == \"/\"").unwrap_err();
+    assert_eq!(err.suggestions(), ["http.request.uri", "http.uri"]);
+
+    let err = scheme.parse("ech(http.host) == \"a\"").unwrap_err();
+    assert_eq!(err.suggestions(), ["echo"]);
+    assert_eq!(
+        err.to_string(),
+        indoc!(
+            r#"
+            Filter parsing error (1:1):
+            ech(http.host) == "a"
+            ^^^ unknown function, did you mean echo?
+            "#
+        )
+    );
+
+    let err = scheme.parse("tcp.port == 80").unwrap_err();
+    assert_eq!(err.suggestions(), [] as [&str; 0]);
+    assert_eq!(err.kind.to_string(), "unknown field");
+
+    let diagnostics = scheme
+        .parse_with_diagnostics("http.hots == \"a\" or ech(ip.src)")
+        .unwrap_err();
+    assert_eq!(diagnostics[0].suggestions, ["http.host"]);
+    assert_eq!(diagnostics[1].suggestions, ["echo"]);
+    assert_eq!(
+        diagnostics[1].to_string(),
+        "1:21: unknown function, did you mean echo?"
+    );
+
+    assert_eq!(
+        scheme
+            .parse("ip.src in {10.0.0.0/8} and ip.srcc == 1.1.1.1")
+            .unwrap_err()
+            .suggestions(),
+        ["ip.src"]
+    );
+}
+
+#[test]
+fn test_parse_with_diagnostics() {
+    use indoc::indoc;
+
+    let scheme = &Scheme! { num: Int, str: Bytes };
+
+    let diagnostics = |input| {
+        scheme
+            .parse_with_diagnostics(input)
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    assert!(scheme
+        .parse_with_diagnostics("num == 1 and (str == \"a\" or not str contains \"b\")")
+        .is_ok());
+
+    assert_eq!(
+        diagnostics(r#"xyz == 1 or num == "a" && str contains "or (" or num == 1)"#),
+        [
+            "1:1: unknown field",
+            "1:20: expected digit",
+            "1:58: unrecognised input",
+        ]
+    );
+
+    assert_eq!(
+        diagnostics("(num == 1 and xyz) xor (num == 2 str) or (num in {1 2"),
+        [
+            "1:15: unknown field",
+            "1:34: expected literal \")\"",
+            "1:54: expected digit",
+            "1:54: expected literal \")\"",
+        ]
+    );
+
+    assert_eq!(
+        diagnostics(indoc!(
+            r#"
+            num == 10 or
+            not num == true or
+            num == 20 and
+            "#
+        )),
+        [
+            "2:12: expected digit",
+            "3:14: expected identifier character"
+        ]
+    );
+
+    assert_eq!(
+        diagnostics(r#"str matches /(a" or/q or str == r"x) or" xor num == "1""#),
+        [
+            "1:21: invalid regex flag 'q', expected one of i, m, s, U or x",
+            "1:53: expected digit",
+        ]
+    );
+
+    let diagnostic = &scheme.parse_with_diagnostics("\n num == 1 2").unwrap_err()[0];
+
+    assert_eq!(
+        diagnostic,
+        &Diagnostic {
+            code: ParseErrorCode::UnexpectedInput,
+            message: "unrecognised input".to_owned(),
+            span_start: 11,
+            span_len: 1,
+            line: 2,
+            column: 11,
+            expected: vec![],
+            suggestions: vec![],
+        }
+    );
+
+    let diagnostic = &scheme
+        .parse_with_diagnostics("num == 1 or (num == 2")
+        .unwrap_err()[0];
+    assert_eq!(diagnostic.code, ParseErrorCode::UnexpectedEnd);
+    assert_eq!(diagnostic.expected, ["\")\""]);
+}
+
+#[test]
+fn test_parse_limits() {
+    let mut scheme = Scheme! { num: Int, str: Bytes };
+    scheme.add_functions(test_functions()).unwrap();
+    scheme
+        .add_macro("nested".into(), "((((num == 1))))".into())
+        .unwrap();
+    let limits = ParseLimits {
+        max_input_len: 40,
+        max_depth: 3,
+        max_set_len: 2,
+        max_regex_size: 1000,
+        max_call_depth: 2,
+        ..ParseLimits::default()
+    };
+    let parse = |input| scheme.parse_with_limits(input, &limits);
+
+    let err = |input| {
+        let err = parse(input).unwrap_err();
+        (err.kind, err.span_start)
+    };
+
+    assert_eq!(
+        err("num == 1 or num == 2 or num == 3 or num == 4"),
+        (LexErrorKind::InputTooLong { limit: 40 }, 40)
+    );
+    assert_eq!(
+        err("str == \"xééééééééééééééééééééé\""),
+        (LexErrorKind::InputTooLong { limit: 40 }, 41)
+    );
+    assert_eq!(
+        err("not ((not num == 1))"),
+        (LexErrorKind::TooDeep { limit: 3 }, 6)
+    );
+    assert_eq!(
+        err("num in {1 2 3}"),
+        (LexErrorKind::SetTooLarge { limit: 2 }, 12)
+    );
+    assert_eq!(
+        err(r#"str matches "\w{100}""#),
+        (LexErrorKind::RegexTooBig { limit: 1000 }, 13)
+    );
+    assert_eq!(
+        err("echo(echo(echo(str))) == \"\""),
+        (LexErrorKind::CallsTooDeep { limit: 2 }, 10)
+    );
+
+    assert!(parse("not ((num == 1)) and (num in {1 2})").is_ok());
+    assert!(parse(r#"echo(echo(str)) matches "\w{5}""#).is_ok());
+
+    // Macros are trusted, even when expanded deep in a filter.
+    assert!(parse("((not $nested))").is_ok());
+
+    assert_eq!(
+        scheme
+            .parse_with_diagnostics_and_limits("((((num == 1)))) or num in {1 2 3}", &limits)
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        [
+            "1:4: expression is nested deeper than 3 levels",
+            "1:33: set has more than 2 items"
+        ]
+    );
+
+    // Default limits keep deeply nested filters from overflowing the stack.
+    let nested = |depth| format!("{}num == 1{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(scheme.parse(&nested(128)).is_ok());
+    let input = nested(100_000);
+    let err = scheme.parse(&input).unwrap_err();
+    assert_eq!(
+        (err.kind, err.span_start),
+        (LexErrorKind::TooDeep { limit: 128 }, 128)
+    );
+}
+
+#[test]
+fn test_extended_literals() {
+    let mut scheme = Scheme! { num: Int, str: Bytes };
+    scheme.add_functions(test_functions()).unwrap();
+
+    let same = |input: &str, expected: &str| {
+        assert_eq!(
+            scheme.parse(input).unwrap(),
+            scheme.parse(expected).unwrap()
+        );
+    };
+
+    same(r##"str == r#"say "hi""#"##, r#"str == "say \"hi\"""#);
+    same(
+        r#"str in {r"C:\dir" "\u{48}\u{1f600}"}"#,
+        r#"str in {"C:\\dir" "H😀"}"#,
+    );
+    same(r#"echo(str) contains r"\x""#, r#"echo(str) contains "\\x""#);
+    same(r#"str matches /^\/a"b/i"#, r#"str matches "(?i)^/a\"b""#);
+    same(r#"str ~ r"\d+""#, r#"str matches "\d+""#);
+
+    assert_eq!(
+        scheme
+            .parse(r#"str matches /a\/b/U and str == r"\""#)
+            .unwrap()
+            .to_string(),
+        r#"str matches "(?U)a/b" and str == "\\""#
+    );
+}
+
+#[test]
+fn test_spans() {
+    let mut scheme = Scheme! { num: Int, str: Bytes };
+    scheme.add_functions(test_functions()).unwrap();
+    scheme.add_macro("small".into(), "num < 10".into()).unwrap();
+
+    let input = "  (str == \"a\" or echo(str, 2) contains \"b\")\n  and not $small ";
+    let ast = scheme.parse(input).unwrap();
+
+    let mut nodes = Vec::new();
+    ast.walk_nodes(&mut |kind, span| nodes.push((kind, span.source(input).unwrap())));
+
+    assert_eq!(
+        nodes,
+        [
+            (
+                NodeKind::Combining,
+                "(str == \"a\" or echo(str, 2) contains \"b\")\n  and not $small"
+            ),
+            (
+                NodeKind::Parenthesized,
+                "(str == \"a\" or echo(str, 2) contains \"b\")"
+            ),
+            (
+                NodeKind::Combining,
+                "str == \"a\" or echo(str, 2) contains \"b\""
+            ),
+            (NodeKind::Comparison, "str == \"a\""),
+            (NodeKind::Literal, "\"a\""),
+            (NodeKind::Comparison, "echo(str, 2) contains \"b\""),
+            (NodeKind::FunctionCall("echo"), "echo(str, 2)"),
+            (NodeKind::Literal, "2"),
+            (NodeKind::Literal, "\"b\""),
+            (NodeKind::Not, "not $small"),
+            (NodeKind::Macro("small"), "$small"),
+        ]
+    );
+
+    assert_eq!(ast.span(), Span { start: 2, end: 60 });
+
+    // Spans don't affect comparisons, and are only serialized on request.
+    let ast = scheme.parse("num == 1").unwrap();
+    assert_eq!(ast, scheme.parse(" num  ==  1 ").unwrap());
+
+    assert_json!(
+        ast,
+        {
+            "lhs": "num",
+            "op": "Equal",
+            "rhs": 1
+        }
+    );
+
+    assert_json!(
+        ast.with_spans(),
+        {
+            "lhs": "num",
+            "op": "Equal",
+            "rhs": 1,
+            "span": { "start": 0, "end": 8 },
+            "rhs_span": { "start": 7, "end": 8 }
+        }
+    );
+}
+
+#[test]
+fn test_comments() {
+    use indoc::indoc;
+
+    let mut scheme = Scheme! { num: Int, str: Bytes };
+    scheme.add_functions(test_functions()).unwrap();
+
+    let input = indoc!(
+        r##"
+        # Leading comment.
+        /* block */ num in { 1 # one
+            2 /* two */ 3 }
+        and (echo(str /* arg */) == "# /* no comment */" # before paren
+        )
+        or str matches "^#[/*]" # trailing
+        "##
+    );
+
+    let (ast, comments) = scheme.parse_with_comments(input).unwrap();
+
+    assert_eq!(
+        ast,
+        scheme
+            .parse(r##"num in {1 2 3} and (echo(str) == "# /* no comment */") or str matches "^#[/*]""##)
+            .unwrap()
+    );
+
+    assert_eq!(
+        comments
+            .iter()
+            .map(|comment| {
+                assert_eq!(comment.span.source(input), Some(&comment.text[..]));
+                &comment.text[..]
+            })
+            .collect::<Vec<_>>(),
+        [
+            "# Leading comment.",
+            "/* block */",
+            "# one",
+            "/* two */",
+            "/* arg */",
+            "# before paren",
+            "# trailing",
+        ]
+    );
+
+    assert_eq!(comments[1].span, Span { start: 19, end: 30 });
+
+    // Comments are printed before the operands they precede, which moves
+    // ones written inside of comparisons.
+    let printed = ast.with_comments(&comments).to_string();
+    assert_eq!(
+        printed,
+        "# Leading comment.\n/* block */ num in {1 2 3} # one\n/* two */ and echo(str) == \
+         \"# /* no comment */\" /* arg */ # before paren\nor str matches \"^#[/*]\" # trailing"
+    );
+    let pretty = format!("{:#}", ast.with_comments(&comments));
+    assert_eq!(
+        pretty,
+        indoc!(
+            r##"
+            # Leading comment.
+            /* block */
+            num in {1 2 3}
+            # one
+            /* two */
+            and echo(str) == "# /* no comment */"
+            /* arg */
+            # before paren
+            or str matches "^#[/*]"
+            # trailing"##
+        )
+        .trim_start()
+    );
+    for printed in &[printed, pretty] {
+        let (reparsed, reparsed_comments) = scheme.parse_with_comments(printed).unwrap();
+        assert_eq!(reparsed.to_string(), ast.to_string());
+        assert_eq!(
+            reparsed_comments
+                .iter()
+                .map(|comment| &comment.text)
+                .collect::<Vec<_>>(),
+            comments
+                .iter()
+                .map(|comment| &comment.text)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    // Unterminated block comments are reported where they start.
+    let input = "num == 1 /* unterminated";
+    assert_eq!(
+        scheme.parse(input),
+        Err(ParseError::new(input, (LexErrorKind::EOF, &input[9..])))
+    );
+
+    let (_, comments) = scheme
+        .parse_with_comments(r###"str in {r#"# no comment"# /* comment */ r"/*"} # trailing"###)
+        .unwrap();
+    assert_eq!(
+        comments
+            .iter()
+            .map(|comment| &comment.text[..])
+            .collect::<Vec<_>>(),
+        ["/* comment */", "# trailing"]
+    );
+}
+
+#[test]
+fn test_display() {
+    let mut scheme = Scheme! { num: Int, str: Bytes, ip: Ip, ssl: Bool };
+    scheme.add_functions(test_functions()).unwrap();
+    scheme.add_macro("small".into(), "num < 10".into()).unwrap();
+
+    let cases = [
+        ("ssl", "ssl"),
+        (
+            r#"num eq 1 && str ~ "a""#,
+            r#"num == 1 and str matches "a""#,
+        ),
+        ("ssl || num > 1 && num < 5", "ssl or num > 1 and num < 5"),
+        (
+            "(ssl or num > 1) and num < 5",
+            "(ssl or num > 1) and num < 5",
+        ),
+        ("ssl or (num > 1 and num < 5)", "ssl or num > 1 and num < 5"),
+        (
+            "ssl and (num > 1 and num < 5)",
+            "ssl and (num > 1 and num < 5)",
+        ),
+        ("ssl ^^ ssl xor ssl", "ssl xor ssl xor ssl"),
+        ("((ssl))", "ssl"),
+        ("!(ssl)", "not ssl"),
+        ("!(ssl || !ssl)", "not (ssl or not ssl)"),
+        ("not $small", "not $small"),
+        (r#"str == "a\"b\\c\x0Aé""#, r#"str == "a\"b\\c\x0aé""#),
+        ("str == 01:02-ab", "str == 01:02:AB"),
+        (r#"str matches "[\"]\"x\d""#, r#"str matches "[\"]\"x\d""#),
+        (
+            r#"echo(str,2) contains "x""#,
+            r#"echo(str, 2) contains "x""#,
+        ),
+        ("num bitwise_and 0x3", "num & 3"),
+        ("num in { 1 2..5 -3 0x10 }", "num in {1 2..5 -3 16}"),
+        (
+            "ip in { 10.0.0.0/8 ::1..::2 1.2.3.4 }",
+            "ip in {10.0.0.0/8 ::1..::2 1.2.3.4}",
+        ),
+        ("ip/24 == 10.0.0.0", "ip/24 == 10.0.0.0"),
+    ];
+
+    for &(input, expected) in &cases {
+        assert_eq!(scheme.parse(input).unwrap().to_string(), expected);
+    }
+
+    let ast = scheme
+        .parse("ssl and (num == 1 or not (num == 2 xor num == 3)) and $small")
+        .unwrap();
+
+    assert_eq!(
+        format!("{:#}", ast),
+        indoc::indoc!(
+            "
+            ssl
+            and (
+                num == 1
+                or not (
+                    num == 2
+                    xor num == 3
+                )
+            )
+            and $small"
+        )
+    );
+}
+
+#[test]
+fn test_display_round_trip() {
+    fn generate(random: &mut dyn FnMut(usize) -> usize, depth: usize, out: &mut String) {
+        const ATOMS: &[&str] = &[
+            "ssl",
+            "num != -1",
+            r##"str contains "\"#/*\\""##,
+            r#"str matches "^[a-z\"]+\.\"$""#,
+            "str in { 00:ff \"\\x7f\" }",
+            "ip in { 2001:db8::/32 10.0.0.1..10.0.0.9 }",
+            "ip/8 != 127.0.0.0",
+            "echo(str, 3) == \"\"",
+            "$small",
+        ];
+        const OPS: &[&str] = &["and", "&&", "or", "||", "xor", "^^"];
+
+        if depth == 0 || random(4) == 0 {
+            out.push_str(ATOMS[random(ATOMS.len())]);
+            return;
+        }
+        match random(3) {
+            0 => {
+                out.push_str("not (");
+                generate(random, depth - 1, out);
+                out.push(')');
+            }
+            1 => {
+                out.push('(');
+                generate(random, depth - 1, out);
+                out.push(')');
+            }
+            _ => {
+                for i in 0..2 + random(2) {
+                    if i != 0 {
+                        out.push(' ');
+                        out.push_str(OPS[random(OPS.len())]);
+                        out.push(' ');
+                    }
+                    generate(random, depth - 1, out);
+                }
+            }
+        }
+    }
+
+    let mut scheme = Scheme! { num: Int, str: Bytes, ip: Ip, ssl: Bool };
+    scheme.add_functions(test_functions()).unwrap();
+    scheme.add_macro("small".into(), "num < 10".into()).unwrap();
+
+    // A fixed xorshift sequence, so that failures are reproducible.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = |n: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % n as u64) as usize
+    };
+
+    for _ in 0..500 {
+        let mut input = String::new();
+        generate(&mut random, 4, &mut input);
+
+        let ast = scheme.parse(&input).unwrap();
+        let json = serde_json::to_value(&ast).unwrap();
+
+        let printed = ast.to_string();
+        let reparsed = scheme.parse(&printed).unwrap();
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json, "{}", input);
+        assert_eq!(reparsed.to_string(), printed, "{}", input);
+
+        let pretty = format!("{:#}", ast);
+        let reparsed = scheme.parse(&pretty).unwrap();
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json, "{}", input);
+    }
+}
+
+#[test]
+fn test_field() {
+    let scheme = &Scheme! {
+        x: Bytes,
+        x.y.z0: Int,
+        is_TCP: Bool,
+    };
+
+    assert_ok!(
+        Field::lex_with("x;", &Parser::trusted(scheme)),
+        scheme.get_field_index("x").unwrap(),
+        ";"
+    );
+
+    assert_ok!(
+        Field::lex_with("x.y.z0-", &Parser::trusted(scheme)),
+        scheme.get_field_index("x.y.z0").unwrap(),
+        "-"
+    );
+
+    assert_ok!(
+        Field::lex_with("is_TCP", &Parser::trusted(scheme)),
+        scheme.get_field_index("is_TCP").unwrap(),
+        ""
+    );
+
+    assert_err!(
+        Field::lex_with("x..y", &Parser::trusted(scheme)),
+        LexErrorKind::ExpectedName("identifier character"),
+        ".y"
+    );
+
+    assert_err!(
+        Field::lex_with("x.#", &Parser::trusted(scheme)),
+        LexErrorKind::ExpectedName("identifier character"),
+        "#"
+    );
+
+    assert_err!(
+        Field::lex_with("x.y.z;", &Parser::trusted(scheme)),
+        LexErrorKind::UnknownField(UnknownFieldError, Suggestions::new("x.y.z", vec!["x.y.z0"])),
+        "x.y.z"
+    );
+}
+
+#[test]
+#[should_panic(expected = "attempt to redefine field foo")]
+fn test_static_field_type_override() {
+    Scheme! { foo: Int, foo: Int };
+}
+
+#[test]
+fn test_field_type_override() {
+    let mut scheme = Scheme! { foo: Int };
+
+    assert_eq!(
+        scheme.add_field("foo".into(), Type::Bytes).unwrap_err(),
+        ItemRedefinitionError::Field(FieldRedefinitionError("foo".into()))
+    )
+}
+
+#[cfg(test)]
+fn test_functions() -> Vec<(String, Function)> {
+    use crate::{
+        functions::{FunctionArgKind, FunctionImpl, FunctionOptParam, FunctionParam},
+        types::LhsValue,
+    };
+
+    vec![(
+        "echo".to_owned(),
+        Function {
+            params: vec![FunctionParam {
+                arg_kind: FunctionArgKind::Field,
+                val_type: Type::Bytes,
+                generic: false,
+            }],
+            opt_params: vec![FunctionOptParam {
+                arg_kind: FunctionArgKind::Literal,
+                default_value: LhsValue::Int(1),
+            }],
+            return_type: Type::Bytes,
+            pure: true,
+            implementation: FunctionImpl::new(|args| args.next().unwrap()),
+        },
+    )]
+}
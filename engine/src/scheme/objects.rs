@@ -0,0 +1,255 @@
+use super::{
+    Field, FieldRedefinitionError, FunctionRedefinitionError, ItemRedefinitionError, Scheme,
+};
+use crate::types::FieldType;
+use failure::Fail;
+use fnv::FnvBuildHasher;
+use indexmap::map::IndexMap;
+use std::fmt::{self, Display, Formatter};
+
+/// An error that occurs if a nested object is used in a filter where a field
+/// with a value is expected.
+#[derive(Debug, PartialEq)]
+pub struct ObjectFieldError {
+    name: String,
+    pub(crate) fields: Vec<String>,
+}
+
+impl Fail for ObjectFieldError {}
+
+impl Display for ObjectFieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is an object", self.name)?;
+        if !self.fields.is_empty() {
+            write!(f, ", expected one of {}", self.fields.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct Object {
+    pub(crate) members: Vec<String>,
+    // Whether the object is a member of another object.
+    pub(crate) nested: bool,
+}
+
+impl<'s> Scheme {
+    /// Registers a field that is either a value of the given type or an
+    /// object with nested fields.
+    ///
+    /// Each nested field is registered as `name.member` and can be used in
+    /// filters as any other field, while the object itself can be passed to
+    /// [`FilterAst::uses`] and populated in one go with
+    /// [`ExecutionContext::set_object_value`](::ExecutionContext::set_object_value).
+    /// If any of the names is already taken, none of the fields is added.
+    pub fn add_nested_field(
+        &mut self,
+        name: String,
+        ty: FieldType,
+    ) -> Result<(), ItemRedefinitionError> {
+        match ty {
+            FieldType::Value(ty) => self.add_field(name, ty),
+            FieldType::Object(members) => {
+                let initial_len = self.fields.len();
+                let initial_objects = self.objects.len();
+                let result = self.add_object(name, members, false);
+                if result.is_err() {
+                    self.remove_fields_from(initial_len);
+                    self.objects.truncate(initial_objects);
+                }
+                result
+            }
+        }
+    }
+
+    fn add_object(
+        &mut self,
+        name: String,
+        members: IndexMap<String, FieldType, FnvBuildHasher>,
+        nested: bool,
+    ) -> Result<(), ItemRedefinitionError> {
+        if self.functions.contains_key(&name) {
+            return Err(ItemRedefinitionError::Function(FunctionRedefinitionError(
+                name,
+            )));
+        }
+        if self.fields.contains_key(&name)
+            || self.aliases.contains_key(&name)
+            || self.objects.contains_key(&name)
+        {
+            return Err(ItemRedefinitionError::Field(FieldRedefinitionError(name)));
+        }
+        self.objects.insert(
+            name.clone(),
+            Object {
+                members: members.keys().cloned().collect(),
+                nested,
+            },
+        );
+        for (member, ty) in members {
+            let path = format!("{}.{}", name, member);
+            match ty {
+                FieldType::Value(ty) => self.add_field(path, ty)?,
+                FieldType::Object(members) => self.add_object(path, members, true)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the names of direct members of a nested object.
+    pub(crate) fn get_object_members(&self, name: &str) -> Option<&[String]> {
+        self.objects
+            .get(name)
+            .map(|object| object.members.as_slice())
+    }
+
+    /// Returns all fields nested in an object, at any depth.
+    pub(crate) fn get_object_fields(&'s self, name: &str) -> Option<Vec<Field<'s>>> {
+        let members = self.get_object_members(name)?;
+        let mut fields = Vec::new();
+        for member in members {
+            let path = format!("{}.{}", name, member);
+            match self.get_object_fields(&path) {
+                Some(nested) => fields.extend(nested),
+                None => fields.push(self.get_field_index(&path).unwrap()),
+            }
+        }
+        Some(fields)
+    }
+
+    pub(crate) fn get_object_error(&self, name: &str) -> Option<ObjectFieldError> {
+        let members = self.get_object_members(name)?;
+        Some(ObjectFieldError {
+            name: name.to_owned(),
+            fields: members
+                .iter()
+                .map(|member| format!("{}.{}", name, member))
+                .collect(),
+        })
+    }
+
+    pub(crate) fn get_object_type(&self, name: &str) -> FieldType {
+        self.get_object_members(name)
+            .unwrap()
+            .iter()
+            .map(|member| {
+                let path = format!("{}.{}", name, member);
+                let ty = match self.fields.get(&path) {
+                    Some(&ty) => FieldType::Value(ty),
+                    None => self.get_object_type(&path),
+                };
+                (member.as_str(), ty)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_nested_fields() {
+    use crate::{
+        scheme::UnknownFieldError,
+        types::{GetType, Type},
+    };
+    use indoc::indoc;
+
+    let uri: FieldType = vec![("path", Type::Bytes), ("query", Type::Bytes)]
+        .into_iter()
+        .collect();
+    let request: FieldType = vec![("method", Type::Bytes.into()), ("uri", uri)]
+        .into_iter()
+        .collect();
+
+    let mut scheme = Scheme! { ip.src: Ip, tcp.port: Int };
+    scheme
+        .add_nested_field(
+            "http".into(),
+            vec![("request", request), ("version", Type::Int.into())]
+                .into_iter()
+                .collect(),
+        )
+        .unwrap();
+
+    assert_eq!(
+        scheme
+            .get_field_index("http.request.uri.path")
+            .unwrap()
+            .get_type(),
+        Type::Bytes
+    );
+
+    assert_eq!(
+        scheme.add_field("http.request".into(), Type::Bytes),
+        Err(ItemRedefinitionError::Field(FieldRedefinitionError(
+            "http.request".into()
+        )))
+    );
+
+    // Failed objects don't leave any fields behind.
+    assert_eq!(
+        scheme.add_nested_field(
+            "tcp".into(),
+            vec![("flags", Type::Int), ("port", Type::Int)]
+                .into_iter()
+                .collect(),
+        ),
+        Err(ItemRedefinitionError::Field(FieldRedefinitionError(
+            "tcp.port".into()
+        )))
+    );
+    assert_eq!(scheme.get_field_index("tcp.flags"), Err(UnknownFieldError));
+    assert!(scheme.get_object_members("tcp").is_none());
+
+    assert_eq!(
+        scheme
+            .parse("http.request == \"GET\"")
+            .unwrap_err()
+            .to_string(),
+        indoc!(
+            r#"
+            Filter parsing error (1:1):
+            http.request == "GET"
+            ^^^^^^^^^^^^ http.request is an object, expected one of http.request.method, http.request.uri
+            "#
+        )
+    );
+
+    let ast = scheme
+        .parse(r#"http.request.uri.path == "/" && tcp.port == 80"#)
+        .unwrap();
+    assert_eq!(ast.uses("http"), Ok(true));
+    assert_eq!(ast.uses("http.request"), Ok(true));
+    assert_eq!(ast.uses("http.request.method"), Ok(false));
+    assert_eq!(ast.uses("ip.src"), Ok(false));
+    assert_eq!(ast.uses("http.req"), Err(UnknownFieldError));
+
+    let json = serde_json::to_value(&scheme).unwrap();
+    assert_eq!(
+        json["fields"],
+        serde_json::json!({
+            "ip.src": "Ip",
+            "tcp.port": "Int",
+            "http": {
+                "request": {
+                    "method": "Bytes",
+                    "uri": { "path": "Bytes", "query": "Bytes" }
+                },
+                "version": "Int"
+            }
+        })
+    );
+
+    let deserialized: Scheme = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&deserialized).unwrap(), json);
+
+    // A plain map of fields can be nested as well.
+    let deserialized: Scheme = serde_json::from_value(serde_json::json!({
+        "http": { "host": "Bytes" },
+        "ssl": "Bool"
+    }))
+    .unwrap();
+    assert_eq!(
+        deserialized.get_object_members("http"),
+        Some(&["host".to_owned()][..])
+    );
+    assert!(deserialized.get_field_index("http.host").is_ok());
+}
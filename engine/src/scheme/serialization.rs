@@ -0,0 +1,715 @@
+use super::{AliasError, FunctionRedefinitionError, MacroError, Scheme, VirtualFieldError};
+use crate::{
+    functions::{Function, FunctionOptParam, FunctionParam},
+    metadata::Metadata,
+    types::{FieldType, GetType, RawValue, Type},
+};
+use fnv::{FnvBuildHasher, FnvHashSet};
+use indexmap::map::{Entry, IndexMap};
+use serde::{
+    de::{
+        self, value::MapAccessDeserializer, DeserializeSeed, Deserializer, IntoDeserializer,
+        MapAccess, Visitor,
+    },
+    ser::SerializeStruct,
+    Deserialize, Serialize, Serializer,
+};
+use std::{
+    fmt::{self, Formatter},
+    mem,
+};
+
+impl Serialize for Scheme {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let mut state = ser.serialize_struct("Scheme", 8)?;
+        let nested_fields = self
+            .objects
+            .keys()
+            .filter_map(|name| self.get_object_fields(name))
+            .flatten()
+            .map(|field| field.index())
+            .collect::<FnvHashSet<_>>();
+        // Nested fields are serialized as parts of their top-level objects,
+        // which follow all other fields.
+        let fields = self
+            .fields
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| {
+                !self.virtual_fields.contains_key(index) && !nested_fields.contains(index)
+            })
+            .map(|(_, (name, &ty))| (name.as_str(), FieldType::Value(ty)))
+            .chain(
+                self.objects
+                    .iter()
+                    .filter(|(_, object)| !object.nested)
+                    .map(|(name, _)| (name.as_str(), self.get_object_type(name))),
+            )
+            .collect::<IndexMap<_, _, FnvBuildHasher>>();
+        state.serialize_field("fields", &fields)?;
+        state.serialize_field("functions", &self.functions)?;
+        if self.virtual_fields.is_empty() {
+            state.skip_field("virtual_fields")?;
+        } else {
+            let virtual_fields = self
+                .virtual_fields
+                .iter()
+                .map(|(&index, field)| (self.fields.get_index(index).unwrap().0, &field.definition))
+                .collect::<IndexMap<_, _, FnvBuildHasher>>();
+            state.serialize_field("virtual_fields", &virtual_fields)?;
+        }
+        if self.macros.is_empty() {
+            state.skip_field("macros")?;
+        } else {
+            state.serialize_field("macros", &self.macros)?;
+        }
+        if self.aliases.is_empty() {
+            state.skip_field("aliases")?;
+        } else {
+            let aliases = self
+                .aliases
+                .iter()
+                .map(|(alias, &index)| (alias, self.fields.get_index(index).unwrap().0))
+                .collect::<IndexMap<_, _, FnvBuildHasher>>();
+            state.serialize_field("aliases", &aliases)?;
+        }
+        if self.deprecations.is_empty() {
+            state.skip_field("deprecations")?;
+        } else {
+            state.serialize_field("deprecations", &self.deprecations)?;
+        }
+        if self.defaults.is_empty() {
+            state.skip_field("defaults")?;
+        } else {
+            let defaults = self
+                .defaults
+                .iter()
+                .map(|(&index, value)| (self.fields.get_index(index).unwrap().0, value))
+                .collect::<IndexMap<_, _, FnvBuildHasher>>();
+            state.serialize_field("defaults", &defaults)?;
+        }
+        if self.metadata.is_empty() {
+            state.skip_field("metadata")?;
+        } else {
+            state.serialize_field("metadata", &self.metadata)?;
+        }
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct FunctionSignature {
+    params: Vec<FunctionParam>,
+    #[serde(default)]
+    opt_params: Vec<FunctionOptParam>,
+    return_type: Type,
+    #[serde(default)]
+    pure: bool,
+}
+
+const SCHEME_PROPERTIES: &[&str] = &[
+    "fields",
+    "functions",
+    "virtual_fields",
+    "macros",
+    "aliases",
+    "deprecations",
+    "defaults",
+    "metadata",
+];
+
+// A scheme definition in either of the serialized formats.
+#[derive(Default)]
+struct SchemeDefinition {
+    fields: IndexMap<String, FieldType, FnvBuildHasher>,
+    functions: IndexMap<String, FunctionSignature, FnvBuildHasher>,
+    virtual_fields: IndexMap<String, String, FnvBuildHasher>,
+    macros: IndexMap<String, String, FnvBuildHasher>,
+    aliases: IndexMap<String, String, FnvBuildHasher>,
+    deprecations: IndexMap<String, String, FnvBuildHasher>,
+    defaults: IndexMap<String, RawValue, FnvBuildHasher>,
+    metadata: IndexMap<String, Metadata, FnvBuildHasher>,
+}
+
+enum SchemeFormat {
+    // A plain map of field names to types.
+    Fields,
+    // An object with `fields`, `functions` and other properties.
+    Full,
+}
+
+// Deserializes the value of a property of the full format.
+struct SchemeProperty<'a> {
+    name: &'a str,
+    definition: &'a mut SchemeDefinition,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for SchemeProperty<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, de: D) -> Result<(), D::Error> {
+        let definition = self.definition;
+        match self.name {
+            "fields" => definition.fields = Deserialize::deserialize(de)?,
+            "functions" => definition.functions = Deserialize::deserialize(de)?,
+            "virtual_fields" => definition.virtual_fields = Deserialize::deserialize(de)?,
+            "macros" => definition.macros = Deserialize::deserialize(de)?,
+            "aliases" => definition.aliases = Deserialize::deserialize(de)?,
+            "deprecations" => definition.deprecations = Deserialize::deserialize(de)?,
+            "defaults" => definition.defaults = Deserialize::deserialize(de)?,
+            "metadata" => definition.metadata = Deserialize::deserialize(de)?,
+            name => return Err(de::Error::unknown_field(name, SCHEME_PROPERTIES)),
+        }
+        Ok(())
+    }
+}
+
+// Deserializes the value of the first entry, which tells the formats apart:
+// properties of the full format are all maps, while plain maps start with a
+// field whose type is a string, or with an object not named like a property.
+struct FirstSchemeEntry<'a> {
+    name: &'a str,
+    definition: &'a mut SchemeDefinition,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for FirstSchemeEntry<'a> {
+    type Value = SchemeFormat;
+
+    fn deserialize<D: Deserializer<'de>>(self, de: D) -> Result<SchemeFormat, D::Error> {
+        de.deserialize_any(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for FirstSchemeEntry<'a> {
+    type Value = SchemeFormat;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("a field type or a map")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<SchemeFormat, E> {
+        let ty = FieldType::deserialize(value.into_deserializer())?;
+        self.definition.fields.insert(self.name.to_owned(), ty);
+        Ok(SchemeFormat::Fields)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SchemeFormat, A::Error> {
+        let de = MapAccessDeserializer::new(map);
+        if SCHEME_PROPERTIES.contains(&self.name) {
+            SchemeProperty {
+                name: self.name,
+                definition: self.definition,
+            }
+            .deserialize(de)?;
+            Ok(SchemeFormat::Full)
+        } else {
+            let ty = FieldType::deserialize(de)?;
+            self.definition.fields.insert(self.name.to_owned(), ty);
+            Ok(SchemeFormat::Fields)
+        }
+    }
+}
+
+struct SchemeDefinitionVisitor;
+
+impl<'de> Visitor<'de> for SchemeDefinitionVisitor {
+    type Value = SchemeDefinition;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("a scheme definition")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SchemeDefinition, A::Error> {
+        let mut definition = SchemeDefinition::default();
+
+        let format = match map.next_key::<String>()? {
+            Some(name) => map.next_value_seed(FirstSchemeEntry {
+                name: &name,
+                definition: &mut definition,
+            })?,
+            None => return Ok(definition),
+        };
+
+        while let Some(name) = map.next_key::<String>()? {
+            match format {
+                SchemeFormat::Fields => {
+                    let ty = map.next_value()?;
+                    definition.fields.insert(name, ty);
+                }
+                SchemeFormat::Full => map.next_value_seed(SchemeProperty {
+                    name: &name,
+                    definition: &mut definition,
+                })?,
+            }
+        }
+
+        Ok(definition)
+    }
+}
+
+impl<'de> Deserialize<'de> for SchemeDefinition {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        de.deserialize_map(SchemeDefinitionVisitor)
+    }
+}
+
+impl SchemeDefinition {
+    fn into_scheme<E: de::Error>(self, registry: Option<&FunctionRegistry>) -> Result<Scheme, E> {
+        let SchemeDefinition {
+            fields,
+            functions,
+            virtual_fields,
+            macros,
+            aliases,
+            deprecations,
+            defaults,
+            metadata,
+        } = self;
+
+        let mut scheme = Scheme::with_capacity(fields.len());
+
+        for (name, ty) in fields {
+            scheme.add_nested_field(name, ty).map_err(E::custom)?;
+        }
+
+        for (name, signature) in functions {
+            let registry = registry.ok_or_else(|| {
+                E::custom(format!(
+                    "cannot bind function {} without a function registry",
+                    name
+                ))
+            })?;
+
+            let function = registry
+                .functions
+                .get(&name)
+                .ok_or_else(|| E::custom(format!("unknown function {}", name)))?;
+
+            if !function.has_signature(
+                &signature.params,
+                &signature.opt_params,
+                signature.return_type,
+                signature.pure,
+            ) {
+                return Err(E::custom(format!(
+                    "function {} does not match the registered signature",
+                    name
+                )));
+            }
+
+            scheme
+                .add_function(name, function.clone())
+                .map_err(E::custom)?;
+        }
+
+        // Virtual fields and macros can refer to aliases and to each other,
+        // and aliases can point to virtual fields, so keep adding whatever
+        // can be added until no progress is made.
+        let mut aliases = aliases.into_iter().collect::<Vec<_>>();
+        let mut virtual_fields = virtual_fields.into_iter().collect::<Vec<_>>();
+        let mut macros = macros.into_iter().collect::<Vec<_>>();
+        loop {
+            let count = aliases.len() + virtual_fields.len() + macros.len();
+            for (alias, field) in mem::take(&mut aliases) {
+                match scheme.add_field_alias(alias.clone(), &field) {
+                    Ok(()) => {}
+                    Err(AliasError::UnknownField(_)) => aliases.push((alias, field)),
+                    Err(err) => return Err(E::custom(err)),
+                }
+            }
+            for (name, definition) in mem::take(&mut virtual_fields) {
+                match scheme.add_virtual_field(name.clone(), definition.clone()) {
+                    Ok(()) => {}
+                    Err(VirtualFieldError::InvalidDefinition { .. }) => {
+                        virtual_fields.push((name, definition))
+                    }
+                    Err(err) => return Err(E::custom(err)),
+                }
+            }
+            for (name, definition) in mem::take(&mut macros) {
+                match scheme.add_macro(name.clone(), definition.clone()) {
+                    Ok(()) => {}
+                    Err(MacroError::InvalidDefinition { .. }) => macros.push((name, definition)),
+                    Err(err) => return Err(E::custom(err)),
+                }
+            }
+            if aliases.len() + virtual_fields.len() + macros.len() == count {
+                break;
+            }
+        }
+        // Report the rest as if added in one go, which also detects cycles.
+        scheme
+            .add_virtual_fields(virtual_fields)
+            .map_err(E::custom)?;
+        scheme.add_macros(macros).map_err(E::custom)?;
+        for (alias, field) in aliases {
+            scheme.add_field_alias(alias, &field).map_err(E::custom)?;
+        }
+
+        for (name, note) in deprecations {
+            scheme.deprecate_field(&name, note).map_err(E::custom)?;
+        }
+
+        for (name, value) in defaults {
+            let ty = scheme
+                .get_field_index(&name)
+                .map_err(|err| E::custom(format!("{} {}", err, name)))?
+                .get_type();
+            let value = value.into_typed(ty).map_err(E::custom)?;
+            scheme.set_field_default(&name, value).map_err(E::custom)?;
+        }
+
+        for (name, metadata) in metadata {
+            if scheme.fields.contains_key(&name) {
+                scheme
+                    .set_field_metadata(&name, metadata)
+                    .map_err(E::custom)?;
+            } else {
+                scheme
+                    .set_function_metadata(&name, metadata)
+                    .map_err(E::custom)?;
+            }
+        }
+
+        Ok(scheme)
+    }
+}
+
+impl<'de> Deserialize<'de> for Scheme {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        SchemeDefinition::deserialize(de)?.into_scheme(None)
+    }
+}
+
+/// A set of function implementations used to bind function signatures when
+/// deserializing a [`Scheme`](struct@Scheme).
+///
+/// Each function declared by the serialized scheme is looked up by name and
+/// must have the same parameters, return type and purity as the registered
+/// one.
+#[derive(Debug, Default)]
+pub struct FunctionRegistry {
+    functions: IndexMap<String, Function, FnvBuildHasher>,
+}
+
+impl FunctionRegistry {
+    /// Creates a new registry.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers a function implementation.
+    pub fn add_function(
+        &mut self,
+        name: String,
+        function: Function,
+    ) -> Result<(), FunctionRedefinitionError> {
+        match self.functions.entry(name) {
+            Entry::Occupied(entry) => Err(FunctionRedefinitionError(entry.key().to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(function);
+                Ok(())
+            }
+        }
+    }
+
+    /// Registers a list of function implementations.
+    pub fn add_functions<I>(&mut self, functions: I) -> Result<(), FunctionRedefinitionError>
+    where
+        I: IntoIterator<Item = (String, Function)>,
+    {
+        for (name, func) in functions {
+            self.add_function(name, func)?;
+        }
+        Ok(())
+    }
+
+    /// Deserializes a scheme, binding its functions to the registered
+    /// implementations.
+    pub fn deserialize_scheme<'de, D: Deserializer<'de>>(&self, de: D) -> Result<Scheme, D::Error> {
+        DeserializeSeed::deserialize(self, de)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for &FunctionRegistry {
+    type Value = Scheme;
+
+    fn deserialize<D: Deserializer<'de>>(self, de: D) -> Result<Scheme, D::Error> {
+        SchemeDefinition::deserialize(de)?.into_scheme(Some(self))
+    }
+}
+
+#[test]
+fn test_serialize() {
+    use crate::scheme::test_functions;
+
+    let mut scheme = Scheme! { http.host: Bytes, ip.src: Ip };
+    scheme.add_functions(test_functions()).unwrap();
+
+    assert_json!(
+        scheme,
+        {
+            "fields": {
+                "http.host": "Bytes",
+                "ip.src": "Ip"
+            },
+            "functions": {
+                "echo": {
+                    "params": [
+                        {
+                            "arg_kind": "Field",
+                            "val_type": "Bytes",
+                            "generic": false
+                        }
+                    ],
+                    "opt_params": [
+                        {
+                            "arg_kind": "Literal",
+                            "val_type": "Int",
+                            "default_value": 1
+                        }
+                    ],
+                    "return_type": "Bytes",
+                    "pure": true
+                }
+            }
+        }
+    );
+}
+
+#[test]
+fn test_deserialize() {
+    use crate::scheme::test_functions;
+
+    let mut registry = FunctionRegistry::new();
+    registry.add_functions(test_functions()).unwrap();
+
+    // Legacy definitions only list fields.
+    let scheme: Scheme =
+        serde_json::from_str(r#"{ "http.host": "Bytes", "ip.src": "Ip" }"#).unwrap();
+    assert_eq!(scheme.get_field_count(), 2);
+    assert_eq!(
+        scheme.get_field_index("ip.src").unwrap().get_type(),
+        Type::Ip
+    );
+
+    // ...which can be named like properties of the full format.
+    let scheme: Scheme =
+        serde_json::from_str(r#"{ "fields": "Int", "macros": { "a": "Bool" } }"#).unwrap();
+    assert_eq!(
+        scheme.get_field_index("macros.a").unwrap().get_type(),
+        Type::Bool
+    );
+
+    let error = |json| {
+        serde_json::from_str::<Scheme>(json)
+            .err()
+            .unwrap()
+            .to_string()
+    };
+
+    assert_eq!(
+        error(r#"{ "http.host": "Bytes", "ip.src": "Ipv4" }"#),
+        "unknown variant `Ipv4`, expected one of `Ip`, `Bytes`, `Int`, `Bool` at line 1 column 40"
+    );
+    assert_eq!(
+        error(r#"{ "fields": { "http": { "host": "Bytez" } } }"#),
+        "unknown variant `Bytez`, expected one of `Ip`, `Bytes`, `Int`, `Bool` at line 1 column 39"
+    );
+    assert_eq!(
+        error(r#"{ "fields": {}, "function": {} }"#),
+        "unknown field `function`, expected one of `fields`, `functions`, `virtual_fields`, `macros`, `aliases`, `deprecations`, `defaults`, `metadata` at line 1 column 28"
+    );
+
+    let mut expected = Scheme! { http.host: Bytes, ip.src: Ip };
+    expected.add_functions(test_functions()).unwrap();
+    let json = serde_json::to_string(&expected).unwrap();
+
+    let scheme = registry
+        .deserialize_scheme(&mut serde_json::Deserializer::from_str(&json))
+        .unwrap();
+    assert_eq!(scheme.get_field_count(), 2);
+    assert_eq!(
+        scheme.get_function("echo").unwrap(),
+        &registry.functions["echo"]
+    );
+    assert_eq!(serde_json::to_string(&scheme).unwrap(), json);
+
+    assert_eq!(
+        serde_json::from_str::<Scheme>(&json)
+            .err()
+            .unwrap()
+            .to_string(),
+        "cannot bind function echo without a function registry"
+    );
+
+    let deserialize =
+        |json| registry.deserialize_scheme(&mut serde_json::Deserializer::from_str(json));
+
+    assert_eq!(
+        deserialize(
+            r#"{
+                "fields": {},
+                "functions": {
+                    "unknown": { "params": [], "return_type": "Int" }
+                }
+            }"#
+        )
+        .err()
+        .unwrap()
+        .to_string(),
+        "unknown function unknown"
+    );
+
+    assert_eq!(
+        deserialize(
+            r#"{
+                "fields": {},
+                "functions": {
+                    "echo": {
+                        "params": [{ "arg_kind": "Field", "val_type": "Bytes" }],
+                        "return_type": "Int"
+                    }
+                }
+            }"#
+        )
+        .err()
+        .unwrap()
+        .to_string(),
+        "function echo does not match the registered signature"
+    );
+
+    // Purity is part of the signature, as calls to pure functions may be
+    // folded at compile time.
+    assert_eq!(
+        deserialize(
+            r#"{
+                "fields": {},
+                "functions": {
+                    "echo": {
+                        "params": [{ "arg_kind": "Field", "val_type": "Bytes" }],
+                        "opt_params": [{ "arg_kind": "Literal", "val_type": "Int", "default_value": 1 }],
+                        "return_type": "Bytes"
+                    }
+                }
+            }"#
+        )
+        .err()
+        .unwrap()
+        .to_string(),
+        "function echo does not match the registered signature"
+    );
+
+    assert_eq!(
+        deserialize(
+            r#"{ "fields": { "echo": "Bytes" }, "functions": { "echo": {
+            "params": [{ "arg_kind": "Field", "val_type": "Bytes" }],
+            "opt_params": [{ "arg_kind": "Literal", "val_type": "Int", "default_value": 1 }],
+            "return_type": "Bytes",
+            "pure": true
+        } } }"#
+        )
+        .err()
+        .unwrap()
+        .to_string(),
+        "attempt to redefine field echo"
+    );
+}
+
+#[test]
+fn test_round_trip() {
+    use crate::{metadata::Metadata, scheme::test_functions, types::LhsValue};
+
+    let mut scheme = Scheme! { a: Int, http.host: Bytes };
+    scheme
+        .add_nested_field(
+            "ip".into(),
+            serde_json::from_str(r#"{ "src": "Ip", "geo": { "asn": "Int" } }"#).unwrap(),
+        )
+        .unwrap();
+    scheme.add_functions(test_functions()).unwrap();
+    scheme.add_field_alias("aa".into(), "a").unwrap();
+    scheme
+        .add_virtual_field("v".into(), "aa == 1".into())
+        .unwrap();
+    scheme.add_field_alias("vv".into(), "v").unwrap();
+    scheme
+        .add_virtual_field("host".into(), "echo(http.host)".into())
+        .unwrap();
+    scheme.add_macro("m".into(), "aa == 1 && v".into()).unwrap();
+    scheme
+        .add_virtual_field("w".into(), r#"$m && host == "example.org""#.into())
+        .unwrap();
+    scheme
+        .add_macro("n".into(), "w || ip.geo.asn == 13335".into())
+        .unwrap();
+    scheme
+        .deprecate_field("aa", "use a instead".into())
+        .unwrap();
+    scheme.set_field_default("aa", LhsValue::Int(1)).unwrap();
+    scheme
+        .set_field_metadata(
+            "ip.geo.asn",
+            Metadata {
+                description: Some("AS number".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    scheme
+        .set_function_metadata(
+            "echo",
+            Metadata {
+                description: Some("Returns its argument".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let mut registry = FunctionRegistry::new();
+    registry.add_functions(test_functions()).unwrap();
+
+    let json = serde_json::to_string(&scheme).unwrap();
+    let deserialized = registry
+        .deserialize_scheme(&mut serde_json::Deserializer::from_str(&json))
+        .unwrap();
+    assert_eq!(serde_json::to_string(&deserialized).unwrap(), json);
+
+    let filter = "$n && vv && aa == 1";
+    assert_eq!(
+        deserialized.parse(filter).unwrap().to_string(),
+        scheme.parse(filter).unwrap().to_string()
+    );
+}
+
+#[test]
+fn test_round_trip_flags() {
+    use crate::builtins::hash_functions;
+
+    let mut scheme = Scheme! { http.host: Bytes, ip.src: Ip };
+    scheme.add_functions(hash_functions()).unwrap();
+
+    let mut registry = FunctionRegistry::new();
+    registry.add_functions(hash_functions()).unwrap();
+
+    let json = serde_json::to_string(&scheme).unwrap();
+    let deserialized = registry
+        .deserialize_scheme(&mut serde_json::Deserializer::from_str(&json))
+        .unwrap();
+    assert_eq!(serde_json::to_string(&deserialized).unwrap(), json);
+    assert!(deserialized.get_function("hash_bucket").unwrap().pure);
+
+    // Hashing an IP is only allowed by the generic parameter.
+    assert!(deserialized.parse("hash_bucket(ip.src, 10) == 1").is_ok());
+
+    let mut value = serde_json::to_value(&scheme).unwrap();
+    value["functions"]["sha256"]["params"][0]["generic"] = false.into();
+    assert_eq!(
+        registry
+            .deserialize_scheme(value)
+            .err()
+            .unwrap()
+            .to_string(),
+        "function sha256 does not match the registered signature"
+    );
+}
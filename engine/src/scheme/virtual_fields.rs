@@ -0,0 +1,322 @@
+use super::{
+    FieldRedefinitionError, FunctionRedefinitionError, ItemRedefinitionError, ParseError, Scheme,
+};
+use crate::{
+    ast::VirtualFieldExpr,
+    lex::{complete, trim, LexErrorKind, LexWith},
+    limits::Parser,
+    types::GetType,
+};
+use fnv::FnvBuildHasher;
+use indexmap::map::IndexMap;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// An error that occurs when registering virtual fields.
+#[derive(Debug, PartialEq)]
+pub enum VirtualFieldError {
+    /// The field name is already taken.
+    Redefinition(ItemRedefinitionError),
+
+    /// The definition isn't a valid filter expression.
+    InvalidDefinition {
+        /// Field name.
+        name: String,
+        /// Description of the parse error.
+        message: String,
+    },
+
+    /// Virtual fields are defined in terms of each other.
+    Cycle(Vec<String>),
+}
+
+impl Error for VirtualFieldError {}
+
+impl Display for VirtualFieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VirtualFieldError::Redefinition(err) => write!(f, "{}", err),
+            VirtualFieldError::InvalidDefinition { name, message } => write!(
+                f,
+                "invalid definition of virtual field {}: {}",
+                name, message
+            ),
+            VirtualFieldError::Cycle(names) => {
+                write!(f, "virtual fields form a cycle: {}", names.join(" -> "))
+            }
+        }
+    }
+}
+
+pub(crate) struct VirtualField {
+    pub(crate) definition: String,
+    // Indices of all fields the definition depends on, directly or through
+    // other virtual fields.
+    pub(crate) dependencies: Vec<usize>,
+}
+
+impl<'s> Scheme {
+    /// Registers a virtual field whose value is computed from other fields.
+    ///
+    /// See [`Scheme::add_virtual_fields`] for details.
+    pub fn add_virtual_field(
+        &mut self,
+        name: String,
+        definition: String,
+    ) -> Result<(), VirtualFieldError> {
+        self.add_virtual_fields(vec![(name, definition)])
+    }
+
+    /// Registers virtual fields whose values are computed from other fields.
+    ///
+    /// A definition is either a field, a function call or a masked IP
+    /// address, in which case the virtual field has the same type, or any
+    /// other filter expression, in which case the virtual field is a boolean.
+    /// For example, `http.is_api` can be defined as
+    /// `starts_with(http.path, "/api/")`.
+    ///
+    /// Virtual fields can't be set in an [`ExecutionContext`](::ExecutionContext).
+    /// Instead, they are computed lazily and at most once per filter
+    /// execution. Definitions can refer to virtual fields registered earlier
+    /// or in the same batch, in any order, as long as they don't form a cycle.
+    pub fn add_virtual_fields<I>(&mut self, definitions: I) -> Result<(), VirtualFieldError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut pending = IndexMap::<String, String, FnvBuildHasher>::default();
+
+        for (name, definition) in definitions {
+            if self.functions.contains_key(&name) {
+                return Err(VirtualFieldError::Redefinition(
+                    ItemRedefinitionError::Function(FunctionRedefinitionError(name)),
+                ));
+            }
+            if self.fields.contains_key(&name)
+                || self.aliases.contains_key(&name)
+                || self.objects.contains_key(&name)
+                || pending.contains_key(&name)
+            {
+                return Err(VirtualFieldError::Redefinition(
+                    ItemRedefinitionError::Field(FieldRedefinitionError(name)),
+                ));
+            }
+            pending.insert(name, definition);
+        }
+
+        // Fields are added as soon as they are resolved, so that other
+        // definitions can refer to them, and removed again on failure.
+        let initial_len = self.fields.len();
+
+        while !pending.is_empty() {
+            // For definitions that refer to other pending fields, the index
+            // of the first such field.
+            let mut waiting_for = Vec::with_capacity(pending.len());
+            let mut resolved = None;
+            let mut failure = None;
+
+            for (name, definition) in &pending {
+                match self.parse_virtual_field(definition) {
+                    Ok(expr) => {
+                        let mut dependencies = Vec::new();
+                        expr.walk_fields(&mut |field| {
+                            dependencies.push(field.index());
+                            if let Some(field) = self.virtual_fields.get(&field.index()) {
+                                dependencies.extend(&field.dependencies);
+                            }
+                        });
+                        dependencies.sort_unstable();
+                        dependencies.dedup();
+                        resolved = Some((name.clone(), expr.get_type(), dependencies));
+                        break;
+                    }
+                    Err(ParseError {
+                        kind: LexErrorKind::UnknownField(..),
+                        input,
+                        span_start,
+                        span_len,
+                        ..
+                    }) if pending.contains_key(&input[span_start..span_start + span_len]) => {
+                        waiting_for.push(
+                            pending
+                                .get_index_of(&input[span_start..span_start + span_len])
+                                .unwrap(),
+                        );
+                    }
+                    Err(err) => {
+                        failure = Some(VirtualFieldError::InvalidDefinition {
+                            name: name.clone(),
+                            message: err.to_string(),
+                        });
+                        break;
+                    }
+                }
+            }
+
+            match (resolved, failure) {
+                (Some((name, ty, dependencies)), _) => {
+                    let definition = pending.shift_remove(&name).unwrap();
+                    let (index, _) = self.fields.insert_full(name, ty);
+                    self.virtual_fields.insert(
+                        index,
+                        VirtualField {
+                            definition,
+                            dependencies,
+                        },
+                    );
+                }
+                (None, Some(err)) => {
+                    self.remove_fields_from(initial_len);
+                    return Err(err);
+                }
+                (None, None) => {
+                    // Every pending definition waits for another one, so
+                    // following them must lead to a cycle.
+                    let mut path = vec![0];
+                    let mut next = waiting_for[0];
+                    while !path.contains(&next) {
+                        path.push(next);
+                        next = waiting_for[next];
+                    }
+                    let start = path.iter().position(|&index| index == next).unwrap();
+                    path.push(next);
+                    self.remove_fields_from(initial_len);
+                    return Err(VirtualFieldError::Cycle(
+                        path[start..]
+                            .iter()
+                            .map(|&index| pending.get_index(index).unwrap().0.clone())
+                            .collect(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn remove_fields_from(&mut self, len: usize) {
+        self.fields.truncate(len);
+        self.virtual_fields.retain(|&index, _| index < len);
+    }
+
+    pub(crate) fn parse_virtual_field<'i>(
+        &'s self,
+        definition: &'i str,
+    ) -> Result<VirtualFieldExpr<'s>, ParseError<'i>> {
+        complete(VirtualFieldExpr::lex_with(
+            trim(definition),
+            &Parser::trusted(self),
+        ))
+        .map_err(|err| ParseError::new(definition, err))
+    }
+}
+
+#[test]
+fn test_virtual_fields() {
+    use crate::{
+        scheme::{test_functions, FunctionRegistry},
+        types::Type,
+    };
+
+    let mut scheme = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+    scheme.add_functions(test_functions()).unwrap();
+
+    scheme
+        .add_virtual_fields(vec![
+            ("http.is_web".into(), "tcp.port in {80 443}".into()),
+            ("http.host_echo".into(), "echo(http.echo_source)".into()),
+            ("http.echo_source".into(), "http.host".into()),
+        ])
+        .unwrap();
+
+    let is_web = scheme.get_field_index("http.is_web").unwrap();
+    assert!(is_web.is_virtual());
+    assert_eq!(is_web.get_type(), Type::Bool);
+    assert_eq!(is_web.virtual_definition(), Some("tcp.port in {80 443}"));
+
+    let host_echo = scheme.get_field_index("http.host_echo").unwrap();
+    assert_eq!(host_echo.get_type(), Type::Bytes);
+    assert!(host_echo.depends_on(scheme.get_field_index("http.host").unwrap()));
+    assert!(host_echo.depends_on(scheme.get_field_index("http.echo_source").unwrap()));
+    assert!(!host_echo.depends_on(scheme.get_field_index("tcp.port").unwrap()));
+    assert!(!scheme.get_field_index("http.host").unwrap().is_virtual());
+
+    assert_eq!(
+        scheme.add_virtual_field("tcp.port".into(), "ip.src".into()),
+        Err(VirtualFieldError::Redefinition(
+            ItemRedefinitionError::Field(FieldRedefinitionError("tcp.port".into()))
+        ))
+    );
+
+    assert_eq!(
+        scheme.add_virtual_field("echo".into(), "ip.src".into()),
+        Err(VirtualFieldError::Redefinition(
+            ItemRedefinitionError::Function(FunctionRedefinitionError("echo".into()))
+        ))
+    );
+
+    let err = scheme
+        .add_virtual_field("a".into(), "http.path".into())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid definition of virtual field a: Filter parsing error (1:1):\nhttp.path\n^^^^^^^^^ unknown field\n"
+    );
+
+    assert_eq!(
+        scheme.add_virtual_fields(vec![
+            ("a".into(), "b".into()),
+            ("b".into(), "c == 1".into()),
+            ("c".into(), "echo(b)".into()),
+        ]),
+        Err(VirtualFieldError::Cycle(vec![
+            "b".into(),
+            "c".into(),
+            "b".into()
+        ]))
+    );
+
+    let err = scheme
+        .add_virtual_field("a".into(), "a".into())
+        .unwrap_err();
+    assert_eq!(err, VirtualFieldError::Cycle(vec!["a".into(), "a".into()]));
+    assert_eq!(err.to_string(), "virtual fields form a cycle: a -> a");
+
+    // Failed batches don't leave any fields behind.
+    assert_eq!(
+        scheme.add_virtual_fields(vec![
+            ("a".into(), "ip.src".into()),
+            ("b".into(), "a ==".into()),
+        ]),
+        Err(VirtualFieldError::InvalidDefinition {
+            name: "b".into(),
+            message: "Filter parsing error (1:5):\na ==\n    ^ expected IP address character\n"
+                .into(),
+        })
+    );
+    assert!(scheme.get_field_index("a").is_err());
+
+    let json = serde_json::to_value(&scheme).unwrap();
+    assert_eq!(
+        json["fields"],
+        serde_json::json!({ "http.host": "Bytes", "ip.src": "Ip", "tcp.port": "Int" })
+    );
+    assert_eq!(
+        json["virtual_fields"],
+        serde_json::json!({
+            "http.is_web": "tcp.port in {80 443}",
+            "http.echo_source": "http.host",
+            "http.host_echo": "echo(http.echo_source)"
+        })
+    );
+
+    let mut registry = FunctionRegistry::new();
+    registry.add_functions(test_functions()).unwrap();
+    let deserialized = registry.deserialize_scheme(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&deserialized).unwrap(), json);
+    assert!(deserialized
+        .get_field_index("http.host_echo")
+        .unwrap()
+        .is_virtual());
+}
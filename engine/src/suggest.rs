@@ -0,0 +1,142 @@
+use std::{
+    cmp::{max, min},
+    fmt::{self, Display, Formatter},
+};
+
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Registered names that an unknown name used in a filter might have meant.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Suggestions(Vec<String>);
+
+impl Suggestions {
+    /// Picks candidates within a small edit distance of `name`, closest
+    /// first, followed by candidates which extend each dotted segment of it,
+    /// e.g. `http.request.uri` for `http.req`.
+    pub(crate) fn new<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Self {
+        let max_distance = max(1, name.len() / 3);
+        let mut close = Vec::new();
+        let mut extending = Vec::new();
+
+        for candidate in candidates {
+            if candidate == name {
+                continue;
+            }
+            let distance = edit_distance(name, candidate);
+            if distance <= max_distance {
+                close.push((distance, candidate));
+            } else if extends_segments(name, candidate) {
+                extending.push(candidate);
+            }
+        }
+
+        // Stable, so that equally close candidates keep the original order.
+        close.sort_by_key(|&(distance, _)| distance);
+
+        Suggestions(
+            close
+                .into_iter()
+                .map(|(_, candidate)| candidate)
+                .chain(extending)
+                .take(MAX_SUGGESTIONS)
+                .map(str::to_owned)
+                .collect(),
+        )
+    }
+
+    /// Returns the suggested names, best matches first.
+    pub fn names(&self) -> &[String] {
+        &self.0
+    }
+}
+
+// Formatted as a hint following the message of an error, or as nothing at all
+// if there is no suggestion.
+impl Display for Suggestions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some((last, rest)) = self.0.split_last() {
+            f.write_str(", did you mean ")?;
+            if !rest.is_empty() {
+                write!(f, "{} or ", rest.join(", "))?;
+            }
+            write!(f, "{}?", last)?;
+        }
+        Ok(())
+    }
+}
+
+/// Levenshtein distance between two names, which are expected to be short.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.as_bytes();
+    let mut row: Vec<usize> = (0..=a.len()).collect();
+
+    for (j, &b_byte) in b.as_bytes().iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = j + 1;
+        for (i, &a_byte) in a.iter().enumerate() {
+            let substitution = diagonal + (a_byte != b_byte) as usize;
+            diagonal = row[i + 1];
+            row[i + 1] = min(substitution, min(row[i], row[i + 1]) + 1);
+        }
+    }
+
+    row[a.len()]
+}
+
+fn extends_segments(prefix: &str, name: &str) -> bool {
+    let mut segments = name.split('.');
+    prefix.split('.').all(|prefix| {
+        segments
+            .next()
+            .is_some_and(|segment| segment.starts_with(prefix))
+    })
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("", ""), 0);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("ip.src", "ip.src"), 0);
+    assert_eq!(edit_distance("ip.scr", "ip.src"), 2);
+    assert_eq!(edit_distance("http.hst", "http.host"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+}
+
+#[test]
+fn test_suggestions() {
+    let candidates = [
+        "http.host",
+        "http.request.uri",
+        "http.request.method",
+        "http.cookie",
+        "ip.src",
+        "ip.dst",
+    ];
+
+    let suggest = |name| Suggestions::new(name, candidates.iter().cloned());
+
+    assert_eq!(suggest("http.hots").names(), ["http.host"]);
+    assert_eq!(suggest("ip.scr").names(), ["ip.src"]);
+    assert_eq!(suggest("ip.srt").names(), ["ip.src", "ip.dst"]);
+    assert_eq!(
+        suggest("http.req").names(),
+        ["http.request.uri", "http.request.method"]
+    );
+    assert_eq!(suggest("h.r.m").names(), ["http.request.method"]);
+    assert_eq!(suggest("tcp.port").names(), [] as [&str; 0]);
+    assert_eq!(suggest("http.host").names(), [] as [&str; 0]);
+
+    assert_eq!(suggest("tcp.port").to_string(), "");
+    assert_eq!(
+        suggest("http.hots").to_string(),
+        ", did you mean http.host?"
+    );
+    assert_eq!(
+        suggest("ip.srt").to_string(),
+        ", did you mean ip.src or ip.dst?"
+    );
+    assert_eq!(
+        suggest("http.r").to_string(),
+        ", did you mean http.request.uri or http.request.method?"
+    );
+}
@@ -1,20 +1,27 @@
 use crate::{
-    lex::{expect, skip_space, Lex, LexResult, LexWith},
+    lex::{expect, skip_space, Lex, LexErrorKind, LexResult, LexWith},
+    limits::ParseLimits,
     rhs_types::{Bytes, IpRange, UninhabitedBool},
     strict_partial_ord::StrictPartialOrd,
 };
 use failure::Fail;
-use serde::{Deserialize, Serialize};
+use fnv::FnvBuildHasher;
+use indexmap::IndexMap;
+use serde::{
+    de::{self, value::MapAccessDeserializer, Deserializer, IntoDeserializer, MapAccess, Visitor},
+    Deserialize, Serialize, Serializer,
+};
 use std::{
     borrow::Cow,
     cmp::Ordering,
     convert::TryFrom,
-    fmt::{self, Debug, Formatter},
+    fmt::{self, Debug, Display, Formatter},
+    iter::FromIterator,
     net::IpAddr,
     ops::RangeInclusive,
 };
 
-fn lex_rhs_values<'i, T: Lex<'i>>(input: &'i str) -> LexResult<'i, Vec<T>> {
+fn lex_rhs_values<'i, T: Lex<'i>>(input: &'i str, max_len: usize) -> LexResult<'i, Vec<T>> {
     let mut input = expect(input, "{")?;
     let mut res = Vec::new();
     loop {
@@ -23,6 +30,9 @@ fn lex_rhs_values<'i, T: Lex<'i>>(input: &'i str) -> LexResult<'i, Vec<T>> {
             input = rest;
             return Ok((res, input));
         } else {
+            if res.len() == max_len {
+                return Err((LexErrorKind::SetTooLarge { limit: max_len }, input));
+            }
             let (item, rest) = T::lex(input)?;
             res.push(item);
             input = rest;
@@ -70,7 +80,7 @@ macro_rules! declare_types {
 
     ($($(# $attrs:tt)* $name:ident ( $(# $lhs_attrs:tt)* $lhs_ty:ty | $rhs_ty:ty | $multi_rhs_ty:ty ) , )*) => {
         /// Enumeration of supported types for field values.
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
         #[repr(C)]
         pub enum Type {
             $($(# $attrs)* $name,)*
@@ -172,11 +182,11 @@ macro_rules! declare_types {
             }
         }
 
-        impl<'i> LexWith<'i, Type> for RhsValues {
-            fn lex_with(input: &str, ty: Type) -> LexResult<'_, Self> {
+        impl<'i, 'a> LexWith<'i, (Type, &'a ParseLimits)> for RhsValues {
+            fn lex_with(input: &'i str, (ty, limits): (Type, &'a ParseLimits)) -> LexResult<'i, Self> {
                 Ok(match ty {
                     $(Type::$name => {
-                        let (value, input) = lex_rhs_values(input)?;
+                        let (value, input) = lex_rhs_values(input, limits.max_set_len)?;
                         (RhsValues::$name(value), input)
                     })*
                 })
@@ -226,6 +236,152 @@ impl<'a> From<&'a RhsValue> for LhsValue<'a> {
     }
 }
 
+// Formatted as a literal in a filter.
+impl Display for RhsValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RhsValue::Ip(ip) => Display::fmt(ip, f),
+            RhsValue::Bytes(bytes) => Display::fmt(bytes, f),
+            RhsValue::Int(integer) => Display::fmt(integer, f),
+            RhsValue::Bool(b) => match *b {},
+        }
+    }
+}
+
+// Formatted as a set literal in a filter.
+impl Display for RhsValues {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn write_items<T>(
+            f: &mut Formatter<'_>,
+            items: &[T],
+            write_item: impl Fn(&mut Formatter<'_>, &T) -> fmt::Result,
+        ) -> fmt::Result {
+            f.write_str("{")?;
+            for (i, item) in items.iter().enumerate() {
+                if i != 0 {
+                    f.write_str(" ")?;
+                }
+                write_item(f, item)?;
+            }
+            f.write_str("}")
+        }
+
+        match self {
+            RhsValues::Ip(ranges) => write_items(f, ranges, |f, range| Display::fmt(range, f)),
+            RhsValues::Bytes(bytes) => write_items(f, bytes, |f, bytes| Display::fmt(bytes, f)),
+            RhsValues::Int(ranges) => write_items(f, ranges, |f, range| {
+                if range.start() == range.end() {
+                    Display::fmt(range.start(), f)
+                } else {
+                    write!(f, "{}..{}", range.start(), range.end())
+                }
+            }),
+            RhsValues::Bool(values) => write_items(f, values, |_, b| match *b {}),
+        }
+    }
+}
+
+// Bytes are serialized as strings when possible, so that values round-trip
+// through the untagged `Deserialize` implementation above.
+impl<'a> Serialize for LhsValue<'a> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        match self {
+            LhsValue::Ip(ip) => ip.serialize(ser),
+            LhsValue::Bytes(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => s.serialize(ser),
+                Err(_) => bytes.serialize(ser),
+            },
+            LhsValue::Int(integer) => integer.serialize(ser),
+            LhsValue::Bool(b) => b.serialize(ser),
+        }
+    }
+}
+
+/// A type of a field that can also be an object with nested fields.
+///
+/// It is serialized either as a [`Type`] or as a map of member names to their
+/// types, so that nested schemas can be described in JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum FieldType {
+    /// A field with a value of the given type.
+    Value(Type),
+    /// An object whose members are accessed as `object.member`.
+    Object(IndexMap<String, FieldType, FnvBuildHasher>),
+}
+
+// Not derived as an untagged enum, so that errors in nested types, such as
+// unknown type names, are reported as they are.
+impl<'de> Deserialize<'de> for FieldType {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct FieldTypeVisitor;
+
+        impl<'de> Visitor<'de> for FieldTypeVisitor {
+            type Value = FieldType;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str("a type name or a map of members to their types")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<FieldType, E> {
+                Type::deserialize(value.into_deserializer()).map(FieldType::Value)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<FieldType, A::Error> {
+                IndexMap::deserialize(MapAccessDeserializer::new(map)).map(FieldType::Object)
+            }
+        }
+
+        de.deserialize_any(FieldTypeVisitor)
+    }
+}
+
+impl From<Type> for FieldType {
+    fn from(ty: Type) -> Self {
+        FieldType::Value(ty)
+    }
+}
+
+impl<S: Into<String>, T: Into<FieldType>> FromIterator<(S, T)> for FieldType {
+    fn from_iter<I: IntoIterator<Item = (S, T)>>(iter: I) -> Self {
+        FieldType::Object(
+            iter.into_iter()
+                .map(|(name, ty)| (name.into(), ty.into()))
+                .collect(),
+        )
+    }
+}
+
+/// A value deserialized without knowing its type, such as a default value or
+/// a value of a nested field, that is checked against the expected type
+/// afterwards.
+///
+/// Unlike the `Deserialize` implementation of [`LhsValue`], this doesn't
+/// guess whether a string is an IP address or bytes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum RawValue {
+    Bool(bool),
+    Int(i32),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl RawValue {
+    pub fn into_typed(self, ty: Type) -> Result<LhsValue<'static>, String> {
+        Ok(match (ty, self) {
+            (Type::Ip, RawValue::Str(s)) => {
+                LhsValue::Ip(s.parse().map_err(|err| format!("{}", err))?)
+            }
+            (Type::Bytes, RawValue::Str(s)) => LhsValue::from(s),
+            (Type::Bytes, RawValue::Bytes(b)) => LhsValue::from(b),
+            (Type::Int, RawValue::Int(i)) => LhsValue::Int(i),
+            (Type::Bool, RawValue::Bool(b)) => LhsValue::Bool(b),
+            (ty, _) => return Err(format!("value is not of type {:?}", ty)),
+        })
+    }
+}
+
 impl<'a> LhsValue<'a> {
     /// Converts a reference to an LhsValue to an LhsValue with an internal
     /// references
@@ -237,6 +393,16 @@ impl<'a> LhsValue<'a> {
             LhsValue::Bool(b) => LhsValue::Bool(*b),
         }
     }
+
+    /// Converts an LhsValue with internal references into an owned one.
+    pub(crate) fn into_owned(self) -> LhsValue<'static> {
+        match self {
+            LhsValue::Ip(ip) => LhsValue::Ip(ip),
+            LhsValue::Bytes(bytes) => LhsValue::Bytes(Cow::Owned(bytes.into_owned())),
+            LhsValue::Int(integer) => LhsValue::Int(integer),
+            LhsValue::Bool(b) => LhsValue::Bool(b),
+        }
+    }
 }
 
 declare_types!(
@@ -291,3 +457,21 @@ fn test_lhs_value_deserialize() {
     let b: LhsValue<'_> = serde_json::from_str("false").unwrap();
     assert_eq!(b, LhsValue::Bool(false));
 }
+
+#[test]
+fn test_lhs_value_serialize() {
+    use std::str::FromStr;
+
+    assert_json!(
+        LhsValue::Ip(IpAddr::from_str("127.0.0.1").unwrap()),
+        "127.0.0.1"
+    );
+
+    assert_json!(LhsValue::from("a string ❤"), "a string ❤");
+
+    assert_json!(LhsValue::from(&b"\xFF\x00"[..]), [255, 0]);
+
+    assert_json!(LhsValue::Int(1337), 1337);
+
+    assert_json!(LhsValue::Bool(true), true);
+}
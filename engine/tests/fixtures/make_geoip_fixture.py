@@ -0,0 +1,177 @@
+#!/usr/bin/env python3
+"""Generates tiny MaxMind DBs used by the GeoIP tests.
+
+The databases follow the MaxMind DB format 2.0 specification
+(https://maxmind.github.io/MaxMind-DB/) and contain both country and ASN
+records, so the same file can serve `ip_country` and `ip_asn`.
+
+`geoip-test-v2.mmdb` is an updated version of `geoip-test.mmdb` with
+`192.0.2.0/24` moved to another country and AS, which is used to test
+replacing databases at runtime.
+
+Usage: python3 make_geoip_fixture.py [output directory]
+"""
+
+import ipaddress
+import os
+import struct
+import sys
+
+NETWORKS = [
+    (
+        "192.0.2.0/24",
+        {
+            "country": {"iso_code": "US"},
+            "autonomous_system_number": 64496,
+            "autonomous_system_organization": "Example Networks",
+        },
+    ),
+    (
+        "198.51.100.0/25",
+        {
+            "registered_country": {"iso_code": "FR"},
+            "autonomous_system_number": 64497,
+        },
+    ),
+    (
+        "2001:db8::/32",
+        {
+            "country": {"iso_code": "DE"},
+            "autonomous_system_number": 4200000000,
+        },
+    ),
+]
+
+NETWORKS_V2 = [
+    (
+        "192.0.2.0/24",
+        {
+            "country": {"iso_code": "CA"},
+            "autonomous_system_number": 64511,
+        },
+    ),
+] + NETWORKS[1:]
+
+
+def encode_control(type_id, size):
+    extended = type_id > 7
+    first = (0 if extended else type_id) << 5
+    if size < 29:
+        head = bytes([first | size])
+    elif size < 29 + 256:
+        head = bytes([first | 29, size - 29])
+    elif size < 285 + 65536:
+        head = bytes([first | 30]) + struct.pack(">H", size - 285)
+    else:
+        head = bytes([first | 31]) + struct.pack(">I", size - 65821)[1:]
+    if extended:
+        head = head[:1] + bytes([type_id - 7]) + head[1:]
+    return head
+
+
+def encode_uint(type_id, value):
+    payload = value.to_bytes((value.bit_length() + 7) // 8, "big")
+    return encode_control(type_id, len(payload)) + payload
+
+
+def encode(value, uint_type=6):
+    if isinstance(value, str):
+        data = value.encode("utf-8")
+        return encode_control(2, len(data)) + data
+    if isinstance(value, int):
+        return encode_uint(uint_type, value)
+    if isinstance(value, dict):
+        out = encode_control(7, len(value))
+        for key, item in value.items():
+            out += encode(key) + encode(item, uint_type)
+        return out
+    if isinstance(value, list):
+        out = encode_control(11, len(value))
+        for item in value:
+            out += encode(item, uint_type)
+        return out
+    raise TypeError(value)
+
+
+def build(networks):
+    # Data section: each record is stored once and referenced by its offset.
+    data = b""
+    offsets = []
+    for _, record in networks:
+        offsets.append(len(data))
+        data += encode(record)
+
+    # Binary trie over 128-bit addresses; IPv4 networks live in ::/96.
+    root = [None, None]
+    for (network, _), offset in zip(networks, offsets):
+        network = ipaddress.ip_network(network)
+        bits, prefix_len = int(network.network_address), network.prefixlen
+        if network.version == 4:
+            prefix_len += 96
+        node = root
+        for i in range(prefix_len):
+            bit = (bits >> (127 - i)) & 1
+            if i == prefix_len - 1:
+                node[bit] = ("data", offset)
+            else:
+                if node[bit] is None:
+                    node[bit] = [None, None]
+                node = node[bit]
+
+    # Number nodes in breadth-first order.
+    nodes = [root]
+    i = 0
+    while i < len(nodes):
+        for child in nodes[i]:
+            if isinstance(child, list):
+                nodes.append(child)
+        i += 1
+    ids = {id(node): index for index, node in enumerate(nodes)}
+    node_count = len(nodes)
+
+    tree = b""
+    for node in nodes:
+        for child in node:
+            if child is None:
+                value = node_count
+            elif isinstance(child, list):
+                value = ids[id(child)]
+            else:
+                value = node_count + 16 + child[1]
+            tree += value.to_bytes(3, "big")
+
+    metadata = {
+        "binary_format_major_version": 2,
+        "binary_format_minor_version": 0,
+        "build_epoch": 1546300800,
+        "database_type": "Wirefilter-Test",
+        "description": {"en": "Test fixture for the wirefilter GeoIP functions"},
+        "ip_version": 6,
+        "languages": ["en"],
+        "node_count": node_count,
+        "record_size": 24,
+    }
+    encoded_metadata = encode_control(7, len(metadata))
+    for key, value in metadata.items():
+        if key in ("binary_format_major_version", "binary_format_minor_version",
+                   "ip_version", "record_size"):
+            item = encode_uint(5, value)
+        elif key == "build_epoch":
+            item = encode_uint(9, value)
+        else:
+            item = encode(value)
+        encoded_metadata += encode(key) + item
+
+    return tree + bytes(16) + data + b"\xab\xcd\xefMaxMind.com" + encoded_metadata
+
+
+if __name__ == "__main__":
+    directory = sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(
+        os.path.abspath(__file__)
+    )
+    for name, networks in [
+        ("geoip-test.mmdb", NETWORKS),
+        ("geoip-test-v2.mmdb", NETWORKS_V2),
+    ]:
+        with open(os.path.join(directory, name), "wb") as f:
+            f.write(build(networks))
@@ -38,6 +38,7 @@ typedef union {
     struct {
         uint8_t _res1;
         wirefilter_rust_allocated_str_t msg;
+        wirefilter_rust_allocated_str_t json;
     } err;
     struct {
         uint8_t _res2;
@@ -55,11 +56,77 @@ typedef enum {
 wirefilter_scheme_t *wirefilter_create_scheme();
 void wirefilter_free_scheme(wirefilter_scheme_t *scheme);
 
-void wirefilter_add_type_field_to_scheme(
+// Functions modifying a scheme return false if it's already shared with a
+// parsed or compiled filter, as well as when the modification is invalid.
+bool wirefilter_add_type_field_to_scheme(
     wirefilter_scheme_t *scheme,
     wirefilter_externally_allocated_str_t name,
     wirefilter_type_t type
 );
+bool wirefilter_add_nested_field_to_scheme(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    wirefilter_externally_allocated_str_t type_json
+);
+bool wirefilter_add_virtual_field_to_scheme(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    wirefilter_externally_allocated_str_t definition
+);
+bool wirefilter_add_macro_to_scheme(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    wirefilter_externally_allocated_str_t definition
+);
+
+bool wirefilter_set_int_field_default(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    int32_t value
+);
+bool wirefilter_set_bytes_field_default(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    wirefilter_externally_allocated_byte_arr_t value
+);
+bool wirefilter_set_ipv6_field_default(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    uint8_t value[16]
+);
+bool wirefilter_set_ipv4_field_default(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    uint8_t value[4]
+);
+bool wirefilter_set_bool_field_default(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    bool value
+);
+
+bool wirefilter_add_hash_functions_to_scheme(wirefilter_scheme_t *scheme);
+bool wirefilter_add_ip_functions_to_scheme(wirefilter_scheme_t *scheme);
+bool wirefilter_add_domain_functions_to_scheme(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t public_suffix_list_path
+);
+bool wirefilter_add_bundled_domain_functions_to_scheme(wirefilter_scheme_t *scheme);
+
+bool wirefilter_set_field_metadata(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    wirefilter_externally_allocated_str_t metadata_json
+);
+bool wirefilter_set_function_metadata(
+    wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t name,
+    wirefilter_externally_allocated_str_t metadata_json
+);
+wirefilter_rust_allocated_str_t wirefilter_get_scheme_items_with_prefix(
+    const wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t prefix
+);
 
 wirefilter_parsing_result_t wirefilter_parse_filter(
     const wirefilter_scheme_t *scheme,
@@ -68,6 +135,11 @@ wirefilter_parsing_result_t wirefilter_parse_filter(
 
 void wirefilter_free_parsing_result(wirefilter_parsing_result_t result);
 
+wirefilter_rust_allocated_str_t wirefilter_get_filter_diagnostics(
+    const wirefilter_scheme_t *scheme,
+    wirefilter_externally_allocated_str_t input
+);
+
 wirefilter_filter_t *wirefilter_compile_filter(wirefilter_filter_ast_t *ast);
 void wirefilter_free_compiled_filter(wirefilter_filter_t *filter);
 
@@ -108,6 +180,17 @@ void wirefilter_add_bool_value_to_execution_context(
     bool value
 );
 
+bool wirefilter_add_json_value_to_execution_context(
+    wirefilter_execution_context_t *exec_ctx,
+    wirefilter_externally_allocated_str_t name,
+    wirefilter_externally_allocated_str_t value_json
+);
+
+bool wirefilter_is_field_set(
+    const wirefilter_execution_context_t *exec_ctx,
+    wirefilter_externally_allocated_str_t name
+);
+
 bool wirefilter_match(
     const wirefilter_filter_t *filter,
     const wirefilter_execution_context_t *exec_ctx
@@ -124,6 +207,10 @@ wirefilter_rust_allocated_str_t wirefilter_serialize_filter_to_json(
     const wirefilter_filter_ast_t *ast
 );
 
+wirefilter_rust_allocated_str_t wirefilter_serialize_scheme_to_json(
+    const wirefilter_scheme_t *scheme
+);
+
 void wirefilter_free_string(wirefilter_rust_allocated_str_t str);
 
 wirefilter_static_rust_allocated_str_t wirefilter_get_version();
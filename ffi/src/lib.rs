@@ -9,59 +9,249 @@ use std::{
     hash::Hasher,
     io::{self, Write},
     net::IpAddr,
+    sync::Arc,
+};
+use wirefilter::{
+    builtins::{domain_functions, PublicSuffixList},
+    ExecutionContext, FieldType, LhsValue, Metadata, OwnedFilter, OwnedFilterAst, ParseError,
+    Scheme, Type,
 };
-use wirefilter::{ExecutionContext, Filter, FilterAst, ParseError, Scheme, Type};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[repr(u8)]
-pub enum ParsingResult<'s> {
-    Err(RustAllocatedString),
-    Ok(RustBox<FilterAst<'s>>),
+pub enum ParsingResult {
+    /// Human-readable message and JSON with the code, span etc. of the error.
+    Err(RustAllocatedString, RustAllocatedString),
+    Ok(RustBox<OwnedFilterAst>),
 }
 
-impl<'s> From<FilterAst<'s>> for ParsingResult<'s> {
-    fn from(filter_ast: FilterAst<'s>) -> Self {
+impl From<OwnedFilterAst> for ParsingResult {
+    fn from(filter_ast: OwnedFilterAst) -> Self {
         ParsingResult::Ok(filter_ast.into())
     }
 }
 
-impl<'s, 'a> From<ParseError<'a>> for ParsingResult<'s> {
+impl<'a> From<ParseError<'a>> for ParsingResult {
     fn from(err: ParseError<'a>) -> Self {
-        ParsingResult::Err(RustAllocatedString::from(err.to_string()))
+        let json = serde_json::to_string(&err)
+            .unwrap_or_else(|json_err| panic!("{} while serializing {:?}", json_err, err));
+        ParsingResult::Err(err.to_string().into(), json.into())
     }
 }
 
-impl<'s> ParsingResult<'s> {
-    pub fn unwrap(self) -> RustBox<FilterAst<'s>> {
+impl ParsingResult {
+    pub fn unwrap(self) -> RustBox<OwnedFilterAst> {
         match self {
-            ParsingResult::Err(err) => panic!("{}", &err as &str),
+            ParsingResult::Err(err, _) => panic!("{}", &err as &str),
             ParsingResult::Ok(filter) => filter,
         }
     }
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_create_scheme() -> RustBox<Scheme> {
+pub extern "C" fn wirefilter_create_scheme() -> RustBox<Arc<Scheme>> {
     Default::default()
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_free_scheme(scheme: RustBox<Scheme>) {
+pub extern "C" fn wirefilter_free_scheme(scheme: RustBox<Arc<Scheme>>) {
     drop(scheme);
 }
 
+/// Applies a modification to the scheme, returning whether it succeeded.
+///
+/// Parsed and compiled filters share the scheme, so it can only be modified
+/// before the first filter is parsed, and all modifications fail afterwards.
+fn modify_scheme<T, E>(
+    scheme: &mut Arc<Scheme>,
+    modify: impl FnOnce(&mut Scheme) -> Result<T, E>,
+) -> bool {
+    match Arc::get_mut(scheme) {
+        Some(scheme) => modify(scheme).is_ok(),
+        None => false,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn wirefilter_add_type_field_to_scheme(
-    scheme: &mut Scheme,
+    scheme: &mut Arc<Scheme>,
     name: ExternallyAllocatedStr<'_>,
     ty: Type,
-) {
-    scheme.add_field(name.into_ref().to_owned(), ty).unwrap();
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.add_field(name.into_ref().to_owned(), ty)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_nested_field_to_scheme(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    type_json: ExternallyAllocatedStr<'_>,
+) -> bool {
+    match serde_json::from_str::<FieldType>(type_json.into_ref()) {
+        Ok(ty) => modify_scheme(scheme, |scheme| {
+            scheme.add_nested_field(name.into_ref().to_owned(), ty)
+        }),
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_virtual_field_to_scheme(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    definition: ExternallyAllocatedStr<'_>,
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.add_virtual_field(name.into_ref().to_owned(), definition.into_ref().to_owned())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_macro_to_scheme(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    definition: ExternallyAllocatedStr<'_>,
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.add_macro(name.into_ref().to_owned(), definition.into_ref().to_owned())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_set_int_field_default(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    value: i32,
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.set_field_default(name.into_ref(), LhsValue::Int(value))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_set_bytes_field_default(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    value: ExternallyAllocatedByteArr<'_>,
+) -> bool {
+    let slice: &[u8] = value.into_ref();
+    modify_scheme(scheme, |scheme| {
+        scheme.set_field_default(name.into_ref(), LhsValue::from(slice.to_vec()))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_set_ipv6_field_default(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    value: &[u8; 16],
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.set_field_default(name.into_ref(), LhsValue::Ip(IpAddr::from(*value)))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_set_ipv4_field_default(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    value: &[u8; 4],
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.set_field_default(name.into_ref(), LhsValue::Ip(IpAddr::from(*value)))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_set_bool_field_default(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    value: bool,
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.set_field_default(name.into_ref(), LhsValue::Bool(value))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_hash_functions_to_scheme(scheme: &mut Arc<Scheme>) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.add_functions(wirefilter::builtins::hash_functions())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_ip_functions_to_scheme(scheme: &mut Arc<Scheme>) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.add_functions(wirefilter::builtins::ip_functions())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_domain_functions_to_scheme(
+    scheme: &mut Arc<Scheme>,
+    public_suffix_list_path: ExternallyAllocatedStr<'_>,
+) -> bool {
+    match PublicSuffixList::from_file(public_suffix_list_path.into_ref()) {
+        Ok(list) => modify_scheme(scheme, |scheme| {
+            scheme.add_functions(domain_functions(list))
+        }),
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_bundled_domain_functions_to_scheme(
+    scheme: &mut Arc<Scheme>,
+) -> bool {
+    modify_scheme(scheme, |scheme| {
+        scheme.add_functions(domain_functions(PublicSuffixList::bundled()))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_set_field_metadata(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    metadata_json: ExternallyAllocatedStr<'_>,
+) -> bool {
+    match serde_json::from_str::<Metadata>(metadata_json.into_ref()) {
+        Ok(metadata) => modify_scheme(scheme, |scheme| {
+            scheme.set_field_metadata(name.into_ref(), metadata)
+        }),
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_set_function_metadata(
+    scheme: &mut Arc<Scheme>,
+    name: ExternallyAllocatedStr<'_>,
+    metadata_json: ExternallyAllocatedStr<'_>,
+) -> bool {
+    match serde_json::from_str::<Metadata>(metadata_json.into_ref()) {
+        Ok(metadata) => modify_scheme(scheme, |scheme| {
+            scheme.set_function_metadata(name.into_ref(), metadata)
+        }),
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_get_scheme_items_with_prefix(
+    scheme: &Arc<Scheme>,
+    prefix: ExternallyAllocatedStr<'_>,
+) -> RustAllocatedString {
+    serde_json::to_string(&scheme.items_with_prefix(prefix.into_ref()))
+        .unwrap_or_else(|err| panic!("{} while serializing scheme items", err))
+        .into()
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_free_parsed_filter(filter_ast: RustBox<FilterAst<'_>>) {
+pub extern "C" fn wirefilter_free_parsed_filter(filter_ast: RustBox<OwnedFilterAst>) {
     drop(filter_ast);
 }
 
@@ -71,18 +261,32 @@ pub extern "C" fn wirefilter_free_string(s: RustAllocatedString) {
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_parse_filter<'s, 'i>(
-    scheme: &'s Scheme,
-    input: ExternallyAllocatedStr<'i>,
-) -> ParsingResult<'s> {
-    match scheme.parse(input.into_ref()) {
+pub extern "C" fn wirefilter_parse_filter(
+    scheme: &Arc<Scheme>,
+    input: ExternallyAllocatedStr<'_>,
+) -> ParsingResult {
+    match OwnedFilterAst::parse(Arc::clone(scheme), input.into_ref()) {
         Ok(filter) => ParsingResult::from(filter),
         Err(err) => ParsingResult::from(err),
     }
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_free_parsing_result(r: ParsingResult<'_>) {
+pub extern "C" fn wirefilter_get_filter_diagnostics(
+    scheme: &Arc<Scheme>,
+    input: ExternallyAllocatedStr<'_>,
+) -> RustAllocatedString {
+    let diagnostics = match scheme.parse_with_diagnostics(input.into_ref()) {
+        Ok(_) => Vec::new(),
+        Err(diagnostics) => diagnostics,
+    };
+    serde_json::to_string(&diagnostics)
+        .unwrap_or_else(|err| panic!("{} while serializing filter diagnostics", err))
+        .into()
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_free_parsing_result(r: ParsingResult) {
     drop(r);
 }
 
@@ -106,13 +310,13 @@ impl<H: Hasher> Write for HasherWrite<H> {
     }
 }
 
-fn unwrap_json_result<T>(filter_ast: &FilterAst<'_>, result: serde_json::Result<T>) -> T {
+fn unwrap_json_result<T>(filter_ast: &OwnedFilterAst, result: serde_json::Result<T>) -> T {
     // Filter serialisation must never fail.
     result.unwrap_or_else(|err| panic!("{} while serializing filter {:#?}", err, filter_ast))
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_get_filter_hash(filter_ast: &FilterAst<'_>) -> u64 {
+pub extern "C" fn wirefilter_get_filter_hash(filter_ast: &OwnedFilterAst) -> u64 {
     let mut hasher = FnvHasher::default();
     // Serialize JSON to our Write-compatible wrapper around FnvHasher,
     // effectively calculating a hash for our filter in a streaming fashion
@@ -125,15 +329,23 @@ pub extern "C" fn wirefilter_get_filter_hash(filter_ast: &FilterAst<'_>) -> u64
 
 #[no_mangle]
 pub extern "C" fn wirefilter_serialize_filter_to_json(
-    filter_ast: &FilterAst<'_>,
+    filter_ast: &OwnedFilterAst,
 ) -> RustAllocatedString {
     let result = serde_json::to_string(filter_ast);
     unwrap_json_result(filter_ast, result).into()
 }
 
+#[no_mangle]
+pub extern "C" fn wirefilter_serialize_scheme_to_json(scheme: &Arc<Scheme>) -> RustAllocatedString {
+    // Scheme serialisation must never fail either.
+    serde_json::to_string(&**scheme)
+        .unwrap_or_else(|err| panic!("{} while serializing scheme", err))
+        .into()
+}
+
 #[no_mangle]
 pub extern "C" fn wirefilter_create_execution_context<'e, 's: 'e>(
-    scheme: &'s Scheme,
+    scheme: &'s Arc<Scheme>,
 ) -> RustBox<ExecutionContext<'e>> {
     ExecutionContext::new(scheme).into()
 }
@@ -200,29 +412,50 @@ pub extern "C" fn wirefilter_add_bool_value_to_execution_context(
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_compile_filter<'s>(
-    filter_ast: RustBox<FilterAst<'s>>,
-) -> RustBox<Filter<'s>> {
+pub extern "C" fn wirefilter_add_json_value_to_execution_context(
+    exec_context: &mut ExecutionContext<'_>,
+    name: ExternallyAllocatedStr<'_>,
+    value_json: ExternallyAllocatedStr<'_>,
+) -> bool {
+    let mut de = serde_json::Deserializer::from_str(value_json.into_ref());
+    exec_context
+        .set_object_value(name.into_ref(), &mut de)
+        .and_then(|()| de.end())
+        .is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_is_field_set(
+    exec_context: &ExecutionContext<'_>,
+    name: ExternallyAllocatedStr<'_>,
+) -> bool {
+    exec_context.is_field_set(name.into_ref()).unwrap()
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_compile_filter(
+    filter_ast: RustBox<OwnedFilterAst>,
+) -> RustBox<OwnedFilter> {
     let filter_ast = filter_ast.into_real_box();
     filter_ast.compile().into()
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_match<'s>(
-    filter: &Filter<'s>,
-    exec_context: &ExecutionContext<'s>,
+pub extern "C" fn wirefilter_match(
+    filter: &OwnedFilter,
+    exec_context: &ExecutionContext<'_>,
 ) -> bool {
     filter.execute(exec_context).unwrap().unwrap_or(false)
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_free_compiled_filter(filter: RustBox<Filter<'_>>) {
+pub extern "C" fn wirefilter_free_compiled_filter(filter: RustBox<OwnedFilter>) {
     drop(filter);
 }
 
 #[no_mangle]
 pub extern "C" fn wirefilter_filter_uses(
-    filter_ast: &FilterAst<'_>,
+    filter_ast: &OwnedFilterAst,
     field_name: ExternallyAllocatedStr<'_>,
 ) -> bool {
     filter_ast.uses(field_name.into_ref()).unwrap()
@@ -238,7 +471,7 @@ mod ffi_test {
     use super::*;
     use regex::Regex;
 
-    fn create_scheme() -> RustBox<Scheme> {
+    fn create_scheme() -> RustBox<Arc<Scheme>> {
         let mut scheme = wirefilter_create_scheme();
 
         wirefilter_add_type_field_to_scheme(
@@ -277,7 +510,9 @@ mod ffi_test {
         scheme
     }
 
-    fn create_execution_context<'e, 's: 'e>(scheme: &'s Scheme) -> RustBox<ExecutionContext<'e>> {
+    fn create_execution_context<'e, 's: 'e>(
+        scheme: &'s Arc<Scheme>,
+    ) -> RustBox<ExecutionContext<'e>> {
         let mut exec_context = wirefilter_create_execution_context(scheme);
 
         wirefilter_add_ipv4_value_to_execution_context(
@@ -319,13 +554,13 @@ mod ffi_test {
         exec_context
     }
 
-    fn parse_filter<'s>(scheme: &'s Scheme, input: &'static str) -> ParsingResult<'s> {
+    fn parse_filter(scheme: &Arc<Scheme>, input: &'static str) -> ParsingResult {
         wirefilter_parse_filter(scheme, ExternallyAllocatedStr::from(input))
     }
 
     fn match_filter(
         input: &'static str,
-        scheme: &Scheme,
+        scheme: &Arc<Scheme>,
         exec_context: &ExecutionContext<'_>,
     ) -> bool {
         let filter = parse_filter(scheme, input).unwrap();
@@ -359,7 +594,7 @@ mod ffi_test {
 
             match result {
                 ParsingResult::Ok(_) => panic!("Error expected"),
-                ParsingResult::Err(err) => {
+                ParsingResult::Err(err, json) => {
                     assert_eq!(
                         &err as &str,
                         indoc!(
@@ -370,7 +605,12 @@ mod ffi_test {
                             "#
                         )
                     );
+                    assert_eq!(
+                        &json as &str,
+                        r#"{"code":"ExpectedNumber","message":"expected digit","span_start":36,"span_len":5,"line":4,"column":13,"expected":["digit"],"suggestions":[]}"#
+                    );
                     wirefilter_free_string(err);
+                    wirefilter_free_string(json);
                 }
             }
         }
@@ -431,6 +671,135 @@ mod ffi_test {
         wirefilter_free_scheme(scheme);
     }
 
+    #[test]
+    fn filter_outlives_scheme() {
+        let scheme = create_scheme();
+        let filter = parse_filter(&scheme, "num1 == 42").unwrap();
+
+        wirefilter_free_scheme(scheme);
+
+        assert!(wirefilter_filter_uses(
+            &filter,
+            ExternallyAllocatedStr::from("num1")
+        ));
+
+        let filter = wirefilter_compile_filter(filter);
+        wirefilter_free_compiled_filter(filter);
+    }
+
+    #[test]
+    fn scheme_shared_with_filter() {
+        let mut scheme = create_scheme();
+
+        assert!(!wirefilter_add_type_field_to_scheme(
+            &mut scheme,
+            ExternallyAllocatedStr::from("num1"),
+            Type::Int,
+        ));
+
+        let filter = parse_filter(&scheme, "num1 == 42").unwrap();
+
+        assert!(!wirefilter_add_type_field_to_scheme(
+            &mut scheme,
+            ExternallyAllocatedStr::from("num3"),
+            Type::Int,
+        ));
+        assert!(!wirefilter_add_hash_functions_to_scheme(&mut scheme));
+
+        wirefilter_free_parsed_filter(filter);
+
+        assert!(wirefilter_add_type_field_to_scheme(
+            &mut scheme,
+            ExternallyAllocatedStr::from("num3"),
+            Type::Int,
+        ));
+        assert!(wirefilter_add_hash_functions_to_scheme(&mut scheme));
+
+        wirefilter_free_scheme(scheme);
+    }
+
+    #[test]
+    fn hash_functions() {
+        let mut scheme = create_scheme();
+        wirefilter_add_hash_functions_to_scheme(&mut scheme);
+
+        {
+            let exec_context = create_execution_context(&scheme);
+
+            assert!(match_filter(
+                "hash_bucket(ip1, 100) == 54 && crc32(str1) == -1331997936",
+                &scheme,
+                &exec_context
+            ));
+
+            assert!(!match_filter(
+                "hash_bucket(num1, 100, 2) == 39",
+                &scheme,
+                &exec_context
+            ));
+
+            wirefilter_free_execution_context(exec_context);
+        }
+
+        wirefilter_free_scheme(scheme);
+    }
+
+    #[test]
+    fn ip_functions() {
+        let mut scheme = create_scheme();
+        wirefilter_add_ip_functions_to_scheme(&mut scheme);
+
+        {
+            let exec_context = create_execution_context(&scheme);
+
+            assert!(match_filter(
+                "is_loopback(ip1) && ip_version(ip2) == 6 && is_private(ipv4_mapped_to_v4(ip2))",
+                &scheme,
+                &exec_context
+            ));
+
+            assert!(match_filter(
+                "ip1/8 == 127.0.0.0 && mask(ip1, 16) == 127.0.0.0",
+                &scheme,
+                &exec_context
+            ));
+
+            wirefilter_free_execution_context(exec_context);
+        }
+
+        wirefilter_free_scheme(scheme);
+    }
+
+    #[test]
+    fn domain_functions() {
+        let mut scheme = create_scheme();
+        assert!(!wirefilter_add_domain_functions_to_scheme(
+            &mut scheme,
+            ExternallyAllocatedStr::from("does/not/exist.dat")
+        ));
+        wirefilter_add_bundled_domain_functions_to_scheme(&mut scheme);
+
+        {
+            let mut exec_context = create_execution_context(&scheme);
+
+            wirefilter_add_bytes_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("str1"),
+                ExternallyAllocatedByteArr::from("www.example.co.uk"),
+            );
+
+            assert!(match_filter(
+                r#"registrable_domain(str1) == "example.co.uk" && label(str1, 0) == "www""#,
+                &scheme,
+                &exec_context
+            ));
+
+            wirefilter_free_execution_context(exec_context);
+        }
+
+        wirefilter_free_scheme(scheme);
+    }
+
     #[test]
     fn filter_hash() {
         let scheme = create_scheme();
@@ -36,7 +36,7 @@ impl<T> From<*mut [T]> for ExternSliceRepr<T> {
         unsafe {
             ExternSliceRepr {
                 data: (*ptr).as_mut_ptr(),
-                length: (*ptr).len(),
+                length: (&*ptr).len(),
             }
         }
     }
@@ -31,14 +31,22 @@ mod ffi_ctest {
     ffi_ctest!(
         create_scheme,
         add_fields_to_scheme,
+        scheme_metadata,
+        scheme_virtual_fields,
+        scheme_macros,
+        scheme_defaults,
+        scheme_nested_fields,
         parse_good_filter,
         parse_bad_filter,
+        filter_diagnostics,
         filter_uses_field,
         filter_hash,
         filter_serialize,
+        scheme_serialize,
         compile_filter,
         create_execution_context,
         add_values_to_execution_context,
         match_filter,
+        match_hash_functions,
     );
 }
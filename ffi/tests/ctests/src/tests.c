@@ -51,6 +51,314 @@ void wirefilter_ffi_ctest_add_fields_to_scheme() {
     wirefilter_free_scheme(scheme);
 }
 
+void wirefilter_ffi_ctest_scheme_metadata() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    rust_assert(
+        wirefilter_set_field_metadata(
+            scheme,
+            wirefilter_string("tcp.port"),
+            wirefilter_string("{\"description\":\"TCP port\",\"operators\":[\"==\"]}")
+        ),
+        "could not set field metadata"
+    );
+
+    rust_assert(
+        !wirefilter_set_field_metadata(
+            scheme,
+            wirefilter_string("tcp.dport"),
+            wirefilter_string("{}")
+        ),
+        "set metadata of an unknown field"
+    );
+
+    rust_assert(
+        !wirefilter_set_function_metadata(
+            scheme,
+            wirefilter_string("tcp.port"),
+            wirefilter_string("{}")
+        ),
+        "set metadata of an unknown function"
+    );
+
+    wirefilter_rust_allocated_str_t json = wirefilter_get_scheme_items_with_prefix(
+        scheme,
+        wirefilter_string("tcp")
+    );
+
+    rust_assert(
+        strncmp(
+            json.data,
+            "[{\"name\":\"tcp.port\",\"kind\":\"Field\",\"type\":\"Int\",\"metadata\":{\"description\":\"TCP port\",\"operators\":[\"==\"]}}]",
+            json.length
+        ) == 0,
+        "invalid scheme items JSON"
+    );
+
+    wirefilter_free_string(json);
+
+    wirefilter_free_scheme(scheme);
+}
+
+void wirefilter_ffi_ctest_scheme_virtual_fields() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    rust_assert(
+        wirefilter_add_virtual_field_to_scheme(
+            scheme,
+            wirefilter_string("tcp.is_web"),
+            wirefilter_string("tcp.port in {80 443}")
+        ),
+        "could not add virtual field"
+    );
+
+    rust_assert(
+        !wirefilter_add_virtual_field_to_scheme(
+            scheme,
+            wirefilter_string("tcp.is_loop"),
+            wirefilter_string("tcp.is_loop")
+        ),
+        "added a self-referencing virtual field"
+    );
+
+    wirefilter_parsing_result_t result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("tcp.is_web && not ssl")
+    );
+    rust_assert(result.success == true, "could not parse good filter");
+
+    wirefilter_filter_t *filter = wirefilter_compile_filter(result.ok.ast);
+    rust_assert(filter != NULL, "could not compile filter");
+
+    wirefilter_execution_context_t *exec_ctx = wirefilter_create_execution_context(scheme);
+    rust_assert(exec_ctx != NULL, "could not create execution context");
+
+    wirefilter_add_bool_value_to_execution_context(
+        exec_ctx,
+        wirefilter_string("ssl"),
+        false
+    );
+
+    wirefilter_add_int_value_to_execution_context(
+        exec_ctx,
+        wirefilter_string("tcp.port"),
+        80
+    );
+
+    rust_assert(wirefilter_match(filter, exec_ctx) == true, "could not match filter");
+
+    wirefilter_free_execution_context(exec_ctx);
+
+    wirefilter_free_compiled_filter(filter);
+
+    wirefilter_free_scheme(scheme);
+}
+
+void wirefilter_ffi_ctest_scheme_macros() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    rust_assert(
+        wirefilter_add_macro_to_scheme(
+            scheme,
+            wirefilter_string("web"),
+            wirefilter_string("tcp.port in {80 443}")
+        ),
+        "could not add macro"
+    );
+
+    rust_assert(
+        !wirefilter_add_macro_to_scheme(
+            scheme,
+            wirefilter_string("bots"),
+            wirefilter_string("$crawlers")
+        ),
+        "added a macro referring to an unknown one"
+    );
+
+    wirefilter_parsing_result_t result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("$web && !ssl")
+    );
+    rust_assert(result.success == true, "could not parse good filter");
+
+    rust_assert(
+        wirefilter_filter_uses(result.ok.ast, wirefilter_string("tcp.port")),
+        "filter should be using field tcp.port through a macro"
+    );
+
+    wirefilter_free_parsing_result(result);
+
+    wirefilter_free_scheme(scheme);
+}
+
+void wirefilter_ffi_ctest_scheme_defaults() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    rust_assert(
+        wirefilter_set_int_field_default(
+            scheme,
+            wirefilter_string("tcp.port"),
+            80
+        ),
+        "could not set default value"
+    );
+
+    rust_assert(
+        !wirefilter_set_bool_field_default(
+            scheme,
+            wirefilter_string("tcp.port"),
+            true
+        ),
+        "set default value of a wrong type"
+    );
+
+    rust_assert(
+        !wirefilter_set_bool_field_default(
+            scheme,
+            wirefilter_string("tcp.unknown"),
+            true
+        ),
+        "set default value of an unknown field"
+    );
+
+    wirefilter_parsing_result_t result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("tcp.port == 80")
+    );
+    rust_assert(result.success == true, "could not parse good filter");
+
+    wirefilter_filter_t *filter = wirefilter_compile_filter(result.ok.ast);
+    rust_assert(filter != NULL, "could not compile filter");
+
+    wirefilter_execution_context_t *exec_ctx = wirefilter_create_execution_context(scheme);
+    rust_assert(exec_ctx != NULL, "could not create execution context");
+
+    rust_assert(
+        wirefilter_match(filter, exec_ctx) == true,
+        "default value should have been used"
+    );
+
+    rust_assert(
+        !wirefilter_is_field_set(exec_ctx, wirefilter_string("tcp.port")),
+        "defaulted field should not be reported as set"
+    );
+
+    wirefilter_add_int_value_to_execution_context(
+        exec_ctx,
+        wirefilter_string("tcp.port"),
+        443
+    );
+
+    rust_assert(
+        wirefilter_match(filter, exec_ctx) == false,
+        "default value should have been overwritten"
+    );
+
+    rust_assert(
+        wirefilter_is_field_set(exec_ctx, wirefilter_string("tcp.port")),
+        "field should be reported as set"
+    );
+
+    wirefilter_free_execution_context(exec_ctx);
+
+    wirefilter_free_compiled_filter(filter);
+
+    wirefilter_free_scheme(scheme);
+}
+
+void wirefilter_ffi_ctest_scheme_nested_fields() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    rust_assert(
+        wirefilter_add_nested_field_to_scheme(
+            scheme,
+            wirefilter_string("http.request"),
+            wirefilter_string("{\"method\": \"Bytes\", \"uri\": {\"path\": \"Bytes\"}}")
+        ),
+        "could not add nested field"
+    );
+
+    rust_assert(
+        !wirefilter_add_nested_field_to_scheme(
+            scheme,
+            wirefilter_string("tcp"),
+            wirefilter_string("{\"port\": \"Int\"}")
+        ),
+        "redefined a field with a nested one"
+    );
+
+    wirefilter_parsing_result_t result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("http.request")
+    );
+    rust_assert(result.success == false, "parsed an object as a field");
+    wirefilter_free_parsing_result(result);
+
+    result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("http.request.uri.path == \"/\" && !ssl")
+    );
+    rust_assert(result.success == true, "could not parse good filter");
+
+    rust_assert(
+        wirefilter_filter_uses(result.ok.ast, wirefilter_string("http.request")),
+        "filter should be using object http.request"
+    );
+
+    wirefilter_filter_t *filter = wirefilter_compile_filter(result.ok.ast);
+    rust_assert(filter != NULL, "could not compile filter");
+
+    wirefilter_execution_context_t *exec_ctx = wirefilter_create_execution_context(scheme);
+    rust_assert(exec_ctx != NULL, "could not create execution context");
+
+    rust_assert(
+        wirefilter_add_json_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("http.request"),
+            wirefilter_string("{\"method\": \"GET\", \"uri\": {\"path\": \"/\"}}")
+        ),
+        "could not set nested value"
+    );
+
+    rust_assert(
+        !wirefilter_add_json_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("http.request"),
+            wirefilter_string("{\"uri\": {\"path\": 1}}")
+        ),
+        "set nested value of a wrong type"
+    );
+
+    wirefilter_add_bool_value_to_execution_context(
+        exec_ctx,
+        wirefilter_string("ssl"),
+        false
+    );
+
+    rust_assert(wirefilter_match(filter, exec_ctx) == true, "could not match filter");
+
+    wirefilter_free_execution_context(exec_ctx);
+
+    wirefilter_free_compiled_filter(filter);
+
+    wirefilter_free_scheme(scheme);
+}
+
 void wirefilter_ffi_ctest_parse_good_filter() {
     wirefilter_scheme_t *scheme = wirefilter_create_scheme();
     rust_assert(scheme != NULL, "could not create scheme");
@@ -81,12 +389,62 @@ void wirefilter_ffi_ctest_parse_bad_filter() {
     );
     rust_assert(result.success == false, "should not parse bad filter");
     rust_assert(result.err.msg.data && result.err.msg.length > 0, "missing error message");
+    rust_assert(
+        strncmp(
+            result.err.json.data,
+            "{\"code\":\"ExpectedNumber\",\"message\":\"expected digit\",\"span_start\":12,"
+            "\"span_len\":12,\"line\":1,\"column\":13,\"expected\":[\"digit\"],\"suggestions\":[]}",
+            result.err.json.length
+        ) == 0,
+        "invalid error JSON"
+    );
 
     wirefilter_free_parsing_result(result);
 
     wirefilter_free_scheme(scheme);
 }
 
+void wirefilter_ffi_ctest_filter_diagnostics() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    wirefilter_rust_allocated_str_t json = wirefilter_get_filter_diagnostics(
+        scheme,
+        wirefilter_string("tcp.port == \"wirefilter\" or tcp.prot == 80")
+    );
+
+    rust_assert(
+        strncmp(
+            json.data,
+            "[{\"code\":\"ExpectedNumber\",\"message\":\"expected digit\",\"span_start\":12,\"span_len\":30,"
+            "\"line\":1,\"column\":13,\"expected\":[\"digit\"],\"suggestions\":[]},"
+            "{\"code\":\"UnknownField\",\"message\":\"unknown field, did you mean tcp.port?\","
+            "\"span_start\":28,\"span_len\":8,\"line\":1,\"column\":29,\"expected\":[],"
+            "\"suggestions\":[\"tcp.port\"]}]",
+            json.length
+        ) == 0,
+        "invalid filter diagnostics JSON"
+    );
+
+    wirefilter_free_string(json);
+
+    json = wirefilter_get_filter_diagnostics(
+        scheme,
+        wirefilter_string("tcp.port == 80")
+    );
+
+    rust_assert(
+        strncmp(json.data, "[]", json.length) == 0,
+        "good filter should not have diagnostics"
+    );
+
+    wirefilter_free_string(json);
+
+    wirefilter_free_scheme(scheme);
+}
+
 void wirefilter_ffi_ctest_filter_uses_field() {
     wirefilter_scheme_t *scheme = wirefilter_create_scheme();
     rust_assert(scheme != NULL, "could not create scheme");
@@ -179,6 +537,30 @@ void wirefilter_ffi_ctest_filter_serialize() {
     wirefilter_free_scheme(scheme);
 }
 
+void wirefilter_ffi_ctest_scheme_serialize() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    wirefilter_rust_allocated_str_t json = wirefilter_serialize_scheme_to_json(scheme);
+
+    rust_assert(json.data != NULL && json.length > 0, "could not serialize scheme to JSON");
+
+    rust_assert(
+        strncmp(
+            json.data,
+            "{\"fields\":{\"http.host\":\"Bytes\",\"ip.addr\":\"Ip\",\"ssl\":\"Bool\",\"tcp.port\":\"Int\"},\"functions\":{}}",
+            json.length
+        ) == 0,
+        "invalid JSON serialization"
+    );
+
+    wirefilter_free_string(json);
+
+    wirefilter_free_scheme(scheme);
+}
+
 void wirefilter_ffi_ctest_compile_filter() {
     wirefilter_scheme_t *scheme = wirefilter_create_scheme();
     rust_assert(scheme != NULL, "could not create scheme");
@@ -309,3 +691,41 @@ void wirefilter_ffi_ctest_match_filter() {
 
     wirefilter_free_scheme(scheme);
 }
+
+void wirefilter_ffi_ctest_match_hash_functions() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+    rust_assert(
+        wirefilter_add_hash_functions_to_scheme(scheme),
+        "could not add hash functions"
+    );
+
+    wirefilter_parsing_result_t result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("hash_bucket(tcp.port, 100, 2) == 5")
+    );
+    rust_assert(result.success == true, "could not parse good filter");
+    rust_assert(result.ok.ast != NULL, "could not parse good filter");
+
+    wirefilter_filter_t *filter = wirefilter_compile_filter(result.ok.ast);
+    rust_assert(filter != NULL, "could not compile filter");
+
+    wirefilter_execution_context_t *exec_ctx = wirefilter_create_execution_context(scheme);
+    rust_assert(exec_ctx != NULL, "could not create execution context");
+
+    wirefilter_add_int_value_to_execution_context(
+        exec_ctx,
+        wirefilter_string("tcp.port"),
+        42
+    );
+
+    rust_assert(wirefilter_match(filter, exec_ctx) == true, "could not match filter");
+
+    wirefilter_free_execution_context(exec_ctx);
+
+    wirefilter_free_compiled_filter(filter);
+
+    wirefilter_free_scheme(scheme);
+}
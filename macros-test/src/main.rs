@@ -39,4 +39,94 @@ mod tests {
         };
         e.filter_context(&scheme).unwrap();
     }
+
+    #[derive(Debug, Filterable, HasFields)]
+    struct Test2 {
+        #[field(default=80)]
+        port: Option<usize>,
+        #[field(name="host")]
+        #[field(default="example.org")]
+        host: Option<String>,
+        #[field(default="10.0.0.1")]
+        ip: Option<IpAddr>,
+        path: Option<String>,
+    }
+
+    #[test]
+    fn handle_default_values() {
+        let mut scheme = Scheme::try_from_iter(Test2::fields()).unwrap();
+        for (name, value) in Test2::defaults() {
+            scheme.set_field_default(&name, value).unwrap();
+        }
+        assert_eq!(scheme.get_field_default("ip"), Ok(Some(&LhsValue::Ip(IpAddr::from([10,0,0,1])))));
+        assert_eq!(scheme.get_field_default("path"), Ok(None));
+
+        let filter = scheme.parse(r#"port == 80 && host == "example.com""#).unwrap().compile();
+        let e = Test2 {
+            port: None,
+            host: Some("example.com".to_string()),
+            ip: None,
+            path: None,
+        };
+        let ctx = e.filter_context(&scheme).unwrap();
+        assert_eq!(ctx.is_field_set("port"), Ok(false));
+        assert_eq!(ctx.is_field_set("host"), Ok(true));
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+    }
+
+    #[derive(Debug, Filterable, HasFields)]
+    struct Uri {
+        path: String,
+        #[field(default="")]
+        query: Option<String>,
+    }
+
+    #[derive(Debug, Filterable, HasFields)]
+    struct Request {
+        method: String,
+        #[field(nested="true")]
+        uri: Uri,
+    }
+
+    #[derive(Debug, Filterable, HasFields)]
+    #[field(name="http")]
+    struct Http {
+        #[field(nested="true")]
+        request: Request,
+        #[field(nested="true")]
+        #[field(name="origin")]
+        origin_request: Option<Request>,
+    }
+
+    #[test]
+    fn handle_nested_fields() {
+        assert_eq!(Http::fields(), vec![
+            ("http.request.method".to_string(), Type::Bytes),
+            ("http.request.uri.path".to_string(), Type::Bytes),
+            ("http.request.uri.query".to_string(), Type::Bytes),
+            ("http.origin.method".to_string(), Type::Bytes),
+            ("http.origin.uri.path".to_string(), Type::Bytes),
+            ("http.origin.uri.query".to_string(), Type::Bytes),
+        ]);
+
+        let mut scheme = Scheme::default();
+        scheme.add_nested_field("http".to_string(), Http::field_type()).unwrap();
+        for (name, value) in Http::defaults() {
+            scheme.set_field_default(&name, value).unwrap();
+        }
+        assert_eq!(scheme.get_field_default("http.origin.uri.query"), Ok(Some(&LhsValue::from(""))));
+
+        let filter = scheme.parse(r#"http.request.uri.path == "/" && http.request.uri.query == """#).unwrap().compile();
+        let e = Http {
+            request: Request {
+                method: "GET".to_string(),
+                uri: Uri { path: "/".to_string(), query: None },
+            },
+            origin_request: None,
+        };
+        let ctx = e.filter_context(&scheme).unwrap();
+        assert_eq!(ctx.is_field_set("http.request.method"), Ok(true));
+        assert_eq!(ctx.is_field_set("http.origin.method"), Ok(false));
+        assert_eq!(filter.execute(&ctx), Ok(Some(true)));
+    }
 }
@@ -1,7 +1,7 @@
 use proc_macro2::{TokenStream, Span};
 use quote::{quote, quote_spanned};
 use syn::spanned::Spanned;
-use syn::{Lit,Meta, parse_macro_input, parse_quote, Data, DeriveInput, Fields, GenericParam, Generics, Index, Type, Path, PathArguments, GenericArgument, Attribute};
+use syn::{Lit,Meta, parse_macro_input, parse_quote, Data, DeriveInput, Fields, FieldsNamed, GenericParam, Generics, Index, Type, Path, PathArguments, GenericArgument, Attribute};
 use wirefilter::derive::Filterable;
 use wirefilter::derive::GetType;
 use wirefilter::{Scheme, ExecutionContext};
@@ -23,9 +23,13 @@ pub fn derive_filterable(input: proc_macro::TokenStream) -> proc_macro::TokenStr
 // non recursive for now
 fn make_filterable(input: &DeriveInput) -> TokenStream {
     let name = &input.ident;
-    let data = &input.data;
+    let fields = match named_fields(input) {
+        Ok(fields) => fields,
+        Err(err) => return err.to_compile_error(),
+    };
     let outer_name = renamed_field(&input.attrs);
-    let members = iter_members_filterable(data, outer_name);
+    let members = iter_members_filterable(fields, outer_name);
+    let nested_members = iter_members_gen_context(fields);
 
 
     quote! {
@@ -36,48 +40,88 @@ fn make_filterable(input: &DeriveInput) -> TokenStream {
                 Ok(ctx)
             }
         }
+
+        // Allows the struct to be a `#[field(nested = "true")]` member of another one.
+        impl wirefilter::derive::GenContext for #name {
+            fn generate_context<'s>(&self, ctx: &mut ExecutionContext<'s>, field_name: &str) -> Result<(), wirefilter::errors::Error> {
+                #nested_members
+                Ok(())
+            }
+        }
     }
 }
 
+// Only structs with named fields can be mapped to scheme fields.
+fn named_fields(input: &DeriveInput) -> Result<&FieldsNamed, syn::Error> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(fields),
+            Fields::Unnamed(fields) => Err(syn::Error::new_spanned(
+                fields,
+                "tuple structs are not supported, use named fields",
+            )),
+            Fields::Unit => Err(syn::Error::new_spanned(
+                &input.ident,
+                "unit structs are not supported, use named fields",
+            )),
+        },
+        Data::Enum(data) => Err(syn::Error::new_spanned(
+            data.enum_token,
+            "enums are not supported, use a struct with named fields",
+        )),
+        Data::Union(data) => Err(syn::Error::new_spanned(
+            data.union_token,
+            "unions are not supported, use a struct with named fields",
+        )),
+    }
+}
 
-fn iter_members_filterable(data: &Data, outer_name: Option<String>) -> TokenStream {
+fn iter_members_gen_context(fields: &FieldsNamed) -> TokenStream {
+    let recurse = fields.named.iter().map(|f| {
+        if !ignore(&f.attrs) {
+            let name = &f.ident;
+            let member_name = renamed_field(&f.attrs).unwrap_or_else(|| name.as_ref().unwrap().to_string());
+            quote_spanned! {f.span() =>
+                self.#name.generate_context(ctx, &format!("{}.{}", field_name, #member_name))?;
+            }
+        } else {
+            quote!{}
+        }
+    });
+    quote! {
+        #(#recurse)*
+    }
+}
+
+
+fn iter_members_filterable(fields: &FieldsNamed, outer_name: Option<String>) -> TokenStream {
     //println!("Outer name {:?}", outer_name);
-    match *data {
-        Data::Struct(ref data) => {
-            match data.fields {
-                Fields::Named(ref fields) => {
-                    let recurse = fields.named.iter().map(|f| {
-                        if !ignore(&f.attrs) {
-                            let name = &f.ident;
-                            let defined_name = renamed_field(&f.attrs).unwrap_or_else(|| {
-                                f.ident.clone().unwrap().to_string()
-                            });
-                            let defined_name: String = vec![outer_name.clone(), Some(defined_name)]
-                                .into_iter()
-                                .flatten()
-                                .collect::<Vec<String>>()
-                                .join(".");
-                            //println!("Defined name {:?}", defined_name);
-                            let ty = &f.ty;
-                            let check = quote_spanned! {f.span() =>
-                                &self.#name.generate_context(&mut ctx, #defined_name);
-                                //println!("Type is {}", stringify!(#ty));
-                            };
-                            quote_spanned! {f.span() =>
-                                #check
-                            }
-                        } else {
-                            quote!{}
-                        }
-                    });
-                    quote! {
-                        #(#recurse)*
-                    }
-                }
-                Fields::Unit | Fields::Unnamed(_) => unimplemented!(),
+    let recurse = fields.named.iter().map(|f| {
+        if !ignore(&f.attrs) {
+            let name = &f.ident;
+            let defined_name = renamed_field(&f.attrs).unwrap_or_else(|| {
+                f.ident.clone().unwrap().to_string()
+            });
+            let defined_name: String = vec![outer_name.clone(), Some(defined_name)]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<String>>()
+                .join(".");
+            //println!("Defined name {:?}", defined_name);
+            let ty = &f.ty;
+            let check = quote_spanned! {f.span() =>
+                &self.#name.generate_context(&mut ctx, #defined_name);
+                //println!("Type is {}", stringify!(#ty));
+            };
+            quote_spanned! {f.span() =>
+                #check
             }
+        } else {
+            quote!{}
         }
-        Data::Enum(_) | Data::Union(_) => unimplemented!(),
+    });
+    quote! {
+        #(#recurse)*
     }
 }
 
@@ -91,9 +135,14 @@ pub fn derive_has_fields(input: proc_macro::TokenStream) -> proc_macro::TokenStr
 // non recursive for now
 fn make_has_fields(input: &DeriveInput) -> TokenStream {
     let name = &input.ident;
-    let data = &input.data;
+    let fields = match named_fields(input) {
+        Ok(fields) => fields,
+        Err(err) => return err.to_compile_error(),
+    };
     let outer_name = renamed_field(&input.attrs);
-    let members = iter_members_has_fields(data, outer_name);
+    let members = iter_members_has_fields(fields, outer_name.clone());
+    let defaults = iter_members_defaults(fields, outer_name);
+    let field_types = iter_members_field_type(fields);
 
     quote! {
         impl HasFields for #name {
@@ -104,7 +153,100 @@ fn make_has_fields(input: &DeriveInput) -> TokenStream {
 
                 new_fields
             }
+
+            fn defaults() -> Vec<(String, LhsValue<'static>)> {
+                let mut new_defaults: Vec<(String, LhsValue<'static>)> = Vec::new();
+                #defaults
+
+                new_defaults
+            }
+
+            fn field_type() -> wirefilter::FieldType {
+                let mut new_members: Vec<(String, wirefilter::FieldType)> = Vec::new();
+                #field_types
+
+                new_members.into_iter().collect()
+            }
+        }
+    }
+}
+
+fn iter_members_field_type(fields: &FieldsNamed) -> TokenStream {
+    let recurse = fields.named.iter().map(|f| {
+        let name = &f.ident;
+        let ty = &f.ty;
+        if ignore(&f.attrs) {
+            quote!{}
+        } else {
+            let member_name = renamed_field(&f.attrs).unwrap_or_else(|| name.as_ref().unwrap().to_string());
+            if nested(&f.attrs) {
+                let inner = option_inner(ty).unwrap_or(ty);
+                quote_spanned! {f.span() =>
+                    new_members.push((String::from(#member_name), <#inner as HasFields>::field_type()));
+                }
+            } else {
+                quote_spanned! {f.span() =>
+                    new_members.push((String::from(#member_name), wirefilter::FieldType::Value(<#ty as wirefilter::derive::GetType>::ty())));
+                }
+            }
+        }
+    });
+    quote! {
+        #(#recurse)*
+    }
+}
+
+// Returns `T` if the type is `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Path(typepath) if typepath.qself.is_none() && path_is_option(&typepath.path) => {
+            match &typepath.path.segments.iter().next().unwrap().arguments {
+                PathArguments::AngleBracketed(params) => match params.args.iter().next() {
+                    Some(GenericArgument::Type(ty)) => Some(ty),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn iter_members_defaults(fields: &FieldsNamed, outer_name: Option<String>) -> TokenStream {
+    let recurse = fields.named.iter().map(|f| {
+        let name = &f.ident;
+        let ty = &f.ty;
+        if nested(&f.attrs) && !ignore(&f.attrs) {
+            let defined_name = renamed_field(&f.attrs).unwrap_or_else(|| name.as_ref().unwrap().to_string());
+
+            let defined_name: String = vec![outer_name.clone(), Some(defined_name)]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<String>>()
+                .join(".");
+            let inner = option_inner(ty).unwrap_or(ty);
+            return quote_spanned! {f.span() =>
+                new_defaults.extend(<#inner as HasFields>::defaults().into_iter().map(|(name, value)| (format!("{}.{}", #defined_name, name), value)));
+            };
+        }
+        match default_lit(&f.attrs) {
+            Some(lit) if !ignore(&f.attrs) => {
+                let defined_name = renamed_field(&f.attrs).unwrap_or_else(|| name.as_ref().unwrap().to_string());
+
+                let defined_name: String = vec![outer_name.clone(), Some(defined_name)]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<String>>()
+                    .join(".");
+                quote_spanned! {f.span() =>
+                    new_defaults.push((String::from(#defined_name), wirefilter::derive::default_value(#lit, <#ty as wirefilter::derive::GetType>::ty())));
+                }
+            }
+            _ => quote!{},
         }
+    });
+    quote! {
+        #(#recurse)*
     }
 }
 //stolen from: https://stackoverflow.com/questions/55271857/how-can-i-get-the-t-from-an-optiont-when-using-syn
@@ -120,75 +262,71 @@ fn path_is_vec(path: &Path) -> bool { //todo this is bahhhd said a sheep
         && path.segments.iter().next().unwrap().ident == "Vec"
 }
 
-fn iter_members_has_fields(data: &Data, outer_name: Option<String>) -> TokenStream {
-    match *data {
-        Data::Struct(ref data) => {
-            match data.fields {
-                Fields::Named(ref fields) => {
-                    let recurse = fields.named.iter().map(|f| {
-                        let name = &f.ident;
-                        let ty = &f.ty;
-                        let r = if !ignore(&f.attrs) {
-                            let defined_name = renamed_field(&f.attrs).unwrap_or_else(|| name.as_ref().unwrap().to_string());
-
-                            let defined_name: String = vec![outer_name.clone(), Some(defined_name)]
-                                .into_iter()
-                                .flatten()
-                                .collect::<Vec<String>>()
-                                .join(".");
-                            println!("Defined name type {:?}", defined_name);
-                            match ty {
-                                Type::Path(typepath) if typepath.qself.is_none() && path_is_option(&typepath.path) => {
-                                    let type_params = &(typepath.path.segments.iter().next()).unwrap().arguments;
-                                    let generic_arg = match type_params {
-                                        PathArguments::AngleBracketed(params) => params.args.iter().next().unwrap(),
-                                        _ => panic!("Missing Bracket"),
-                                    };
-                                    // This argument must be a type:
-                                    let gen = match generic_arg {
-                                        GenericArgument::Type(ty) => ty,
-                                        _ => panic!("Missing Generic"),
-                                    };
-                                    quote_spanned! {f.span() =>
-                                        new_fields.push((String::from(#defined_name), Option::<#gen>::ty()));
-                                    }
-                                },
-                                Type::Path(typepath) if typepath.qself.is_none() && path_is_vec(&typepath.path) => {
-                                    let type_params = &(typepath.path.segments.iter().next()).unwrap().arguments;
-                                    let generic_arg = match type_params {
-                                        PathArguments::AngleBracketed(params) => params.args.iter().next().unwrap(),
-                                        _ => panic!("Missing Bracket"),
-                                    };
-                                    // This argument must be a type:
-                                    let gen = match generic_arg {
-                                        GenericArgument::Type(ty) => ty,
-                                        _ => panic!("Missing Generic"),
-                                    };
-                                    quote_spanned! {f.span() =>
-                                        new_fields.push((String::from(#defined_name), Vec::<#gen>::ty()));
-                                    }
-                                }
-                                _ => {
-                                    quote_spanned! {f.span() =>
-                                        new_fields.push((String::from(#defined_name), #ty::ty()));
-                                        //new_fields.push((String::from(stringify!(#name)), GetType::ty<#ty>()));
-                                    }
-                                }
-                            }
-                        } else {
-                            quote!{}
-                        };
-                        r
-
-                    });
-                    quote! {
-                        #(#recurse)*
+fn iter_members_has_fields(fields: &FieldsNamed, outer_name: Option<String>) -> TokenStream {
+    let recurse = fields.named.iter().map(|f| {
+        let name = &f.ident;
+        let ty = &f.ty;
+        let r = if !ignore(&f.attrs) {
+            let defined_name = renamed_field(&f.attrs).unwrap_or_else(|| name.as_ref().unwrap().to_string());
+
+            let defined_name: String = vec![outer_name.clone(), Some(defined_name)]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<String>>()
+                .join(".");
+            println!("Defined name type {:?}", defined_name);
+            if nested(&f.attrs) {
+                let inner = option_inner(ty).unwrap_or(ty);
+                return quote_spanned! {f.span() =>
+                    new_fields.extend(wirefilter::derive::nested_fields(#defined_name, <#inner as HasFields>::field_type()));
+                };
+            }
+            match ty {
+                Type::Path(typepath) if typepath.qself.is_none() && path_is_option(&typepath.path) => {
+                    let type_params = &(typepath.path.segments.iter().next()).unwrap().arguments;
+                    let generic_arg = match type_params {
+                        PathArguments::AngleBracketed(params) => params.args.iter().next().unwrap(),
+                        _ => panic!("Missing Bracket"),
+                    };
+                    // This argument must be a type:
+                    let gen = match generic_arg {
+                        GenericArgument::Type(ty) => ty,
+                        _ => panic!("Missing Generic"),
+                    };
+                    quote_spanned! {f.span() =>
+                        new_fields.push((String::from(#defined_name), Option::<#gen>::ty()));
+                    }
+                },
+                Type::Path(typepath) if typepath.qself.is_none() && path_is_vec(&typepath.path) => {
+                    let type_params = &(typepath.path.segments.iter().next()).unwrap().arguments;
+                    let generic_arg = match type_params {
+                        PathArguments::AngleBracketed(params) => params.args.iter().next().unwrap(),
+                        _ => panic!("Missing Bracket"),
+                    };
+                    // This argument must be a type:
+                    let gen = match generic_arg {
+                        GenericArgument::Type(ty) => ty,
+                        _ => panic!("Missing Generic"),
+                    };
+                    quote_spanned! {f.span() =>
+                        new_fields.push((String::from(#defined_name), Vec::<#gen>::ty()));
+                    }
+                }
+                _ => {
+                    quote_spanned! {f.span() =>
+                        new_fields.push((String::from(#defined_name), #ty::ty()));
+                        //new_fields.push((String::from(stringify!(#name)), GetType::ty<#ty>()));
                     }
                 }
-                Fields::Unit | Fields::Unnamed(_) => unimplemented!(),
             }
-        }
-        Data::Enum(_) | Data::Union(_) => unimplemented!(),
+        } else {
+            quote!{}
+        };
+        r
+
+    });
+    quote! {
+        #(#recurse)*
     }
 }
 
@@ -210,6 +348,32 @@ fn renamed_field(attrs: &Vec<Attribute>) -> Option<String> {
     None
 }
 
+fn nested(attrs: &[Attribute]) -> bool {
+    for attr in attrs.iter() {
+        let parsed_attr: Meta = attr.parse_args().unwrap();
+        if let Meta::NameValue(pairs) = parsed_attr {
+            let key = pairs.path.segments.first().unwrap();
+            if key.ident == "nested" {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn default_lit(attrs: &[Attribute]) -> Option<Lit> {
+    for attr in attrs.iter() {
+        let parsed_attr: Meta = attr.parse_args().unwrap();
+        if let Meta::NameValue(pairs) = parsed_attr {
+            let key = pairs.path.segments.first().unwrap();
+            if key.ident == "default" {
+                return Some(pairs.lit);
+            }
+        }
+    }
+    None
+}
+
 fn ignore(attrs: &Vec<Attribute>) -> bool {
     for attr in attrs.iter() {
         let parsed_attr: Meta = attr.parse_args().unwrap();
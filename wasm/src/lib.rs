@@ -4,19 +4,103 @@ use wasm_bindgen::prelude::*;
 pub struct Scheme(wirefilter::Scheme);
 
 #[allow(clippy::needless_pass_by_value)]
-fn into_js_error(err: impl std::error::Error) -> JsValue {
+fn into_js_error(err: impl std::fmt::Display) -> JsValue {
     js_sys::Error::new(&err.to_string()).into()
 }
 
+// Also attaches the code, span etc. of the error as its `details` property.
+#[allow(clippy::needless_pass_by_value)]
+fn into_js_parse_error(err: wirefilter::ParseError<'_>) -> JsValue {
+    let js_err = js_sys::Error::new(&err.to_string());
+    let details = match JsValue::from_serde(&err) {
+        Ok(details) => details,
+        Err(err) => return into_js_error(err),
+    };
+    match js_sys::Reflect::set(&js_err, &"details".into(), &details) {
+        Ok(_) => js_err.into(),
+        Err(err) => err,
+    }
+}
+
 #[wasm_bindgen]
 impl Scheme {
     #[wasm_bindgen(constructor)]
-    pub fn try_from(fields: &JsValue) -> Result<Scheme, JsValue> {
-        fields.into_serde().map(Scheme).map_err(into_js_error)
+    pub fn try_from(definition: &JsValue) -> Result<Scheme, JsValue> {
+        let mut registry = wirefilter::FunctionRegistry::new();
+        registry
+            .add_functions(wirefilter::builtins::hash_functions())
+            .map_err(into_js_error)?;
+        registry
+            .add_functions(wirefilter::builtins::ip_functions())
+            .map_err(into_js_error)?;
+        let json = definition
+            .into_serde::<serde_json::Value>()
+            .map_err(into_js_error)?;
+        registry
+            .deserialize_scheme(json)
+            .map(Scheme)
+            .map_err(into_js_error)
+    }
+
+    #[wasm_bindgen(js_name = toJSON)]
+    pub fn to_json(&self) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.0).map_err(into_js_error)
+    }
+
+    #[wasm_bindgen(js_name = addHashFunctions)]
+    pub fn add_hash_functions(&mut self) -> Result<(), JsValue> {
+        self.0
+            .add_functions(wirefilter::builtins::hash_functions())
+            .map_err(into_js_error)
+    }
+
+    #[wasm_bindgen(js_name = addIpFunctions)]
+    pub fn add_ip_functions(&mut self) -> Result<(), JsValue> {
+        self.0
+            .add_functions(wirefilter::builtins::ip_functions())
+            .map_err(into_js_error)
+    }
+
+    #[wasm_bindgen(js_name = addDomainFunctions)]
+    pub fn add_domain_functions(&mut self, public_suffix_list: &str) -> Result<(), JsValue> {
+        let list = wirefilter::builtins::PublicSuffixList::parse(public_suffix_list)
+            .map_err(into_js_error)?;
+        self.0
+            .add_functions(wirefilter::builtins::domain_functions(list))
+            .map_err(into_js_error)
+    }
+
+    #[wasm_bindgen(js_name = setFieldMetadata)]
+    pub fn set_field_metadata(&mut self, name: &str, metadata: &JsValue) -> Result<(), JsValue> {
+        let metadata = metadata.into_serde().map_err(into_js_error)?;
+        self.0
+            .set_field_metadata(name, metadata)
+            .map_err(into_js_error)
+    }
+
+    #[wasm_bindgen(js_name = setFunctionMetadata)]
+    pub fn set_function_metadata(&mut self, name: &str, metadata: &JsValue) -> Result<(), JsValue> {
+        let metadata = metadata.into_serde().map_err(into_js_error)?;
+        self.0
+            .set_function_metadata(name, metadata)
+            .map_err(into_js_error)
+    }
+
+    #[wasm_bindgen(js_name = itemsWithPrefix)]
+    pub fn items_with_prefix(&self, prefix: &str) -> Result<JsValue, JsValue> {
+        JsValue::from_serde(&self.0.items_with_prefix(prefix)).map_err(into_js_error)
     }
 
     pub fn parse(&self, s: &str) -> Result<JsValue, JsValue> {
-        let filter = self.0.parse(s).map_err(into_js_error)?;
+        let filter = self.0.parse(s).map_err(into_js_parse_error)?;
         JsValue::from_serde(&filter).map_err(into_js_error)
     }
+
+    pub fn diagnostics(&self, s: &str) -> Result<JsValue, JsValue> {
+        let diagnostics = match self.0.parse_with_diagnostics(s) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics,
+        };
+        JsValue::from_serde(&diagnostics).map_err(into_js_error)
+    }
 }
@@ -1,6 +1,7 @@
 use cidr::{Ipv4Cidr, Ipv6Cidr};
 use regex::bytes::RegexBuilder;
 use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter, Write};
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::ops::{Deref, RangeInclusive};
 use std::str::FromStr;
@@ -11,11 +12,31 @@ pub struct Var<'i>(pub Cow<'i, str>);
 #[derive(Debug)]
 pub struct Regex(regex::bytes::Regex);
 
+#[derive(Debug, PartialEq)]
+pub enum Lhs<'i> {
+    Var(Var<'i>),
+    FunctionCall {
+        name: Cow<'i, str>,
+        args: Vec<FunctionArg<'i>>,
+    },
+    IpMask {
+        lhs: Box<Lhs<'i>>,
+        prefix_len: u8,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FunctionArg<'i> {
+    Lhs(Lhs<'i>),
+    Literal(Rhs<'i>),
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Rhs<'i> {
     Int(i32),
     IntRange(RangeInclusive<i32>),
     String(Cow<'i, [u8]>),
+    Bytes(Vec<u8>),
     Ipv4(Ipv4Addr),
     Ipv6(Ipv6Addr),
     Ipv4Range(RangeInclusive<Ipv4Addr>),
@@ -23,6 +44,7 @@ pub enum Rhs<'i> {
     Ipv4Cidr(Ipv4Cidr),
     Ipv6Cidr(Ipv6Cidr),
     Regex(Regex),
+    Set(Vec<Rhs<'i>>),
 }
 
 #[derive(Debug, PartialEq)]
@@ -39,14 +61,28 @@ pub enum BinOp {
     In,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum LogicalOp {
+    Or,
+    Xor,
+    And,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Expr<'i> {
-    Unary(Var<'i>),
+    Unary(Lhs<'i>),
     Binary {
-        lhs: Var<'i>,
+        lhs: Lhs<'i>,
         op: BinOp,
         rhs: Rhs<'i>,
     },
+    Not(Box<Expr<'i>>),
+    Macro(Cow<'i, str>),
+    Parenthesized(Box<Expr<'i>>),
+    Combining {
+        op: LogicalOp,
+        items: Vec<Expr<'i>>,
+    },
 }
 
 impl PartialEq for Regex {
@@ -72,3 +108,177 @@ impl FromStr for Regex {
         RegexBuilder::new(s).unicode(false).build().map(Regex)
     }
 }
+
+// NOTE: the formatting below produces the canonical source of the tree, which
+// is what the engine's lexer gets when lowering a checked tree.
+impl Display for Var<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Display for Lhs<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Lhs::Var(var) => var.fmt(f),
+            Lhs::FunctionCall { name, args } => {
+                write!(f, "{}(", name)?;
+
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+
+                    match arg {
+                        FunctionArg::Lhs(lhs) => lhs.fmt(f)?,
+                        FunctionArg::Literal(rhs) => rhs.fmt(f)?,
+                    }
+                }
+
+                f.write_str(")")
+            }
+            Lhs::IpMask { lhs, prefix_len } => write!(f, "{}/{}", lhs, prefix_len),
+        }
+    }
+}
+
+fn fmt_bytes(bytes: &[u8], f: &mut Formatter<'_>) -> fmt::Result {
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            f.write_char(':')?;
+        }
+
+        write!(f, "{:02x}", b)?;
+    }
+
+    Ok(())
+}
+
+impl Display for Regex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut in_char_class = false;
+        let mut chars = self.as_str().chars();
+
+        f.write_char('"')?;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    f.write_char(c)?;
+
+                    if let Some(c) = chars.next() {
+                        f.write_char(c)?;
+                    }
+                }
+                '"' if !in_char_class => f.write_str("\\\"")?,
+                '[' if !in_char_class => {
+                    in_char_class = true;
+                    f.write_char(c)?;
+                }
+                ']' if in_char_class => {
+                    in_char_class = false;
+                    f.write_char(c)?;
+                }
+                c => f.write_char(c)?,
+            }
+        }
+
+        f.write_char('"')
+    }
+}
+
+impl Display for Rhs<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Rhs::Int(i) => i.fmt(f),
+            Rhs::IntRange(r) => write!(f, "{}..{}", r.start(), r.end()),
+            // NOTE: strings can only hold UTF-8 text, so anything else is
+            // written as hex bytes.
+            Rhs::String(s) => match std::str::from_utf8(s) {
+                Ok(s) => {
+                    f.write_char('"')?;
+
+                    for c in s.chars() {
+                        match c {
+                            '"' | '\\' => write!(f, "\\{}", c)?,
+                            c if c.is_ascii_control() => write!(f, "\\x{:02x}", c as u8)?,
+                            c => f.write_char(c)?,
+                        }
+                    }
+
+                    f.write_char('"')
+                }
+                Err(_) => fmt_bytes(s, f),
+            },
+            Rhs::Bytes(b) => fmt_bytes(b, f),
+            Rhs::Ipv4(ip) => ip.fmt(f),
+            Rhs::Ipv6(ip) => ip.fmt(f),
+            Rhs::Ipv4Range(r) => write!(f, "{}..{}", r.start(), r.end()),
+            Rhs::Ipv6Range(r) => write!(f, "{}..{}", r.start(), r.end()),
+            Rhs::Ipv4Cidr(c) => c.fmt(f),
+            Rhs::Ipv6Cidr(c) => c.fmt(f),
+            Rhs::Regex(r) => r.fmt(f),
+            Rhs::Set(items) => {
+                f.write_char('{')?;
+
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+
+                f.write_str(" }")
+            }
+        }
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinOp::Eq => "==",
+            BinOp::NotEq => "!=",
+            BinOp::GreaterOrEq => ">=",
+            BinOp::LessOrEq => "<=",
+            BinOp::Greater => ">",
+            BinOp::Less => "<",
+            BinOp::BitwiseAnd => "&",
+            BinOp::Contains => "contains",
+            BinOp::Matches => "matches",
+            BinOp::In => "in",
+        })
+    }
+}
+
+impl Display for LogicalOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogicalOp::Or => "or",
+            LogicalOp::Xor => "xor",
+            LogicalOp::And => "and",
+        })
+    }
+}
+
+// NOTE: explicit parentheses are kept in the tree, so combining expressions
+// never need to be parenthesized here.
+impl Display for Expr<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Unary(lhs) => lhs.fmt(f),
+            Expr::Binary { lhs, op, rhs } => write!(f, "{} {} {}", lhs, op, rhs),
+            Expr::Not(expr) => write!(f, "not {}", expr),
+            Expr::Macro(name) => write!(f, "${}", name),
+            Expr::Parenthesized(expr) => write!(f, "({})", expr),
+            Expr::Combining { op, items } => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " {} ", op)?;
+                    }
+
+                    item.fmt(f)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
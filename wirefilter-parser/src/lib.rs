@@ -8,13 +8,14 @@ use semantics::ValidateSemantics;
 use std::borrow::Cow;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::ops::RangeInclusive;
+use wirefilter::{FilterAst, Scheme};
 
 #[derive(PestParser)]
 #[grammar = "./grammar.pest"]
 pub struct Parser;
 
 pub type ParseResult<T> = Result<T, ParseError<Rule>>;
-pub type Node<'i> = pest_consume::Node<'i, Rule, ()>;
+pub type Node<'i> = pest_consume::Node<'i, Rule, Option<&'i Scheme>>;
 
 trait IntoParseResult<T> {
     fn into_parse_result(self, node: &Node) -> ParseResult<T>;
@@ -58,13 +59,145 @@ macro_rules! parse_range {
     };
 }
 
+/// Parses a filter into its syntax tree.
+pub fn parse(input: &str) -> ParseResult<ast::Expr<'_>> {
+    let node = Parser::parse_with_userdata(Rule::filter, input, None)?.single()?;
+
+    Parser::filter(node)
+}
+
+/// Parses a filter, checks it against the scheme and lowers it to the engine's
+/// syntax tree.
+pub fn parse_with_scheme<'s>(scheme: &'s Scheme, input: &str) -> ParseResult<FilterAst<'s>> {
+    let node = Parser::parse_with_userdata(Rule::filter, input, Some(scheme))?.single()?;
+    let span = node.as_span();
+    let expr = Parser::filter(node)?;
+
+    // NOTE: the engine's syntax tree can only be built by its own lexer, so we feed
+    // it the canonical source of the checked tree. This can only fail if the two
+    // grammars disagree.
+    scheme.parse(&expr.to_string()).map_err(|err| {
+        let err_var = ErrorVariant::CustomError {
+            message: err.to_string(),
+        };
+
+        ParseError::new_from_span(err_var, span)
+    })
+}
+
+/// Removes the backslashes escaping the delimiter of a regex outside of
+/// character classes, keeping all the other ones for the regex engine.
+fn unescape_regex(src: &str, delimiter: char) -> String {
+    let mut regex = String::with_capacity(src.len());
+    let mut in_char_class = false;
+    let mut chars = src.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    if in_char_class || c != delimiter {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+            }
+            '[' if !in_char_class => {
+                in_char_class = true;
+                regex.push(c);
+            }
+            ']' if in_char_class => {
+                in_char_class = false;
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+
+    regex
+}
+
+fn combine(op: ast::LogicalOp, mut items: Vec<ast::Expr>) -> ast::Expr {
+    if items.len() == 1 {
+        items.pop().unwrap()
+    } else {
+        ast::Expr::Combining { op, items }
+    }
+}
+
 #[pest_consume::parser]
 impl Parser {
     fn var(node: Node) -> ParseResult<ast::Var> {
-        // TODO check in scheme
+        // NOTE: vars are resolved in the scheme once we know they are used as
+        // fields, since `ab` can also be a bytes literal in function arguments.
         Ok(ast::Var(node.as_str().into()))
     }
 
+    fn fn_name(node: Node) -> ParseResult<Cow<str>> {
+        Ok(node.as_str().into())
+    }
+
+    fn macro_name(node: Node) -> ParseResult<Cow<str>> {
+        Ok(node.as_str().into())
+    }
+
+    fn prefix_len(node: Node) -> ParseResult<u8> {
+        let len = parse_type!(node, u8)?;
+
+        if len > 128 {
+            let msg = format!("network length {} is too long (maximum: 128)", len);
+
+            return Err(msg).into_parse_result(&node);
+        }
+
+        Ok(len)
+    }
+
+    fn lhs(node: Node) -> ParseResult<ast::Lhs> {
+        let ip_mask = |lhs, prefix_len| ast::Lhs::IpMask {
+            lhs: Box::new(lhs),
+            prefix_len,
+        };
+
+        let lhs = match_nodes! {
+            node.children();
+            [var(var)] => ast::Lhs::Var(var),
+            [fn_call(call)] => call,
+            [var(var), prefix_len(len)] => ip_mask(ast::Lhs::Var(var), len),
+            [fn_call(call), prefix_len(len)] => ip_mask(call, len)
+        };
+
+        if let (Some(scheme), ast::Lhs::IpMask { lhs: masked, .. }) = (node.user_data(), &lhs) {
+            let masked_node = node.children().next().unwrap();
+
+            semantics::check_ip_mask(scheme, masked, &masked_node)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn fn_arg(node: Node) -> ParseResult<ast::FunctionArg> {
+        Ok(match_nodes! {
+            node.children();
+            [lhs(lhs)] => ast::FunctionArg::Lhs(lhs),
+            [lit(lit)] => ast::FunctionArg::Literal(lit)
+        })
+    }
+
+    fn fn_call(node: Node) -> ParseResult<ast::Lhs> {
+        let (name, args) = match_nodes! {
+            node.children();
+            [fn_name(name), fn_arg(args)..] => (name, args.collect())
+        };
+
+        let args = match node.user_data() {
+            Some(scheme) => semantics::check_fn_call(scheme, &node)?,
+            None => args,
+        };
+
+        Ok(ast::Lhs::FunctionCall { name, args })
+    }
+
     fn int_lit(node: Node) -> ParseResult<i32> {
         use Rule::*;
 
@@ -103,7 +236,7 @@ impl Parser {
         let content = node.into_children().collect::<Vec<_>>();
 
         // NOTE: if there are no escapes then we can avoid allocating.
-        if content.len() == 1 && matches!(content[0].as_rule(), Rule::text) {
+        if content.len() == 1 && matches!(content[0].as_rule(), Rule::text | Rule::raw_text) {
             return Ok(content[0].as_str().as_bytes().into());
         }
 
@@ -114,6 +247,14 @@ impl Parser {
                 text => s.extend_from_slice(node.as_str().as_bytes()),
                 esc_alias => s.push(Parser::esc_alias(node)?),
                 esc_hex_byte => s.push(parse_num!(node, u8, 16)?),
+                esc_oct_byte => s.push(parse_num!(node, u8, 8)?),
+                esc_unicode => {
+                    let c = char::from_u32(parse_num!(node, u32, 16)?)
+                        .ok_or("invalid Unicode scalar value")
+                        .into_parse_result(&node)?;
+
+                    s.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
                 _ => unreachable!(),
             }
         }
@@ -121,6 +262,21 @@ impl Parser {
         Ok(s.into())
     }
 
+    fn bytes_lit(node: Node) -> ParseResult<Vec<u8>> {
+        let digits = node
+            .as_str()
+            .split(&[':', '-', '.'][..])
+            .collect::<Vec<_>>();
+
+        let mut bytes = Vec::with_capacity(digits.len());
+
+        for byte in digits {
+            bytes.push(u8::from_str_radix(byte, 16).into_parse_result(&node)?);
+        }
+
+        Ok(bytes)
+    }
+
     fn int_range(node: Node) -> ParseResult<RangeInclusive<i32>> {
         parse_range!(node, int_lit)
     }
@@ -155,31 +311,91 @@ impl Parser {
         parse_range!(node, ipv6_lit)
     }
 
+    fn re_flags(node: Node) -> ParseResult<String> {
+        let mut flags = String::new();
+
+        for c in node.as_str().chars() {
+            if !"imsUx".contains(c) || flags.contains(c) {
+                let msg = format!(
+                    "invalid regex flag {:?}, expected one of i, m, s, U or x",
+                    c
+                );
+
+                return Err(msg).into_parse_result(&node);
+            }
+
+            flags.push(c);
+        }
+
+        Ok(flags)
+    }
+
     fn re_lit(node: Node) -> ParseResult<ast::Regex> {
-        node.children()
-            .single()
-            .unwrap()
-            .as_str()
-            .parse()
-            .into_parse_result(&node)
+        let (content, flags) = match_nodes! {
+            node.children();
+            [re_content(content), re_flags(flags)] => (content, flags)
+        };
+
+        // NOTE: flags are set for the whole regex by a leading group.
+        let regex = match flags.as_str() {
+            "" => content,
+            flags => format!("(?{}){}", flags, content),
+        };
+
+        regex.parse().into_parse_result(&node)
     }
 
-    fn rhs(node: Node) -> ParseResult<ast::Rhs> {
+    fn re_content(node: Node) -> ParseResult<String> {
+        Ok(unescape_regex(node.as_str(), '/'))
+    }
+
+    fn re_str(node: Node) -> ParseResult<ast::Regex> {
+        let content = node.children().single().unwrap();
+
+        let regex = match content.as_rule() {
+            Rule::raw_text => content.as_str().into(),
+            _ => unescape_regex(content.as_str(), '"'),
+        };
+
+        regex.parse().into_parse_result(&node)
+    }
+
+    fn lit(node: Node) -> ParseResult<ast::Rhs> {
         Ok(match_nodes! {
             node.children();
             [int_lit(i)] => ast::Rhs::Int(i),
             [int_range(r)] => ast::Rhs::IntRange(r),
             [str_lit(s)] => ast::Rhs::String(s),
+            [bytes_lit(b)] => ast::Rhs::Bytes(b),
             [ipv4_lit(i)] => ast::Rhs::Ipv4(i),
             [ipv6_lit(i)] => ast::Rhs::Ipv6(i),
             [ipv4_cidr(c)] => ast::Rhs::Ipv4Cidr(c),
             [ipv6_cidr(c)] => ast::Rhs::Ipv6Cidr(c),
             [ipv4_range(r)] => ast::Rhs::Ipv4Range(r),
-            [ipv6_range(r)] => ast::Rhs::Ipv6Range(r),
+            [ipv6_range(r)] => ast::Rhs::Ipv6Range(r)
+        })
+    }
+
+    fn rhs_set(node: Node) -> ParseResult<Vec<ast::Rhs>> {
+        Ok(match_nodes! {
+            node.children();
+            [lit(items)..] => items.collect()
+        })
+    }
+
+    fn rhs(node: Node) -> ParseResult<ast::Rhs> {
+        Ok(match_nodes! {
+            node.children();
+            [lit(lit)] => lit,
+            [rhs_set(s)] => ast::Rhs::Set(s),
             [re_lit(r)] => ast::Rhs::Regex(r)
         })
     }
 
+    fn matches_op(_node: Node) -> ParseResult<()> {
+        Ok(())
+    }
+
     fn bin_op(node: Node) -> ParseResult<ast::BinOp> {
         use ast::BinOp::*;
         use Rule::*;
@@ -201,12 +417,91 @@ impl Parser {
         })
     }
 
+    #[alias(term)]
     fn expr(node: Node) -> ParseResult<ast::Expr> {
-        // TODO type checks
+        let expr = match_nodes! {
+            node.children();
+            [lhs(lhs), matches_op(_), re_str(re)] => ast::Expr::Binary {
+                lhs,
+                op: ast::BinOp::Matches,
+                rhs: ast::Rhs::Regex(re)
+            },
+            [lhs(lhs), bin_op(op), rhs(rhs)] => ast::Expr::Binary {lhs, op, rhs},
+            [lhs(lhs)] => ast::Expr::Unary(lhs)
+        };
+
+        match node.user_data() {
+            Some(scheme) => {
+                let nodes = node.children().collect::<Vec<_>>();
+
+                semantics::check_expr(scheme, expr, &nodes)
+            }
+            None => Ok(expr),
+        }
+    }
+
+    #[alias(term)]
+    fn not_expr(node: Node) -> ParseResult<ast::Expr> {
+        Ok(match_nodes! {
+            node.children();
+            [term(expr)] => ast::Expr::Not(Box::new(expr))
+        })
+    }
+
+    #[alias(term)]
+    fn macro_expr(node: Node) -> ParseResult<ast::Expr> {
+        let name = match_nodes! {
+            node.children();
+            [macro_name(name)] => name
+        };
+
+        if let Some(scheme) = node.user_data() {
+            scheme
+                .get_macro_definition(&name)
+                .into_parse_result(&node)?;
+        }
+
+        Ok(ast::Expr::Macro(name))
+    }
+
+    #[alias(term)]
+    fn parenthesized_expr(node: Node) -> ParseResult<ast::Expr> {
         Ok(match_nodes! {
             node.children();
-            [var(var), bin_op(op), rhs(rhs)] => ast::Expr::Binary {lhs: var, op, rhs},
-            [var(var)] => ast::Expr::Unary(var)
+            [compound_expr(expr)] => ast::Expr::Parenthesized(Box::new(expr))
+        })
+    }
+
+    fn and_expr(node: Node) -> ParseResult<ast::Expr> {
+        Ok(match_nodes! {
+            node.children();
+            [term(items)..] => combine(ast::LogicalOp::And, items.collect())
+        })
+    }
+
+    fn xor_expr(node: Node) -> ParseResult<ast::Expr> {
+        Ok(match_nodes! {
+            node.children();
+            [and_expr(items)..] => combine(ast::LogicalOp::Xor, items.collect())
+        })
+    }
+
+    fn compound_expr(node: Node) -> ParseResult<ast::Expr> {
+        Ok(match_nodes! {
+            node.children();
+            [xor_expr(items)..] => combine(ast::LogicalOp::Or, items.collect())
+        })
+    }
+
+    #[allow(non_snake_case)]
+    fn EOI(_node: Node) -> ParseResult<()> {
+        Ok(())
+    }
+
+    fn filter(node: Node) -> ParseResult<ast::Expr> {
+        Ok(match_nodes! {
+            node.children();
+            [compound_expr(expr), EOI(_)] => expr
         })
     }
 }
@@ -220,7 +515,7 @@ mod tests {
 
     macro_rules! parse {
         ($rule:ident, $input:expr) => {
-            Parser::parse(Rule::$rule, $input)
+            Parser::parse_with_userdata(Rule::$rule, $input, None)
                 .and_then(|p| p.single())
                 .and_then(Parser::$rule)
         };
@@ -266,6 +561,16 @@ mod tests {
         ok! { int_lit "-0x2a" => -42 }
         ok! { int_lit "052" => 42 }
         ok! { int_lit "-052" => -42 }
+        ok! { int_lit "0" => 0 }
+
+        err! { int_lit "08" =>
+            " --> 1:1
+            |
+          1 | 08
+            | ^---
+            |
+            = expected int_lit"
+        }
 
         err! { int_lit "-abc" =>
             " --> 1:2
@@ -326,18 +631,25 @@ mod tests {
         ok! { str_lit r#""foobar baz  qux""# => "foobar baz  qux".as_bytes().into() }
         ok! { str_lit r#""foo \x41\x42 bar\x43""# => "foo AB barC".as_bytes().into() }
 
+        ok! { str_lit r#""\101\102\000""# => "AB\0".as_bytes().into() }
+
         ok! {
             str_lit r#""\n foo \t\r \\ baz \" bar ""#  =>
             "\n foo \t\r \\ baz \" bar ".as_bytes().into()
         }
 
+        ok! { str_lit r#""\u{48}\u{1f600}""# => "H😀".as_bytes().into() }
+        ok! { str_lit r#"r"a\b""# => r"a\b".as_bytes().into() }
+        ok! { str_lit r###"r##"say "hi"#"##"### => r##"say "hi"#"##.as_bytes().into() }
+        ok! { str_lit r#"r"""# => "".as_bytes().into() }
+
         err! { str_lit r#""foobar \i""# =>
             r#" --> 1:10
             |
           1 | "foobar \i"
             |          ^---
             |
-            = expected esc_alias"#
+            = expected esc_alias or esc_oct_byte"#
         }
 
         err! { str_lit r#""foobar \x3z""# =>
@@ -348,6 +660,15 @@ mod tests {
             |
             = expected esc_hex_byte"#
         }
+
+        err! { str_lit r#""\u{110000}""# =>
+            r#" --> 1:5
+            |
+          1 | "\u{110000}"
+            |     ^----^
+            |
+            = invalid Unicode scalar value"#
+        }
     }
 
     #[test]
@@ -374,12 +695,12 @@ mod tests {
 
     #[test]
     fn pare_expr() {
-        ok! { expr "foo.bar.baz" => ast::Expr::Unary(ast::Var("foo.bar.baz".into())) }
+        ok! { expr "foo.bar.baz" => ast::Expr::Unary(ast::Lhs::Var(ast::Var("foo.bar.baz".into()))) }
 
         ok! {
             expr "foo.bar.baz > 42" =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo.bar.baz".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo.bar.baz".into())),
                 op: ast::BinOp::Greater,
                 rhs: ast::Rhs::Int(42)
             }
@@ -388,7 +709,7 @@ mod tests {
         ok! {
             expr "foo.bar.baz in 32..42" =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo.bar.baz".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo.bar.baz".into())),
                 op: ast::BinOp::In,
                 rhs: ast::Rhs::IntRange(32..=42)
             }
@@ -397,7 +718,7 @@ mod tests {
         ok! {
             expr "foo == 220.12.13.1" =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo".into())),
                 op: ast::BinOp::Eq,
                 rhs: ast::Rhs::Ipv4(Ipv4Addr::new(220, 12, 13, 1))
             }
@@ -406,7 +727,7 @@ mod tests {
         ok! {
             expr "foo in 220.12.13.1..220.12.13.2" =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo".into())),
                 op: ast::BinOp::In,
                 rhs: ast::Rhs::Ipv4Range(
                     Ipv4Addr::new(220, 12, 13, 1)..=Ipv4Addr::new(220, 12, 13, 2)
@@ -417,7 +738,7 @@ mod tests {
         ok! {
             expr "foo in 192.0.0.0/16" =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo".into())),
                 op: ast::BinOp::In,
                 rhs: ast::Rhs::Ipv4Cidr(
                     Ipv4Cidr::new(Ipv4Addr::new(192, 0, 0, 0), 16).unwrap()
@@ -428,7 +749,7 @@ mod tests {
         ok! {
             expr "foo in ::1/128" =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo".into())),
                 op: ast::BinOp::In,
                 rhs: ast::Rhs::Ipv6Cidr(
                     Ipv6Cidr::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 128).unwrap()
@@ -439,7 +760,7 @@ mod tests {
         ok! {
             expr "foo == 2001:db8::1" =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo".into())),
                 op: ast::BinOp::Eq,
                 rhs: ast::Rhs::Ipv6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
             }
@@ -448,7 +769,7 @@ mod tests {
         ok! {
             expr r#"foo.bar == "test\n""# =>
             ast::Expr::Binary {
-                lhs: ast::Var("foo.bar".into()),
+                lhs: ast::Lhs::Var(ast::Var("foo.bar".into())),
                 op: ast::BinOp::Eq,
                 rhs: ast::Rhs::String("test\n".as_bytes().into())
             }
@@ -475,7 +796,7 @@ mod tests {
           1 | 300.0.0.1
             | ^-------^
             |
-            = invalid IP address syntax"
+            = invalid IPv4 address syntax"
         }
     }
 
@@ -497,7 +818,7 @@ mod tests {
           1 | 2001:dz8::1
             | ^---------^
             |
-            = invalid IP address syntax"
+            = invalid IPv6 address syntax"
         }
     }
 
@@ -604,7 +925,7 @@ mod tests {
           1 | 2001:db8::1..2001:dz8::ff
             |              ^----------^
             |
-            = invalid IP address syntax"
+            = invalid IPv6 address syntax"
         }
 
         err! { ipv6_range "2001:db8::ff..2001:db8::11" =>
@@ -623,5 +944,257 @@ mod tests {
             re_lit r#"/[-]?[0-9]+[,.]?[0-9]*([\/][0-9]+[,.]?[0-9]*)*/"# =>
             r#"[-]?[0-9]+[,.]?[0-9]*([\/][0-9]+[,.]?[0-9]*)*"#.parse().unwrap()
         }
+
+        ok! { re_lit r#"/a\/b[\/]"/"# => r#"a/b[\/]""#.parse().unwrap() }
+        ok! { re_lit "/a.b/is" => "(?is)a.b".parse().unwrap() }
+
+        err! { re_lit "/a/ii" =>
+            r#" --> 1:4
+            |
+          1 | /a/ii
+            |    ^^
+            |
+            = invalid regex flag 'i', expected one of i, m, s, U or x"#
+        }
+    }
+
+    #[test]
+    fn parse_re_str() {
+        ok! { re_str r#""a\"b[\"]\d""# => r#"a"b[\"]\d"#.parse().unwrap() }
+        ok! { re_str r#""[a"]+""# => r#"[a"]+"#.parse().unwrap() }
+        ok! { re_str r##"r#"\d+"[/"]"#"## => r#"\d+"[/"]"#.parse().unwrap() }
+
+        err! { re_str r#""a(""# =>
+            r#" --> 1:1
+            |
+          1 | "a("
+            | ^--^
+            |
+            = regex parse error:
+              a(
+               ^
+          error: unclosed group"#
+        }
+    }
+
+    #[test]
+    fn parse_bytes_lit() {
+        ok! { bytes_lit "01:2e:f3-77.12" => vec![0x01, 0x2e, 0xf3, 0x77, 0x12] }
+        ok! { bytes_lit "ab" => vec![0xab] }
+
+        ok! { lit "42" => ast::Rhs::Int(42) }
+        ok! { lit "0a" => ast::Rhs::Bytes(vec![0x0a]) }
+        ok! { lit "aa:bb" => ast::Rhs::Bytes(vec![0xaa, 0xbb]) }
+
+        ok! {
+            lit "fe:80::1" =>
+            ast::Rhs::Ipv6(Ipv6Addr::new(0xfe, 0x80, 0, 0, 0, 0, 0, 1))
+        }
+    }
+
+    #[test]
+    fn parse_rhs_set() {
+        ok! {
+            rhs r#"{ 1 3..5 "foo" 10.0.0.0/8 }"# =>
+            ast::Rhs::Set(vec![
+                ast::Rhs::Int(1),
+                ast::Rhs::IntRange(3..=5),
+                ast::Rhs::String("foo".as_bytes().into()),
+                ast::Rhs::Ipv4Cidr(Ipv4Cidr::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap())
+            ])
+        }
+
+        ok! { rhs "{}" => ast::Rhs::Set(vec![]) }
+
+        err! { rhs "{1, 2}" =>
+            " --> 1:3
+            |
+          1 | {1, 2}
+            |   ^---
+            |
+            = expected lit"
+        }
+    }
+
+    #[test]
+    fn parse_lhs() {
+        let var = |name| ast::Lhs::Var(ast::Var(name));
+
+        ok! { lhs "ip.src" => var("ip.src".into()) }
+
+        ok! {
+            lhs "ip.src / 24" =>
+            ast::Lhs::IpMask { lhs: Box::new(var("ip.src".into())), prefix_len: 24 }
+        }
+
+        ok! {
+            lhs r#"concat(http.host, "foo", aa:bb, lower(http.path))/8"# =>
+            ast::Lhs::IpMask {
+                lhs: Box::new(ast::Lhs::FunctionCall {
+                    name: "concat".into(),
+                    args: vec![
+                        ast::FunctionArg::Lhs(var("http.host".into())),
+                        ast::FunctionArg::Literal(ast::Rhs::String("foo".as_bytes().into())),
+                        ast::FunctionArg::Literal(ast::Rhs::Bytes(vec![0xaa, 0xbb])),
+                        ast::FunctionArg::Lhs(ast::Lhs::FunctionCall {
+                            name: "lower".into(),
+                            args: vec![ast::FunctionArg::Lhs(var("http.path".into()))],
+                        }),
+                    ],
+                }),
+                prefix_len: 8,
+            }
+        }
+
+        ok! { lhs "now()" => ast::Lhs::FunctionCall { name: "now".into(), args: vec![] } }
+
+        err! { lhs "ip.src/200" =>
+            " --> 1:8
+            |
+          1 | ip.src/200
+            |        ^-^
+            |
+            = network length 200 is too long (maximum: 128)"
+        }
+    }
+
+    #[test]
+    fn parse_filter() {
+        let field = |name| ast::Expr::Unary(ast::Lhs::Var(ast::Var(name)));
+
+        ok! {
+            filter "a or b ^^ c and d || e" =>
+            ast::Expr::Combining {
+                op: ast::LogicalOp::Or,
+                items: vec![
+                    field("a".into()),
+                    ast::Expr::Combining {
+                        op: ast::LogicalOp::Xor,
+                        items: vec![
+                            field("b".into()),
+                            ast::Expr::Combining {
+                                op: ast::LogicalOp::And,
+                                items: vec![field("c".into()), field("d".into())],
+                            },
+                        ],
+                    },
+                    field("e".into()),
+                ],
+            }
+        }
+
+        ok! {
+            filter "not a and !(b or $c.d)" =>
+            ast::Expr::Combining {
+                op: ast::LogicalOp::And,
+                items: vec![
+                    ast::Expr::Not(Box::new(field("a".into()))),
+                    ast::Expr::Not(Box::new(ast::Expr::Parenthesized(Box::new(
+                        ast::Expr::Combining {
+                            op: ast::LogicalOp::Or,
+                            items: vec![field("b".into()), ast::Expr::Macro("c.d".into())],
+                        }
+                    )))),
+                ],
+            }
+        }
+
+        ok! {
+            filter r#"http.host matches "^foo\.\"bar\"$""# =>
+            ast::Expr::Binary {
+                lhs: ast::Lhs::Var(ast::Var("http.host".into())),
+                op: ast::BinOp::Matches,
+                rhs: ast::Rhs::Regex(r#"^foo\."bar"$"#.parse().unwrap()),
+            }
+        }
+
+        ok! {
+            filter "port in { 80 443 8000..8080 }" =>
+            ast::Expr::Binary {
+                lhs: ast::Lhs::Var(ast::Var("port".into())),
+                op: ast::BinOp::In,
+                rhs: ast::Rhs::Set(vec![
+                    ast::Rhs::Int(80),
+                    ast::Rhs::Int(443),
+                    ast::Rhs::IntRange(8000..=8080),
+                ]),
+            }
+        }
+
+        ok! {
+            filter "/* leading */ a and # line\n b /* before paren */" =>
+            ast::Expr::Combining {
+                op: ast::LogicalOp::And,
+                items: vec![field("a".into()), field("b".into())],
+            }
+        }
+
+        err! { filter "a and" =>
+            " --> 1:6
+            |
+          1 | a and
+            |      ^---
+            |
+            = expected lhs, parenthesized_expr, not_expr, or macro_expr"
+        }
+    }
+    #[test]
+    fn check_with_scheme() {
+        let scheme = Scheme! {
+            http.host: Bytes,
+            tcp.port: Int,
+        };
+
+        macro_rules! check_err {
+            ($input:expr => $expected:expr) => {
+                assert_eq!(
+                    parse_with_scheme(&scheme, $input).unwrap_err().to_string(),
+                    indoc!($expected)
+                );
+            };
+        }
+
+        assert_eq!(
+            parse_with_scheme(&scheme, "tcp.port in {80 443}").unwrap(),
+            scheme.parse("tcp.port in { 80 443 }").unwrap()
+        );
+
+        for (input, expected) in &[
+            (r#"http.host == r"a\b""#, r#"http.host == "a\\b""#),
+            (r#"http.host == "\u{41}""#, r#"http.host == "A""#),
+            ("http.host matches /a/i", r#"http.host matches "(?i)a""#),
+        ] {
+            assert_eq!(
+                parse_with_scheme(&scheme, input).unwrap(),
+                scheme.parse(expected).unwrap()
+            );
+        }
+
+        check_err! { "http.host == 12 or tcp.prot == 80" =>
+            " --> 1:20
+            |
+          1 | http.host == 12 or tcp.prot == 80
+            |                    ^------^
+            |
+            = unknown field"
+        }
+
+        check_err! { "http.host & 1" =>
+            " --> 1:11
+            |
+          1 | http.host & 1
+            |           ^
+            |
+            = cannot use `&` with values of type Bytes"
+        }
+
+        check_err! { r#"tcp.port in { 80 "443" }"# =>
+            r#" --> 1:18
+            |
+          1 | tcp.port in { 80 "443" }
+            |                  ^---^
+            |
+            = expected value of type Int, but got Bytes"#
+        }
     }
 }
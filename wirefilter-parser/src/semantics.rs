@@ -1,4 +1,9 @@
+use crate::ast::{BinOp, Expr, FunctionArg, Lhs, Rhs};
+use crate::{IntoParseResult, Node, ParseResult, Parser, Rule};
+use pest_consume::Parser as _;
+use std::net::Ipv6Addr;
 use std::ops::RangeInclusive;
+use wirefilter::{FunctionArgKind, GetType, Scheme, Type, TypeMismatchError};
 
 pub trait ValidateSemantics: Sized {
     fn validate_semantics(self) -> Result<Self, &'static str>;
@@ -13,3 +18,250 @@ impl<T: PartialOrd> ValidateSemantics for RangeInclusive<T> {
         }
     }
 }
+
+fn mismatch(expected: Type, actual: Type) -> String {
+    TypeMismatchError { expected, actual }.to_string()
+}
+
+fn hex_bytes(s: &str) -> Option<Vec<u8>> {
+    s.split(&[':', '-', '.'][..])
+        .map(|byte| match byte.len() {
+            2 => u8::from_str_radix(byte, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Gives a literal the type expected by the other side of the comparison.
+///
+/// The grammar can't know the type of the field, so some literals need to be
+/// reinterpreted from their source: e.g. `12` is an int, but also a valid hex
+/// byte, and `aa:bb:cc:dd:ee:ff:00:11` is both hex bytes and an IPv6 address.
+fn coerce_lit<'i>(lit: Rhs<'i>, src: &str, ty: Type, in_set: bool) -> Result<Rhs<'i>, String> {
+    let actual = match lit {
+        Rhs::Int(_) | Rhs::IntRange(_) => Type::Int,
+        Rhs::String(_) | Rhs::Bytes(_) => Type::Bytes,
+        _ => Type::Ip,
+    };
+
+    Ok(match (ty, lit) {
+        (Type::Bytes, lit @ Rhs::String(_)) | (Type::Bytes, lit @ Rhs::Bytes(_)) => lit,
+        (Type::Bytes, _) => hex_bytes(src)
+            .map(Rhs::Bytes)
+            .ok_or_else(|| mismatch(ty, actual))?,
+        (Type::Int, lit @ Rhs::Int(_)) => lit,
+        (Type::Ip, lit @ Rhs::Ipv4(_)) | (Type::Ip, lit @ Rhs::Ipv6(_)) => lit,
+        (Type::Ip, Rhs::Bytes(_)) => src
+            .parse::<Ipv6Addr>()
+            .map(Rhs::Ipv6)
+            .map_err(|_| mismatch(ty, actual))?,
+        (Type::Int, lit @ Rhs::IntRange(_))
+        | (Type::Ip, lit @ Rhs::Ipv4Range(_))
+        | (Type::Ip, lit @ Rhs::Ipv6Range(_))
+        | (Type::Ip, lit @ Rhs::Ipv4Cidr(_))
+        | (Type::Ip, lit @ Rhs::Ipv6Cidr(_))
+            if in_set =>
+        {
+            lit
+        }
+        _ if ty == actual => return Err("ranges are only allowed in sets".into()),
+        _ => return Err(mismatch(ty, actual)),
+    })
+}
+
+fn typed_lit<'i>(node: Node<'i>, ty: Type, in_set: bool) -> ParseResult<Rhs<'i>> {
+    let lit = Parser::lit(node.clone())?;
+
+    coerce_lit(lit, node.as_str(), ty, in_set).into_parse_result(&node)
+}
+
+fn literal_arg(node: Node, val_type: Type) -> ParseResult<Rhs> {
+    if node.as_rule() == Rule::lit {
+        return typed_lit(node, val_type, false);
+    }
+
+    // NOTE: bare hex bytes like `ab` are parsed as fields, so we give them
+    // another chance as literals.
+    let src = node.as_str();
+
+    Parser::parse_with_userdata(Rule::lit, src, None)
+        .and_then(|nodes| nodes.single())
+        .ok()
+        .filter(|lit| lit.as_str() == src)
+        .and_then(|lit| literal_arg(lit, val_type).ok())
+        .ok_or_else(|| node.error("expected a literal"))
+}
+
+/// Resolves the type of an already parsed lhs, checking that the fields it
+/// refers to exist.
+pub fn lhs_type(scheme: &Scheme, lhs: &Lhs, node: &Node) -> ParseResult<Type> {
+    match lhs {
+        // NOTE: the span of the lhs rule can include trailing whitespace.
+        Lhs::Var(var) => {
+            let var_node = match node.as_rule() {
+                Rule::lhs => node.children().next().unwrap(),
+                _ => node.clone(),
+            };
+
+            scheme.get_field_type(&var.0).into_parse_result(&var_node)
+        }
+        // NOTE: function calls are checked as soon as they are parsed.
+        Lhs::FunctionCall { name, .. } => Ok(scheme.get_function(name).unwrap().return_type),
+        Lhs::IpMask { .. } => Ok(Type::Ip),
+    }
+}
+
+pub fn check_ip_mask(scheme: &Scheme, lhs: &Lhs, node: &Node) -> ParseResult<()> {
+    match lhs_type(scheme, lhs, node)? {
+        Type::Ip => Ok(()),
+        ty => Err(mismatch(Type::Ip, ty)).into_parse_result(node),
+    }
+}
+
+pub fn check_fn_call<'i>(scheme: &Scheme, node: &Node<'i>) -> ParseResult<Vec<FunctionArg<'i>>> {
+    let mut nodes = node.children();
+    let name_node = nodes.next().unwrap();
+    let function = scheme
+        .get_function(name_node.as_str())
+        .into_parse_result(&name_node)?;
+
+    let arg_nodes = nodes.collect::<Vec<_>>();
+    let (min, max) = (
+        function.params.len(),
+        function.params.len() + function.opt_params.len(),
+    );
+
+    if arg_nodes.len() < min || arg_nodes.len() > max {
+        let msg = format!(
+            "invalid number of arguments: expected from {} to {}, but found {}",
+            min,
+            max,
+            arg_nodes.len()
+        );
+
+        return Err(msg).into_parse_result(node);
+    }
+
+    let mut args = Vec::with_capacity(arg_nodes.len());
+
+    for (index, arg_node) in arg_nodes.into_iter().enumerate() {
+        let (arg_kind, val_type, generic) = match function.params.get(index) {
+            Some(param) => (param.arg_kind.clone(), param.val_type, param.generic),
+            None => {
+                let param = &function.opt_params[index - min];
+                (
+                    param.arg_kind.clone(),
+                    param.default_value.get_type(),
+                    false,
+                )
+            }
+        };
+
+        let inner = arg_node.children().single().unwrap();
+
+        let arg = match arg_kind {
+            FunctionArgKind::Field if inner.as_rule() == Rule::lhs => {
+                let lhs = Parser::lhs(inner.clone())?;
+                let ty = lhs_type(scheme, &lhs, &inner)?;
+
+                if !generic && ty != val_type {
+                    let msg = format!(
+                        "invalid type of argument #{}: {}",
+                        index,
+                        mismatch(val_type, ty)
+                    );
+
+                    return Err(msg).into_parse_result(&arg_node);
+                }
+
+                FunctionArg::Lhs(lhs)
+            }
+            FunctionArgKind::Field => {
+                let msg = format!("invalid kind of argument #{}: expected a field", index);
+
+                return Err(msg).into_parse_result(&arg_node);
+            }
+            FunctionArgKind::Literal => FunctionArg::Literal(literal_arg(inner, val_type)?),
+        };
+
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+fn is_supported(ty: Type, op: &BinOp) -> bool {
+    match (ty, op) {
+        (Type::Bool, _) => false,
+        (Type::Int, BinOp::BitwiseAnd) => true,
+        (Type::Bytes, BinOp::Contains) | (Type::Bytes, BinOp::Matches) => true,
+        (_, BinOp::BitwiseAnd) | (_, BinOp::Contains) | (_, BinOp::Matches) => false,
+        _ => true,
+    }
+}
+
+/// Checks that the operator and the rhs of an expression are compatible with
+/// the type of its lhs, giving literals their final type.
+pub fn check_expr<'i>(
+    scheme: &Scheme,
+    expr: Expr<'i>,
+    nodes: &[Node<'i>],
+) -> ParseResult<Expr<'i>> {
+    let (lhs, op, rhs) = match expr {
+        Expr::Unary(lhs) => {
+            return match lhs_type(scheme, &lhs, &nodes[0])? {
+                Type::Bool => Ok(Expr::Unary(lhs)),
+                ty => {
+                    let msg = format!(
+                        "expected a comparison operator after a value of type {:?}",
+                        ty
+                    );
+
+                    Err(msg).into_parse_result(&nodes[0])
+                }
+            };
+        }
+        Expr::Binary { lhs, op, rhs } => (lhs, op, rhs),
+        expr => return Ok(expr),
+    };
+
+    let ty = lhs_type(scheme, &lhs, &nodes[0])?;
+    let (op_node, rhs_node) = (&nodes[1], &nodes[2]);
+
+    if !is_supported(ty, &op) {
+        let msg = format!(
+            "cannot use `{}` with values of type {:?}",
+            op_node.as_str(),
+            ty
+        );
+
+        return Err(msg).into_parse_result(op_node);
+    }
+
+    let rhs = match (&op, rhs) {
+        (BinOp::Matches, rhs @ Rhs::Regex(_)) => rhs,
+        (BinOp::Matches, _) => return Err("expected a regex").into_parse_result(rhs_node),
+        (_, Rhs::Regex(_)) => return Err("unexpected regex").into_parse_result(rhs_node),
+        (BinOp::In, _) => {
+            let inner = rhs_node.children().single().unwrap();
+
+            if inner.as_rule() != Rule::rhs_set {
+                return Err("expected a set").into_parse_result(rhs_node);
+            }
+
+            let mut set = Vec::new();
+
+            for item in inner.into_children() {
+                set.push(typed_lit(item, ty, true)?);
+            }
+
+            Rhs::Set(set)
+        }
+        (_, Rhs::Set(_)) => {
+            return Err("sets can only be used with `in`").into_parse_result(rhs_node)
+        }
+        (_, _) => typed_lit(rhs_node.children().single().unwrap(), ty, false)?,
+    };
+
+    Ok(Expr::Binary { lhs, op, rhs })
+}
@@ -0,0 +1,295 @@
+use serde_json::{json, Value};
+use wirefilter::{
+    Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam, LhsValue, Scheme, Type,
+};
+use wirefilter_parser::ast::{BinOp, Expr, FunctionArg, Lhs, LogicalOp, Rhs};
+
+fn first_arg<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+    args.next().unwrap()
+}
+
+fn scheme() -> Scheme {
+    let mut scheme = Scheme! {
+        ip.src: Ip,
+        ip.dst: Ip,
+        tcp.port: Int,
+        tcp.flags: Int,
+        tcp.flags.syn: Bool,
+        http.host: Bytes,
+        http.path: Bytes,
+        ssl: Bool,
+        _internal.id: Int,
+    };
+
+    let function = |params: Vec<(FunctionArgKind, Type)>, return_type| Function {
+        params: params
+            .into_iter()
+            .map(|(arg_kind, val_type)| FunctionParam {
+                arg_kind,
+                val_type,
+                generic: false,
+            })
+            .collect(),
+        opt_params: vec![],
+        return_type,
+        pure: true,
+        implementation: FunctionImpl::new(first_arg),
+    };
+
+    let functions = vec![
+        (
+            "lower",
+            function(vec![(FunctionArgKind::Field, Type::Bytes)], Type::Bytes),
+        ),
+        (
+            "len",
+            function(vec![(FunctionArgKind::Field, Type::Bytes)], Type::Int),
+        ),
+        (
+            "echo_ip",
+            function(vec![(FunctionArgKind::Field, Type::Ip)], Type::Ip),
+        ),
+        (
+            "concat",
+            function(
+                vec![
+                    (FunctionArgKind::Field, Type::Bytes),
+                    (FunctionArgKind::Literal, Type::Bytes),
+                ],
+                Type::Bytes,
+            ),
+        ),
+    ];
+
+    for (name, function) in functions {
+        scheme.add_function(name.into(), function).unwrap();
+    }
+
+    for (name, definition) in MACROS {
+        scheme
+            .add_macro(name.to_string(), definition.to_string())
+            .unwrap();
+    }
+
+    scheme
+}
+
+const MACROS: &[(&str, &str)] = &[
+    ("web", "tcp.port in { 80 443 }"),
+    (
+        "is_local",
+        "ip.src in { 10.0.0.0/8 192.168.0.0/16 } or ip.src == ::1",
+    ),
+];
+
+// The engine doesn't keep the syntax tree around, so we compare its JSON
+// representation with the one built from the parser's tree.
+fn lhs_to_json(lhs: &Lhs<'_>) -> Value {
+    match lhs {
+        Lhs::Var(var) => json!(var.0),
+        Lhs::FunctionCall { name, args } => {
+            let args = args
+                .iter()
+                .map(|arg| match arg {
+                    FunctionArg::Lhs(lhs) => {
+                        json!({"kind": "LhsFieldExpr", "value": lhs_to_json(lhs)})
+                    }
+                    FunctionArg::Literal(rhs) => {
+                        json!({"kind": "Literal", "value": rhs_to_json(rhs)})
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            json!({"name": name, "args": args})
+        }
+        Lhs::IpMask { lhs, prefix_len } => {
+            json!({"lhs": lhs_to_json(lhs), "prefix_len": prefix_len})
+        }
+    }
+}
+
+fn rhs_to_json(rhs: &Rhs<'_>) -> Value {
+    let range = |start: String, end: String| json!({"start": start, "end": end});
+
+    match rhs {
+        Rhs::Int(i) => json!(i),
+        Rhs::IntRange(r) => json!({"start": r.start(), "end": r.end()}),
+        Rhs::String(s) => match std::str::from_utf8(s) {
+            Ok(s) => json!(s),
+            Err(_) => json!(s),
+        },
+        Rhs::Bytes(b) => json!(b),
+        Rhs::Ipv4(ip) => json!(ip.to_string()),
+        Rhs::Ipv6(ip) => json!(ip.to_string()),
+        Rhs::Ipv4Range(r) => range(r.start().to_string(), r.end().to_string()),
+        Rhs::Ipv6Range(r) => range(r.start().to_string(), r.end().to_string()),
+        Rhs::Ipv4Cidr(c) => json!(c.to_string()),
+        Rhs::Ipv6Cidr(c) => json!(c.to_string()),
+        Rhs::Regex(r) => json!(r.as_str()),
+        // Sets of ints are always stored as ranges by the engine.
+        Rhs::Set(items) => items
+            .iter()
+            .map(|item| match item {
+                Rhs::Int(i) => json!({"start": i, "end": i}),
+                item => rhs_to_json(item),
+            })
+            .collect(),
+    }
+}
+
+fn expr_to_json(expr: &Expr<'_>) -> Value {
+    match expr {
+        Expr::Unary(lhs) => json!({"lhs": lhs_to_json(lhs), "op": "IsTrue"}),
+        Expr::Binary { lhs, op, rhs } => {
+            let op = match op {
+                BinOp::Eq => "Equal",
+                BinOp::NotEq => "NotEqual",
+                BinOp::GreaterOrEq => "GreaterThanEqual",
+                BinOp::LessOrEq => "LessThanEqual",
+                BinOp::Greater => "GreaterThan",
+                BinOp::Less => "LessThan",
+                BinOp::BitwiseAnd => "BitwiseAnd",
+                BinOp::Contains => "Contains",
+                BinOp::Matches => "Matches",
+                BinOp::In => "OneOf",
+            };
+
+            json!({"lhs": lhs_to_json(lhs), "op": op, "rhs": rhs_to_json(rhs)})
+        }
+        Expr::Not(arg) => json!({"op": "Not", "arg": expr_to_json(arg)}),
+        Expr::Macro(name) => {
+            let (_, definition) = MACROS.iter().find(|(n, _)| n == name).unwrap();
+
+            expr_to_json(&wirefilter_parser::parse(definition).unwrap())
+        }
+        Expr::Parenthesized(expr) => expr_to_json(expr),
+        Expr::Combining { op, items } => {
+            let op = match op {
+                LogicalOp::Or => "Or",
+                LogicalOp::Xor => "Xor",
+                LogicalOp::And => "And",
+            };
+
+            json!({"op": op, "items": items.iter().map(expr_to_json).collect::<Vec<_>>()})
+        }
+    }
+}
+
+fn corpus(src: &str) -> impl Iterator<Item = &str> {
+    src.lines().filter(|line| !line.trim().is_empty())
+}
+
+#[test]
+fn valid_filters() {
+    let scheme = scheme();
+
+    for filter in corpus(include_str!("corpus/valid.txt")) {
+        let expected = match scheme.parse(filter) {
+            Ok(ast) => serde_json::to_value(&ast).unwrap(),
+            Err(err) => panic!("engine failed to parse {:?}:\n{}", filter, err),
+        };
+
+        let actual = match wirefilter_parser::parse(filter) {
+            Ok(expr) => expr_to_json(&expr),
+            Err(err) => panic!("parser failed to parse {:?}:\n{}", filter, err),
+        };
+
+        assert_eq!(actual, expected, "{:?}", filter);
+
+        let lowered = match wirefilter_parser::parse_with_scheme(&scheme, filter) {
+            Ok(ast) => serde_json::to_value(&ast).unwrap(),
+            Err(err) => panic!("parser failed to check {:?}:\n{}", filter, err),
+        };
+
+        assert_eq!(lowered, expected, "{:?}", filter);
+    }
+}
+
+// Literals in these filters get their type from the other side of the
+// comparison, so only the checked tree matches the engine.
+#[test]
+fn typed_filters() {
+    let scheme = scheme();
+
+    for filter in corpus(include_str!("corpus/typed.txt")) {
+        let expected = match scheme.parse(filter) {
+            Ok(ast) => serde_json::to_value(&ast).unwrap(),
+            Err(err) => panic!("engine failed to parse {:?}:\n{}", filter, err),
+        };
+
+        let lowered = match wirefilter_parser::parse_with_scheme(&scheme, filter) {
+            Ok(ast) => serde_json::to_value(&ast).unwrap(),
+            Err(err) => panic!("parser failed to check {:?}:\n{}", filter, err),
+        };
+
+        assert_eq!(lowered, expected, "{:?}", filter);
+    }
+}
+
+// Filters printed by the engine must parse back into the same tree, both in
+// the engine and in the parser.
+#[test]
+fn printed_filters() {
+    let scheme = scheme();
+
+    let filters =
+        corpus(include_str!("corpus/valid.txt")).chain(corpus(include_str!("corpus/typed.txt")));
+
+    for filter in filters {
+        let ast = match scheme.parse(filter) {
+            Ok(ast) => ast,
+            Err(err) => panic!("engine failed to parse {:?}:\n{}", filter, err),
+        };
+        let expected = serde_json::to_value(&ast).unwrap();
+
+        for printed in &[ast.to_string(), format!("{:#}", ast)] {
+            let reparsed = match scheme.parse(printed) {
+                Ok(ast) => serde_json::to_value(&ast).unwrap(),
+                Err(err) => panic!("engine failed to parse {:?}:\n{}", printed, err),
+            };
+
+            assert_eq!(reparsed, expected, "{:?}", printed);
+
+            let lowered = match wirefilter_parser::parse_with_scheme(&scheme, printed) {
+                Ok(ast) => serde_json::to_value(&ast).unwrap(),
+                Err(err) => panic!("parser failed to check {:?}:\n{}", printed, err),
+            };
+
+            assert_eq!(lowered, expected, "{:?}", printed);
+        }
+    }
+}
+
+#[test]
+fn invalid_filters() {
+    let scheme = scheme();
+
+    for filter in corpus(include_str!("corpus/invalid.txt")) {
+        assert!(scheme.parse(filter).is_err(), "engine parsed {:?}", filter);
+        assert!(
+            wirefilter_parser::parse(filter).is_err(),
+            "parser parsed {:?}",
+            filter
+        );
+    }
+}
+
+#[test]
+fn ill_typed_filters() {
+    let scheme = scheme();
+
+    for filter in corpus(include_str!("corpus/ill_typed.txt")) {
+        assert!(scheme.parse(filter).is_err(), "engine parsed {:?}", filter);
+        assert!(
+            wirefilter_parser::parse(filter).is_ok(),
+            "parser rejected {:?}",
+            filter
+        );
+
+        assert!(
+            wirefilter_parser::parse_with_scheme(&scheme, filter).is_err(),
+            "parser checked {:?}",
+            filter
+        );
+    }
+}